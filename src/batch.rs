@@ -0,0 +1,227 @@
+//
+// batch.rs
+// Distributed under terms of the GPLv3 license.
+//
+// Several documents (e.g. all decks of a lecture) can be built with one command. All documents
+// are built at the same time, so that the frames of all of them share the worker threads.
+//
+
+use crate::clean::collect_garbage_after_build;
+use crate::events::{self, Event};
+use crate::options::Options;
+use crate::process_file::{process_file, variant_filename, Result};
+use crate::project::resolve_root;
+use rayon::prelude::*;
+use std::path::{Path, PathBuf};
+
+/// A document to build and the files its output is written to.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Job {
+    pub input_file: String,
+    pub output_file: String,
+    pub page_map_file: Option<String>,
+    /// File that was edited when `input_file` is the main document of a multi-file project
+    pub edited_file: Option<String>,
+}
+
+/// Expands glob patterns (e.g. `lectures/*.tex`) in `inputs`. Inputs that are existing files or
+/// do not match anything are kept as they are.
+pub fn expand_inputs(inputs: &[&str]) -> Vec<String> {
+    let mut expanded = Vec::new();
+    for input in inputs {
+        let is_pattern = input.contains(|c| c == '*' || c == '?' || c == '[');
+        if !is_pattern || Path::new(input).is_file() {
+            expanded.push(input.to_string());
+            continue;
+        }
+
+        // Hidden files (e.g. `.talk.md.tex` of Markdown input) are not presentations
+        let options = glob::MatchOptions {
+            require_literal_leading_dot: true,
+            ..glob::MatchOptions::new()
+        };
+        let mut matches: Vec<PathBuf> = match glob::glob_with(input, options) {
+            Ok(paths) => paths
+                .filter_map(|p| p.ok())
+                .filter(|p| p.is_file())
+                .collect(),
+            Err(err) => {
+                warn!("Invalid pattern {:?}: {}", input, err);
+                Vec::new()
+            }
+        };
+        if matches.is_empty() {
+            warn!("No files match {:?}", input);
+            expanded.push(input.to_string());
+        } else {
+            matches.sort();
+            expanded.extend(matches.iter().map(|p| p.to_string_lossy().into_owned()));
+        }
+    }
+    expanded
+}
+
+/// Output file used if none is given: `<stem>.preview.pdf` next to the input.
+pub const DEFAULT_OUTPUT_PATTERN: &str = "{stem}.preview.pdf";
+
+/// Replaces the placeholders `{stem}` (file name of the input without extension) and `{profile}`
+/// in `pattern`.
+pub fn expand_output_pattern(pattern: &str, input_file: &str, profile: Option<&str>) -> String {
+    let stem = Path::new(input_file)
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy();
+    pattern
+        .replace("{stem}", &stem)
+        .replace("{profile}", profile.unwrap_or("default"))
+}
+
+/// Output file of `input_file`: `output_pattern` (relative to the current directory) or the
+/// default pattern next to the input.
+pub fn output_filename(
+    output_pattern: Option<&str>,
+    input_file: &str,
+    profile: Option<&str>,
+) -> String {
+    match output_pattern {
+        Some(pattern) => expand_output_pattern(pattern, input_file, profile),
+        None => Path::new(input_file)
+            .with_file_name(expand_output_pattern(
+                DEFAULT_OUTPUT_PATTERN,
+                input_file,
+                profile,
+            ))
+            .to_string_lossy()
+            .into_owned(),
+    }
+}
+
+/// Creates the jobs for `inputs`. With several inputs, `output_pattern` needs to contain `{stem}`
+/// and page maps get the input name appended.
+pub fn make_jobs(
+    inputs: &[String],
+    output_pattern: Option<&str>,
+    page_map_file: Option<&str>,
+    profile: Option<&str>,
+) -> Vec<Job> {
+    let output_pattern = match output_pattern {
+        Some(pattern) if inputs.len() > 1 && !pattern.contains("{stem}") => {
+            warn!(
+                "Ignoring output file {} since several input files are given (use {{stem}} in its name)",
+                pattern
+            );
+            None
+        }
+        pattern => pattern,
+    };
+
+    inputs
+        .iter()
+        .map(|input_file| {
+            let stem = Path::new(input_file)
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy();
+            Job {
+                input_file: input_file.clone(),
+                output_file: output_filename(output_pattern, input_file, profile),
+                page_map_file: if inputs.len() > 1 {
+                    page_map_file.map(|p| variant_filename(p, &stem))
+                } else {
+                    page_map_file.map(|p| p.to_string())
+                },
+                edited_file: None,
+            }
+        })
+        .collect()
+}
+
+/// Creates the jobs of a build with the inputs and options of `args`.
+pub fn build_jobs(args: &Options) -> Vec<Job> {
+    let mut inputs: Vec<&str> = args.inputs.iter().map(String::as_str).collect();
+    let mut output_file = args.output_file.as_deref();
+    // `faster-beamer input.tex output.pdf`
+    if inputs.len() == 2 && output_file.is_none() && inputs[1].ends_with(".pdf") {
+        output_file = inputs.pop();
+    }
+    let jobs = make_jobs(
+        &expand_inputs(&inputs),
+        output_file,
+        args.page_map.as_deref(),
+        args.profile.as_deref(),
+    );
+    if args.find_root {
+        jobs.into_iter().map(resolve_root).collect()
+    } else {
+        jobs
+    }
+}
+
+/// Builds all `jobs`. Returns the result of every job (in the order of `jobs`).
+pub fn process_jobs(jobs: &[Job], args: &Options) -> Vec<Result<()>> {
+    let results: Vec<Result<()>> = jobs
+        .par_iter()
+        .map(|job| {
+            info!("Processing {:?}.", job.input_file);
+            let result = process_file(job, args);
+            if let Err(err) = &result {
+                error!("{}: {}", job.input_file, err);
+                events::emit(Event::from_error(&job.input_file, err));
+            }
+            result
+        })
+        .collect();
+    collect_garbage_after_build(args);
+    if jobs.len() == 1 {
+        return results;
+    }
+
+    let num_failed = results.iter().filter(|r| r.is_err()).count();
+    info!(
+        "Built {} of {} documents.",
+        jobs.len() - num_failed,
+        jobs.len()
+    );
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_make_jobs() {
+        let single = make_jobs(&["talk.tex".to_string()], None, Some("frames.json"), None);
+        assert_eq!(
+            single,
+            vec![Job {
+                input_file: "talk.tex".to_string(),
+                output_file: "talk.preview.pdf".to_string(),
+                page_map_file: Some("frames.json".to_string()),
+                edited_file: None,
+            }]
+        );
+
+        let batch = make_jobs(
+            &["lectures/week1.tex".to_string(), "week2.tex".to_string()],
+            Some("ignored.pdf"),
+            Some("frames.json"),
+            None,
+        );
+        assert_eq!(batch[0].output_file, "lectures/week1.preview.pdf");
+        assert_eq!(
+            batch[0].page_map_file,
+            Some("frames-week1.json".to_string())
+        );
+        assert_eq!(batch[1].output_file, "week2.preview.pdf");
+
+        let batch = make_jobs(
+            &["lectures/week1.tex".to_string(), "week2.tex".to_string()],
+            Some("out/{stem}-{profile}.pdf"),
+            None,
+            Some("handout"),
+        );
+        assert_eq!(batch[0].output_file, "out/week1-handout.pdf");
+        assert_eq!(batch[1].output_file, "out/week2-handout.pdf");
+    }
+}
@@ -4,10 +4,308 @@
 // Distributed under terms of the GPLv3 license.
 //
 
-use crate::parsing::ParsedFile;
+use crate::includes::is_commented;
+use crate::parsing::{balanced_group, ParsedFile};
 use crate::tree_traversal::{get_children, TraversalOrder};
+use regex::{Captures, Regex};
+use std::collections::{BTreeMap, HashSet};
 use tree_sitter::Node;
 
+lazy_static! {
+    static ref FRAME_OPTIONS_REGEX: Regex =
+        Regex::new(r"^\s*\\(?:begin\{frame\}|frame)\s*(?:<[^>]*>\s*)?\[([^\]]*)\]").unwrap();
+}
+lazy_static! {
+    static ref AGAINFRAME_REGEX: Regex = Regex::new(
+        r"^\s*\\againframe\s*(?:<([^>]*)>\s*)?(?:\[([^\]]*)\]\s*)?(?:<([^>]*)>\s*)?\{([^}]*)\}"
+    )
+    .unwrap();
+}
+lazy_static! {
+    static ref FRAME_HEADER_REGEX: Regex =
+        Regex::new(r"^(\s*\\(?:begin\{frame\}|frame))(?:\s*<([^>]*)>)?(?:\s*\[([^\]]*)\])?")
+            .unwrap();
+}
+lazy_static! {
+    /// Arguments of the `\frame` command before its body
+    static ref FRAME_COMMAND_REGEX: Regex =
+        Regex::new(r"^\s*(?:<[^>]*>\s*)?(?:\[[^\]]*\]\s*)?(?:<[^>]*>\s*)?\{").unwrap();
+}
+lazy_static! {
+    static ref FRAME_TITLE_ARG_REGEX: Regex = Regex::new(
+        r"^\s*\\begin\{frame\}\s*(?:<[^>]*>\s*)?(?:\[[^\]]*\]\s*)?(?:<[^>]*>\s*)?\{"
+    )
+    .unwrap();
+}
+lazy_static! {
+    static ref FRAMETITLE_REGEX: Regex =
+        Regex::new(r"\\frametitle\s*(?:<[^>]*>\s*)?(?:\[[^\]]*\]\s*)?\{").unwrap();
+}
+lazy_static! {
+    static ref DOCUMENTCLASS_REGEX: Regex =
+        Regex::new(r"\\documentclass\s*(?:\[([^\]]*)\])?\s*\{").unwrap();
+}
+lazy_static! {
+    static ref USETHEME_REGEX: Regex =
+        Regex::new(r"\\usetheme\s*(?:\[[^\]]*\]\s*)?\{[^}]*\}").unwrap();
+}
+lazy_static! {
+    static ref BEAMERARTICLE_REGEX: Regex =
+        Regex::new(r"(?m)^[ \t]*\\usepackage\s*(?:\[[^\]]*\]\s*)?\{beamerarticle\}[^\n]*\n?")
+            .unwrap();
+}
+lazy_static! {
+    static ref NOTES_OPTION_REGEX: Regex =
+        Regex::new(r"\\setbeameroption\s*\{[^}]*\bnotes\b[^}]*\}[ \t]*\n?").unwrap();
+}
+lazy_static! {
+    static ref INCLUDEONLYFRAMES_REGEX: Regex =
+        Regex::new(r"\\includeonlyframes\s*\{([^}]*)\}[ \t]*\n?").unwrap();
+}
+lazy_static! {
+    static ref LABEL_REGEX: Regex = Regex::new(r"\\label\s*\{([^}]*)\}").unwrap();
+}
+lazy_static! {
+    static ref NEWIF_REGEX: Regex = Regex::new(r"\\newif\s*\\if([a-zA-Z@]+)").unwrap();
+}
+lazy_static! {
+    /// `\nametrue` or `\namefalse`
+    static ref SET_CONDITIONAL_REGEX: Regex =
+        Regex::new(r"\\([a-zA-Z@]+)(true|false)\b").unwrap();
+}
+
+/// Template overrides that remove navigation symbols, headline, footline and logo of a theme.
+pub const STRIP_DECORATIONS: &str = "\\setbeamertemplate{navigation symbols}{}\
+                                     \\setbeamertemplate{headline}{}\
+                                     \\setbeamertemplate{footline}{}\
+                                     \\logo{}\n";
+
+/// Code before the document content that is carried into the document of a frame: `\section`s
+/// in it must not add the frames of `\AtBeginSection` (and of parts and subsections), and a
+/// `\maketitle` outside of frames must not add a title frame. The pages that it produces anyway
+/// (e.g. of a `\note` or `\includepdf` between frames, or of stray text) are discarded, so that
+/// the PDF of the frame only has the pages of the frame.
+pub const CARRIED_CONTENT_BEGIN: &str = "\\makeatletter\
+     \\newif\\iffasterbeamer@discard\
+     \\fasterbeamer@discardtrue\
+     \\ifdefined\\AddToHook\
+     \\AddToHook{shipout/before}{\\iffasterbeamer@discard\\DiscardShipoutBox\\fi}\
+     \\else\\ifdefined\\AtBeginShipout\
+     \\AtBeginShipout{\\iffasterbeamer@discard\\AtBeginShipoutDiscard\\fi}\
+     \\fi\\fi\
+     \\edef\\fasterbeamer@page{\\the\\c@page}\
+     \\def\\beamer@atbeginpart{}\
+     \\def\\beamer@atbeginsections{}\
+     \\def\\beamer@atbeginsubsections{}\
+     \\def\\beamer@atbeginsubsubsections{}\
+     \\def\\fasterbeamer@gobblehook{\\@ifnextchar[\\fasterbeamer@gobbleoptional\\@gobble}\
+     \\def\\fasterbeamer@gobbleoptional[#1]#2{}\
+     \\let\\AtBeginPart\\@gobble\
+     \\let\\AtBeginSection\\fasterbeamer@gobblehook\
+     \\let\\AtBeginSubsection\\fasterbeamer@gobblehook\
+     \\let\\AtBeginSubsubsection\\fasterbeamer@gobblehook\
+     \\let\\fasterbeamer@maketitle\\maketitle\
+     \\let\\maketitle\\relax\
+     \\makeatother\n";
+
+/// Code after the document content that is carried into the document of a frame: ships out (and
+/// discards) the pages that the content produced, and restores the page number of the frame.
+pub const CARRIED_CONTENT_END: &str = "\n\\makeatletter\
+     \\let\\maketitle\\fasterbeamer@maketitle\
+     \\clearpage\
+     \\fasterbeamer@discardfalse\
+     \\setcounter{page}{\\fasterbeamer@page}\
+     \\makeatother\n";
+
+/// Sets the class option `key` (to `value`, if given) in the `\\documentclass` line of `preamble`,
+/// replacing a previous value of this option.
+pub fn set_class_option(preamble: &str, key: &str, value: Option<&str>) -> String {
+    let captures = match DOCUMENTCLASS_REGEX.captures(preamble) {
+        Some(captures) => captures,
+        None => {
+            warn!("Could not find \\documentclass to set option {}", key);
+            return preamble.to_string();
+        }
+    };
+    let whole = captures.get(0).unwrap();
+
+    let mut options: Vec<String> = captures
+        .get(1)
+        .map(|o| o.as_str())
+        .unwrap_or("")
+        .split(',')
+        .map(|o| o.trim())
+        .filter(|o| !o.is_empty() && o.splitn(2, '=').next().unwrap().trim() != key)
+        .map(|o| o.to_string())
+        .collect();
+    options.push(match value {
+        Some(value) => format!("{}={}", key, value),
+        None => key.to_string(),
+    });
+
+    format!(
+        "{}\\documentclass[{}]{{{}",
+        &preamble[..whole.start()],
+        options.join(","),
+        &preamble[whole.end()..]
+    )
+}
+
+/// Replaces the document class of `preamble` (including its options) by `class`, e.g.
+/// `[beamer]{standalone}`.
+pub fn set_document_class(preamble: &str, class: &str) -> String {
+    let whole = match DOCUMENTCLASS_REGEX.find(preamble) {
+        Some(whole) => whole,
+        None => return format!("\\documentclass{}\n{}", class, preamble),
+    };
+    let class_end = preamble[whole.end()..]
+        .find('}')
+        .map(|i| whole.end() + i + 1)
+        .unwrap_or(preamble.len());
+    format!(
+        "{}\\documentclass{}{}",
+        &preamble[..whole.start()],
+        class,
+        &preamble[class_end..]
+    )
+}
+
+/// Beamer modes that can be selected with `--beamer-mode`.
+pub const BEAMER_MODES: &[&str] = &["presentation", "handout", "article"];
+
+/// Class options that select the mode of beamer or beamerswitch.
+const MODE_OPTIONS: &[&str] = &["beamer", "handout", "trans", "article"];
+
+/// Name and options of the document class of `preamble`.
+fn document_class(preamble: &str) -> Option<(String, Vec<String>)> {
+    let captures = DOCUMENTCLASS_REGEX.captures(preamble)?;
+    let class_start = captures.get(0).unwrap().end();
+    let class_end = class_start + preamble[class_start..].find('}')?;
+    let options = captures
+        .get(1)
+        .map(|o| o.as_str())
+        .unwrap_or("")
+        .split(',')
+        .map(|o| o.trim())
+        .filter(|o| !o.is_empty())
+        .map(|o| o.to_string())
+        .collect();
+    Some((preamble[class_start..class_end].trim().to_string(), options))
+}
+
+/// Selects the beamer `mode` (one of `BEAMER_MODES`) in `preamble`: through the class options of
+/// beamerswitch, or with the beamer class (with the `handout` option) or the article class with
+/// the beamerarticle package.
+pub fn set_beamer_mode(preamble: &str, mode: &str) -> String {
+    let (class, options) = match document_class(preamble) {
+        Some(class) => class,
+        None => {
+            warn!("Could not find \\documentclass to set the beamer mode");
+            return preamble.to_string();
+        }
+    };
+    let mut options: Vec<String> = options
+        .into_iter()
+        .filter(|o| !MODE_OPTIONS.contains(&&o[..]))
+        .collect();
+    let class_with_options = |options: &[String], class: &str| {
+        if options.is_empty() {
+            format!("{{{}}}", class)
+        } else {
+            format!("[{}]{{{}}}", options.join(","), class)
+        }
+    };
+
+    if class == "beamerswitch" {
+        options.push(match mode {
+            "presentation" => "beamer".to_string(),
+            mode => mode.to_string(),
+        });
+        return set_document_class(preamble, &class_with_options(&options, &class));
+    }
+    let preamble = BEAMERARTICLE_REGEX.replace_all(preamble, "");
+    match mode {
+        "article" => set_document_class(
+            &preamble,
+            &(class_with_options(&options, "article") + "\n\\usepackage{beamerarticle}"),
+        ),
+        mode => {
+            if mode == "handout" {
+                options.push("handout".to_string());
+            }
+            set_document_class(&preamble, &class_with_options(&options, "beamer"))
+        }
+    }
+}
+
+/// Ways to compile the overlays of frames that can be selected with `--overlays`.
+pub const OVERLAY_MODES: &[&str] = &["all", "collapse", "last"];
+
+/// Preamble code that keeps only the last page of the document: every page is held back when it
+/// is shipped out, and the last one is shipped out at the end.
+const LAST_PAGE_ONLY: &str = "\\usepackage{atbegshi}\n\
+                              \\newbox\\fasterbeamerlastpage\n\
+                              \\newif\\iffasterbeamerlastpage\n\
+                              \\AtBeginShipout{\\iffasterbeamerlastpage\\else\
+                              \\global\\setbox\\fasterbeamerlastpage=\\copy\\AtBeginShipoutBox\
+                              \\AtBeginShipoutDiscard\\fi}\n\
+                              \\AtEndDocument{\\clearpage\\global\\fasterbeamerlastpagetrue\
+                              \\ifvoid\\fasterbeamerlastpage\\else\\shipout\\box\\fasterbeamerlastpage\\fi}\n";
+
+/// Compiles the overlays of the frames of `preamble` as separate pages (`all`), collapsed into one
+/// page with beamer's `trans` mode (`collapse`) or keeps only the last overlay of every frame
+/// (`last`, for documents with a single frame).
+pub fn set_overlays(preamble: &str, mode: &str) -> String {
+    match mode {
+        "collapse" => set_class_option(preamble, "trans", None),
+        "last" => format!("{}\n{}", preamble, LAST_PAGE_ONLY),
+        _ => preamble.to_string(),
+    }
+}
+
+/// Modes of the notes that can be selected with `--notes`.
+pub const NOTES_MODES: &[&str] = &["none", "show", "only"];
+
+/// Hides the notes of `preamble` (`none`), shows them on pages of their own after the slides
+/// (`show`) or shows only the notes (`only`), instead of the `\\setbeameroption` of the document.
+/// In a whole document, the option is set at the end of its preamble.
+pub fn set_notes(preamble: &str, mode: &str) -> String {
+    let option = match mode {
+        "show" => "show notes",
+        "only" => "show only notes",
+        _ => "hide notes",
+    };
+    let (preamble, body) = match preamble.find("\\begin{document}") {
+        Some(start) => preamble.split_at(start),
+        None => (preamble, ""),
+    };
+    format!(
+        "{}\n\\setbeameroption{{{}}}\n{}",
+        NOTES_OPTION_REGEX.replace_all(preamble, ""),
+        option,
+        body
+    )
+}
+
+/// Replaces all `\\usetheme` commands of `preamble` by `\\usetheme{theme}` (or adds one).
+pub fn set_theme(preamble: &str, theme: &str) -> String {
+    let usetheme = format!("\\usetheme{{{}}}", theme);
+    if USETHEME_REGEX.is_match(preamble) {
+        USETHEME_REGEX
+            .replace_all(preamble, regex::NoExpand(&usetheme))
+            .into_owned()
+    } else {
+        format!("{}\n{}\n", preamble, usetheme)
+    }
+}
+
+/// A label that is defined more than once in the document.
+/// `positions` are byte offsets into the input file.
+pub struct DuplicateLabel {
+    pub label: String,
+    pub positions: Vec<usize>,
+}
+
 pub fn get_frames(parsed_file: &ParsedFile) -> Vec<Node> {
     let mut frames = Vec::new();
 
@@ -35,9 +333,972 @@ fn has_begin_frame(node: Node, parsed_file: &ParsedFile) -> bool {
             .contains("{frame}")
 }
 
+/// Environments whose content is not LaTeX, in which `\end{frame}` does not end a frame.
+const VERBATIM_ENVIRONMENTS: &[&str] = &[
+    "verbatim",
+    "verbatim*",
+    "Verbatim",
+    "Verbatim*",
+    "BVerbatim",
+    "LVerbatim",
+    "semiverbatim",
+    "lstlisting",
+    "minted",
+    "comment",
+    "filecontents",
+    "filecontents*",
+];
+
+/// Conditionals of TeX and of common packages, which end with `\fi` like the ones of `\newif`
+/// (unlike e.g. `\ifthenelse` or the `\if...` commands of etoolbox).
+const TEX_CONDITIONALS: &[&str] = &[
+    "if",
+    "ifcat",
+    "ifnum",
+    "ifdim",
+    "ifodd",
+    "ifvmode",
+    "ifhmode",
+    "ifmmode",
+    "ifinner",
+    "ifvoid",
+    "ifhbox",
+    "ifvbox",
+    "ifx",
+    "ifeof",
+    "iftrue",
+    "iffalse",
+    "ifcase",
+    "ifdefined",
+    "ifcsname",
+    "iffontchar",
+    "ifincsname",
+    "ifpdf",
+    "ifxetex",
+    "ifluatex",
+];
+
+/// Whether `\name` starts a conditional (which ends with `\fi`), where `conditionals` are the
+/// ones declared with `\newif`.
+fn is_conditional(name: &str, conditionals: &BTreeMap<String, bool>) -> bool {
+    TEX_CONDITIONALS.contains(&name)
+        || name
+            .strip_prefix("if")
+            .is_some_and(|name| conditionals.contains_key(name))
+}
+
+/// Value of the conditional `\name`, if it is known: `\iftrue`, `\iffalse` or a conditional of
+/// `conditionals`.
+fn conditional_value(name: &str, conditionals: &BTreeMap<String, bool>) -> Option<bool> {
+    match name {
+        "iftrue" => Some(true),
+        "iffalse" => Some(false),
+        _ => conditionals.get(name.strip_prefix("if")?).copied(),
+    }
+}
+
+/// Byte offset after the control sequence (e.g. `\ifdraft`) after `start`, which is skipped with
+/// the whitespace and the `=` before it.
+fn skip_control_sequence(content: &str, start: usize) -> usize {
+    let rest = content[start..].trim_start();
+    let rest = rest.strip_prefix('=').unwrap_or(rest).trim_start();
+    let offset = content.len() - rest.len();
+    match rest.strip_prefix('\\') {
+        Some(name) => {
+            let name_len = name.bytes().take_while(u8::is_ascii_alphabetic).count();
+            offset + 1 + name_len.max(name.chars().next().map_or(0, char::len_utf8))
+        }
+        None => start,
+    }
+}
+
+/// Values of the conditionals that `preamble` declares with `\newif\ifname`: false, unless they
+/// are set with `\nametrue` after it.
+pub fn document_conditionals(preamble: &str) -> BTreeMap<String, bool> {
+    let mut conditionals = BTreeMap::new();
+    for c in NEWIF_REGEX.captures_iter(preamble) {
+        if !is_commented(preamble, c.get(0).unwrap().start()) {
+            conditionals.insert(c[1].to_string(), false);
+        }
+    }
+    for c in SET_CONDITIONAL_REGEX.captures_iter(preamble) {
+        if is_commented(preamble, c.get(0).unwrap().start()) {
+            continue;
+        }
+        if let Some(value) = conditionals.get_mut(&c[1]) {
+            *value = &c[2] == "true";
+        }
+    }
+    conditionals
+}
+
+/// Parses a conditional given on the command line: `name=true`, `name=false` or `name` (true).
+pub fn parse_conditional(value: &str) -> Option<(String, bool)> {
+    let (name, value) = match value.find('=') {
+        Some(i) => (&value[..i], &value[i + 1..]),
+        None => (value, "true"),
+    };
+    let name = name.trim().trim_start_matches("\\if");
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphabetic() || c == '@') {
+        return None;
+    }
+    match value.trim() {
+        "true" => Some((name.to_string(), true)),
+        "false" => Some((name.to_string(), false)),
+        _ => None,
+    }
+}
+
+/// Sets the conditionals of `values` that `preamble` declares with `\newif` at its end.
+pub fn set_conditionals(preamble: &str, values: &[(String, bool)]) -> String {
+    let declared = document_conditionals(preamble);
+    let mut preamble = preamble.to_string();
+    for (name, value) in values {
+        if declared.contains_key(name) {
+            preamble += &format!("\n\\{}{}\n", name, value);
+        }
+    }
+    preamble
+}
+
+/// Name of the environment in `\begin{name}` or `\end{name}`, where `start` is the byte offset
+/// after `\begin` or `\end`, and the byte offset after it.
+fn environment_name(content: &str, start: usize) -> Option<(&str, usize)> {
+    let open = start + content[start..].len() - content[start..].trim_start().len();
+    let (name, end) = balanced_group(content, open)?;
+    Some((name.trim(), end))
+}
+
+/// Byte offset after the inline verbatim argument (of `\verb` or `\lstinline`) starting at
+/// `start`, which is delimited by braces or by any other character.
+fn skip_inline_verbatim(content: &str, start: usize) -> usize {
+    let mut start = start + usize::from(content[start..].starts_with('*'));
+    if content[start..].starts_with('[') {
+        start = content[start..]
+            .find(']')
+            .map_or(content.len(), |i| start + i + 1);
+    }
+    let delimiter = match content[start..].chars().next() {
+        Some('{') => '}',
+        Some(c) if !c.is_whitespace() => c,
+        _ => return start,
+    };
+    let body = start + delimiter.len_utf8();
+    content[body..]
+        .find([delimiter, '\n'])
+        .map_or(content.len(), |i| body + i + 1)
+}
+
+/// Byte offset after the body of the `\frame` command whose arguments start at `start` (or of a
+/// hook like `\AtBeginSection[...]{...}`, which has the same arguments).
+fn frame_command_end(content: &str, start: usize) -> Option<usize> {
+    let body = start + FRAME_COMMAND_REGEX.find(&content[start..])?.end() - 1;
+    balanced_group(content, body).map(|(_, end)| end)
+}
+
+/// Byte offset after the branch of a conditional starting at `start` that is skipped: after its
+/// `\else` or `\fi`, and whether it is the `\else`. Comments and nested conditionals are skipped,
+/// too.
+fn skip_conditional(
+    content: &str,
+    start: usize,
+    conditionals: &BTreeMap<String, bool>,
+) -> (usize, bool) {
+    let bytes = content.as_bytes();
+    let mut depth = 1;
+    let mut i = start;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => i = content[i..].find('\n').map_or(bytes.len(), |n| i + n),
+            b'\\' => {
+                let name_len = bytes[i + 1..]
+                    .iter()
+                    .take_while(|c| c.is_ascii_alphabetic())
+                    .count();
+                let name = &content[i + 1..i + 1 + name_len];
+                i += 1 + name_len.max(1);
+                match name {
+                    "fi" => depth -= 1,
+                    "else" if depth == 1 => return (i, true),
+                    "newif" => i = skip_control_sequence(content, i),
+                    "let" => i = skip_control_sequence(content, skip_control_sequence(content, i)),
+                    name if is_conditional(name, conditionals) => depth += 1,
+                    _ => {}
+                }
+                if depth == 0 {
+                    return (i, false);
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    (bytes.len(), false)
+}
+
+/// Start of the frame whose `\begin{frame}` is at `begin`: the start of its line if only
+/// whitespace precedes it (and of the blank lines before it, but not before `min_start`).
+fn frame_start(content: &str, begin: usize, min_start: usize) -> usize {
+    let blank = content[..begin].trim_end().len().max(min_start);
+    if blank == 0 || content[..blank].ends_with('\n') {
+        return blank;
+    }
+    match content[blank..begin].find('\n') {
+        Some(i) => blank + i + 1,
+        None => begin,
+    }
+}
+
+/// Byte ranges of the frames of `content`. Frames begin and end at `\begin{frame}` and
+/// `\end{frame}` outside of comments, false branches of conditionals and verbatim content (e.g. a
+/// `\end{frame}` in a listing does not end the frame), or are `\frame{...}` (or
+/// `\againframe{label}`) commands. A frame includes the indentation and blank lines before it.
+/// Conditionals are evaluated if they are `\iftrue`, `\iffalse` or in `conditionals` (e.g.
+/// `draft` for `\ifdraft`); both branches of other conditionals are scanned.
+pub fn frame_ranges(content: &str, conditionals: &BTreeMap<String, bool>) -> Vec<(usize, usize)> {
+    let bytes = content.as_bytes();
+    let mut ranges = Vec::new();
+    let mut begin = None;
+    let mut last_end = 0;
+    // For every conditional around the scanned position: whether its `\else` branch is skipped
+    let mut skip_else = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                i = content[i..].find('\n').map_or(bytes.len(), |n| i + n);
+            }
+            b'\\' => {
+                let name_len = bytes[i + 1..]
+                    .iter()
+                    .take_while(|c| c.is_ascii_alphabetic())
+                    .count();
+                let after = i + 1 + name_len;
+                i = match &content[i + 1..after] {
+                    // Escaped character (e.g. `\%`)
+                    "" => i + 1 + content[i + 1..].chars().next().map_or(0, char::len_utf8),
+                    "verb" | "lstinline" => skip_inline_verbatim(content, after),
+                    // Conditionals that are declared, not started (e.g. `\let\ifdraft\iftrue`)
+                    "newif" => skip_control_sequence(content, after),
+                    "let" => skip_control_sequence(content, skip_control_sequence(content, after)),
+                    "else" if skip_else.last() == Some(&true) => {
+                        skip_else.pop();
+                        skip_conditional(content, after, conditionals).0
+                    }
+                    "fi" => {
+                        skip_else.pop();
+                        after
+                    }
+                    name if is_conditional(name, conditionals) => {
+                        match conditional_value(name, conditionals) {
+                            Some(false) => {
+                                let (end, is_else) = skip_conditional(content, after, conditionals);
+                                if is_else {
+                                    skip_else.push(false);
+                                }
+                                end
+                            }
+                            value => {
+                                skip_else.push(value == Some(true));
+                                after
+                            }
+                        }
+                    }
+                    // Frames of hooks are not where the hooks are defined
+                    "AtBeginPart"
+                    | "AtBeginSection"
+                    | "AtBeginSubsection"
+                    | "AtBeginSubsubsection"
+                    | "AtBeginLecture" => frame_command_end(content, after).unwrap_or(after),
+                    "frame" | "againframe" if begin.is_none() => {
+                        match frame_command_end(content, after) {
+                            Some(end) => {
+                                ranges.push((frame_start(content, i, last_end), end));
+                                last_end = end;
+                                end
+                            }
+                            None => after,
+                        }
+                    }
+                    command @ "begin" | command @ "end" => match environment_name(content, after) {
+                        Some(("frame", end)) if command == "begin" => {
+                            if begin.is_none() {
+                                begin = Some(frame_start(content, i, last_end));
+                            }
+                            end
+                        }
+                        Some(("frame", end)) => {
+                            if let Some(start) = begin.take() {
+                                ranges.push((start, end));
+                                last_end = end;
+                            }
+                            end
+                        }
+                        Some((name, end))
+                            if command == "begin" && VERBATIM_ENVIRONMENTS.contains(&name) =>
+                        {
+                            let end_verbatim = format!("\\end{{{}}}", name);
+                            content[end..]
+                                .find(&end_verbatim)
+                                .map_or(bytes.len(), |n| end + n + end_verbatim.len())
+                        }
+                        Some((_, end)) => end,
+                        None => after,
+                    },
+                    _ => after,
+                };
+            }
+            _ => i += 1,
+        }
+    }
+    ranges
+}
+
+/// Content of the document body of `content` before each of the frames at `offsets` that is not
+/// part of a frame (definitions, templates, sections, ...), where `frames` are the byte ranges of
+/// all frames of `content`.
+pub fn content_between_frames(
+    content: &str,
+    frames: &[(usize, usize)],
+    offsets: &[usize],
+) -> Vec<String> {
+    let body_start = match content.find("\\begin{document}") {
+        Some(begin) => begin + "\\begin{document}".len(),
+        None => return vec![String::new(); offsets.len()],
+    };
+    let mut gaps = Vec::with_capacity(frames.len() + 1);
+    let mut gap_start = body_start;
+    for (start, end) in frames {
+        if *start > gap_start {
+            gaps.push((gap_start, *start));
+        }
+        gap_start = gap_start.max(*end);
+    }
+    offsets
+        .iter()
+        .map(|offset| {
+            gaps.iter()
+                .take_while(|(start, _)| start < offset)
+                .map(|(start, end)| &content[*start..*end.min(offset)])
+                .collect()
+        })
+        .collect()
+}
+
+/// Whether `frame` is a `\frame{...}` command (rather than a `frame` environment).
+pub fn is_frame_command(frame: &str) -> bool {
+    frame.trim_start().starts_with("\\frame")
+}
+
+/// Labels of the frames that the `\includeonlyframes` of `preamble` selects, if there is one.
+pub fn includeonlyframes(preamble: &str) -> Option<Vec<String>> {
+    INCLUDEONLYFRAMES_REGEX
+        .captures_iter(preamble)
+        .filter(|c| !is_commented(preamble, c.get(0).unwrap().start()))
+        .last()
+        .map(|c| split_labels(&c[1]))
+}
+
+/// Removes `\includeonlyframes` from `preamble`. Frames are selected before they are compiled on
+/// their own, so the documents of the frames contain only selected frames.
+pub fn remove_includeonlyframes(preamble: &str) -> String {
+    INCLUDEONLYFRAMES_REGEX
+        .replace_all(preamble, |c: &Captures| {
+            if is_commented(preamble, c.get(0).unwrap().start()) {
+                c[0].to_string()
+            } else {
+                String::new()
+            }
+        })
+        .into_owned()
+}
+
+/// The labels of a comma-separated list.
+pub fn split_labels(labels: &str) -> Vec<String> {
+    labels
+        .split(',')
+        .map(|label| label.trim().to_string())
+        .filter(|label| !label.is_empty())
+        .collect()
+}
+
+/// `frames` (with their `offsets`, before resolving `\againframe`s) whose label is one of
+/// `labels`. An `\againframe` is selected by the label of the frame that it shows.
+pub fn select_frames(
+    frames: Vec<String>,
+    offsets: Vec<usize>,
+    labels: &[String],
+) -> (Vec<String>, Vec<usize>) {
+    let is_selected = |frame: &str| {
+        matches!(
+            againframe_label(frame).or_else(|| frame_label(frame)),
+            Some(label) if labels.contains(&label)
+        )
+    };
+    for label in labels {
+        if !frames
+            .iter()
+            .any(|frame| frame_label(frame).as_ref() == Some(label))
+        {
+            warn!("No frame with label \"{}\"", label);
+        }
+    }
+    let selected: Vec<usize> = frames
+        .iter()
+        .zip(&offsets)
+        .filter(|(frame, _)| is_selected(frame))
+        .map(|(_, offset)| *offset)
+        .collect();
+    // `\againframe`s need all frames
+    let (frames, offsets) = resolve_againframes(frames, offsets);
+    frames
+        .into_iter()
+        .zip(offsets)
+        .filter(|(_, offset)| selected.contains(offset))
+        .unzip()
+}
+
+/// Label of the frame that `frame` shows again, if it is an `\againframe`.
+pub fn againframe_label(frame: &str) -> Option<String> {
+    AGAINFRAME_REGEX
+        .captures(frame)
+        .map(|c| c[4].trim().to_string())
+}
+
+/// The frame that `againframe` (an `\againframe`) shows: `frame` with the overlay specification
+/// and the options of `againframe`, but without its label.
+pub fn repeat_frame(againframe: &str, frame: &str) -> String {
+    let again = match AGAINFRAME_REGEX.captures(againframe) {
+        Some(again) => again,
+        None => return frame.to_string(),
+    };
+    let header = match FRAME_HEADER_REGEX.captures(frame) {
+        Some(header) => header,
+        None => return frame.to_string(),
+    };
+    let overlay = again
+        .get(1)
+        .or_else(|| again.get(3))
+        .or_else(|| header.get(2));
+    let options: Vec<&str> = header
+        .get(3)
+        .into_iter()
+        .chain(again.get(2))
+        .flat_map(|options| options.as_str().split(','))
+        .filter(|option| {
+            let key = option.split('=').next().unwrap_or("").trim();
+            !key.is_empty() && key != "label"
+        })
+        .collect();
+
+    let mut repeated = header[1].to_string();
+    if let Some(overlay) = overlay {
+        repeated += &format!("<{}>", overlay.as_str());
+    }
+    if !options.is_empty() {
+        repeated += &format!("[{}]", options.join(","));
+    }
+    repeated + &frame[header.get(0).unwrap().end()..]
+}
+
+/// `frames` (with their `offsets`) with the `\againframe`s replaced by the frames they show.
+/// `\againframe`s of unknown labels are dropped.
+pub fn resolve_againframes(frames: Vec<String>, offsets: Vec<usize>) -> (Vec<String>, Vec<usize>) {
+    let labels: Vec<Option<String>> = frames.iter().map(|frame| frame_label(frame)).collect();
+    let mut resolved = Vec::with_capacity(frames.len());
+    let mut resolved_offsets = Vec::with_capacity(frames.len());
+    for (frame, offset) in frames.iter().zip(offsets) {
+        let frame = match againframe_label(frame) {
+            Some(label) => match labels.iter().position(|l| l.as_deref() == Some(&label[..])) {
+                Some(idx) => repeat_frame(frame, &frames[idx]),
+                None => {
+                    warn!("\\againframe of unknown frame \"{}\"", label);
+                    continue;
+                }
+            },
+            None => frame.clone(),
+        };
+        resolved.push(frame);
+        resolved_offsets.push(offset);
+    }
+    (resolved, resolved_offsets)
+}
+
+/// Returns the options of a frame (`\begin{frame}[options]` or `\frame[options]{...}`) if there
+/// are any.
+pub fn frame_options(frame: &str) -> Option<&str> {
+    FRAME_OPTIONS_REGEX
+        .captures(frame)
+        .map(|c| c.get(1).unwrap().as_str())
+}
+
+/// Returns the label given by the `label=` option of a frame.
+pub fn frame_label(frame: &str) -> Option<String> {
+    frame_options(frame)?
+        .split(',')
+        .filter_map(|option| {
+            let mut key_value = option.splitn(2, '=');
+            match (key_value.next().unwrap_or("").trim(), key_value.next()) {
+                ("label", Some(value)) => Some(
+                    value
+                        .trim()
+                        .trim_matches(|c| c == '{' || c == '}')
+                        .to_string(),
+                ),
+                _ => None,
+            }
+        })
+        .find(|label| !label.is_empty())
+}
+
+/// Returns the title of a frame, given either as `\begin{frame}{title}` or by `\frametitle{title}`.
+pub fn frame_title(frame: &str) -> Option<String> {
+    let title_start = FRAME_TITLE_ARG_REGEX
+        .find(frame)
+        .or_else(|| FRAMETITLE_REGEX.find(frame))?
+        .end()
+        - 1;
+    balanced_group(frame, title_start)
+        .map(|(title, _)| title.trim().to_string())
+        .filter(|title| !title.is_empty())
+}
+
+/// Names of the PDF destinations of `frames` in the united output: `frame-<label>` for labelled
+/// frames, `frame-<index>` otherwise. A frame whose label is already used by an earlier
+/// frame (e.g. a repeated divider slide) is named by its index instead.
+pub fn frame_destinations(frames: &[&str]) -> Vec<String> {
+    unique_destinations(
+        frames
+            .iter()
+            .enumerate()
+            .map(|(frame_idx, frame)| (frame_idx, frame_label(frame)))
+            .collect(),
+    )
+}
+
+/// Names of the PDF destinations of frames given by their index and (optional) label, falling
+/// back to the index for names that are already used (e.g. of a frame labelled `5` before frame 5),
+/// and then to the index with a number (`frame-5-2`).
+pub fn unique_destinations(frames: Vec<(usize, Option<String>)>) -> Vec<String> {
+    let mut used = HashSet::new();
+    frames
+        .into_iter()
+        .map(|(frame_idx, label)| {
+            let name = destination_name(frame_idx, label.as_deref());
+            if used.insert(name.clone()) {
+                return name;
+            }
+            let fallback = destination_name(frame_idx, None);
+            let mut name = fallback.clone();
+            let mut number = 2;
+            while !used.insert(name.clone()) {
+                name = format!("{}-{}", fallback, number);
+                number += 1;
+            }
+            name
+        })
+        .collect()
+}
+
+/// Name of the PDF destination of the frame with index `frame_idx` and (optional) `label`.
+/// Characters of the label other than letters, digits, `-` and `:` are escaped as `.` and the hex
+/// digits of their UTF-8 bytes (e.g. `my_frame` as `frame-my.5fframe`), so that different labels
+/// have different names, which can also be typeset by TeX (e.g. as bookmarks of `merge_tex`).
+pub fn destination_name(frame_idx: usize, label: Option<&str>) -> String {
+    let name = label
+        .map(|l| l.to_string())
+        .unwrap_or_else(|| frame_idx.to_string());
+    let mut escaped = String::with_capacity(name.len());
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() || c == '-' || c == ':' {
+            escaped.push(c);
+        } else {
+            let mut bytes = [0; 4];
+            for byte in c.encode_utf8(&mut bytes).bytes() {
+                escaped += &format!(".{:02x}", byte);
+            }
+        }
+    }
+    format!("frame-{}", escaped)
+}
+
+/// Returns all labels defined by a frame, either by the `label=` frame option or by `\label{...}`,
+/// together with their byte offset relative to the start of the frame.
+pub fn frame_labels(frame: &str) -> Vec<(String, usize)> {
+    let mut labels = Vec::new();
+
+    if let Some(captures) = FRAME_OPTIONS_REGEX.captures(frame) {
+        let options = captures.get(1).unwrap();
+        let mut offset = options.start();
+        for option in options.as_str().split(',') {
+            let mut key_value = option.splitn(2, '=');
+            let key = key_value.next().unwrap_or("").trim();
+            if let ("label", Some(value)) = (key, key_value.next()) {
+                let value = value.trim().trim_matches(|c| c == '{' || c == '}');
+                if !value.is_empty() {
+                    labels.push((value.to_string(), offset));
+                }
+            }
+            offset += option.len() + 1;
+        }
+    }
+
+    for captures in LABEL_REGEX.captures_iter(frame) {
+        let label = captures.get(1).unwrap().as_str().trim();
+        if !label.is_empty() {
+            labels.push((label.to_string(), captures.get(0).unwrap().start()));
+        }
+    }
+    labels
+}
+
+/// Finds labels that are defined more than once across all frames.
+/// `frames` are pairs of (byte offset of frame in input file, frame content).
+pub fn find_duplicate_labels(frames: &[(usize, &str)]) -> Vec<DuplicateLabel> {
+    let mut definitions: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+
+    for (frame_offset, frame) in frames {
+        for (label, offset) in frame_labels(frame) {
+            definitions
+                .entry(label)
+                .or_insert_with(Vec::new)
+                .push(frame_offset + offset);
+        }
+    }
+
+    definitions
+        .into_iter()
+        .filter(|(_, positions)| positions.len() > 1)
+        .map(|(label, positions)| DuplicateLabel { label, positions })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
+    use super::*;
 
     #[test]
     fn it_works() {}
+
+    #[test]
+    fn test_duplicate_labels() {
+        let first = "\\begin{frame}[fragile,label=intro]\n\\label{eq:1}\n\\end{frame}";
+        let second = "\\begin{frame}<2->[label={intro}]\n\\label{eq:2}\n\\end{frame}";
+        let third = "\\begin{frame}\n\\label{eq:1}\n\\end{frame}";
+
+        let duplicates = find_duplicate_labels(&[(0, first), (100, second), (200, third)]);
+        assert_eq!(duplicates.len(), 2);
+        assert_eq!(duplicates[0].label, "eq:1");
+        assert_eq!(duplicates[0].positions, vec![35, 214]);
+        assert_eq!(duplicates[1].label, "intro");
+        assert_eq!(duplicates[1].positions, vec![22, 118]);
+
+        assert_eq!(
+            frame_destinations(&[first, third, "\\begin{frame}[label=my_frame]"]),
+            vec!["frame-intro", "frame-1", "frame-my.5fframe"]
+        );
+        assert_ne!(
+            destination_name(0, Some("a_b")),
+            destination_name(0, Some("a-b"))
+        );
+        assert_eq!(destination_name(0, Some("sec.5f")), "frame-sec.2e5f");
+        assert_eq!(
+            unique_destinations(vec![
+                (0, Some("5".to_string())),
+                (1, Some("5".to_string())),
+                (2, Some("1".to_string())),
+                (5, None),
+            ]),
+            vec!["frame-5", "frame-1", "frame-2", "frame-5-2"]
+        );
+        assert_eq!(destination_name(0, Some("é")), "frame-.c3.a9");
+        // A repeated frame
+        assert_eq!(
+            frame_destinations(&[first, third, first]),
+            vec!["frame-intro", "frame-1", "frame-2"]
+        );
+    }
+
+    #[test]
+    fn test_preamble_overrides() {
+        let preamble = "\\documentclass[aspectratio=43, c]{beamer}\n\\usetheme[progressbar=frametitle]{Madrid}\n";
+        assert_eq!(
+            set_class_option(preamble, "aspectratio", Some("169")),
+            "\\documentclass[c,aspectratio=169]{beamer}\n\\usetheme[progressbar=frametitle]{Madrid}\n"
+        );
+        assert_eq!(
+            set_class_option("\\documentclass{beamer}", "handout", None),
+            "\\documentclass[handout]{beamer}"
+        );
+        assert_eq!(
+            set_document_class(preamble, "[beamer]{standalone}"),
+            "\\documentclass[beamer]{standalone}\n\\usetheme[progressbar=frametitle]{Madrid}\n"
+        );
+        assert_eq!(
+            set_theme(preamble, "metropolis"),
+            "\\documentclass[aspectratio=43, c]{beamer}\n\\usetheme{metropolis}\n"
+        );
+    }
+
+    #[test]
+    fn test_beamer_mode() {
+        let preamble = "\\documentclass[11pt, handout]{beamer}\n\\usetheme{Madrid}\n";
+        assert_eq!(
+            set_beamer_mode(preamble, "presentation"),
+            "\\documentclass[11pt]{beamer}\n\\usetheme{Madrid}\n"
+        );
+        assert_eq!(
+            set_beamer_mode("\\documentclass{beamer}", "handout"),
+            "\\documentclass[handout]{beamer}"
+        );
+        let article = set_beamer_mode(preamble, "article");
+        assert_eq!(
+            article,
+            "\\documentclass[11pt]{article}\n\\usepackage{beamerarticle}\n\\usetheme{Madrid}\n"
+        );
+        assert_eq!(
+            set_beamer_mode(&article, "handout"),
+            "\\documentclass[11pt,handout]{beamer}\n\\usetheme{Madrid}\n"
+        );
+        assert_eq!(
+            set_beamer_mode(
+                "\\documentclass[handout,12pt]{beamerswitch}",
+                "presentation"
+            ),
+            "\\documentclass[12pt,beamer]{beamerswitch}"
+        );
+    }
+
+    #[test]
+    fn test_set_overlays() {
+        let preamble = "\\documentclass[11pt]{beamer}\n";
+        assert_eq!(set_overlays(preamble, "all"), preamble);
+        assert_eq!(
+            set_overlays(preamble, "collapse"),
+            "\\documentclass[11pt,trans]{beamer}\n"
+        );
+        assert!(set_overlays(preamble, "last").ends_with("\\fasterbeamerlastpage\\fi}\n"));
+    }
+
+    #[test]
+    fn test_set_notes() {
+        let preamble =
+            "\\documentclass{beamer}\n\\setbeameroption{show notes on second screen=right}\n";
+        assert_eq!(
+            set_notes(preamble, "only"),
+            "\\documentclass{beamer}\n\n\\setbeameroption{show only notes}\n"
+        );
+        assert_eq!(
+            set_notes("\\documentclass{beamer}", "none"),
+            "\\documentclass{beamer}\n\\setbeameroption{hide notes}\n"
+        );
+        assert_eq!(
+            set_notes(
+                "\\documentclass{beamer}\n\\begin{document}\nText\n\\end{document}\n",
+                "show"
+            ),
+            "\\documentclass{beamer}\n\n\\setbeameroption{show notes}\n\
+             \\begin{document}\nText\n\\end{document}\n"
+        );
+    }
+
+    #[test]
+    fn test_frame_ranges() {
+        let content = "\\begin{document}\n\
+                       % \\begin{frame}{Commented}\n\
+                       \n\
+                       \x20 \\begin{frame}[fragile]{Code}\n\
+                       \\begin{lstlisting}\n\
+                       \\end{frame}\n\
+                       \\end{lstlisting}\n\
+                       \\verb|\\end{frame}| 100\\% % \\end{frame}\n\
+                       \\end{frame}\n\
+                       \\begin{frame}{Inline}\\end{frame}\n\
+                       \\begin{verbatim}\\begin{frame}\\end{verbatim}\n\
+                       \\end{document}\n";
+        let frames: Vec<&str> = frame_ranges(content, &BTreeMap::new())
+            .into_iter()
+            .map(|(start, end)| &content[start..end])
+            .collect();
+        assert_eq!(frames.len(), 2);
+        assert!(frames[0].starts_with("\n  \\begin{frame}[fragile]{Code}\n"));
+        assert!(frames[0].ends_with("% \\end{frame}\n\\end{frame}"));
+        assert_eq!(frames[1], "\\begin{frame}{Inline}\\end{frame}");
+    }
+
+    #[test]
+    fn test_frame_ranges_iffalse() {
+        let content = "\\iffalse\n\
+                       \\begin{frame}{Hidden}\\ifnum1=1 \\fi\n\\end{frame}\n\
+                       \\else\n\
+                       \\begin{frame}{Shown}\n\\end{frame}\n\
+                       \\fi\n\
+                       \\iffalse % \\fi\n\
+                       \\begin{frame}{Hidden}\n\\end{frame}\n\
+                       \\fi\n";
+        let frames: Vec<&str> = frame_ranges(content, &BTreeMap::new())
+            .into_iter()
+            .map(|(start, end)| &content[start..end])
+            .collect();
+        assert_eq!(frames, vec!["\\begin{frame}{Shown}\n\\end{frame}"]);
+    }
+
+    #[test]
+    fn test_frame_ranges_hooks() {
+        let content = "\\documentclass{beamer}\n\
+                       \\AtBeginSection[]{\\begin{frame}{Outline}\\tableofcontents[currentsection]\\end{frame}}\n\
+                       \\AtBeginSubsection{\\frame{\\subsectionpage}}\n\
+                       \\begin{document}\n\
+                       \\section{Intro}\n\
+                       \\begin{frame}{Intro}\n\\end{frame}\n\
+                       \\end{document}\n";
+        let frames: Vec<&str> = frame_ranges(content, &BTreeMap::new())
+            .into_iter()
+            .map(|(start, end)| &content[start..end])
+            .collect();
+        assert_eq!(frames, vec!["\\begin{frame}{Intro}\n\\end{frame}"]);
+    }
+
+    #[test]
+    fn test_conditionals() {
+        let preamble = "\\documentclass{beamer}\n\
+                        \\newif\\ifdraft\n\
+                        \\newif\\ifsolutions \\solutionstrue\n\
+                        % \\newif\\ifold\n";
+        let conditionals = document_conditionals(preamble);
+        assert_eq!(conditionals.get("draft"), Some(&false));
+        assert_eq!(conditionals.get("solutions"), Some(&true));
+        assert_eq!(conditionals.len(), 2);
+        assert_eq!(
+            parse_conditional("draft=true"),
+            Some(("draft".to_string(), true))
+        );
+        assert_eq!(
+            parse_conditional("draft"),
+            Some(("draft".to_string(), true))
+        );
+        assert_eq!(parse_conditional("draft=maybe"), None);
+        assert_eq!(
+            set_conditionals(
+                preamble,
+                &[("draft".to_string(), true), ("old".to_string(), true)]
+            ),
+            preamble.to_string() + "\n\\drafttrue\n"
+        );
+
+        let content = preamble.to_string()
+            + "\\begin{document}\n\
+               \\ifdraft\n\
+               \\begin{frame}{Draft}\\ifx\\a\\b\\else\\fi\n\\end{frame}\n\
+               \\else\n\
+               \\begin{frame}{Final}\n\\end{frame}\n\
+               \\fi\n\
+               \\ifsolutions\n\
+               \\begin{frame}{Solutions}\n\\end{frame}\n\
+               \\else\n\
+               \\begin{frame}{Exercises}\n\\end{frame}\n\
+               \\fi\n\
+               \\end{document}\n";
+        let titles = |conditionals: &BTreeMap<String, bool>| -> Vec<Option<String>> {
+            frame_ranges(&content, conditionals)
+                .into_iter()
+                .map(|(start, end)| frame_title(&content[start..end]))
+                .collect()
+        };
+        let title = |title: &str| Some(title.to_string());
+        assert_eq!(
+            titles(&conditionals),
+            vec![title("Final"), title("Solutions")]
+        );
+        let mut conditionals = conditionals;
+        conditionals.insert("draft".to_string(), true);
+        conditionals.insert("solutions".to_string(), false);
+        assert_eq!(
+            titles(&conditionals),
+            vec![title("Draft"), title("Exercises")]
+        );
+        // Both branches of conditionals with unknown values
+        assert_eq!(titles(&BTreeMap::new()).len(), 4);
+    }
+
+    #[test]
+    fn test_frame_command() {
+        let content = "\\begin{document}\n\
+                       \\frame<2>[label=intro]{\\frametitle{Intro} {Nested} \\}}\n\
+                       \\frame{\\titlepage}\n\
+                       \\end{document}\n";
+        let frames: Vec<&str> = frame_ranges(content, &BTreeMap::new())
+            .into_iter()
+            .map(|(start, end)| &content[start..end])
+            .collect();
+        assert_eq!(
+            frames,
+            vec![
+                "\\frame<2>[label=intro]{\\frametitle{Intro} {Nested} \\}}",
+                "\\frame{\\titlepage}"
+            ]
+        );
+        assert!(is_frame_command(frames[0]));
+        assert_eq!(frame_label(frames[0]), Some("intro".to_string()));
+        assert_eq!(frame_title(frames[0]), Some("Intro".to_string()));
+        assert_eq!(frame_title(frames[1]), None);
+    }
+
+    #[test]
+    fn test_againframe() {
+        let frames = vec![
+            "\\begin{frame}<1-2>[t,label=plan]{Plan}\n\\end{frame}".to_string(),
+            "\\begin{frame}{Other}\n\\end{frame}".to_string(),
+            "\\againframe<3>[shrink]{plan}".to_string(),
+            "\\againframe{missing}".to_string(),
+            "\\againframe{plan}".to_string(),
+        ];
+        assert_eq!(againframe_label(&frames[2]), Some("plan".to_string()));
+        assert_eq!(againframe_label(&frames[0]), None);
+
+        let (resolved, offsets) = resolve_againframes(frames.clone(), vec![0, 10, 20, 30, 40]);
+        assert_eq!(offsets, vec![0, 10, 20, 40]);
+        assert_eq!(resolved[..2], frames[..2]);
+        assert_eq!(
+            resolved[2],
+            "\\begin{frame}<3>[t,shrink]{Plan}\n\\end{frame}"
+        );
+        assert_eq!(resolved[3], "\\begin{frame}<1-2>[t]{Plan}\n\\end{frame}");
+    }
+
+    #[test]
+    fn test_select_frames() {
+        let preamble =
+            "% \\includeonlyframes{old}\n\\includeonlyframes{plan, results}\n\\usetheme{x}";
+        let labels = includeonlyframes(preamble).unwrap();
+        assert_eq!(labels, vec!["plan", "results"]);
+        assert_eq!(
+            remove_includeonlyframes(preamble),
+            "% \\includeonlyframes{old}\n\\usetheme{x}"
+        );
+        assert_eq!(includeonlyframes("\\usetheme{x}"), None);
+
+        let frames = vec![
+            "\\begin{frame}[label=plan]{Plan}\n\\end{frame}".to_string(),
+            "\\begin{frame}{Other}\n\\end{frame}".to_string(),
+            "\\frame[label=results]{\\frametitle{Results}}".to_string(),
+            "\\againframe<2>{plan}".to_string(),
+        ];
+        let (selected, offsets) = select_frames(frames.clone(), vec![0, 10, 20, 30], &labels);
+        assert_eq!(offsets, vec![0, 20, 30]);
+        assert_eq!(selected[..2], [frames[0].clone(), frames[2].clone()]);
+        assert_eq!(selected[2], "\\begin{frame}<2>{Plan}\n\\end{frame}");
+    }
+
+    #[test]
+    fn test_frame_title() {
+        assert_eq!(
+            frame_title("\\begin{frame}[t]{Results {\\em now}}\n\\end{frame}"),
+            Some("Results {\\em now}".to_string())
+        );
+        assert_eq!(
+            frame_title("\\begin{frame}\n\\frametitle<2>{Outlook}\n\\end{frame}"),
+            Some("Outlook".to_string())
+        );
+        assert_eq!(frame_title("\\begin{frame}\ntext\n\\end{frame}"), None);
+    }
 }
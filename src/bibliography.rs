@@ -0,0 +1,320 @@
+//
+// bibliography.rs
+// Distributed under terms of the GPLv3 license.
+//
+// Citations need a bibliography tool (biber or bibtex) to run between two LaTeX runs, which never
+// happens for the documents of single frames, so citations come out as "?". With `--bibliography`,
+// the tool runs once on a stub document that cites everything the frames cite. Its `.bbl` is
+// cached under the hash of the stub and of the `.bib` files, and given to every frame that cites:
+// biblatex reads it as the `.bbl` of the frame, and the citation labels of bibtex (`\bibcite` in
+// the `.aux` of the stub) are defined in the document of the frame.
+//
+
+use crate::compiler::Compiler;
+use crate::container;
+use crate::dependencies::bibliography_files;
+use crate::includes::is_commented;
+use crate::preamble::content_hash;
+use crate::process_file::{format_line, FasterBeamerError, Frame, Result};
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+lazy_static! {
+    /// Keys of `\cite{a,b}`, `\parencite[p.~3]{a}`, `\nocite{a}`, ...
+    static ref CITE_KEYS_REGEX: Regex =
+        Regex::new(r"\\[a-zA-Z]*cite[a-zA-Z]*\*?\s*(?:\[[^\]]*\]\s*){0,2}\{([^}]*)\}").unwrap();
+}
+lazy_static! {
+    static ref BIBLATEX_REGEX: Regex =
+        Regex::new(r"\\usepackage\s*(?:\[([^\]]*)\])?\s*\{biblatex\}").unwrap();
+}
+lazy_static! {
+    /// Commands of bibtex bibliographies, which the stub document needs
+    static ref BIBTEX_COMMANDS_REGEX: Regex =
+        Regex::new(r"\\(?:bibliographystyle|bibliography)\s*\{[^}]*\}").unwrap();
+}
+lazy_static! {
+    static ref PRINT_BIBLIOGRAPHY_REGEX: Regex =
+        Regex::new(r"\\(?:printbibliography|bibliography)\b").unwrap();
+}
+lazy_static! {
+    static ref BIBCITE_REGEX: Regex = Regex::new(r"(?m)^\\bibcite\{.*$").unwrap();
+}
+
+/// Program that processes the bibliography.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BibliographyTool {
+    Biber,
+    Bibtex,
+}
+
+impl BibliographyTool {
+    pub fn command(self) -> &'static str {
+        match self {
+            BibliographyTool::Biber => "biber",
+            BibliographyTool::Bibtex => "bibtex",
+        }
+    }
+}
+
+/// Tool for the bibliography of a document with `preamble` and `content`: biber for biblatex
+/// (unless its backend is bibtex), bibtex for `\bibliography`.
+pub fn bibliography_tool(preamble: &str, content: &str) -> Option<BibliographyTool> {
+    let biblatex = BIBLATEX_REGEX
+        .captures_iter(preamble)
+        .find(|c| !is_commented(preamble, c.get(0).unwrap().start()));
+    if let Some(biblatex) = biblatex {
+        let options = biblatex.get(1).map_or("", |o| o.as_str());
+        let bibtex_backend = options
+            .split(',')
+            .any(|option| option.replace(' ', "").starts_with("backend=bibtex"));
+        return Some(if bibtex_backend {
+            BibliographyTool::Bibtex
+        } else {
+            BibliographyTool::Biber
+        });
+    }
+    BIBTEX_COMMANDS_REGEX
+        .find_iter(content)
+        .any(|m| !is_commented(content, m.start()))
+        .then_some(BibliographyTool::Bibtex)
+}
+
+/// Keys that `frames` cite, in order of their first citation.
+pub fn cited_keys(frames: &[&str]) -> Vec<String> {
+    let mut keys: Vec<String> = Vec::new();
+    for frame in frames {
+        for c in CITE_KEYS_REGEX.captures_iter(frame) {
+            if is_commented(frame, c.get(0).unwrap().start()) {
+                continue;
+            }
+            for key in c[1].split(',').map(|key| key.trim()) {
+                if !key.is_empty() && !keys.iter().any(|k| k == key) {
+                    keys.push(key.to_string());
+                }
+            }
+        }
+    }
+    keys
+}
+
+/// Whether `frame` cites or prints the bibliography.
+pub fn uses_bibliography(frame: &str) -> bool {
+    !cited_keys(&[frame]).is_empty()
+        || PRINT_BIBLIOGRAPHY_REGEX
+            .find_iter(frame)
+            .any(|m| !is_commented(frame, m.start()))
+}
+
+/// Document that cites `keys`, with the precompiled format `format_name` of `preamble` (if any) and
+/// the bibtex commands of `content`.
+pub fn stub_document(
+    format_name: Option<&str>,
+    preamble: &str,
+    content: &str,
+    keys: &[String],
+    tool: BibliographyTool,
+) -> String {
+    let mut body = format!("\\nocite{{{}}}\n", keys.join(","));
+    if tool == BibliographyTool::Bibtex {
+        for m in BIBTEX_COMMANDS_REGEX.find_iter(content) {
+            if !is_commented(content, m.start()) {
+                body += m.as_str();
+                body += "\n";
+            }
+        }
+    }
+    format!(
+        "{}{}\n\\begin{{document}}\n{}\\end{{document}}\n",
+        format_line(format_name),
+        preamble,
+        body
+    )
+}
+
+/// Bibliography of a document, processed once for all frames.
+pub struct SharedBibliography {
+    pub hash: md5::Digest,
+    pub tool: BibliographyTool,
+    /// Processed bibliography
+    pub bbl: PathBuf,
+    /// Citation labels of bibtex (`\bibcite` lines of the `.aux` file)
+    pub bibcites: String,
+}
+
+impl SharedBibliography {
+    /// Code for the document of a frame that cites.
+    pub fn frame_code(&self) -> String {
+        let mut code = format!("% bibliography {:x}\n", self.hash);
+        if !self.bibcites.is_empty() {
+            code += &format!("\\makeatletter\n{}\\makeatother\n", self.bibcites);
+        }
+        code
+    }
+
+    /// Makes the bibliography available to the compile of `frame`.
+    pub fn copy_to(&self, frame: &Frame, cache_subdir: &Path) {
+        let bbl = cache_subdir.join(format!("{:x}.bbl", frame.hash));
+        if let Err(err) = fs::copy(&self.bbl, &bbl) {
+            warn!(
+                "Failed to copy the bibliography to {}: {}",
+                bbl.display(),
+                err
+            );
+        }
+    }
+}
+
+/// Runs the bibliography `tool` on the job `jobname` in `working_dir`.
+fn run_tool(
+    tool: BibliographyTool,
+    jobname: &str,
+    working_dir: &Path,
+    env: &BTreeMap<String, String>,
+) -> Result<()> {
+    let program = tool.command();
+    let output = container::command(program, working_dir, env)
+        .arg(jobname)
+        .output();
+    match output {
+        Err(e) => Err(FasterBeamerError::Program {
+            program: program.to_string(),
+            message: e.to_string(),
+        }),
+        Ok(output) if !output.status.success() => Err(FasterBeamerError::Program {
+            program: program.to_string(),
+            message: format!(
+                "Failed to process {}: {}",
+                jobname,
+                String::from_utf8_lossy(&output.stdout)
+            ),
+        }),
+        Ok(_) => Ok(()),
+    }
+}
+
+/// Processes the bibliography of the document with `preamble` (precompiled as `format_name`, if it
+/// is) and `content` in `cache_subdir`, unless it is cached. None if no frame cites anything.
+#[allow(clippy::too_many_arguments)]
+pub fn shared_bibliography(
+    compiler: &dyn Compiler,
+    format_name: Option<&str>,
+    preamble: &str,
+    content: &str,
+    frames: &[&str],
+    input_dir: &Path,
+    cache_subdir: &Path,
+    compiler_args: &[String],
+    env: &BTreeMap<String, String>,
+) -> Result<Option<SharedBibliography>> {
+    let tool = match bibliography_tool(preamble, content) {
+        Some(tool) => tool,
+        None => return Ok(None),
+    };
+    let keys = cited_keys(frames);
+    if keys.is_empty() {
+        return Ok(None);
+    }
+    let stub = stub_document(format_name, preamble, content, &keys, tool);
+    let bib_files = bibliography_files(&(preamble.to_string() + content), input_dir);
+    let hash = content_hash(&stub, &bib_files);
+    let jobname = format!("bibliography-{:x}", hash);
+    let bbl = cache_subdir.join(format!("{}.bbl", jobname));
+    let aux = cache_subdir.join(format!("{}.aux", jobname));
+
+    if !bbl.is_file() {
+        info!("Processing the bibliography with {}", tool.command());
+        let stub_file = cache_subdir.join(format!("{}.tex", jobname));
+        fs::write(&stub_file, &stub).map_err(FasterBeamerError::io(&stub_file))?;
+        let compile = || {
+            compiler
+                .compile(&stub_file, cache_subdir, compiler_args, env)
+                .map_err(|e| FasterBeamerError::Program {
+                    program: "LaTeX".to_string(),
+                    message: format!("Failed to compile {}: {}", stub_file.display(), e),
+                })
+        };
+        compile()?;
+        run_tool(tool, &jobname, cache_subdir, env)?;
+        if tool == BibliographyTool::Bibtex {
+            // Typesetting the `.bbl` writes the citation labels into the `.aux` file
+            compile()?;
+        }
+    }
+
+    let bibcites = match tool {
+        BibliographyTool::Bibtex => fs::read_to_string(&aux)
+            .map(|aux| {
+                BIBCITE_REGEX
+                    .find_iter(&aux)
+                    .map(|m| m.as_str().to_string() + "\n")
+                    .collect()
+            })
+            .unwrap_or_default(),
+        BibliographyTool::Biber => String::new(),
+    };
+    Ok(Some(SharedBibliography {
+        hash,
+        tool,
+        bbl,
+        bibcites,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stub_document() {
+        let preamble = "\\documentclass{beamer}\n\\usepackage[style=numeric]{biblatex}\n";
+        assert_eq!(
+            bibliography_tool(preamble, ""),
+            Some(BibliographyTool::Biber)
+        );
+        assert_eq!(
+            bibliography_tool("\\usepackage[backend = bibtex]{biblatex}", ""),
+            Some(BibliographyTool::Bibtex)
+        );
+        let content = "\\begin{document}\n\
+                       % \\bibliography{old}\n\
+                       \\bibliographystyle{plain}\n\
+                       \\begin{frame}\\bibliography{refs}\\end{frame}\n\
+                       \\end{document}\n";
+        assert_eq!(
+            bibliography_tool("\\documentclass{beamer}", content),
+            Some(BibliographyTool::Bibtex)
+        );
+        assert_eq!(bibliography_tool("\\documentclass{beamer}", ""), None);
+
+        let frames = [
+            "\\begin{frame}\\cite{knuth,lamport} % \\cite{old}\n\\end{frame}",
+            "\\begin{frame}\\parencite[p.~3]{lamport}\\textcite{turing}\\end{frame}",
+        ];
+        assert!(uses_bibliography(frames[1]));
+        assert!(uses_bibliography(
+            "\\begin{frame}\\printbibliography\\end{frame}"
+        ));
+        assert!(!uses_bibliography(
+            "\\begin{frame}% \\cite{old}\n\\end{frame}"
+        ));
+        let keys = cited_keys(&frames);
+        assert_eq!(keys, vec!["knuth", "lamport", "turing"]);
+        assert_eq!(
+            stub_document(
+                Some("fmt"),
+                "\\documentclass{beamer}",
+                content,
+                &keys,
+                BibliographyTool::Bibtex
+            ),
+            "%&fmt\n\\documentclass{beamer}\n\\begin{document}\n\
+             \\nocite{knuth,lamport,turing}\n\
+             \\bibliographystyle{plain}\n\
+             \\bibliography{refs}\n\
+             \\end{document}\n"
+        );
+    }
+}
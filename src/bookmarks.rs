@@ -0,0 +1,293 @@
+//
+// bookmarks.rs
+// Distributed under terms of the GPLv3 license.
+//
+// As every frame is compiled on its own, the PDFs of the frames have no bookmarks for the sections
+// of the presentation and `--pdfunite` loses the outline of the document. The sections,
+// subsections and frame titles of the document are written as bookmarks into the united PDF.
+//
+
+use crate::outline::OutlineEntry;
+use crate::page_map::PageRange;
+use lopdf::{Dictionary, Document, Object, ObjectId, StringFormat};
+use regex::Regex;
+use std::path::Path;
+
+lazy_static! {
+    static ref COMMAND_REGEX: Regex = Regex::new(r"\\[a-zA-Z@]+\*?\s*(\[[^\]]*\])?").unwrap();
+}
+
+/// Entry of the outline of a PDF.
+#[derive(Debug, PartialEq)]
+pub struct Bookmark {
+    pub title: String,
+    /// Page the bookmark points to (0-based)
+    pub page: usize,
+    pub children: Vec<Bookmark>,
+}
+
+impl Bookmark {
+    fn new(title: &str, page: usize) -> Bookmark {
+        Bookmark {
+            title: plain_text(title),
+            page,
+            children: Vec::new(),
+        }
+    }
+}
+
+/// Text of the LaTeX code `tex` without commands and braces (e.g. for PDF bookmarks).
+pub fn plain_text(tex: &str) -> String {
+    let text = tex.replace("\\\\", " ").replace('~', " ");
+    let text = COMMAND_REGEX.replace_all(&text, "");
+    let text: String = text
+        .chars()
+        .filter(|c| !matches!(c, '{' | '}' | '$'))
+        .collect();
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Bookmarks of the sections, subsections and frames (with a title) of `outline`, where the
+/// frames are on the pages of `page_map`. A section points to the page of the frame after it.
+/// Frames that continue the previous frame (same title, e.g. with `allowframebreaks`) get no
+/// bookmark of their own.
+pub fn bookmark_tree(outline: &[OutlineEntry], page_map: &[PageRange]) -> Vec<Bookmark> {
+    let mut sections: Vec<Bookmark> = Vec::new();
+    // Whether the last section has a subsection that frames belong to
+    let mut in_subsection = false;
+    let mut last_title = None;
+    let mut next_page = 0;
+
+    for entry in outline {
+        match entry {
+            OutlineEntry::Section(title) => {
+                sections.push(Bookmark::new(title, next_page));
+                in_subsection = false;
+            }
+            OutlineEntry::Subsection(title) => {
+                let subsection = Bookmark::new(title, next_page);
+                match sections.last_mut() {
+                    Some(section) => {
+                        section.children.push(subsection);
+                        in_subsection = true;
+                    }
+                    None => sections.push(subsection),
+                }
+            }
+            OutlineEntry::Frame { index, title } => {
+                let pages = match page_map.get(*index) {
+                    Some(pages) => pages,
+                    None => continue,
+                };
+                next_page = pages.last_page;
+                if title.is_none() || *title == last_title {
+                    continue;
+                }
+                last_title = title.clone();
+                let bookmark =
+                    Bookmark::new(title.as_deref().unwrap_or_default(), pages.first_page - 1);
+                let parent = match sections.last_mut() {
+                    Some(section) if in_subsection => section.children.last_mut(),
+                    section => section,
+                };
+                match parent {
+                    Some(parent) => parent.children.push(bookmark),
+                    None => sections.push(bookmark),
+                }
+            }
+        }
+    }
+    sections
+}
+
+/// Key of the sections and subsections of `outline` and of the frames they are before, which
+/// change the bookmarks of the united PDF without changing a frame.
+pub fn sections_key(outline: &[OutlineEntry]) -> String {
+    let sections: Vec<String> = outline
+        .iter()
+        .map(|entry| match entry {
+            OutlineEntry::Frame { index, .. } => index.to_string(),
+            entry => format!("{:?}", entry),
+        })
+        .collect();
+    format!("{:x}", md5::compute(sections.join("\n")))
+}
+
+/// PDF text string of `text` (UTF-16 if it is not ASCII).
+pub fn text_string(text: &str) -> Object {
+    if text.is_ascii() {
+        return Object::string_literal(text);
+    }
+    let mut bytes = vec![0xfe, 0xff];
+    for unit in text.encode_utf16() {
+        bytes.extend_from_slice(&unit.to_be_bytes());
+    }
+    Object::String(bytes, StringFormat::Hexadecimal)
+}
+
+/// Adds the outline items of `bookmarks` below `parent` to `doc`. Returns the first and last item
+/// and the number of visible items.
+fn add_items(
+    doc: &mut Document,
+    bookmarks: &[Bookmark],
+    parent: ObjectId,
+    pages: &[ObjectId],
+) -> Option<(ObjectId, ObjectId, i64)> {
+    let ids: Vec<ObjectId> = bookmarks.iter().map(|_| doc.new_object_id()).collect();
+    let mut count = ids.len() as i64;
+    for (idx, bookmark) in bookmarks.iter().enumerate() {
+        let page = pages[bookmark.page.min(pages.len() - 1)];
+        let mut item = Dictionary::new();
+        item.set("Title", text_string(&bookmark.title));
+        item.set("Parent", Object::Reference(parent));
+        item.set(
+            "Dest",
+            vec![Object::Reference(page), Object::Name(b"Fit".to_vec())],
+        );
+        if idx > 0 {
+            item.set("Prev", Object::Reference(ids[idx - 1]));
+        }
+        if let Some(next) = ids.get(idx + 1) {
+            item.set("Next", Object::Reference(*next));
+        }
+        if let Some((first, last, num_children)) =
+            add_items(doc, &bookmark.children, ids[idx], pages)
+        {
+            item.set("First", Object::Reference(first));
+            item.set("Last", Object::Reference(last));
+            // Sections are open
+            item.set("Count", num_children);
+            count += num_children;
+        }
+        doc.objects.insert(ids[idx], Object::Dictionary(item));
+    }
+    Some((*ids.first()?, *ids.last()?, count))
+}
+
+/// Replaces the outline of `pdf` by `bookmarks`. Returns the number of bookmarks.
+pub fn write_bookmarks(pdf: &Path, bookmarks: &[Bookmark]) -> lopdf::Result<usize> {
+    let mut doc = Document::load(pdf)?;
+    let pages: Vec<ObjectId> = doc.get_pages().values().cloned().collect();
+    let catalog_id = doc.trailer.get(b"Root")?.as_reference()?;
+    let mut catalog = doc.get_dictionary(catalog_id)?.clone();
+
+    let outlines_id = doc.new_object_id();
+    let items = if pages.is_empty() {
+        None
+    } else {
+        add_items(&mut doc, bookmarks, outlines_id, &pages)
+    };
+    match items {
+        Some((first, last, count)) => {
+            let mut outlines = Dictionary::new();
+            outlines.set("Type", Object::Name(b"Outlines".to_vec()));
+            outlines.set("First", Object::Reference(first));
+            outlines.set("Last", Object::Reference(last));
+            outlines.set("Count", count);
+            doc.objects
+                .insert(outlines_id, Object::Dictionary(outlines));
+            catalog.set("Outlines", Object::Reference(outlines_id));
+            catalog.set("PageMode", Object::Name(b"UseOutlines".to_vec()));
+        }
+        None => {
+            catalog.remove(b"Outlines");
+        }
+    }
+    doc.objects.insert(catalog_id, Object::Dictionary(catalog));
+    // Items of the previous outline
+    doc.prune_objects();
+    doc.save(pdf)?;
+    Ok(count_bookmarks(bookmarks))
+}
+
+/// Writes the bookmarks of `outline` into the united PDF `pdf`, on which the frames are on the
+/// pages of `page_map`.
+pub fn add_bookmarks(pdf: &Path, outline: &[OutlineEntry], page_map: &[PageRange]) {
+    match write_bookmarks(pdf, &bookmark_tree(outline, page_map)) {
+        Ok(0) => {}
+        Ok(num_bookmarks) => debug!("Wrote {} bookmarks", num_bookmarks),
+        Err(err) => warn!(
+            "Failed to write the bookmarks of {}: {}",
+            pdf.display(),
+            err
+        ),
+    }
+}
+
+fn count_bookmarks(bookmarks: &[Bookmark]) -> usize {
+    bookmarks
+        .iter()
+        .map(|bookmark| 1 + count_bookmarks(&bookmark.children))
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pages(ranges: &[(usize, usize)]) -> Vec<PageRange> {
+        ranges
+            .iter()
+            .enumerate()
+            .map(|(frame, (first_page, last_page))| PageRange {
+                frame,
+                label: None,
+                first_page: *first_page,
+                last_page: *last_page,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_plain_text() {
+        assert_eq!(
+            plain_text("The \\emph{fast} way~to \\textbf[x]{build}\\\\ slides"),
+            "The fast way to build slides"
+        );
+        assert_eq!(plain_text("$O(n^2)$ algorithms"), "O(n^2) algorithms");
+    }
+
+    #[test]
+    fn test_bookmark_tree() {
+        let frame = |index, title: Option<&str>| OutlineEntry::Frame {
+            index,
+            title: title.map(|t| t.to_string()),
+        };
+        let outline = vec![
+            frame(0, None),
+            OutlineEntry::Section("Intro".to_string()),
+            frame(1, Some("Motivation")),
+            OutlineEntry::Subsection("Details".to_string()),
+            frame(2, Some("Proof")),
+            frame(3, Some("Proof")),
+        ];
+        let bookmark = |title: &str, page, children| Bookmark {
+            title: title.to_string(),
+            page,
+            children,
+        };
+        assert_eq!(
+            bookmark_tree(&outline, &pages(&[(1, 1), (2, 4), (5, 5), (6, 6)])),
+            vec![bookmark(
+                "Intro",
+                1,
+                vec![
+                    bookmark("Motivation", 1, vec![]),
+                    bookmark("Details", 4, vec![bookmark("Proof", 4, vec![])]),
+                ]
+            )]
+        );
+    }
+
+    #[test]
+    fn test_text_string() {
+        assert!(matches!(
+            text_string("Intro"),
+            Object::String(bytes, StringFormat::Literal) if bytes == b"Intro"
+        ));
+        assert!(matches!(
+            text_string("Ü"),
+            Object::String(bytes, StringFormat::Hexadecimal) if bytes == [0xfe, 0xff, 0x00, 0xdc]
+        ));
+    }
+}
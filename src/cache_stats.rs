@@ -0,0 +1,130 @@
+//
+// cache_stats.rs
+// Distributed under terms of the GPLv3 license.
+//
+// `faster-beamer cache stats` shows how big the cache is, which presentations it holds frames of
+// and how many frames their last builds took from it, e.g. to judge when to clean it.
+//
+
+use crate::clean::{cache_entries, CacheEntry};
+use crate::logs::{read_cache_record, read_manifest};
+use crate::process_file::{get_cachedir, Result};
+use crate::status::format_age;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// A size in bytes for humans, e.g. "1.5 MB".
+pub fn format_size(size: u64) -> String {
+    match size {
+        0..=1023 => format!("{} B", size),
+        1024..=1_048_575 => format!("{:.1} kB", size as f64 / 1024.0),
+        1_048_576..=1_073_741_823 => format!("{:.1} MB", size as f64 / 1_048_576.0),
+        _ => format!("{:.1} GB", size as f64 / 1_073_741_824.0),
+    }
+}
+
+/// Manifests of the last builds (see `logs`) in `dir` and its subdirectories.
+fn manifests(dir: &Path, found: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+        let path = entry.path();
+        match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => manifests(&path, found),
+            Ok(file_type)
+                if file_type.is_file()
+                    && path.extension().map_or(false, |ext| ext == "manifest") =>
+            {
+                found.push(path)
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The presentation (its path without extension) whose build wrote `manifest` into `cachedir`.
+/// The cache of a presentation mirrors the absolute path of its directory.
+pub fn presentation_name(cachedir: &Path, manifest: &Path) -> String {
+    let relative = manifest
+        .strip_prefix(cachedir)
+        .unwrap_or(manifest)
+        .with_extension("");
+    Path::new("/").join(relative).display().to_string()
+}
+
+/// Prints the statistics of the cache.
+pub fn print_stats() -> Result<()> {
+    let cachedir = get_cachedir()?;
+    let mut entries: Vec<CacheEntry> = Vec::new();
+    cache_entries(&cachedir, &mut entries);
+    let size: u64 = entries.iter().map(|entry| entry.size).sum();
+    let num_files: usize = entries.iter().map(|entry| entry.files.len()).sum();
+    println!("Cache: {}", cachedir.display());
+    println!("Size: {} in {} files", format_size(size), num_files);
+
+    let now = SystemTime::now();
+    let describe = |entry: &CacheEntry| {
+        let age = now.duration_since(entry.last_used).unwrap_or_default();
+        let file = entry.files.first().cloned().unwrap_or_default();
+        format!("{} (used {})", file.display(), format_age(age))
+    };
+    if let Some(oldest) = entries.iter().min_by_key(|entry| entry.last_used) {
+        println!("Oldest entry: {}", describe(oldest));
+    }
+    if let Some(newest) = entries.iter().max_by_key(|entry| entry.last_used) {
+        println!("Newest entry: {}", describe(newest));
+    }
+
+    let mut found = Vec::new();
+    manifests(&cachedir, &mut found);
+    found.sort();
+    if found.is_empty() {
+        println!("Presentations: none");
+        return Ok(());
+    }
+    println!("Presentations:");
+    for manifest in found {
+        let cache_subdir = manifest.parent().unwrap_or(&cachedir);
+        let stem = manifest
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let frames = read_manifest(cache_subdir, &stem).unwrap_or_default();
+        let num_cached = frames
+            .iter()
+            .filter(|frame| cache_subdir.join(format!("{}.pdf", frame.hash)).is_file())
+            .count();
+        let last_build = match read_cache_record(cache_subdir, &stem) {
+            Some(record) => format!(
+                "last build: {} cache hits, {} compiled",
+                record.hits, record.misses
+            ),
+            None => "last build: unknown".to_string(),
+        };
+        println!(
+            "  {}: {} of {} frames cached, {}",
+            presentation_name(&cachedir, &manifest),
+            num_cached,
+            frames.len(),
+            last_build
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_stats() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(3 << 20), "3.0 MB");
+        assert_eq!(
+            presentation_name(
+                Path::new("/cache/faster-beamer"),
+                Path::new("/cache/faster-beamer/home/me/talks/talk.manifest")
+            ),
+            "/home/me/talks/talk"
+        );
+    }
+}
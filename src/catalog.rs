@@ -0,0 +1,399 @@
+//
+// catalog.rs
+// Distributed under terms of the GPLv3 license.
+//
+// `--pdfunite` only copies pages (with their annotations), but multimedia frames (`\movie`,
+// media9, animate) also need parts of the document catalog: embedded files, document JavaScript,
+// optional content groups and form fields. These are merged from the frame PDFs into the catalog
+// of the merged PDF. Objects that were already copied with the pages (pages, annotations,
+// optional content groups used by the pages) are matched to their copies so that they are not
+// duplicated.
+//
+
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+/// Name trees of the catalog that are merged.
+const NAME_TREES: &[&[u8]] = &[b"EmbeddedFiles", b"JavaScript"];
+
+/// Entries (key and value) of the name tree `tree`, in the order of the tree.
+pub fn name_tree_entries<'a>(
+    doc: &'a Document,
+    tree: &'a Dictionary,
+) -> Vec<(Vec<u8>, &'a Object)> {
+    let mut entries = Vec::new();
+    let mut nodes = vec![tree];
+    while let Some(node) = nodes.pop() {
+        if let Ok(Object::Array(names)) = node.get_deref(b"Names", doc) {
+            for pair in names.chunks(2) {
+                if let [Object::String(name, _), value] = pair {
+                    entries.push((name.clone(), value));
+                }
+            }
+        }
+        if let Ok(Object::Array(kids)) = node.get_deref(b"Kids", doc) {
+            for kid in kids.iter().rev() {
+                if let Ok((_, Object::Dictionary(kid))) = doc.dereference(kid) {
+                    nodes.push(kid);
+                }
+            }
+        }
+    }
+    entries
+}
+
+/// Copies `object` of `source` and all objects it references into `target`. `copies` maps
+/// objects of `source` to their counterparts in `target`.
+fn import(
+    source: &Document,
+    target: &mut Document,
+    object: &Object,
+    copies: &mut HashMap<ObjectId, ObjectId>,
+) -> Object {
+    match object {
+        Object::Reference(id) => {
+            if let Some(copy) = copies.get(id) {
+                return Object::Reference(*copy);
+            }
+            let copy = target.new_object_id();
+            copies.insert(*id, copy);
+            let imported = match source.get_object(*id) {
+                Ok(object) => import(source, target, object, copies),
+                Err(_) => Object::Null,
+            };
+            target.objects.insert(copy, imported);
+            Object::Reference(copy)
+        }
+        Object::Array(array) => Object::Array(
+            array
+                .iter()
+                .map(|o| import(source, target, o, copies))
+                .collect(),
+        ),
+        Object::Dictionary(dict) => Object::Dictionary(import_dict(source, target, dict, copies)),
+        Object::Stream(stream) => {
+            let mut stream = stream.clone();
+            stream.dict = import_dict(source, target, &stream.dict, copies);
+            Object::Stream(stream)
+        }
+        object => object.clone(),
+    }
+}
+
+/// Copies `dict` of `source` into `target` like `import`.
+pub fn import_dict(
+    source: &Document,
+    target: &mut Document,
+    dict: &Dictionary,
+    copies: &mut HashMap<ObjectId, ObjectId>,
+) -> Dictionary {
+    let mut imported = Dictionary::new();
+    for (key, value) in dict.iter() {
+        imported.set(key.clone(), import(source, target, value, copies));
+    }
+    imported
+}
+
+/// References in the array `key` of `dict`.
+fn references(doc: &Document, dict: &Dictionary, key: &[u8]) -> Vec<ObjectId> {
+    match dict.get_deref(key, doc) {
+        Ok(Object::Array(array)) => array.iter().filter_map(|o| o.as_reference().ok()).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Optional content groups used by the contents of `page` (by resource name).
+fn page_properties(doc: &Document, page: ObjectId) -> BTreeMap<Vec<u8>, ObjectId> {
+    doc.get_dictionary(page)
+        .ok()
+        .and_then(|page| page.get_deref(b"Resources", doc).ok())
+        .and_then(|resources| resources.as_dict().ok())
+        .and_then(|resources| resources.get_deref(b"Properties", doc).ok())
+        .and_then(|properties| properties.as_dict().ok())
+        .map(|properties| {
+            properties
+                .iter()
+                .filter_map(|(name, o)| Some((name.clone(), o.as_reference().ok()?)))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Objects of `frame` that were copied to `merged` with its pages, where the pages of `frame` start at
+/// `first_page`: its pages, their annotations and the optional content groups of the pages.
+fn copied_objects(
+    frame: &Document,
+    merged: &Document,
+    merged_pages: &[ObjectId],
+    first_page: usize,
+) -> HashMap<ObjectId, ObjectId> {
+    let mut copies = HashMap::new();
+    for (idx, page) in frame.get_pages().values().enumerate() {
+        let merged_page = match merged_pages.get(first_page + idx) {
+            Some(merged_page) => *merged_page,
+            None => break,
+        };
+        copies.insert(*page, merged_page);
+
+        let annots = |doc: &Document, page| {
+            doc.get_dictionary(page)
+                .map(|p| references(doc, p, b"Annots"))
+                .unwrap_or_default()
+        };
+        let (annots, merged_annots) = (annots(frame, *page), annots(merged, merged_page));
+        if annots.len() == merged_annots.len() {
+            copies.extend(annots.into_iter().zip(merged_annots));
+        }
+
+        let merged_properties = page_properties(merged, merged_page);
+        for (name, ocg) in page_properties(frame, *page) {
+            if let Some(merged_ocg) = merged_properties.get(&name) {
+                copies.insert(ocg, *merged_ocg);
+            }
+        }
+    }
+    copies
+}
+
+/// The parts of the catalogs of the frames that are merged.
+#[derive(Default)]
+struct CatalogParts {
+    name_trees: BTreeMap<Vec<u8>, BTreeMap<Vec<u8>, Object>>,
+    ocgs: Vec<Object>,
+    ocgs_on: Vec<Object>,
+    ocgs_off: Vec<Object>,
+    ocgs_order: Vec<Object>,
+    ocgs_as: Vec<Object>,
+    fields: Vec<Object>,
+}
+
+impl CatalogParts {
+    fn is_empty(&self) -> bool {
+        self.name_trees.values().all(|tree| tree.is_empty())
+            && self.ocgs.is_empty()
+            && self.fields.is_empty()
+    }
+
+    /// Adds the parts of the catalog of `source`, copied to `target`.
+    fn add(
+        &mut self,
+        source: &Document,
+        target: &mut Document,
+        copies: &mut HashMap<ObjectId, ObjectId>,
+    ) {
+        let catalog = match source.catalog() {
+            Ok(catalog) => catalog,
+            Err(_) => return,
+        };
+        let mut import_array = |dict: &Dictionary, key: &[u8], parts: &mut Vec<Object>| {
+            if let Ok(Object::Array(array)) = dict.get_deref(key, source) {
+                parts.extend(array.iter().map(|o| import(source, target, o, copies)));
+            }
+        };
+
+        if let Ok(Object::Dictionary(oc)) = catalog.get_deref(b"OCProperties", source) {
+            import_array(oc, b"OCGs", &mut self.ocgs);
+            if let Ok(Object::Dictionary(config)) = oc.get_deref(b"D", source) {
+                import_array(config, b"ON", &mut self.ocgs_on);
+                import_array(config, b"OFF", &mut self.ocgs_off);
+                import_array(config, b"Order", &mut self.ocgs_order);
+                import_array(config, b"AS", &mut self.ocgs_as);
+            }
+        }
+        if let Ok(Object::Dictionary(form)) = catalog.get_deref(b"AcroForm", source) {
+            import_array(form, b"Fields", &mut self.fields);
+        }
+
+        if let Ok(Object::Dictionary(names)) = catalog.get_deref(b"Names", source) {
+            for tree_name in NAME_TREES {
+                if let Ok(Object::Dictionary(tree)) = names.get_deref(tree_name, source) {
+                    for (name, value) in name_tree_entries(source, tree) {
+                        let value = import(source, target, value, copies);
+                        self.name_trees
+                            .entry(tree_name.to_vec())
+                            .or_insert_with(BTreeMap::new)
+                            .entry(name)
+                            .or_insert(value);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Writes the parts into the catalog of `doc`.
+    fn write(self, doc: &mut Document) -> lopdf::Result<()> {
+        let catalog_id = doc.trailer.get(b"Root")?.as_reference()?;
+        let mut catalog = doc.get_dictionary(catalog_id)?.clone();
+
+        if !self.ocgs.is_empty() {
+            let mut config = Dictionary::new();
+            config.set("ON", self.ocgs_on);
+            config.set("OFF", self.ocgs_off);
+            config.set("Order", self.ocgs_order);
+            config.set("AS", self.ocgs_as);
+            let mut oc = Dictionary::new();
+            oc.set("OCGs", self.ocgs);
+            oc.set("D", config);
+            catalog.set("OCProperties", oc);
+        }
+        if !self.fields.is_empty() {
+            let mut form = Dictionary::new();
+            form.set("Fields", self.fields);
+            catalog.set("AcroForm", form);
+        }
+
+        let mut names = match catalog.get_deref(b"Names", doc) {
+            Ok(Object::Dictionary(names)) => names.clone(),
+            _ => Dictionary::new(),
+        };
+        for (tree_name, entries) in self.name_trees {
+            if entries.is_empty() {
+                continue;
+            }
+            // Keys of name trees are sorted
+            let mut array = Vec::with_capacity(2 * entries.len());
+            for (name, value) in entries {
+                array.push(Object::string_literal(name));
+                array.push(value);
+            }
+            let mut tree = Dictionary::new();
+            tree.set("Names", array);
+            names.set(tree_name, tree);
+        }
+        if !names.is_empty() {
+            catalog.set("Names", names);
+        }
+
+        doc.objects.insert(catalog_id, Object::Dictionary(catalog));
+        Ok(())
+    }
+}
+
+/// Merges the embedded files, document JavaScript, optional content and form fields of
+/// `frame_pdfs` into the catalog of `merged` (their concatenation page by page). Returns whether
+/// there was anything to merge.
+pub fn merge_catalogs(merged: &Path, frame_pdfs: &[PathBuf]) -> lopdf::Result<bool> {
+    let mut doc = Document::load(merged)?;
+    let merged_pages: Vec<ObjectId> = doc.get_pages().values().cloned().collect();
+
+    let mut parts = CatalogParts::default();
+    let mut first_page = 0;
+    for pdf in frame_pdfs {
+        let frame = Document::load(pdf)?;
+        let mut copies = copied_objects(&frame, &doc, &merged_pages, first_page);
+        parts.add(&frame, &mut doc, &mut copies);
+        first_page += frame.get_pages().len();
+    }
+
+    if parts.is_empty() {
+        return Ok(false);
+    }
+    parts.write(&mut doc)?;
+    doc.save(merged)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_pdf::{catalog_mut, pdf_with_pages};
+    use tempfile::tempdir;
+
+    /// PDF with one page per entry of `pages` (whether the page uses an optional content group,
+    /// which is listed in the catalog if `with_catalog` is set) and the embedded files `files`.
+    fn test_pdf(pages: &[bool], files: &[&str], with_catalog: bool) -> Document {
+        let mut ocgs = Vec::new();
+        let mut doc = pdf_with_pages(pages.len(), |doc, page_idx, _, page| {
+            if pages[page_idx] {
+                let mut ocg = Dictionary::new();
+                ocg.set("Type", Object::Name(b"OCG".to_vec()));
+                let ocg = doc.add_object(ocg);
+                ocgs.push(Object::Reference(ocg));
+                let mut properties = Dictionary::new();
+                properties.set("oc1", Object::Reference(ocg));
+                let mut resources = Dictionary::new();
+                resources.set("Properties", properties);
+                page.set("Resources", resources);
+            }
+        });
+        if !with_catalog {
+            return doc;
+        }
+
+        let mut names = Vec::new();
+        for file in files {
+            let content = lopdf::Stream::new(Dictionary::new(), file.as_bytes().to_vec());
+            let mut filespec = Dictionary::new();
+            filespec.set("Type", Object::Name(b"Filespec".to_vec()));
+            let mut ef = Dictionary::new();
+            ef.set("F", Object::Reference(doc.add_object(content)));
+            filespec.set("EF", ef);
+            names.push(Object::string_literal(*file));
+            names.push(Object::Reference(doc.add_object(filespec)));
+        }
+        let mut tree = Dictionary::new();
+        tree.set("Names", names);
+        let mut names_dict = Dictionary::new();
+        names_dict.set("EmbeddedFiles", tree);
+
+        let mut config = Dictionary::new();
+        config.set("ON", ocgs.clone());
+        let mut oc = Dictionary::new();
+        oc.set("OCGs", ocgs);
+        oc.set("D", config);
+
+        let catalog = catalog_mut(&mut doc);
+        catalog.set("Names", names_dict);
+        catalog.set("OCProperties", oc);
+        doc
+    }
+
+    #[test]
+    fn test_merge_catalogs() {
+        let dir = tempdir().unwrap();
+        let first = dir.path().join("first.pdf");
+        let second = dir.path().join("second.pdf");
+        let merged = dir.path().join("merged.pdf");
+
+        test_pdf(&[true], &["movie.mp4"], true)
+            .save(&first)
+            .unwrap();
+        test_pdf(&[false], &["movie.mp4", "anim.js"], true)
+            .save(&second)
+            .unwrap();
+
+        // What pdfunite produces: the pages of both (with the group of the first page)
+        test_pdf(&[true, false], &[], false).save(&merged).unwrap();
+        assert!(merge_catalogs(&merged, &[first, second]).unwrap());
+
+        let doc = Document::load(&merged).unwrap();
+        let catalog = doc.catalog().unwrap();
+        let names = catalog
+            .get_deref(b"Names", &doc)
+            .unwrap()
+            .as_dict()
+            .unwrap();
+        let files = names
+            .get_deref(b"EmbeddedFiles", &doc)
+            .unwrap()
+            .as_dict()
+            .unwrap();
+        let files: Vec<Vec<u8>> = name_tree_entries(&doc, files)
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(files, vec![b"anim.js".to_vec(), b"movie.mp4".to_vec()]);
+
+        // The group of the catalog is the one used by the merged page
+        let page = *doc.get_pages().values().next().unwrap();
+        let used = page_properties(&doc, page)[&b"oc1".to_vec()];
+        let oc = catalog
+            .get_deref(b"OCProperties", &doc)
+            .unwrap()
+            .as_dict()
+            .unwrap();
+        let ocgs = references(&doc, oc, b"OCGs");
+        assert_eq!(ocgs, vec![used]);
+    }
+}
@@ -0,0 +1,328 @@
+//
+// clean.rs
+// Distributed under terms of the GPLv3 license.
+//
+
+use crate::options::Options;
+use crate::preamble::shared_format_dir;
+use crate::process_file::{
+    get_cache_subdir, get_cachedir, get_input_dir, is_tagged_cachedir, FasterBeamerError, Result,
+    CACHEDIR_TAG,
+};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Maximum age of the files in the cache with `clean --gc` and without `--cache-max-size` or
+/// `--cache-max-age`.
+pub const DEFAULT_MAX_AGE_DAYS: u64 = 30;
+
+/// Removes the files of the cache directory `cachedir` outside of the cache of a document in
+/// `cache_subdir` that belong to it: the shared copies of its precompiled preambles (which would be
+/// linked again otherwise) and the error slide.
+fn remove_shared_files(cachedir: &Path, cache_subdir: &Path) {
+    let shared_dir = shared_format_dir(cachedir);
+    let formats = fs::read_dir(cache_subdir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.file_name())
+        .filter(|name| {
+            Path::new(name)
+                .extension()
+                .map_or(false, |ext| ext == "fmt")
+        });
+    let error_slide = ["error.tex", "error.log", "error.aux", "error.pdf"]
+        .iter()
+        .map(|name| cachedir.join(name));
+    for file in formats.map(|name| shared_dir.join(name)).chain(error_slide) {
+        if file.is_file() {
+            match fs::remove_file(&file) {
+                Ok(()) => debug!("Removed {}", file.display()),
+                Err(err) => warn!("Failed to remove {}: {}", file.display(), err),
+            }
+        }
+    }
+}
+
+/// Err unless `cachedir` was created by faster-beamer (e.g. `--cache-dir .` by mistake), whose
+/// files must not be removed.
+fn check_cachedir(cachedir: &Path) -> Result<()> {
+    if is_tagged_cachedir(cachedir) {
+        return Ok(());
+    }
+    Err(FasterBeamerError::InvalidOptions(format!(
+        "{} was not created by faster-beamer (it has no {}), refusing to remove files from it",
+        cachedir.display(),
+        CACHEDIR_TAG
+    )))
+}
+
+/// Removes the cache of the documents in the directory of `input_file` (which share their
+/// preambles and frames) with the shared copies of their preambles, or the whole cache with
+/// `all`. With `gc`, only removes the entries that exceed the limits of `args` (or are older than
+/// `DEFAULT_MAX_AGE_DAYS`).
+pub fn clean(input_file: Option<&str>, all: bool, gc: bool, args: &Options) -> Result<()> {
+    let cachedir = get_cachedir()?;
+    check_cachedir(&cachedir)?;
+    if gc {
+        let limits = match cache_limits(args) {
+            Some(limits) => limits,
+            None => CacheLimits {
+                max_size: None,
+                max_age: Some(days(DEFAULT_MAX_AGE_DAYS)),
+            },
+        };
+        return collect_garbage(&cachedir, &limits);
+    }
+    let cache_dir = match input_file {
+        Some(input_file) if !all => {
+            if !Path::new(input_file).exists() {
+                return Err(FasterBeamerError::InputFileNotExistent(input_file.into()));
+            }
+            let cache_subdir = get_cache_subdir(&cachedir, &get_input_dir(input_file));
+            remove_shared_files(&cachedir, &cache_subdir);
+            cache_subdir
+        }
+        _ => cachedir,
+    };
+    if !cache_dir.is_dir() {
+        info!("Nothing to clean in {}", cache_dir.display());
+        return Ok(());
+    }
+    fs::remove_dir_all(&cache_dir).map_err(FasterBeamerError::io(&cache_dir))?;
+    info!("Removed {}", cache_dir.display());
+    Ok(())
+}
+
+/// Limits of the size of the cache and of the time since its files were last used.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CacheLimits {
+    pub max_size: Option<u64>,
+    pub max_age: Option<Duration>,
+}
+
+fn days(days: u64) -> Duration {
+    Duration::from_secs(days * 24 * 60 * 60)
+}
+
+/// Parses a size in bytes with an optional suffix K, M or G (e.g. `500M`).
+pub fn parse_size(size: &str) -> Option<u64> {
+    let size = size.trim();
+    let (number, factor) = match size.char_indices().last()? {
+        (i, 'k') | (i, 'K') => (&size[..i], 1 << 10),
+        (i, 'm') | (i, 'M') => (&size[..i], 1 << 20),
+        (i, 'g') | (i, 'G') => (&size[..i], 1 << 30),
+        _ => (size, 1),
+    };
+    number.trim().parse::<u64>().ok().map(|n| n * factor)
+}
+
+/// Limits of `--cache-max-size` and `--cache-max-age` (None if neither is given).
+pub fn cache_limits(args: &Options) -> Option<CacheLimits> {
+    let max_size = args.cache_max_size;
+    let max_age = args.cache_max_age.map(days);
+    if max_size.is_none() && max_age.is_none() {
+        None
+    } else {
+        Some(CacheLimits { max_size, max_age })
+    }
+}
+
+/// Files in the cache that belong together, e.g. the document, PDF and log of a frame (which
+/// share the hash of the frame before the extension).
+#[derive(Debug)]
+pub struct CacheEntry {
+    pub files: Vec<PathBuf>,
+    pub size: u64,
+    /// Last modification of any of the files (builds that take a frame from the cache write its
+    /// `Frame::use_marker`)
+    pub last_used: SystemTime,
+}
+
+/// Entries of the cache in `dir` (and its subdirectories). Links to input files and entries
+/// that are being written (see `recovery`) are left out.
+pub fn cache_entries(dir: &Path, entries: &mut Vec<CacheEntry>) {
+    let mut groups: BTreeMap<String, CacheEntry> = BTreeMap::new();
+    let mut in_progress = Vec::new();
+    for dir_entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+        let path = dir_entry.path();
+        let metadata = match fs::symlink_metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
+        if metadata.file_type().is_symlink() {
+            continue;
+        }
+        if metadata.is_dir() {
+            cache_entries(&path, entries);
+            continue;
+        }
+        let name = dir_entry.file_name().to_string_lossy().into_owned();
+        if name == CACHEDIR_TAG {
+            continue;
+        }
+        let stem = name.split('.').next().unwrap_or_default().to_string();
+        if name.ends_with(".building") {
+            in_progress.push(stem.clone());
+        }
+        let last_used = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+        let entry = groups.entry(stem).or_insert_with(|| CacheEntry {
+            files: Vec::new(),
+            size: 0,
+            last_used,
+        });
+        entry.files.push(path);
+        entry.size += metadata.len();
+        entry.last_used = entry.last_used.max(last_used);
+    }
+    entries.extend(
+        groups
+            .into_iter()
+            .filter(|(stem, _)| !in_progress.contains(stem))
+            .map(|(_, entry)| entry),
+    );
+}
+
+/// Entries of `entries` that exceed `limits` at `now`: the ones not used within the maximum age,
+/// then the least recently used ones until the others fit into the maximum size.
+pub fn select_garbage(
+    mut entries: Vec<CacheEntry>,
+    limits: &CacheLimits,
+    now: SystemTime,
+) -> Vec<CacheEntry> {
+    entries.sort_by_key(|entry| entry.last_used);
+    let mut size: u64 = entries.iter().map(|entry| entry.size).sum();
+    let mut garbage = Vec::new();
+    for entry in entries {
+        let too_old = limits.max_age.map_or(false, |max_age| {
+            now.duration_since(entry.last_used)
+                .map_or(false, |age| age > max_age)
+        });
+        let too_big = limits.max_size.map_or(false, |max_size| size > max_size);
+        if !too_old && !too_big {
+            break;
+        }
+        size -= entry.size;
+        garbage.push(entry);
+    }
+    garbage
+}
+
+/// Removes the entries of the cache in `cachedir` that exceed `limits`.
+pub fn collect_garbage(cachedir: &Path, limits: &CacheLimits) -> Result<()> {
+    check_cachedir(cachedir)?;
+    let mut entries = Vec::new();
+    cache_entries(cachedir, &mut entries);
+    let garbage = select_garbage(entries, limits, SystemTime::now());
+    let mut removed_size = 0;
+    for entry in &garbage {
+        for file in &entry.files {
+            fs::remove_file(file).map_err(FasterBeamerError::io(file))?;
+        }
+        removed_size += entry.size;
+    }
+    info!(
+        "Removed {} cache entries ({:.1} MB) from {}",
+        garbage.len(),
+        removed_size as f64 / (1 << 20) as f64,
+        cachedir.display()
+    );
+    Ok(())
+}
+
+/// Collects the garbage of the cache after a build with `--cache-max-size` or `--cache-max-age`.
+pub fn collect_garbage_after_build(args: &Options) {
+    if let Some(limits) = cache_limits(args) {
+        let result = get_cachedir().and_then(|cachedir| collect_garbage(&cachedir, &limits));
+        if let Err(err) = result {
+            warn!("Failed to collect the garbage of the cache: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remove_shared_files() {
+        let cachedir = tempfile::tempdir().unwrap();
+        let cache_subdir = cachedir.path().join("talks");
+        let shared_dir = shared_format_dir(cachedir.path());
+        fs::create_dir_all(&cache_subdir).unwrap();
+        fs::create_dir_all(&shared_dir).unwrap();
+        for file in &[
+            cache_subdir.join("abc_false.fmt"),
+            shared_dir.join("abc_false.fmt"),
+            shared_dir.join("def_false.fmt"),
+            cachedir.path().join("error.pdf"),
+        ] {
+            fs::write(file, "").unwrap();
+        }
+        remove_shared_files(cachedir.path(), &cache_subdir);
+        assert!(!shared_dir.join("abc_false.fmt").exists());
+        assert!(shared_dir.join("def_false.fmt").exists());
+        assert!(!cachedir.path().join("error.pdf").exists());
+    }
+
+    #[test]
+    fn test_check_cachedir() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("talk.tex"), "\\documentclass{beamer}").unwrap();
+        let limits = CacheLimits {
+            max_size: Some(0),
+            max_age: None,
+        };
+        assert!(collect_garbage(dir.path(), &limits).is_err());
+        assert!(dir.path().join("talk.tex").is_file());
+
+        fs::write(dir.path().join(CACHEDIR_TAG), "").unwrap();
+        collect_garbage(dir.path(), &limits).unwrap();
+        assert!(!dir.path().join("talk.tex").exists());
+        assert!(dir.path().join(CACHEDIR_TAG).is_file());
+    }
+
+    #[test]
+    fn test_select_garbage() {
+        assert_eq!(parse_size("500M"), Some(500 << 20));
+        assert_eq!(parse_size("2g"), Some(2 << 30));
+        assert_eq!(parse_size("1024"), Some(1024));
+        assert_eq!(parse_size("lots"), None);
+
+        let now = SystemTime::now();
+        let entry = |name: &str, size: u64, age_days: u64| CacheEntry {
+            files: vec![PathBuf::from(name)],
+            size,
+            last_used: now - days(age_days),
+        };
+        let entries = || {
+            vec![
+                entry("new", 100, 0),
+                entry("old", 100, 60),
+                entry("older", 100, 90),
+            ]
+        };
+        let names = |garbage: Vec<CacheEntry>| -> Vec<PathBuf> {
+            garbage.into_iter().flat_map(|entry| entry.files).collect()
+        };
+
+        let by_age = CacheLimits {
+            max_size: None,
+            max_age: Some(days(30)),
+        };
+        assert_eq!(
+            names(select_garbage(entries(), &by_age, now)),
+            vec![PathBuf::from("older"), PathBuf::from("old")]
+        );
+        let by_size = CacheLimits {
+            max_size: Some(250),
+            max_age: None,
+        };
+        assert_eq!(
+            names(select_garbage(entries(), &by_size, now)),
+            vec![PathBuf::from("older")]
+        );
+    }
+}
@@ -0,0 +1,910 @@
+//
+// cli.rs
+// Distributed under terms of the GPLv3 license.
+//
+
+use crate::beamer;
+use crate::clean;
+use crate::container::{self, Container};
+use crate::error::{FasterBeamerError, Result};
+use crate::events;
+use crate::export;
+use crate::merger;
+use crate::options::{Options, INTERACTION_MODES};
+use crate::process_file::{get_cachedir, set_cachedir};
+use clap::{App, AppSettings, Arg, SubCommand};
+use std::env::current_dir;
+use std::path::PathBuf;
+
+/// Command line interface of faster-beamer. The library parses its options with it as well.
+pub fn app() -> App<'static, 'static> {
+    App::new("faster-beamer")
+    .version("0.1.6")
+    .author("Stephan Seitz <stephan.seitz@fau.de>")
+    .about("Incremental compiler for Beamer LaTeX presentations")
+    .setting(AppSettings::SubcommandsNegateReqs)
+    // `faster-beamer file.tex` is the same as `faster-beamer build file.tex`
+    .args(&build_args())
+    .arg(
+        Arg::with_name("watch")
+            .short("w")
+            .long("watch")
+            .help("Keeps running and builds the presentation again whenever one of its files changes"),
+    )
+    .args(&watch_args())
+    .arg(
+        Arg::with_name("frame-numbers")
+            .short("f")
+            .long("frame-numbers")
+            .global(true)
+            .help("Try to print correct frames numbers. This can harm cache performance when swapping frames."),
+    )
+    .arg(
+        Arg::with_name("tree-sitter")
+            .short("t")
+            .long("tree-sitter")
+            .global(true)
+            .help("Use tree-sitter to parse LaTeX (instead of regexes)"),
+    )
+    .arg(
+        Arg::with_name("profile")
+            .long("profile")
+            .takes_value(true)
+            .value_name("NAME")
+            .global(true)
+            .help("Name of the build profile (used for {profile} in output file names)"),
+    )
+    .arg(
+        Arg::with_name("jobs")
+            .short("j")
+            .long("jobs")
+            .takes_value(true)
+            .value_name("N")
+            .global(true)
+            .help("Number of frames compiled in parallel (default: number of CPUs), shared by all input files"),
+    )
+    .arg(
+        Arg::with_name("compiler")
+            .short("c")
+            .long("compiler")
+            .takes_value(true)
+            .default_value("pdflatex")
+            .global(true)
+            .help("LaTeX compiler (e.g. pdflatex, xelatex, lualatex or tectonic), or latexmk to compile frames with latexmk")
+    )
+    .arg(
+        Arg::with_name("max-reruns")
+            .long("max-reruns")
+            .takes_value(true)
+            .value_name("N")
+            .global(true)
+            .validator(|value| match value.parse::<usize>() {
+                Ok(_) => Ok(()),
+                Err(_) => Err(format!("expected a number, got '{}'", value)),
+            })
+            .help("Compiles a frame again, up to N times, while LaTeX asks for a rerun to get references right (default: 2, 0 disables reruns)"),
+    )
+    .arg(
+        Arg::with_name("latexmk-engine")
+            .long("latexmk-engine")
+            .takes_value(true)
+            .value_name("ENGINE")
+            .default_value("pdflatex")
+            .global(true)
+            .help("Engine that latexmk runs with --compiler latexmk (e.g. pdflatex, xelatex or lualatex)"),
+    )
+    .arg(
+        Arg::with_name("latex-arg")
+            .long("latex-arg")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("ARG")
+            .global(true)
+            .allow_hyphen_values(true)
+            .help("Passes ARG to every run of the LaTeX compiler (frames, preamble and united document, e.g. '-file-line-error'). Can be given multiple times."),
+    )
+    .arg(
+        Arg::with_name("shell-escape")
+            .long("shell-escape")
+            .global(true)
+            .overrides_with("no-shell-escape")
+            .help("Lets the LaTeX compiler run external commands (e.g. for minted or gnuplottex). Only use it with documents that you trust."),
+    )
+    .arg(
+        Arg::with_name("no-shell-escape")
+            .long("no-shell-escape")
+            .global(true)
+            .overrides_with("shell-escape")
+            .help("Does not let the LaTeX compiler run external commands (default, overrides shell-escape = true in faster-beamer.toml)"),
+    )
+    .arg(
+        Arg::with_name("container")
+            .long("container")
+            .takes_value(true)
+            .value_name("IMAGE")
+            .global(true)
+            .help("Runs the LaTeX compiler, biber and makeindex in a container of IMAGE (e.g. texlive/texlive) with the cache directory and the directories of the input files mounted, so that no TeX installation is needed"),
+    )
+    .arg(
+        Arg::with_name("container-runtime")
+            .long("container-runtime")
+            .takes_value(true)
+            .possible_values(container::RUNTIMES)
+            .default_value("auto")
+            .global(true)
+            .help("Runs --container with docker or podman ('auto' prefers podman if it is installed)"),
+    )
+    .arg(
+        Arg::with_name("remote")
+            .long("remote")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("HOST")
+            .global(true)
+            .help("Compiles frames on the SSH host HOST (e.g. user@build-server), which needs rsync and the same TeX distribution. Can be given multiple times; combine with --jobs to keep all workers busy."),
+    )
+    .arg(
+        Arg::with_name("remote-dir")
+            .long("remote-dir")
+            .takes_value(true)
+            .value_name("DIR")
+            .global(true)
+            .help("Directory of the frames on the --remote hosts, relative to the home directory [default: .cache/faster-beamer-remote]"),
+    )
+    .arg(
+        Arg::with_name("remote-cache")
+            .long("remote-cache")
+            .takes_value(true)
+            .value_name("URL")
+            .global(true)
+            .help("Shares compiled frames through the HTTP(S) or WebDAV server URL, or s3://BUCKET/PREFIX: frames that are not in the local cache are downloaded from it, and compiled frames are uploaded to it"),
+    )
+    .arg(
+        Arg::with_name("remote-cache-read-only")
+            .long("remote-cache-read-only")
+            .global(true)
+            .requires("remote-cache")
+            .help("Only downloads frames from --remote-cache (e.g. on machines without write access)"),
+    )
+    .arg(
+        Arg::with_name("cache-dir")
+            .long("cache-dir")
+            .takes_value(true)
+            .value_name("DIR")
+            .global(true)
+            .help("Directory of the cache [default: faster-beamer in the cache directory of the user]"),
+    )
+    .arg(
+        Arg::with_name("cache-max-size")
+            .long("cache-max-size")
+            .takes_value(true)
+            .value_name("SIZE")
+            .global(true)
+            .validator(|value| match clean::parse_size(&value) {
+                Some(_) => Ok(()),
+                None => Err(format!("expected a size like 500M or 2G, got '{}'", value)),
+            })
+            .help("Removes the least recently used files of the cache after every build while it is bigger than SIZE (e.g. 500M or 2G)"),
+    )
+    .arg(
+        Arg::with_name("cache-max-age")
+            .long("cache-max-age")
+            .takes_value(true)
+            .value_name("DAYS")
+            .global(true)
+            .validator(|value| match value.parse::<u64>() {
+                Ok(_) => Ok(()),
+                Err(_) => Err(format!("expected a number, got '{}'", value)),
+            })
+            .help("Removes the files of the cache that were not used for DAYS days after every build"),
+    )
+    .arg(
+        Arg::with_name("message-format")
+            .long("message-format")
+            .takes_value(true)
+            .possible_values(events::MESSAGE_FORMATS)
+            .default_value("human")
+            .global(true)
+            .help("With 'json', writes the progress of the build to stdout as one JSON object per line (frames found, cache hits, compiles started and finished, outputs and errors) instead of a progress bar"),
+    )
+    .arg(
+        Arg::with_name("merger")
+            .long("merger")
+            .takes_value(true)
+            .possible_values(merger::MERGERS)
+            .default_value("auto")
+            .global(true)
+            .help("Backend that merges the frames with --pdfunite: 'builtin' needs no external program, 'auto' retries PDFs that it cannot merge with the first of pdfunite, qpdf, mutool and gs that is installed"),
+    )
+    .arg(
+        Arg::with_name("interaction")
+            .long("interaction")
+            .takes_value(true)
+            .possible_values(INTERACTION_MODES)
+            .default_value("nonstopmode")
+            .global(true)
+            .help("Interaction mode of the compiler for frames"),
+    )
+    .arg(
+        Arg::with_name("halt-on-error")
+            .long("halt-on-error")
+            .global(true)
+            .help("Stops compiling a frame at its first error instead of producing a possibly garbled PDF"),
+    )
+    .arg(
+        Arg::with_name("navigation")
+            .short("n")
+            .long("navigation")
+            .global(true)
+            .help("Compiles frames with the navigation data of the whole presentation (mini frames, section navigation). Needs a second pass when the structure changes."),
+    )
+    .arg(
+        Arg::with_name("bibliography")
+            .long("bibliography")
+            .global(true)
+            .help("Runs biber or bibtex once for the citations of all frames and compiles the frames that cite with the resulting .bbl"),
+    )
+    .arg(
+        Arg::with_name("strip-decorations")
+            .short("s")
+            .long("strip-decorations")
+            .global(true)
+            .help("Removes navigation symbols, headline, footline and logo from frames for a faster, less noisy preview"),
+    )
+    .arg(
+        Arg::with_name("only-frames")
+            .long("only-frames")
+            .takes_value(true)
+            .value_name("LABELS")
+            .global(true)
+            .help("Compiles and merges only the frames with these labels (comma-separated, e.g. intro,results). Overrides \\includeonlyframes of the preamble."),
+    )
+    .arg(
+        Arg::with_name("conditional")
+            .long("conditional")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("NAME=BOOL")
+            .global(true)
+            .validator(|value| match beamer::parse_conditional(&value) {
+                Some(_) => Ok(()),
+                None => Err(format!("expected NAME=true or NAME=false, got '{}'", value)),
+            })
+            .help("Sets the conditional \\ifNAME of \\newif (e.g. draft=false), which excludes the frames in its false branch. Can be given multiple times."),
+    )
+    .arg(
+        Arg::with_name("aspectratio")
+            .long("aspectratio")
+            .takes_value(true)
+            .global(true)
+            .help("Overrides the aspect ratio of the presentation (e.g. 169 or 43)"),
+    )
+    .arg(
+        Arg::with_name("beamer-mode")
+            .long("beamer-mode")
+            .takes_value(true)
+            .possible_values(beamer::BEAMER_MODES)
+            .global(true)
+            .help("Builds the presentation, the handout or the article version of the document"),
+    )
+    .arg(
+        Arg::with_name("handout")
+            .long("handout")
+            .conflicts_with("beamer-mode")
+            .global(true)
+            .help("Builds the handout version with the overlays of every frame collapsed (short for --beamer-mode handout)"),
+    )
+    .arg(
+        Arg::with_name("overlays")
+            .long("overlays")
+            .takes_value(true)
+            .possible_values(beamer::OVERLAY_MODES)
+            .global(true)
+            .help("Compiles every overlay of the frames, collapses them into one page per frame (trans mode) or keeps only the last overlay of every frame, e.g. for quicker drafts"),
+    )
+    .arg(
+        Arg::with_name("notes")
+            .long("notes")
+            .takes_value(true)
+            .possible_values(beamer::NOTES_MODES)
+            .global(true)
+            .help("Hides the notes of the frames, shows them after the slides or shows only the notes (\\setbeameroption)"),
+    )
+    .arg(
+        Arg::with_name("theme")
+            .long("theme")
+            .takes_value(true)
+            .global(true)
+            .help("Overrides the beamer theme of the presentation (e.g. metropolis)"),
+    )
+    .arg(
+        Arg::with_name("preamble-extra")
+            .long("preamble-extra")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("CODE")
+            .global(true)
+            .help("Appends CODE to the preamble (e.g. '\\usepackage{...}'). Can be given multiple times."),
+    )
+    .arg(
+        Arg::with_name("preamble")
+            .long("preamble")
+            .takes_value(true)
+            .value_name("FILE")
+            .global(true)
+            .help("Preamble file for directory input (default: preamble.tex in the directory)"),
+    )
+    .arg(
+        Arg::with_name("pandoc-arg")
+            .long("pandoc-arg")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("ARG")
+            .global(true)
+            .allow_hyphen_values(true)
+            .help("Passes ARG to pandoc when converting Markdown input (e.g. '--slide-level=2'). Can be given multiple times."),
+    )
+    .arg(
+        Arg::with_name("keep-intermediates")
+            .long("keep-intermediates")
+            .global(true)
+            .help("Keeps the document compiled for every frame in the cache as <input>-frame-<index>[-<label>].tex"),
+    )
+    .subcommand(
+        SubCommand::with_name("build")
+            .about("Builds the presentations (the default without a subcommand)")
+            .args(&build_args()),
+    )
+    .subcommand(
+        SubCommand::with_name("watch")
+            .about("Builds the presentations and builds them again whenever one of their files changes")
+            .args(&build_args())
+            .args(&watch_args()),
+    )
+    .subcommand(
+        SubCommand::with_name("clean")
+            .about("Removes the cached preambles and frames of the documents in the directory of the input")
+            .arg(
+                Arg::with_name("INPUT")
+                    .help("Sets the input file to use")
+                    .required_unless_one(&["all", "gc", "project"])
+                    .index(1),
+            )
+            .arg(
+                Arg::with_name("project")
+                    .long("project")
+                    .takes_value(true)
+                    .value_name("FILE")
+                    .conflicts_with_all(&["INPUT", "all"])
+                    .help("Removes the cache of the documents in the directory of FILE (the same as giving FILE as INPUT)"),
+            )
+            .arg(
+                Arg::with_name("all")
+                    .long("all")
+                    .help("Removes the whole cache"),
+            )
+            .arg(
+                Arg::with_name("gc")
+                    .long("gc")
+                    .conflicts_with("all")
+                    .help("Removes the least recently used files of the whole cache that exceed --cache-max-size or --cache-max-age (default: files unused for 30 days)"),
+            ),
+    )
+    .subcommand(
+        SubCommand::with_name("cache")
+            .about("Inspects the cache")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                SubCommand::with_name("stats")
+                    .about("Shows the size of the cache, its oldest and newest files, and for every presentation how many frames are cached and how many the last build took from the cache"),
+            ),
+    )
+    .subcommand(
+        SubCommand::with_name("frames")
+            .about("Lists the frames with their label, title and whether they are compiled")
+            .arg(
+                Arg::with_name("INPUT")
+                    .help("Sets the input file to use")
+                    .required(true)
+                    .index(1),
+            ),
+    )
+    .subcommand(
+        SubCommand::with_name("daemon")
+            .about("Stays resident and builds documents on commands sent to a Unix socket (build, frame-at, status, shutdown)")
+            .arg(
+                Arg::with_name("socket")
+                    .long("socket")
+                    .takes_value(true)
+                    .value_name("PATH")
+                    .help("Path of the socket [default: daemon.sock in the cache directory]"),
+            ),
+    )
+    .subcommand(
+        SubCommand::with_name("lsp")
+            .about("Runs a Language Server on stdin and stdout that builds documents on save, publishes the errors of their frames as diagnostics and compiles the frame under the cursor as code action"),
+    )
+    .subcommand(
+        SubCommand::with_name("nvim")
+            .about("Speaks msgpack-RPC on stdin and stdout for a Neovim plugin that sends the buffer and the cursor position and receives the build status and the page of the frame under the cursor"),
+    )
+    .subcommand(
+        SubCommand::with_name("lint")
+            .about("Compiles all frames and reports frames whose content overflows")
+            .arg(
+                Arg::with_name("INPUT")
+                    .help("Sets the input file to use")
+                    .required(true)
+                    .index(1),
+            )
+            .arg(
+                Arg::with_name("vbox-tolerance")
+                    .long("vbox-tolerance")
+                    .takes_value(true)
+                    .default_value("0")
+                    .help("Vertical overflow (in pt) above which a frame is reported as error"),
+            )
+            .arg(
+                Arg::with_name("hbox-tolerance")
+                    .long("hbox-tolerance")
+                    .takes_value(true)
+                    .default_value("1")
+                    .help("Overfull hboxes (in pt) above this are reported as warnings"),
+            )
+            .arg(
+                Arg::with_name("max-hboxes")
+                    .long("max-hboxes")
+                    .takes_value(true)
+                    .default_value("3")
+                    .help("Frames with more overfull hboxes than this are reported as errors"),
+            ),
+    )
+    .subcommand(
+        SubCommand::with_name("outline")
+            .about("Writes a short PDF listing only sections and frame titles")
+            .arg(
+                Arg::with_name("INPUT")
+                    .help("Sets the input file to use")
+                    .required(true)
+                    .index(1),
+            )
+            .arg(
+                Arg::with_name("OUTPUT")
+                    .help("Filename for output PDF")
+                    .default_value("outline.pdf")
+                    .index(2),
+            ),
+    )
+    .subcommand(
+        SubCommand::with_name("log")
+            .about("Prints the LaTeX log of a frame from the last build")
+            .arg(
+                Arg::with_name("INPUT")
+                    .help("Sets the input file to use")
+                    .required(true)
+                    .index(1),
+            )
+            .arg(
+                Arg::with_name("frame")
+                    .long("frame")
+                    .takes_value(true)
+                    .value_name("INDEX")
+                    .help("Index of the frame (starting at 0)"),
+            )
+            .arg(
+                Arg::with_name("label")
+                    .long("label")
+                    .takes_value(true)
+                    .conflicts_with("frame")
+                    .help("Label of the frame"),
+            )
+            .arg(
+                Arg::with_name("open")
+                    .long("open")
+                    .help("Opens the log file instead of printing it"),
+            ),
+    )
+    .subcommand(
+        SubCommand::with_name("dump-frame")
+            .about("Prints the document that is compiled for a frame (without the navigation data of --navigation)")
+            .arg(
+                Arg::with_name("INPUT")
+                    .help("Sets the input file to use")
+                    .required(true)
+                    .index(1),
+            )
+            .arg(
+                Arg::with_name("FRAME")
+                    .help("Index of the frame (starting at 0)")
+                    .required_unless("label")
+                    .index(2),
+            )
+            .arg(
+                Arg::with_name("label")
+                    .long("label")
+                    .takes_value(true)
+                    .conflicts_with("FRAME")
+                    .help("Label of the frame"),
+            ),
+    )
+    .subcommand(
+        SubCommand::with_name("frame-at")
+            .about("Prints the index of the frame at a line of the input and its first page in the united PDF (separated by a tab)")
+            .arg(
+                Arg::with_name("POSITION")
+                    .help("Input file and line (starting at 1), e.g. talk.tex:42")
+                    .required(true)
+                    .index(1),
+            ),
+    )
+    .subcommand(
+        SubCommand::with_name("locate")
+            .about("Prints FILE:LINE of the source at a position in the united PDF (inverse search, needs frames compiled with --synctex)")
+            .args(&[
+                Arg::with_name("INPUT")
+                    .help("Input file")
+                    .required(true)
+                    .index(1),
+                Arg::with_name("page")
+                    .long("page")
+                    .takes_value(true)
+                    .required(true)
+                    .help("Page of the united PDF (starting at 1)"),
+                Arg::with_name("x")
+                    .long("x")
+                    .takes_value(true)
+                    .required(true)
+                    .help("Horizontal position in PDF points from the left of the page"),
+                Arg::with_name("y")
+                    .long("y")
+                    .takes_value(true)
+                    .required(true)
+                    .help("Vertical position in PDF points from the top of the page"),
+            ]),
+    )
+    .subcommand(
+        SubCommand::with_name("status")
+            .about("Shows whether the preamble and the frames are compiled and the outcome of the last build")
+            .arg(
+                Arg::with_name("INPUT")
+                    .help("Sets the input file to use")
+                    .required(true)
+                    .index(1),
+            ),
+    )
+    .subcommand(
+        SubCommand::with_name("warm")
+            .about("Precompiles the preamble and all frames into the cache without writing an output (e.g. for CI caches)")
+            .arg(
+                Arg::with_name("INPUT")
+                    .help("Sets the input files to use (glob patterns are expanded)")
+                    .required(true)
+                    .multiple(true)
+                    .index(1),
+            ),
+    )
+    .subcommand(
+        SubCommand::with_name("merge")
+            .about("Merges PDFs (e.g. cached frames) into one PDF with a bookmark and named destination per input")
+            .arg(
+                Arg::with_name("OUTPUT")
+                    .help("Filename for output PDF")
+                    .required(true)
+                    .index(1),
+            )
+            .arg(
+                Arg::with_name("PDFS")
+                    .help("PDFs to merge")
+                    .multiple(true)
+                    .required_unless("from-manifest")
+                    .index(2),
+            )
+            .arg(
+                Arg::with_name("from-manifest")
+                    .long("from-manifest")
+                    .takes_value(true)
+                    .value_name("FILE")
+                    .conflicts_with("PDFS")
+                    .help("Merges the frames of the last build of FILE (an input document, or a JSON manifest: a .manifest file of the cache or a .json file next to the frame PDFs)"),
+            )
+            .arg(
+                Arg::with_name("pdfunite")
+                    .short("x")
+                    .long("pdfunite")
+                    .help("Merges page by page like pdfunite (without bookmarks)"),
+            ),
+    )
+    .subcommand(
+        SubCommand::with_name("extract")
+            .about("Writes every frame to its own file")
+            .arg(
+                Arg::with_name("INPUT")
+                    .help("Sets the input file to use")
+                    .required(true)
+                    .index(1),
+            )
+            .arg(
+                Arg::with_name("out")
+                    .long("out")
+                    .takes_value(true)
+                    .value_name("DIR")
+                    .default_value("frames")
+                    .help("Directory for the frame files"),
+            )
+            .arg(
+                Arg::with_name("standalone")
+                    .long("standalone")
+                    .help("Wraps every frame into a compilable document (instead of writing the preamble to preamble.tex)"),
+            ),
+    )
+    .subcommand(
+        SubCommand::with_name("figures")
+            .about("Compiles every tikzpicture and figure environment as a cropped PDF (or PNG)")
+            .arg(
+                Arg::with_name("INPUT")
+                    .help("Sets the input file to use")
+                    .required(true)
+                    .index(1),
+            )
+            .arg(
+                Arg::with_name("out")
+                    .long("out")
+                    .takes_value(true)
+                    .value_name("DIR")
+                    .default_value("figures")
+                    .help("Directory for the compiled figures"),
+            )
+            .arg(
+                Arg::with_name("png")
+                    .long("png")
+                    .help("Writes PNG images (using pdftoppm) instead of PDFs"),
+            ),
+    )
+    .subcommand(
+        SubCommand::with_name("export")
+            .about("Renders every page of the frames to an image named by the index and title of its frame")
+            .arg(
+                Arg::with_name("INPUT")
+                    .help("Sets the input file to use")
+                    .required(true)
+                    .index(1),
+            )
+            .arg(
+                Arg::with_name("out")
+                    .long("out")
+                    .takes_value(true)
+                    .value_name("DIR")
+                    .default_value("images")
+                    .help("Directory for the images"),
+            )
+            .arg(
+                Arg::with_name("format")
+                    .long("format")
+                    .takes_value(true)
+                    .possible_values(export::EXPORT_FORMATS)
+                    .default_value("png")
+                    .help("Format of the images (PNG with pdftoppm, SVG with pdftocairo), or a self-contained HTML slideshow of PNG images"),
+            )
+            .arg(
+                Arg::with_name("dpi")
+                    .long("dpi")
+                    .takes_value(true)
+                    .default_value("150")
+                    .help("Resolution of PNG images in pixels per inch"),
+            )
+            .arg(
+                Arg::with_name("thumbnails")
+                    .long("thumbnails")
+                    .help("Writes small images and a contact sheet (index.html) that shows all of them"),
+            ),
+    )
+    .subcommand(
+        SubCommand::with_name("export-frame")
+            .about("Stitches the overlays of a frame into an animated GIF or an MP4 video (using ffmpeg)")
+            .arg(
+                Arg::with_name("INPUT")
+                    .help("Sets the input file to use")
+                    .required(true)
+                    .index(1),
+            )
+            .arg(
+                Arg::with_name("index")
+                    .long("index")
+                    .takes_value(true)
+                    .value_name("N")
+                    .required_unless("label")
+                    .help("Index of the frame (starting at 0)"),
+            )
+            .arg(
+                Arg::with_name("label")
+                    .long("label")
+                    .takes_value(true)
+                    .conflicts_with("index")
+                    .help("Label of the frame"),
+            )
+            .arg(
+                Arg::with_name("format")
+                    .long("format")
+                    .takes_value(true)
+                    .possible_values(export::ANIMATION_FORMATS)
+                    .default_value("gif")
+                    .help("Format of the animation"),
+            )
+            .arg(
+                Arg::with_name("delay")
+                    .long("delay")
+                    .takes_value(true)
+                    .value_name("SECONDS")
+                    .default_value("1")
+                    .help("Time for which every overlay is shown"),
+            )
+            .arg(
+                Arg::with_name("dpi")
+                    .long("dpi")
+                    .takes_value(true)
+                    .default_value("150")
+                    .help("Resolution of the animation in pixels per inch"),
+            )
+            .arg(
+                Arg::with_name("output")
+                    .short("o")
+                    .long("output")
+                    .takes_value(true)
+                    .value_name("FILE")
+                    .help("Output file (default: <input>-frame-<index>.<format> next to the input)"),
+            ),
+    )
+    //.arg(
+    //Arg::with_name("draft")
+    //.short("d")
+    //.help("Compile in draft mode")
+    //)
+}
+
+/// Options of `build` (and of faster-beamer without a subcommand).
+fn build_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("INPUT")
+            .help("Sets the input files to use (glob patterns like 'lectures/*.tex' are expanded). A single input may be followed by the output file. Directories are read as one file per frame plus a preamble file.")
+            .required(true)
+            .multiple(true)
+            .index(1),
+        Arg::with_name("find-root")
+            .short("r")
+            .long("find-root")
+            .help("If an input is part of a multi-file project, builds the main document instead (found by a '% !TeX root' comment, a latexmkrc or by searching parent directories) and compiles the frames of the input first"),
+        Arg::with_name("unite")
+            .short("u")
+            .long("unite")
+            .help("Unites all slides to a PDF (default is only to output newest slide)"),
+        Arg::with_name("pdfunite")
+            .short("x")
+            .long("pdfunite")
+            .help("Unites all slides to a PDF page by page (like pdfunite)"),
+        Arg::with_name("synctex")
+            .long("synctex")
+            .help("Compiles the frames with SyncTeX and merges their SyncTeX files into one for the output PDF"),
+        Arg::with_name("pdfpc")
+            .long("pdfpc")
+            .help("Writes the notes and overlays of the frames to a .pdfpc file next to the united PDF for the pdfpc presenter console"),
+        Arg::with_name("optimize-output")
+            .long("optimize-output")
+            .help("Reduces the size of the united PDF by removing unused objects and compressing streams"),
+        Arg::with_name("tagged")
+            .long("tagged")
+            .help("Compiles the output PDF from the whole document (twice) instead of merging the frames, which keeps the structure tree of tagged PDFs (\\DocumentMetadata, tagpdf)"),
+        Arg::with_name("variants")
+            .long("variants")
+            .takes_value(true)
+            .use_delimiter(true)
+            .conflicts_with("aspectratio")
+            .value_name("ASPECTRATIOS")
+            .help("Builds one output per aspect ratio (e.g. 169,43), named like the output file with the aspect ratio appended"),
+        Arg::with_name("watermark")
+            .long("watermark")
+            .takes_value(true)
+            .value_name("TEXT")
+            .help("Stamps TEXT (e.g. \"DRAFT\") on every page of the output PDF"),
+        Arg::with_name("page-map")
+            .long("page-map")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("Writes the page range of each frame in the united PDF to FILE (JSON if FILE ends with .json, text otherwise)"),
+        Arg::with_name("OUTPUT")
+            .short("o")
+            .long("output")
+            .help("Filename for output PDF. {stem} is replaced by the name of the input file, {profile} by the profile. [default: <input>.preview.pdf next to the input]")
+            .takes_value(true),
+    ]
+}
+
+/// Options of `watch` in addition to those of `build`.
+fn watch_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("debounce")
+            .long("debounce")
+            .takes_value(true)
+            .value_name("MS")
+            .help("Milliseconds without changes to wait for before building in watch mode (default: 200)"),
+        Arg::with_name("watch-ignore")
+            .long("watch-ignore")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("PATTERN")
+            .help("Does not build again in watch mode when only files matching the glob PATTERN change (e.g. '*.bak'). Can be given multiple times."),
+    ]
+}
+
+/// Applies the options that hold for the whole process: the cache directory, the container of
+/// the compiler and the format of the messages.
+pub fn set_globals(options: &Options) {
+    if let Some(cachedir) = &options.cache_dir {
+        set_cachedir(cachedir);
+    }
+    container::set_container(options.container.as_ref().map(|image| {
+        // The input directories are mounted for every compile (see `container`)
+        let mut mounts: Vec<PathBuf> = get_cachedir().into_iter().collect();
+        mounts.extend(current_dir());
+        Container::new(&options.container_runtime, image, &mounts)
+    }));
+    events::set_enabled(options.message_format == "json");
+}
+
+/// Parses `argv` (without the program name and the subcommand) like the command line of
+/// `faster-beamer <subcommand>` and returns the options of `subcommand`.
+pub fn subcommand_options(subcommand: &str, argv: &[String]) -> Result<Options> {
+    let argv: Vec<String> = vec!["faster-beamer".to_string(), subcommand.to_string()]
+        .into_iter()
+        .chain(argv.iter().cloned())
+        .collect();
+    let matches = app()
+        .get_matches_from_safe(argv)
+        .map_err(|err| FasterBeamerError::InvalidOptions(err.message))?;
+    let options = Options::from_matches(&matches)?;
+    set_globals(&options);
+    Ok(options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_alias() {
+        let parse = |argv: &[&str]| app().get_matches_from_safe(argv).unwrap();
+        let matches = parse(&["faster-beamer", "--unite", "talk.tex", "talk.pdf"]);
+        assert_eq!(matches.subcommand_name(), None);
+        assert_eq!(matches.values_of("INPUT").unwrap().count(), 2);
+
+        let matches = parse(&["faster-beamer", "build", "--unite", "talk.tex"]);
+        let (name, build_matches) = matches.subcommand();
+        assert_eq!(name, "build");
+        assert!(build_matches.unwrap().is_present("unite"));
+
+        let matches = parse(&["faster-beamer", "-c", "lualatex", "watch", "talk.tex"]);
+        let watch_matches = matches.subcommand_matches("watch").unwrap();
+        assert_eq!(watch_matches.value_of("compiler"), Some("lualatex"));
+        assert_eq!(watch_matches.value_of("INPUT"), Some("talk.tex"));
+    }
+
+    #[test]
+    fn test_shell_escape() {
+        let parse = |argv: &[&str]| app().get_matches_from_safe(argv).unwrap();
+        assert!(!parse(&["faster-beamer", "talk.tex"]).is_present("shell-escape"));
+        assert!(parse(&["faster-beamer", "--shell-escape", "talk.tex"]).is_present("shell-escape"));
+        // The last one wins, so that the command line overrides the configuration
+        let matches = parse(&[
+            "faster-beamer",
+            "--shell-escape",
+            "--no-shell-escape",
+            "talk.tex",
+        ]);
+        assert!(!matches.is_present("shell-escape"));
+    }
+}
@@ -0,0 +1,419 @@
+//
+// compiler.rs
+// Distributed under terms of the GPLv3 license.
+//
+
+use crate::container;
+use crate::labels::referenced_labels;
+use crate::latexcompile::{LatexCompiler, LatexError, LatexInput, LatexRunOptions};
+use crate::log_filter::asks_for_rerun;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::str;
+
+/// TeX engine that a compiler command (e.g. `xelatex` or `/usr/bin/lualatex-dev`) runs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Engine {
+    PdfTex,
+    XeTex,
+    LuaTex,
+    /// Tectonic (based on XeTeX), which downloads the packages it needs instead of using a TeX
+    /// distribution
+    Tectonic,
+}
+
+impl Engine {
+    pub fn of(command: &str) -> Engine {
+        let name = Path::new(command)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or(command);
+        if name.starts_with("tectonic") {
+            Engine::Tectonic
+        } else if name.starts_with("xe") {
+            Engine::XeTex
+        } else if name.starts_with("lua") {
+            Engine::LuaTex
+        } else {
+            Engine::PdfTex
+        }
+    }
+
+    /// Whether the engine loads system fonts (e.g. with fontspec), which cannot be dumped into a
+    /// format.
+    pub fn has_native_fonts(self) -> bool {
+        self != Engine::PdfTex
+    }
+
+    /// Whether the preamble can be precompiled into a format (Tectonic has no `-ini` mode).
+    pub fn precompiles_formats(self) -> bool {
+        self != Engine::Tectonic
+    }
+}
+
+/// Arguments of Tectonic for the LaTeX arguments `args` of a compile in `working_dir`. Tectonic
+/// keeps no logs and auxiliary files by default and writes its output next to the input file.
+/// Arguments that Tectonic has no equivalent of (e.g. `-interaction`) are dropped.
+pub fn tectonic_args(args: &[String], working_dir: &Path) -> Vec<String> {
+    let mut tectonic_args: Vec<String> = vec![
+        "--keep-logs".to_string(),
+        "--keep-intermediates".to_string(),
+        "--outdir".to_string(),
+        working_dir.to_string_lossy().into_owned(),
+    ];
+    for arg in args {
+        match arg.trim_start_matches('-') {
+            "shell-escape" => {
+                tectonic_args.push("-Z".to_string());
+                tectonic_args.push("shell-escape".to_string());
+            }
+            option if option.starts_with("synctex") => tectonic_args.push("--synctex".to_string()),
+            option
+                if option.starts_with("interaction")
+                    || option == "halt-on-error"
+                    || option == "file-line-error" => {}
+            _ => tectonic_args.push(arg.to_string()),
+        }
+    }
+    tectonic_args
+}
+/// Runs the TeX engine for the preamble and the frames. Tests use a fake implementation that
+/// needs no TeX installation.
+pub trait Compiler: Sync {
+    /// Precompiles `preamble_file` into the format `<jobname>.fmt` in `working_dir`, passing `args`
+    /// to the compiler.
+    fn precompile_format(
+        &self,
+        preamble_file: &Path,
+        jobname: &str,
+        working_dir: &Path,
+        args: &[String],
+        env: &BTreeMap<String, String>,
+    ) -> Result<(), LatexError>;
+
+    /// Compiles `tex_file` to a PDF (and log) of the same name in `working_dir`.
+    fn compile(
+        &self,
+        tex_file: &Path,
+        working_dir: &Path,
+        args: &[String],
+        env: &BTreeMap<String, String>,
+    ) -> Result<(), LatexError>;
+}
+
+/// Compiler that runs `command` (e.g. pdflatex).
+pub struct TexCompiler {
+    pub command: String,
+}
+
+impl TexCompiler {
+    pub fn new(command: &str) -> TexCompiler {
+        TexCompiler {
+            command: command.to_string(),
+        }
+    }
+}
+
+impl Compiler for TexCompiler {
+    fn precompile_format(
+        &self,
+        preamble_file: &Path,
+        jobname: &str,
+        working_dir: &Path,
+        args: &[String],
+        env: &BTreeMap<String, String>,
+    ) -> Result<(), LatexError> {
+        if !Engine::of(&self.command).precompiles_formats() {
+            return Err(LatexError::LatexError(format!(
+                "{} cannot precompile formats",
+                self.command
+            )));
+        }
+        let output = container::command(&self.command, working_dir, env)
+            .args(args)
+            .arg("-ini")
+            .arg(format!("-jobname=\"{}\"", jobname))
+            .arg("\"&".to_owned() + &self.command + "\"")
+            .arg("mylatexformat.ltx")
+            .arg(preamble_file)
+            .output()
+            .map_err(LatexError::Io)?;
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(LatexError::LatexError(
+                str::from_utf8(&output.stderr)
+                    .unwrap_or_default()
+                    .to_string(),
+            ))
+        }
+    }
+
+    fn compile(
+        &self,
+        tex_file: &Path,
+        working_dir: &Path,
+        args: &[String],
+        env: &BTreeMap<String, String>,
+    ) -> Result<(), LatexError> {
+        let mut compiler = LatexCompiler::new(&self.command)?;
+        for arg in args {
+            compiler = compiler.add_arg(arg);
+        }
+        for (key, value) in env {
+            compiler = compiler.add_env(key, value);
+        }
+        compiler.working_dir = working_dir.to_owned();
+
+        compiler
+            .run(
+                &tex_file.to_string_lossy(),
+                &LatexInput::new(),
+                LatexRunOptions::new(),
+            )
+            .map(|_| ())
+    }
+}
+
+/// Whether `command` is latexmk, which drives the engine instead of being one.
+pub fn is_latexmk(command: &str) -> bool {
+    Path::new(command)
+        .file_name()
+        .map_or(false, |name| name == "latexmk")
+}
+
+/// Compiler that runs latexmk for the frames, which runs the engine `engine` as often as a frame
+/// needs (e.g. for TikZ pictures with `remember picture` or for longtable). The preamble is
+/// precompiled with the engine itself.
+pub struct LatexmkCompiler {
+    pub command: String,
+    pub engine: TexCompiler,
+}
+
+impl LatexmkCompiler {
+    pub fn new(command: &str, engine: &str) -> LatexmkCompiler {
+        LatexmkCompiler {
+            command: command.to_string(),
+            engine: TexCompiler::new(engine),
+        }
+    }
+}
+
+/// Arguments of latexmk for the LaTeX arguments `args` of a frame compiled with `engine`.
+pub fn latexmk_args(engine: &str, args: &[String]) -> Vec<String> {
+    let (mode, command_option) = match Engine::of(engine) {
+        Engine::XeTex => ("-xelatex", "-xelatex"),
+        Engine::LuaTex => ("-lualatex", "-lualatex"),
+        Engine::PdfTex | Engine::Tectonic => ("-pdf", "-pdflatex"),
+    };
+    // The command of the engine, e.g. pdflatex-dev instead of pdflatex
+    let mut latexmk_args = vec![
+        mode.to_string(),
+        format!("{}={} %O %S", command_option, engine),
+    ];
+    latexmk_args.extend(args.iter().cloned());
+    latexmk_args
+}
+
+impl Compiler for LatexmkCompiler {
+    fn precompile_format(
+        &self,
+        preamble_file: &Path,
+        jobname: &str,
+        working_dir: &Path,
+        args: &[String],
+        env: &BTreeMap<String, String>,
+    ) -> Result<(), LatexError> {
+        self.engine
+            .precompile_format(preamble_file, jobname, working_dir, args, env)
+    }
+
+    fn compile(
+        &self,
+        tex_file: &Path,
+        working_dir: &Path,
+        args: &[String],
+        env: &BTreeMap<String, String>,
+    ) -> Result<(), LatexError> {
+        let args = latexmk_args(&self.engine.command, args);
+        TexCompiler::new(&self.command).compile(tex_file, working_dir, &args, env)
+    }
+}
+
+/// Compiler that compiles a document again (up to `max_reruns` times) while its log asks for it,
+/// so that references within a frame converge.
+pub struct RerunningCompiler {
+    pub compiler: Box<dyn Compiler>,
+    pub max_reruns: usize,
+}
+
+impl Compiler for RerunningCompiler {
+    fn precompile_format(
+        &self,
+        preamble_file: &Path,
+        jobname: &str,
+        working_dir: &Path,
+        args: &[String],
+        env: &BTreeMap<String, String>,
+    ) -> Result<(), LatexError> {
+        self.compiler
+            .precompile_format(preamble_file, jobname, working_dir, args, env)
+    }
+
+    fn compile(
+        &self,
+        tex_file: &Path,
+        working_dir: &Path,
+        args: &[String],
+        env: &BTreeMap<String, String>,
+    ) -> Result<(), LatexError> {
+        let references = fs::read_to_string(tex_file)
+            .map(|tex| !referenced_labels(&tex).is_empty())
+            .unwrap_or(true);
+        let log = working_dir.join(tex_file.with_extension("log").file_name().unwrap());
+        let mut reruns = 0;
+        loop {
+            self.compiler.compile(tex_file, working_dir, args, env)?;
+            let rerun = fs::read_to_string(&log)
+                .map(|log| asks_for_rerun(&log, references))
+                .unwrap_or(false);
+            if !rerun || reruns == self.max_reruns {
+                return Ok(());
+            }
+            reruns += 1;
+            debug!(
+                "Compiling {} again, as its log asks for it",
+                tex_file.display()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod fake {
+    use super::*;
+    use crate::test_pdf::pdf_with_pages;
+    use lopdf::{Dictionary, Document, Object, Stream};
+    use std::fs;
+    use std::sync::Mutex;
+
+    /// Compiler for tests. Every document becomes a PDF with one page per `\pause` (plus one),
+    /// whose content is the document. Documents containing `\fakeerror` fail.
+    #[derive(Default)]
+    pub struct FakeCompiler {
+        /// Names of the compiled documents and formats
+        pub compiled: Mutex<Vec<String>>,
+    }
+
+    impl FakeCompiler {
+        pub fn num_compiled(&self) -> usize {
+            self.compiled.lock().unwrap().len()
+        }
+
+        pub fn clear(&self) {
+            self.compiled.lock().unwrap().clear();
+        }
+    }
+
+    fn fake_pdf(tex: &str) -> Document {
+        pdf_with_pages(tex.matches("\\pause").count() + 1, |doc, _, _, page| {
+            let content = doc.add_object(Stream::new(Dictionary::new(), tex.into()));
+            page.set("Contents", Object::Reference(content));
+        })
+    }
+
+    impl Compiler for FakeCompiler {
+        fn precompile_format(
+            &self,
+            preamble_file: &Path,
+            jobname: &str,
+            working_dir: &Path,
+            _args: &[String],
+            _env: &BTreeMap<String, String>,
+        ) -> Result<(), LatexError> {
+            let preamble = fs::read(preamble_file).map_err(LatexError::Io)?;
+            fs::write(working_dir.join(format!("{}.fmt", jobname)), preamble)
+                .map_err(LatexError::Io)?;
+            self.compiled
+                .lock()
+                .unwrap()
+                .push(format!("{}.fmt", jobname));
+            Ok(())
+        }
+
+        fn compile(
+            &self,
+            tex_file: &Path,
+            working_dir: &Path,
+            _args: &[String],
+            _env: &BTreeMap<String, String>,
+        ) -> Result<(), LatexError> {
+            let tex = fs::read_to_string(tex_file).map_err(LatexError::Io)?;
+            let stem = tex_file.file_stem().unwrap().to_string_lossy().into_owned();
+            self.compiled.lock().unwrap().push(stem.clone());
+
+            let log = working_dir.join(format!("{}.log", stem));
+            if tex.contains("\\fakeerror") {
+                fs::write(&log, "! Undefined control sequence.\n").map_err(LatexError::Io)?;
+                return Err(LatexError::LatexError(format!("{} failed", stem)));
+            }
+            let pdf = working_dir.join(format!("{}.pdf", stem));
+            let mut doc = fake_pdf(&tex);
+            doc.save(&pdf).map_err(LatexError::Io)?;
+            fs::write(
+                &log,
+                format!(
+                    "Output written on {}.pdf ({} pages, {} bytes).\n",
+                    stem,
+                    doc.get_pages().len(),
+                    fs::metadata(&pdf).map_err(LatexError::Io)?.len()
+                ),
+            )
+            .map_err(LatexError::Io)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tectonic_args() {
+        assert_eq!(Engine::of("/usr/local/bin/tectonic"), Engine::Tectonic);
+        assert_eq!(Engine::of("lualatex-dev"), Engine::LuaTex);
+        assert_eq!(Engine::of("pdflatex"), Engine::PdfTex);
+
+        let args: Vec<String> = vec![
+            "-shell-escape",
+            "-interaction=nonstopmode",
+            "-synctex=1",
+            "--print",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect();
+        assert_eq!(
+            latexmk_args("xelatex", &args[..1]),
+            vec!["-xelatex", "-xelatex=xelatex %O %S", "-shell-escape"]
+        );
+        assert_eq!(
+            latexmk_args("pdflatex-dev", &[])[1],
+            "-pdflatex=pdflatex-dev %O %S"
+        );
+        assert_eq!(
+            tectonic_args(&args, Path::new("/cache")),
+            vec![
+                "--keep-logs",
+                "--keep-intermediates",
+                "--outdir",
+                "/cache",
+                "-Z",
+                "shell-escape",
+                "--synctex",
+                "--print"
+            ]
+        );
+    }
+}
@@ -0,0 +1,536 @@
+//
+// config.rs
+// Distributed under terms of the GPLv3 license.
+//
+// Settings that are kept with a project in a `faster-beamer.toml`, which is searched for in the
+// directory of the input file and its parents, e.g. the environment variables of the compiler
+// processes for a site-specific TeX setup:
+//
+//     compiler = "lualatex"
+//     cache_dir = ".cache"
+//     latex_args = ["-file-line-error"]
+//
+//     [defaults]
+//     unite = true
+//     aspectratio = "169"
+//
+//     [watch]
+//     ignore = ["*.bak", "figures/generated/*"]
+//
+//     [env]
+//     TEXINPUTS = "./styles//:"
+//
+//     [dependencies]
+//     exclude = ["build-date.tex"]
+//     include = ["data/**/*.csv"]
+//
+//     [profile.print]
+//     unite_template = '\includepdf[pages=-,fitpaper,{destination_option}]{{pdf}}'
+//
+//     [profile.print.env]
+//     TZ = "UTC"
+//
+// Settings of the selected profile (`--profile`) override the others. Paths are relative to the
+// directory of the configuration file. A global `faster-beamer.toml` in the configuration
+// directory of the user (e.g. `~/.config/faster-beamer/`) applies to all projects; settings of
+// the project override it, and options on the command line override both.
+//
+// A project may come from anyone (e.g. a cloned repository), so its configuration can only set
+// the options that change how the presentation is built (`PROJECT_OPTIONS`) and the variables of
+// the environment that tell TeX where to find files or how to write its output
+// (`PROJECT_VARIABLES`), and it cannot enable the shell escape of TeX. The compiler, the cache
+// directory, `--shell-escape`, the options that run programs or use other machines (e.g.
+// `--remote`, `--container` or `--remote-cache`) and the other variables of the environment (e.g.
+// `PATH`, `LD_PRELOAD` or `TEXMFCNF`, which choose the programs that run, the libraries they load
+// or the configuration of TeX) are only taken from the command line and the global configuration.
+//
+
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::Value;
+
+pub const CONFIG_FILE: &str = "faster-beamer.toml";
+
+/// Options that the `[defaults]` of a project can set. The others choose the programs that are
+/// run, where files are written or which machines take part in the build.
+pub const PROJECT_OPTIONS: &[&str] = &[
+    "aspectratio",
+    "beamer-mode",
+    "bibliography",
+    "conditional",
+    "debounce",
+    "find-root",
+    "frame-numbers",
+    "halt-on-error",
+    "handout",
+    "interaction",
+    "keep-intermediates",
+    "latex-arg",
+    "max-reruns",
+    "navigation",
+    "notes",
+    "only-frames",
+    "optimize-output",
+    "overlays",
+    "pdfpc",
+    "pdfunite",
+    "preamble",
+    "preamble-extra",
+    "strip-decorations",
+    "synctex",
+    "tagged",
+    "theme",
+    "tree-sitter",
+    "unite",
+    "variants",
+    "watch-ignore",
+    "watermark",
+];
+
+/// Variables of the environment (and of `-cnf-line`) that the configuration of a project can set:
+/// search paths of the files of a presentation, the line lengths of the log and the date of the
+/// build.
+pub const PROJECT_VARIABLES: &[&str] = &[
+    "BIBINPUTS",
+    "BSTINPUTS",
+    "ENCFONTS",
+    "FORCE_SOURCE_DATE",
+    "LUAINPUTS",
+    "OPENTYPEFONTS",
+    "SOURCE_DATE_EPOCH",
+    "T1FONTS",
+    "TEXINPUTS",
+    "TFMFONTS",
+    "TTFONTS",
+    "TZ",
+    "VFFONTS",
+    "error_line",
+    "half_error_line",
+    "max_print_line",
+];
+
+/// Whether a project cannot pass the compiler argument `arg`: it enables the shell escape (e.g.
+/// `-shell-escape`) or sets another variable than those of `PROJECT_VARIABLES` (e.g.
+/// `-cnf-line=shell_escape=t`).
+fn is_privileged_arg(arg: &str) -> bool {
+    let name = arg.trim_start_matches('-');
+    name.starts_with("shell-escape")
+        || name.starts_with("enable-write18")
+        || name.strip_prefix("cnf-line=").map_or(false, |line| {
+            !PROJECT_VARIABLES.contains(&line.split('=').next().unwrap_or_default().trim())
+        })
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    /// Environment variables of the compiler processes
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Code that replaces every frame in the united document (see `--unite`)
+    pub unite_template: Option<String>,
+    /// Default values of command line options
+    #[serde(default)]
+    pub defaults: BTreeMap<String, Value>,
+}
+
+/// Settings of `--watch`.
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Watch {
+    /// Glob patterns of files whose changes do not trigger a build
+    #[serde(default)]
+    pub ignore: Vec<String>,
+}
+
+/// Files whose content is hashed (in addition to those loaded by the preamble), so that the cache
+/// is invalidated when they change.
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Dependencies {
+    /// Glob patterns of files that are hashed although the preamble does not load them
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// Glob patterns of files loaded by the preamble that are not hashed
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// LaTeX compiler (see `--compiler`)
+    pub compiler: Option<String>,
+    /// Directory of the cache (see `--cache-dir`)
+    pub cache_dir: Option<PathBuf>,
+    /// Additional arguments of the compiler (see `--latex-arg`)
+    #[serde(default)]
+    pub latex_args: Vec<String>,
+    /// Default values of command line options, e.g. `unite = true` or `aspectratio = "169"`
+    #[serde(default)]
+    pub defaults: BTreeMap<String, Value>,
+    /// Environment variables of the compiler processes
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Code that replaces every frame in the united document (see `--unite`)
+    pub unite_template: Option<String>,
+    #[serde(default)]
+    pub dependencies: Dependencies,
+    #[serde(default)]
+    pub watch: Watch,
+    #[serde(default)]
+    pub profile: BTreeMap<String, Profile>,
+    /// Directory of the configuration file
+    #[serde(skip)]
+    pub directory: Option<PathBuf>,
+}
+
+impl Config {
+    pub fn parse(content: &str) -> Result<Config, toml::de::Error> {
+        toml::from_str(content)
+    }
+
+    /// Reads the configuration of `input_file`: the global configuration overridden by the
+    /// configuration of the project (each of them may be missing).
+    pub fn find(input_file: &str) -> Config {
+        let global = match global_config_file() {
+            Some(config_file) if config_file.is_file() => Config::load(&config_file),
+            _ => Config::default(),
+        };
+        match find_config_file(input_file) {
+            Some(config_file) => global.merge(Config::load(&config_file).unprivileged()),
+            None => global,
+        }
+    }
+
+    /// The configuration without the settings that a project cannot make (see above).
+    fn unprivileged(mut self) -> Config {
+        let file = self
+            .directory
+            .as_ref()
+            .map(|dir| dir.join(CONFIG_FILE).display().to_string())
+            .unwrap_or_else(|| CONFIG_FILE.to_string());
+        if let Some(compiler) = self.compiler.take() {
+            warn!(
+                "Ignoring compiler = \"{}\" in {}: use --compiler or the global configuration",
+                compiler, file
+            );
+        }
+        if let Some(cache_dir) = self.cache_dir.take() {
+            warn!(
+                "Ignoring cache_dir = \"{}\" in {}: use --cache-dir or the global configuration",
+                cache_dir.display(),
+                file
+            );
+        }
+        let ignore_arg = |arg: &str| {
+            let privileged = is_privileged_arg(arg);
+            if privileged {
+                warn!(
+                    "Ignoring the compiler argument {} in {}: use --latex-arg, --shell-escape or \
+                     the global configuration",
+                    arg, file
+                );
+            }
+            privileged
+        };
+        let ignore_options = |defaults: &mut BTreeMap<String, Value>| {
+            defaults.retain(|option, _| {
+                let allowed = PROJECT_OPTIONS.contains(&option.as_str());
+                if !allowed {
+                    warn!(
+                        "Ignoring {} in {}: use --{} or the global configuration",
+                        option, file, option
+                    );
+                }
+                allowed
+            });
+            if let Some(Value::Array(args)) = defaults.get_mut("latex-arg") {
+                args.retain(|arg| !arg.as_str().map_or(false, |arg| ignore_arg(arg)));
+            }
+            if let Some(Value::String(arg)) = defaults.get("latex-arg") {
+                if ignore_arg(arg) {
+                    defaults.remove("latex-arg");
+                }
+            }
+        };
+        let ignore_variables = |env: &mut BTreeMap<String, String>| {
+            env.retain(|key, _| {
+                let allowed = PROJECT_VARIABLES.contains(&key.as_str());
+                if !allowed {
+                    warn!(
+                        "Ignoring the variable {} in {}: set it in the global configuration",
+                        key, file
+                    );
+                }
+                allowed
+            });
+        };
+        ignore_options(&mut self.defaults);
+        ignore_variables(&mut self.env);
+        // A profile is selected on the command line, but its settings come from the project
+        for profile in self.profile.values_mut() {
+            ignore_options(&mut profile.defaults);
+            ignore_variables(&mut profile.env);
+        }
+        self.latex_args.retain(|arg| !ignore_arg(arg));
+        self
+    }
+
+    /// Reads `config_file` (or returns the default configuration if it is invalid).
+    fn load(config_file: &Path) -> Config {
+        let parsed = fs::read_to_string(config_file)
+            .map_err(|err| err.to_string())
+            .and_then(|content| Config::parse(&content).map_err(|err| err.to_string()));
+        match parsed {
+            Ok(config) => {
+                debug!("Using configuration {}", config_file.display());
+                let directory = config_file.parent().map(|dir| dir.to_owned());
+                Config {
+                    cache_dir: match (&directory, config.cache_dir) {
+                        (Some(directory), Some(cache_dir)) => Some(directory.join(cache_dir)),
+                        (_, cache_dir) => cache_dir,
+                    },
+                    directory,
+                    ..config
+                }
+            }
+            Err(err) => {
+                warn!("Ignoring {}: {}", config_file.display(), err);
+                Config::default()
+            }
+        }
+    }
+
+    /// The settings of `self` overridden by those of `other`. Lists of both are joined.
+    pub fn merge(mut self, other: Config) -> Config {
+        self.latex_args.extend(other.latex_args);
+        self.defaults.extend(other.defaults);
+        self.env.extend(other.env);
+        self.dependencies.include.extend(other.dependencies.include);
+        self.dependencies.exclude.extend(other.dependencies.exclude);
+        self.watch.ignore.extend(other.watch.ignore);
+        self.profile.extend(other.profile);
+        Config {
+            compiler: other.compiler.or(self.compiler),
+            cache_dir: other.cache_dir.or(self.cache_dir),
+            unite_template: other.unite_template.or(self.unite_template),
+            directory: other.directory.or(self.directory),
+            ..self
+        }
+    }
+
+    /// Settings of `profile` (if it is configured).
+    fn get_profile(&self, profile: Option<&str>) -> Option<&Profile> {
+        let name = profile?;
+        let profile = self.profile.get(name);
+        if profile.is_none() && !self.profile.is_empty() {
+            warn!("Unknown profile {}", name);
+        }
+        profile
+    }
+
+    /// Environment variables of the compiler processes with the overrides of `profile`.
+    pub fn environment(&self, profile: Option<&str>) -> BTreeMap<String, String> {
+        let mut env = self.env.clone();
+        if let Some(profile) = self.get_profile(profile) {
+            env.extend(profile.env.clone());
+        }
+        env
+    }
+
+    /// Default values of the command line options (by their long name) with the overrides of
+    /// `profile`.
+    pub fn option_defaults(&self, profile: Option<&str>) -> BTreeMap<String, Value> {
+        let mut defaults = BTreeMap::new();
+        if let Some(compiler) = &self.compiler {
+            defaults.insert("compiler".to_string(), Value::from(compiler.as_str()));
+        }
+        if let Some(cache_dir) = &self.cache_dir {
+            defaults.insert(
+                "cache-dir".to_string(),
+                Value::from(cache_dir.to_string_lossy().into_owned()),
+            );
+        }
+        if !self.latex_args.is_empty() {
+            defaults.insert(
+                "latex-arg".to_string(),
+                Value::from(self.latex_args.clone()),
+            );
+        }
+        if !self.watch.ignore.is_empty() {
+            defaults.insert(
+                "watch-ignore".to_string(),
+                Value::from(self.watch.ignore.clone()),
+            );
+        }
+        defaults.extend(self.defaults.clone());
+        if let Some(profile) = self.get_profile(profile) {
+            defaults.extend(profile.defaults.clone());
+        }
+        defaults
+    }
+
+    /// Template of the code that replaces every frame in the united document.
+    pub fn unite_template(&self, profile: Option<&str>) -> Option<&str> {
+        self.get_profile(profile)
+            .and_then(|p| p.unite_template.as_deref())
+            .or_else(|| self.unite_template.as_deref())
+    }
+}
+
+/// The global `faster-beamer.toml` in the configuration directory of the user.
+fn global_config_file() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("faster-beamer").join(CONFIG_FILE))
+}
+
+/// The closest `faster-beamer.toml` in the directory of `input_file` or one of its parents.
+fn find_config_file(input_file: &str) -> Option<PathBuf> {
+    let path = Path::new(input_file).canonicalize().ok()?;
+    let start = if path.is_dir() { &path } else { path.parent()? };
+    start
+        .ancestors()
+        .map(|dir| dir.join(CONFIG_FILE))
+        .find(|file| file.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_environment() {
+        let config = Config::parse(
+            "[env]\nTEXINPUTS = \"./styles//:\"\nTZ = \"Europe/Berlin\"\n\n\
+             [profile.print.env]\nTZ = \"UTC\"\n",
+        )
+        .unwrap();
+        let env = config.environment(Some("print"));
+        assert_eq!(env["TEXINPUTS"], "./styles//:");
+        assert_eq!(env["TZ"], "UTC");
+        assert_eq!(config.environment(None)["TZ"], "Europe/Berlin");
+        assert!(Config::parse("[envv]\n").is_err());
+        let config = Config::parse("[dependencies]\nexclude = [\"date.tex\"]\n").unwrap();
+        assert_eq!(config.dependencies.exclude, vec!["date.tex"]);
+
+        let config = Config::parse(
+            "unite_template = \"\\\\includepdf{{pdf}}\"\n\
+             [profile.print]\nunite_template = \"\\\\includepdf[fitpaper]{{pdf}}\"\n",
+        )
+        .unwrap();
+        assert_eq!(config.unite_template(None), Some("\\includepdf{{pdf}}"));
+        assert_eq!(
+            config.unite_template(Some("print")),
+            Some("\\includepdf[fitpaper]{{pdf}}")
+        );
+    }
+
+    #[test]
+    fn test_find_config() {
+        let dir = tempdir().unwrap();
+        fs::create_dir(dir.path().join("talks")).unwrap();
+        fs::write(dir.path().join("talks/talk.tex"), "").unwrap();
+        fs::write(
+            dir.path().join(CONFIG_FILE),
+            "[env]\nmax_print_line = \"1000\"\n",
+        )
+        .unwrap();
+
+        let config = Config::find(dir.path().join("talks/talk.tex").to_str().unwrap());
+        assert_eq!(config.env["max_print_line"], "1000");
+        assert_eq!(config.directory, Some(dir.path().canonicalize().unwrap()));
+    }
+
+    #[test]
+    fn test_merge_config() {
+        let global = Config::parse(
+            "compiler = \"lualatex\"\nlatex_args = [\"-file-line-error\"]\n\
+             [defaults]\nunite = true\nstrip-decorations = true\n",
+        )
+        .unwrap();
+        let project = Config::parse(
+            "cache_dir = \".cache\"\nlatex_args = [\"-8bit\"]\n\
+             [defaults]\nstrip-decorations = false\n\
+             [watch]\nignore = [\"*.bak\"]\n\
+             [profile.print.defaults]\nunite = false\n",
+        )
+        .unwrap();
+
+        let config = global.merge(project);
+        assert_eq!(config.compiler.as_deref(), Some("lualatex"));
+        assert_eq!(config.latex_args, vec!["-file-line-error", "-8bit"]);
+        let defaults = config.option_defaults(None);
+        assert_eq!(defaults["unite"], Value::Boolean(true));
+        assert_eq!(defaults["strip-decorations"], Value::Boolean(false));
+        assert_eq!(defaults["cache-dir"], Value::from(".cache"));
+        assert_eq!(defaults["watch-ignore"], Value::from(vec!["*.bak"]));
+        assert_eq!(
+            config.option_defaults(Some("print"))["unite"],
+            Value::Boolean(false)
+        );
+    }
+
+    #[test]
+    fn test_project_variables() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("talk.tex"), "").unwrap();
+        fs::write(
+            dir.path().join(CONFIG_FILE),
+            "latex_args = [\"-cnf-line=TEXMFHOME=./texmf\", \"-cnf-line=max_print_line=1000\"]\n\
+             [env]\nPATH = \"./bin\"\nTEXMFCNF = \"./texmf\"\nLD_PRELOAD = \"./evil.so\"\n\
+             DYLD_INSERT_LIBRARIES = \"./evil.dylib\"\nTEXINPUTS = \"./styles//:\"\n",
+        )
+        .unwrap();
+
+        let config = Config::find(dir.path().join("talk.tex").to_str().unwrap());
+        let env = config.environment(None);
+        assert_eq!(env.keys().collect::<Vec<_>>(), vec!["TEXINPUTS"]);
+        assert_eq!(config.latex_args, vec!["-cnf-line=max_print_line=1000"]);
+    }
+
+    #[test]
+    fn test_profile_variables() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("talk.tex"), "").unwrap();
+        fs::write(
+            dir.path().join(CONFIG_FILE),
+            "[profile.x.env]\nPATH = \"./evil\"\nLD_LIBRARY_PATH = \"./lib\"\n\
+             TEXMFHOME = \"./texmf\"\nTZ = \"UTC\"\n",
+        )
+        .unwrap();
+
+        let config = Config::find(dir.path().join("talk.tex").to_str().unwrap());
+        let env = config.environment(Some("x"));
+        assert_eq!(env.keys().collect::<Vec<_>>(), vec!["TZ"]);
+    }
+
+    #[test]
+    fn test_unprivileged_config() {
+        let project = Config::parse(
+            "compiler = \"/tmp/evil\"\ncache_dir = \"/home/me\"\n\
+             latex_args = [\"-8bit\", \"-shell-escape\", \"--enable-write18\", \"-cnf-line=openout_any=a\"]\n\
+             [defaults]\nshell-escape = true\nunite = true\nlatex-arg = [\"-shell-escape\"]\n\
+             remote = [\"attacker\"]\ncontainer = \"evil/image\"\nremote-cache = \"https://evil\"\n\
+             cache-max-size = \"1\"\n\
+             [env]\nshell_escape = \"t\"\nopenout_any = \"a\"\nTZ = \"UTC\"\n\
+             [profile.print.env]\nshell_escape_commands = \"sh\"\n",
+        )
+        .unwrap()
+        .unprivileged();
+        assert_eq!(project.compiler, None);
+        assert_eq!(project.cache_dir, None);
+        assert_eq!(project.latex_args, vec!["-8bit"]);
+        let defaults = project.option_defaults(Some("print"));
+        assert_eq!(
+            defaults.keys().collect::<Vec<_>>(),
+            vec!["latex-arg", "unite"]
+        );
+        assert_eq!(defaults["unite"], Value::Boolean(true));
+        assert_eq!(defaults["latex-arg"], Value::Array(vec![]));
+        let env = project.environment(Some("print"));
+        assert_eq!(env.keys().collect::<Vec<_>>(), vec!["TZ"]);
+    }
+}
@@ -0,0 +1,244 @@
+//
+// container.rs
+// Distributed under terms of the GPLv3 license.
+//
+// With `--container IMAGE`, the TeX programs (the compiler, biber, makeindex) run in a container of
+// IMAGE with docker or podman, so that the host needs no TeX installation and builds do not
+// depend on the TeX distribution of the machine. The cache directory, the current directory and
+// the directories that the links in the working directory of a run point to (the input directory
+// and the dependencies of the document, see `LatexInput`) are bind-mounted at the same paths, so
+// that the paths of the host are valid in the container as well.
+//
+
+use crate::preflight::is_runnable;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+
+lazy_static! {
+    static ref CONTAINER: Mutex<Option<Container>> = Mutex::new(None);
+}
+
+pub const RUNTIMES: &[&str] = &["auto", "docker", "podman"];
+
+/// Container in which the TeX programs run.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Container {
+    /// docker or podman
+    pub runtime: String,
+    pub image: String,
+    /// Directories that are mounted at the same path in the container
+    pub mounts: Vec<PathBuf>,
+    /// User and group (`uid:gid`) that docker runs the programs as, so that the files in the
+    /// cache are not owned by root. Podman keeps the user with `--userns=keep-id`.
+    pub user: Option<String>,
+}
+
+impl Container {
+    /// Container of `image` run with `runtime` (`auto` for podman if it is installed, otherwise
+    /// docker) that mounts `mounts`.
+    pub fn new(runtime: &str, image: &str, mounts: &[PathBuf]) -> Container {
+        let runtime = match runtime {
+            "auto" if is_runnable("podman", "--version") => "podman",
+            "auto" => "docker",
+            runtime => runtime,
+        };
+        let mounts = outermost_dirs(mounts);
+        let user = if runtime == "docker" {
+            mounts.first().and_then(|dir| owner(dir))
+        } else {
+            None
+        };
+        Container {
+            runtime: runtime.to_string(),
+            image: image.to_string(),
+            mounts,
+            user,
+        }
+    }
+
+    /// Arguments of the runtime that run `program` in `working_dir` with the environment
+    /// variables `env` (the arguments of `program` follow them).
+    pub fn run_args(
+        &self,
+        program: &str,
+        working_dir: &Path,
+        env: &BTreeMap<String, String>,
+    ) -> Vec<String> {
+        let mut args = vec!["run".to_string(), "--rm".to_string()];
+        match &self.user {
+            Some(user) => args.extend(vec!["--user".to_string(), user.clone()]),
+            None if self.runtime == "podman" => args.push("--userns=keep-id".to_string()),
+            None => {}
+        }
+        let mut mounts = self.mounts.clone();
+        link_targets(working_dir, &mut mounts);
+        for dir in outermost_dirs(&mounts) {
+            args.push("--volume".to_string());
+            args.push(format!("{0}:{0}", dir.display()));
+        }
+        let working_dir = working_dir
+            .canonicalize()
+            .unwrap_or_else(|_| working_dir.to_owned());
+        args.push("--workdir".to_string());
+        args.push(working_dir.to_string_lossy().into_owned());
+        for (key, value) in env {
+            args.push("--env".to_string());
+            args.push(format!("{}={}", key, value));
+        }
+        args.push(self.image.clone());
+        args.push(program.to_string());
+        args
+    }
+}
+
+/// Canonical paths of `dirs` without the ones within others (which are mounted with them).
+fn outermost_dirs(dirs: &[PathBuf]) -> Vec<PathBuf> {
+    let mut canonical_dirs: Vec<PathBuf> = dirs
+        .iter()
+        .map(|dir| dir.canonicalize().unwrap_or_else(|_| dir.to_owned()))
+        .collect();
+    canonical_dirs.sort();
+    canonical_dirs.dedup();
+    canonical_dirs
+        .iter()
+        .filter(|dir| {
+            !canonical_dirs
+                .iter()
+                .any(|other| other != *dir && dir.starts_with(other))
+        })
+        .cloned()
+        .collect()
+}
+
+/// Directories that the links in `dir` (and its subdirectories) point to, or that contain the
+/// files they point to.
+pub fn link_targets(dir: &Path, targets: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+        let path = entry.path();
+        match entry.file_type() {
+            Ok(file_type) if file_type.is_symlink() => {
+                let target = match path.canonicalize() {
+                    Ok(target) if target.is_dir() => target,
+                    Ok(target) => match target.parent() {
+                        Some(parent) => parent.to_owned(),
+                        None => continue,
+                    },
+                    Err(_) => continue,
+                };
+                if !targets.contains(&target) {
+                    targets.push(target);
+                }
+            }
+            Ok(file_type) if file_type.is_dir() => link_targets(&path, targets),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(unix)]
+fn owner(dir: &Path) -> Option<String> {
+    use std::os::unix::fs::MetadataExt;
+    let metadata = dir.metadata().ok()?;
+    Some(format!("{}:{}", metadata.uid(), metadata.gid()))
+}
+
+#[cfg(not(unix))]
+fn owner(_dir: &Path) -> Option<String> {
+    None
+}
+
+/// Runs the TeX programs in `container` (None to run them on the host).
+pub fn set_container(container: Option<Container>) {
+    *CONTAINER.lock().unwrap() = container;
+}
+
+/// Container of `--container`, if any.
+pub fn container() -> Option<Container> {
+    CONTAINER.lock().unwrap().clone()
+}
+
+/// Command that runs the TeX program `program` in `working_dir` with the environment variables
+/// `env`: in the container of `--container`, or on the host.
+pub fn command(program: &str, working_dir: &Path, env: &BTreeMap<String, String>) -> Command {
+    match container() {
+        Some(container) => {
+            let mut command = Command::new(&container.runtime);
+            command.args(container.run_args(program, working_dir, env));
+            command
+        }
+        None => {
+            let mut command = Command::new(program);
+            command.current_dir(working_dir).envs(env);
+            command
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_args() {
+        let container = Container {
+            runtime: "podman".to_string(),
+            image: "texlive/texlive".to_string(),
+            mounts: vec![PathBuf::from("/cache"), PathBuf::from("/talks")],
+            user: None,
+        };
+        let mut env = BTreeMap::new();
+        env.insert("TEXINPUTS".to_string(), "./styles//:".to_string());
+        assert_eq!(
+            container.run_args("pdflatex", Path::new("/cache/talks"), &env),
+            vec![
+                "run",
+                "--rm",
+                "--userns=keep-id",
+                "--volume",
+                "/cache:/cache",
+                "--volume",
+                "/talks:/talks",
+                "--workdir",
+                "/cache/talks",
+                "--env",
+                "TEXINPUTS=./styles//:",
+                "texlive/texlive",
+                "pdflatex",
+            ]
+        );
+
+        let container = Container::new(
+            "docker",
+            "texlive/texlive",
+            &[PathBuf::from("/cache"), PathBuf::from("/cache/talks")],
+        );
+        assert_eq!(container.mounts, vec![PathBuf::from("/cache")]);
+    }
+
+    #[test]
+    fn test_link_targets() {
+        let cache = tempfile::tempdir().unwrap();
+        let input = tempfile::tempdir().unwrap();
+        let styles = tempfile::tempdir().unwrap();
+        fs::write(input.path().join("figure.pdf"), "").unwrap();
+        fs::create_dir_all(cache.path().join("deps")).unwrap();
+        symlink::symlink_file(
+            input.path().join("figure.pdf"),
+            cache.path().join("figure.pdf"),
+        )
+        .unwrap();
+        symlink::symlink_dir(styles.path(), cache.path().join("deps").join("styles")).unwrap();
+        let mut targets = Vec::new();
+        link_targets(cache.path(), &mut targets);
+        targets.sort();
+        let mut expected = vec![
+            input.path().canonicalize().unwrap(),
+            styles.path().canonicalize().unwrap(),
+        ];
+        expected.sort();
+        assert_eq!(targets, expected);
+    }
+}
@@ -0,0 +1,247 @@
+//
+// daemon.rs
+// Distributed under terms of the GPLv3 license.
+//
+// `faster-beamer daemon` stays resident and builds documents on request, so that an editor does
+// not pay for starting a process and parsing the whole document on every save: the parsed
+// documents and the frames of the previous builds stay in memory. Every connection to the Unix
+// socket sends one command on one line and receives the answer, whose first line starts with
+// `ok` or `error`:
+//
+//     build [OPTIONS] FILE      builds FILE like `faster-beamer build`
+//     frame-at FILE:LINE        index of the frame at LINE of FILE
+//     status                    documents built so far and the outcome of their last build
+//     shutdown                  stops the daemon
+//
+
+use crate::batch::{build_jobs, process_jobs};
+use crate::cli::subcommand_options;
+use crate::preflight;
+use crate::process_file::{frame_at_line, get_cachedir, load_frames, FasterBeamerError, Result};
+use crate::status::parse_position;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+
+/// Name of the socket in the cache directory, unless `--socket` is given.
+pub const SOCKET_NAME: &str = "daemon.sock";
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    /// Arguments of `faster-beamer build`
+    Build(Vec<String>),
+    FrameAt {
+        input_file: String,
+        line: usize,
+    },
+    Status,
+    Shutdown,
+}
+
+/// Parses a command line sent to the daemon.
+pub fn parse_command(line: &str) -> Result<Command> {
+    let mut words = line.split_whitespace();
+    let command = match words.next() {
+        Some("build") => Command::Build(words.map(|word| word.to_string()).collect()),
+        Some("frame-at") => {
+            let position = words.next().unwrap_or_default();
+            match parse_position(position) {
+                Some((input_file, line)) => Command::FrameAt {
+                    input_file: input_file.to_string(),
+                    line,
+                },
+                None => {
+                    return Err(FasterBeamerError::InvalidOptions(format!(
+                        "expected FILE:LINE after frame-at, got {:?}",
+                        position
+                    )))
+                }
+            }
+        }
+        Some("status") => Command::Status,
+        Some("shutdown") => Command::Shutdown,
+        _ => {
+            return Err(FasterBeamerError::InvalidOptions(format!(
+                "unknown command {:?}",
+                line
+            )))
+        }
+    };
+    Ok(command)
+}
+
+/// State of the daemon between commands.
+#[derive(Default)]
+struct Daemon {
+    /// Outcome of the last build of every document
+    builds: BTreeMap<String, String>,
+}
+
+impl Daemon {
+    /// Runs `command` and returns the answer (without the leading `ok`).
+    fn execute(&mut self, command: Command) -> Result<String> {
+        match command {
+            Command::Build(argv) => {
+                let args = subcommand_options("build", &argv)?;
+                let jobs = build_jobs(&args);
+                preflight::check(&jobs, &args)?;
+                let mut first_error = None;
+                for (job, result) in jobs.iter().zip(process_jobs(&jobs, &args)) {
+                    let outcome = match result {
+                        Ok(()) => "succeeded".to_string(),
+                        Err(err) => {
+                            let outcome = format!("failed: {}", err);
+                            first_error = first_error.or(Some(err));
+                            outcome
+                        }
+                    };
+                    self.builds.insert(job.input_file.clone(), outcome);
+                }
+                match first_error {
+                    Some(err) => Err(err),
+                    None => Ok(String::new()),
+                }
+            }
+            Command::FrameAt { input_file, line } => {
+                let args = subcommand_options("frames", &[input_file.clone()])?;
+                let extracted = load_frames(&input_file, &args)?;
+                match frame_at_line(&extracted, line) {
+                    Some(frame_idx) => Ok(format!(" {}", frame_idx)),
+                    None => Err(FasterBeamerError::NotFound(format!(
+                        "No frame at {}:{}",
+                        input_file, line
+                    ))),
+                }
+            }
+            Command::Status => Ok(self
+                .builds
+                .iter()
+                .map(|(input_file, outcome)| format!("\n{}: {}", input_file, outcome))
+                .collect()),
+            Command::Shutdown => Ok(String::new()),
+        }
+    }
+}
+
+/// Answers the command of one connection. Returns whether the daemon should stop.
+fn handle_connection(daemon: &mut Daemon, stream: UnixStream) -> bool {
+    let mut line = String::new();
+    if let Err(err) = BufReader::new(&stream).read_line(&mut line) {
+        warn!("Failed to read a command: {}", err);
+        return false;
+    }
+    debug!("Received {:?}", line.trim());
+
+    let command = parse_command(line.trim());
+    let shutdown = matches!(command, Ok(Command::Shutdown));
+    let answer = match command.and_then(|command| daemon.execute(command)) {
+        Ok(answer) => format!("ok{}\n", answer),
+        Err(err) => format!("error {}\n", err),
+    };
+    if let Err(err) = (&stream).write_all(answer.as_bytes()) {
+        warn!("Failed to answer {:?}: {}", line.trim(), err);
+    }
+    shutdown
+}
+
+/// Answers commands on `socket` until a `shutdown` command arrives. Commands are run one after
+/// the other; a build runs its frames in parallel as usual.
+pub fn serve(socket: &Path) -> Result<()> {
+    if socket.exists() {
+        if UnixStream::connect(socket).is_ok() {
+            return Err(FasterBeamerError::InvalidOptions(format!(
+                "a daemon is already listening on {}",
+                socket.display()
+            )));
+        }
+        // Left over from a daemon that did not shut down
+        fs::remove_file(socket).map_err(FasterBeamerError::io(socket))?;
+    }
+    let listener = UnixListener::bind(socket).map_err(FasterBeamerError::io(socket))?;
+    info!("Listening on {}", socket.display());
+
+    let mut daemon = Daemon::default();
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                if handle_connection(&mut daemon, stream) {
+                    break;
+                }
+            }
+            Err(err) => warn!("Failed to accept a connection: {}", err),
+        }
+    }
+    fs::remove_file(socket).map_err(FasterBeamerError::io(socket))?;
+    info!("Shut down");
+    Ok(())
+}
+
+/// Socket of the daemon: `socket` (of `--socket`) or `daemon.sock` in the cache directory.
+pub fn socket_path(socket: Option<&str>) -> Result<PathBuf> {
+    match socket {
+        Some(socket) => Ok(PathBuf::from(socket)),
+        None => Ok(get_cachedir()?.join(SOCKET_NAME)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::thread;
+    use std::time::Duration;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(
+            parse_command("build --unite talk.tex").ok(),
+            Some(Command::Build(vec![
+                "--unite".to_string(),
+                "talk.tex".to_string()
+            ]))
+        );
+        assert_eq!(
+            parse_command("frame-at slides/talk.tex:42").ok(),
+            Some(Command::FrameAt {
+                input_file: "slides/talk.tex".to_string(),
+                line: 42
+            })
+        );
+        assert!(parse_command("frame-at talk.tex").is_err());
+        assert!(parse_command("restart").is_err());
+    }
+
+    #[test]
+    fn test_serve() {
+        let dir = tempdir().unwrap();
+        let socket = dir.path().join(SOCKET_NAME);
+        let talk = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/talk.tex");
+        let server = {
+            let socket = socket.clone();
+            thread::spawn(move || serve(&socket))
+        };
+
+        let send = |command: &str| {
+            let mut stream = loop {
+                match UnixStream::connect(&socket) {
+                    Ok(stream) => break stream,
+                    Err(_) => thread::sleep(Duration::from_millis(10)),
+                }
+            };
+            writeln!(stream, "{}", command).unwrap();
+            let mut answer = String::new();
+            stream.read_to_string(&mut answer).unwrap();
+            answer
+        };
+        assert_eq!(send("status"), "ok\n");
+        assert_eq!(send(&format!("frame-at {}:11", talk.display())), "ok 1\n");
+        assert!(send(&format!("frame-at {}:1", talk.display())).starts_with("error "));
+        assert!(send("restart").starts_with("error Invalid options: unknown command"));
+        assert_eq!(send("shutdown"), "ok\n");
+        assert!(server.join().unwrap().is_ok());
+        assert!(!socket.exists());
+    }
+}
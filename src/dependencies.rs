@@ -0,0 +1,160 @@
+//
+// dependencies.rs
+// Distributed under terms of the GPLv3 license.
+//
+// A frame depends on more than its LaTeX code: on the files it includes, on its images and
+// listings, and (if it cites anything) on the bibliography. These files are hashed with the frame,
+// so that only the frames that read a changed file are compiled again, and watch mode maps every
+// file to the frames that read it, so that changes of any of them start a build.
+//
+
+use crate::graphics::graphics_files;
+use crate::includes::{frame_dependencies, is_commented};
+use crate::preamble::local_dependencies;
+use crate::process_file::{document_preamble, ExtractedFrames};
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+lazy_static! {
+    static ref BIBLIOGRAPHY_REGEX: Regex =
+        Regex::new(r"\\(addbibresource|bibliography)\s*(?:\[[^\]]*\]\s*)?\{([^}]*)\}").unwrap();
+}
+lazy_static! {
+    static ref CITE_REGEX: Regex =
+        Regex::new(r"\\(?:[a-zA-Z]*cite[a-zA-Z]*\*?|printbibliography|bibliography)\b").unwrap();
+}
+
+/// Local bibliography files (relative to `input_dir`) of `content`, from `\addbibresource{a.bib}`
+/// or `\bibliography{a,b}`.
+pub fn bibliography_files(content: &str, input_dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = BIBLIOGRAPHY_REGEX
+        .captures_iter(content)
+        .filter(|c| !is_commented(content, c.get(0).unwrap().start()))
+        .flat_map(|c| match &c[1] {
+            "addbibresource" => vec![c[2].trim().to_string()],
+            _ => c[2]
+                .split(',')
+                .map(|name| name.trim())
+                .filter(|name| !name.is_empty())
+                .map(|name| name.trim_end_matches(".bib").to_string() + ".bib")
+                .collect(),
+        })
+        .map(|name| input_dir.join(name))
+        .filter(|path| path.is_file())
+        .collect();
+    files.sort();
+    files.dedup();
+    files
+}
+
+/// Local files (relative to `input_dir`) that `frame` of a document with `preamble` reads.
+pub fn frame_files(frame: &str, preamble: &str, input_dir: &Path) -> Vec<PathBuf> {
+    let mut files = frame_dependencies(frame, input_dir);
+    files.extend(graphics_files(frame, preamble, input_dir));
+    if CITE_REGEX.is_match(frame) {
+        files.extend(bibliography_files(preamble, input_dir));
+        files.extend(bibliography_files(frame, input_dir));
+    }
+    files.sort();
+    files.dedup();
+    files
+}
+
+/// Local files (canonicalized) that the document of `extracted` in `input_dir` reads, with the
+/// frames that read them. Files of the preamble are read by all frames, included files by the
+/// frames in them (and by none if they contain no frames).
+pub fn dependency_map(
+    extracted: &ExtractedFrames,
+    input_dir: &Path,
+) -> BTreeMap<PathBuf, Vec<usize>> {
+    let mut map: BTreeMap<PathBuf, Vec<usize>> = BTreeMap::new();
+    let mut add = |path: &Path, frames: &mut dyn Iterator<Item = usize>| {
+        if let Ok(path) = path.canonicalize() {
+            map.entry(path).or_default().extend(frames);
+        }
+    };
+
+    let parsed_file = &extracted.parsed_file;
+    let content = &parsed_file.file_content;
+    let preamble = document_preamble(content);
+    let num_frames = extracted.frames.len();
+    let preamble_end = content.find("\\begin{document}").unwrap_or(0);
+    for file in parsed_file.sources.files_before(preamble_end) {
+        add(&file.path, &mut (0..num_frames));
+    }
+    for file in local_dependencies(&preamble, input_dir) {
+        add(&file, &mut (0..num_frames));
+    }
+    for file in parsed_file.sources.files() {
+        add(&file.path, &mut std::iter::empty());
+    }
+    for (frame_idx, (frame, offset)) in extracted
+        .frames
+        .iter()
+        .zip(&extracted.frame_offsets)
+        .enumerate()
+    {
+        if let Some((file, _)) = parsed_file.sources.source(*offset) {
+            add(&file.path, &mut std::iter::once(frame_idx));
+        }
+        for file in frame_files(frame, &preamble, input_dir) {
+            add(&file, &mut std::iter::once(frame_idx));
+        }
+    }
+    for frames in map.values_mut() {
+        frames.sort_unstable();
+        frames.dedup();
+    }
+    map
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::Options;
+    use crate::process_file::load_frames;
+    use std::fs;
+
+    #[test]
+    fn test_dependency_map() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).canonicalize().unwrap();
+        fs::write(
+            dir.path().join("macros.tex"),
+            "\\newcommand{\\R}{\\mathbb{R}}",
+        )
+        .unwrap();
+        fs::write(dir.path().join("refs.bib"), "").unwrap();
+        fs::write(dir.path().join("plot.pdf"), "").unwrap();
+        fs::write(
+            dir.path().join("results.tex"),
+            "\\begin{frame}{Results}\\includegraphics{plot}\n\\end{frame}\n",
+        )
+        .unwrap();
+        let input_file = dir.path().join("talk.tex");
+        fs::write(
+            &input_file,
+            "\\documentclass{beamer}\n\
+             \\input{macros}\n\
+             \\addbibresource{refs.bib}\n\
+             \\begin{document}\n\
+             \\begin{frame}{Intro}As shown by \\cite{knuth}\n\\end{frame}\n\
+             \\input{results}\n\
+             \\end{document}\n",
+        )
+        .unwrap();
+
+        let args = Options::new();
+        let extracted = load_frames(input_file.to_str().unwrap(), &args)
+            .ok()
+            .unwrap();
+        let map = dependency_map(&extracted, dir.path());
+        let frames = |name: &str| map.get(&path(name)).cloned();
+        assert_eq!(map.len(), 4);
+        assert_eq!(frames("macros.tex"), Some(vec![0, 1]));
+        assert_eq!(frames("refs.bib"), Some(vec![0]));
+        assert_eq!(frames("results.tex"), Some(vec![1]));
+        assert_eq!(frames("plot.pdf"), Some(vec![1]));
+    }
+}
@@ -0,0 +1,177 @@
+//
+// error.rs
+// Distributed under terms of the GPLv3 license.
+//
+
+use crate::latexcompile::LatexError;
+use crate::log_filter::interesting_lines;
+use crate::logs::read_log;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Number of lines of a LaTeX log shown in an error.
+const MAX_EXCERPT_LINES: usize = 10;
+
+#[derive(Debug)]
+pub enum FasterBeamerError {
+    /// The input file (or frame directory) does not exist.
+    InputFileNotExistent(PathBuf),
+    /// Something else that was asked for does not exist (e.g. a frame or a previous build).
+    NotFound(String),
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    /// tree-sitter failed to parse the document.
+    Parse(PathBuf),
+    /// An external program (e.g. pandoc or pdftoppm) could not be run or failed.
+    Program {
+        program: String,
+        message: String,
+    },
+    PreambleCompile {
+        preamble: PathBuf,
+        log_excerpt: String,
+    },
+    FrameCompile {
+        frame_idx: usize,
+        log_excerpt: String,
+    },
+    /// Another document (e.g. the united document, an outline or a figure) failed to compile.
+    Compile {
+        file: PathBuf,
+        log_excerpt: String,
+    },
+    /// The frames could not be merged into `output`.
+    Merge {
+        output: PathBuf,
+        message: String,
+    },
+    /// Programs needed for the build are missing (one message per program).
+    MissingProgram(Vec<String>),
+    InvalidOptions(String),
+}
+
+pub type Result<T> = ::std::result::Result<T, FasterBeamerError>;
+
+impl FasterBeamerError {
+    /// Adapter for `map_err` of IO operations on `path`.
+    pub fn io<P: AsRef<Path>>(path: P) -> impl FnOnce(io::Error) -> FasterBeamerError {
+        let path = path.as_ref().to_owned();
+        move |source| FasterBeamerError::Io { path, source }
+    }
+
+    /// Adapter for `map_err` of `LatexCompiler` and `LatexInput` operations on `path` (which
+    /// only fail to read or write files).
+    pub fn latex_input<P: AsRef<Path>>(path: P) -> impl FnOnce(LatexError) -> FasterBeamerError {
+        let path = path.as_ref().to_owned();
+        move |err| match err {
+            LatexError::Io(source) | LatexError::Input(source) => {
+                FasterBeamerError::Io { path, source }
+            }
+            LatexError::LatexError(message) => FasterBeamerError::Io {
+                path,
+                source: io::Error::new(io::ErrorKind::Other, message),
+            },
+        }
+    }
+}
+
+/// The interesting lines of the LaTeX log `log` (at most `MAX_EXCERPT_LINES`), or `message` if
+/// there is no log.
+pub fn log_excerpt(log: &Path, message: &str) -> String {
+    match read_log(log) {
+        Some(log) => {
+            let lines = interesting_lines(&log);
+            lines[lines.len().saturating_sub(MAX_EXCERPT_LINES)..].join("\n")
+        }
+        None => message.to_string(),
+    }
+}
+
+impl fmt::Display for FasterBeamerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FasterBeamerError::InputFileNotExistent(path) => {
+                write!(f, "Could not open {}", path.display())
+            }
+            FasterBeamerError::NotFound(what) => write!(f, "{}", what),
+            FasterBeamerError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            FasterBeamerError::Parse(path) => write!(f, "Failed to parse {}", path.display()),
+            FasterBeamerError::Program { program, message } => {
+                write!(f, "{} failed: {}", program, message)
+            }
+            FasterBeamerError::PreambleCompile {
+                preamble,
+                log_excerpt,
+            } => write!(
+                f,
+                "Failed to compile the preamble {}\n{}",
+                preamble.display(),
+                log_excerpt
+            ),
+            FasterBeamerError::FrameCompile {
+                frame_idx,
+                log_excerpt,
+            } => write!(f, "Failed to compile frame {}\n{}", frame_idx, log_excerpt),
+            FasterBeamerError::Compile { file, log_excerpt } => {
+                write!(f, "Failed to compile {}\n{}", file.display(), log_excerpt)
+            }
+            FasterBeamerError::Merge { output, message } => {
+                write!(
+                    f,
+                    "Failed to merge frames into {}: {}",
+                    output.display(),
+                    message
+                )
+            }
+            FasterBeamerError::MissingProgram(messages) => write!(f, "{}", messages.join("\n")),
+            FasterBeamerError::InvalidOptions(message) => write!(f, "Invalid options: {}", message),
+        }
+    }
+}
+
+impl Error for FasterBeamerError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            FasterBeamerError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_error_context() {
+        let err = fs::read("/nonexistent/talk.tex")
+            .map_err(FasterBeamerError::io("/nonexistent/talk.tex"))
+            .unwrap_err();
+        assert!(err.to_string().starts_with("/nonexistent/talk.tex: "));
+        assert!(err.source().is_some());
+
+        let dir = tempdir().unwrap();
+        let log = dir.path().join("frame.log");
+        fs::write(
+            &log,
+            "This is pdfTeX\n! Undefined control sequence.\nl.12 \\foo\n",
+        )
+        .unwrap();
+        let err = FasterBeamerError::FrameCompile {
+            frame_idx: 3,
+            log_excerpt: log_excerpt(&log, "no log"),
+        };
+        assert!(err.to_string().starts_with("Failed to compile frame 3\n"));
+        assert!(err.to_string().contains("Undefined control sequence"));
+        assert_eq!(
+            log_excerpt(&dir.path().join("missing.log"), "no log"),
+            "no log"
+        );
+    }
+}
@@ -0,0 +1,136 @@
+//
+// events.rs
+// Distributed under terms of the GPLv3 license.
+//
+// With `--message-format=json`, the progress of a build is written to stdout as one JSON object
+// per line (instead of the progress bar), e.g. for editor plugins and CI:
+//
+//     {"event":"compile-started","input_file":"talk.tex","frame":3}
+//
+
+use crate::error::FasterBeamerError;
+use serde::Serialize;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Whether events are written (`--message-format=json`)
+static JSON_EVENTS: AtomicBool = AtomicBool::new(false);
+
+pub const MESSAGE_FORMATS: &[&str] = &["human", "json"];
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event<'a> {
+    /// A frame was found in the document (`line` is 1-based).
+    Frame {
+        input_file: &'a str,
+        frame: usize,
+        line: usize,
+        label: Option<String>,
+    },
+    /// The PDF of a frame is already in the cache.
+    CacheHit {
+        input_file: &'a str,
+        frame: usize,
+    },
+    CompileStarted {
+        input_file: &'a str,
+        frame: usize,
+    },
+    CompileFinished {
+        input_file: &'a str,
+        frame: usize,
+        success: bool,
+        seconds: f64,
+    },
+    /// The output PDF was written (merged from the frames or linked to one of them).
+    Output {
+        input_file: &'a str,
+        output_file: &'a str,
+    },
+    Error {
+        input_file: &'a str,
+        frame: Option<usize>,
+        message: String,
+        log_excerpt: Option<String>,
+    },
+}
+
+impl<'a> Event<'a> {
+    /// The error event of a failed build of `input_file`.
+    pub fn from_error(input_file: &'a str, err: &FasterBeamerError) -> Event<'a> {
+        let (frame, log_excerpt) = match err {
+            FasterBeamerError::FrameCompile {
+                frame_idx,
+                log_excerpt,
+            } => (Some(*frame_idx), Some(log_excerpt.clone())),
+            FasterBeamerError::PreambleCompile { log_excerpt, .. }
+            | FasterBeamerError::Compile { log_excerpt, .. } => (None, Some(log_excerpt.clone())),
+            _ => (None, None),
+        };
+        Event::Error {
+            input_file,
+            frame,
+            message: err
+                .to_string()
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+            log_excerpt,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+/// Switches the events on (`--message-format=json`) or off.
+pub fn set_enabled(enabled: bool) {
+    JSON_EVENTS.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    JSON_EVENTS.load(Ordering::Relaxed)
+}
+
+/// Writes `event` to stdout if events are switched on.
+pub fn emit(event: Event) {
+    if !is_enabled() {
+        return;
+    }
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    if let Err(err) = writeln!(stdout, "{}", event.to_json()).and_then(|()| stdout.flush()) {
+        warn!("Failed to write event: {}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_json() {
+        assert_eq!(
+            Event::CompileFinished {
+                input_file: "talk.tex",
+                frame: 2,
+                success: true,
+                seconds: 0.5,
+            }
+            .to_json(),
+            r#"{"event":"compile-finished","input_file":"talk.tex","frame":2,"success":true,"seconds":0.5}"#
+        );
+
+        let err = FasterBeamerError::FrameCompile {
+            frame_idx: 3,
+            log_excerpt: "! Undefined control sequence.\nl.12 \\foo".to_string(),
+        };
+        assert_eq!(
+            Event::from_error("talk.tex", &err).to_json(),
+            r#"{"event":"error","input_file":"talk.tex","frame":3,"message":"Failed to compile frame 3","log_excerpt":"! Undefined control sequence.\nl.12 \\foo"}"#
+        );
+    }
+}
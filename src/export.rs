@@ -0,0 +1,660 @@
+//
+// export.rs
+// Distributed under terms of the GPLv3 license.
+//
+// As every frame is compiled into a PDF of its own, the frames of a presentation can be rendered
+// to images (e.g. for web pages or videos) straight from the cache. `export` compiles the frames
+// that are not cached yet and renders every page of their PDFs with pdftoppm (PNG) or pdftocairo
+// (SVG). With `--thumbnails`, the images are small and a contact sheet (`index.html`) shows all of
+// them at a glance, e.g. to spot layout regressions in a large presentation. `--format html`
+// embeds the PNG images of all pages into a single HTML file that shows them as a slideshow.
+// `export-frame` stitches the overlays of a single frame into an animated GIF or an MP4 video with
+// ffmpeg.
+//
+
+use crate::beamer::frame_title;
+use crate::bookmarks::plain_text;
+use crate::error::log_excerpt;
+use crate::extract::{find_frame, frame_stem};
+use crate::metadata::escape_xml;
+use crate::options::Options;
+use crate::page_map::build_page_map;
+use crate::preflight::is_runnable;
+use crate::process_file::{compile_frames, FasterBeamerError, Result};
+use rayon::prelude::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+pub const EXPORT_FORMATS: &[&str] = &["png", "svg", "html"];
+
+pub const ANIMATION_FORMATS: &[&str] = &["gif", "mp4"];
+
+/// Width and height (whichever is larger) of thumbnails in pixels
+pub const THUMBNAIL_SIZE: u32 = 240;
+
+/// File name of the contact sheet of thumbnails.
+pub const CONTACT_SHEET: &str = "index.html";
+
+const SLIDESHOW_STYLE: &str = "\
+html, body { margin: 0; height: 100%; background: #000; }
+.slide { display: block; width: 100vw; height: 100vh; object-fit: contain; }
+.slide[hidden] { display: none; }
+#counter { position: fixed; right: 1em; bottom: 0.5em; color: #888; font: 12px sans-serif; }
+";
+
+/// Keyboard navigation of the slideshow: arrows, page up and down, space, home and end (and `f`
+/// for full screen). The current page is kept in the URL.
+const SLIDESHOW_SCRIPT: &str = r##"
+const slides = document.querySelectorAll(".slide");
+const counter = document.getElementById("counter");
+let current = 0;
+function show(idx) {
+  current = Math.max(0, Math.min(slides.length - 1, idx));
+  slides.forEach((slide, i) => slide.hidden = i !== current);
+  counter.textContent = (current + 1) + " / " + slides.length;
+  history.replaceState(null, "", "#" + (current + 1));
+}
+document.addEventListener("keydown", (event) => {
+  switch (event.key) {
+    case "ArrowRight": case "ArrowDown": case "PageDown": case " ": case "Enter":
+      show(current + 1); break;
+    case "ArrowLeft": case "ArrowUp": case "PageUp": case "Backspace":
+      show(current - 1); break;
+    case "Home": show(0); break;
+    case "End": show(slides.length - 1); break;
+    case "f":
+      if (document.fullscreenElement) document.exitFullscreen();
+      else document.documentElement.requestFullscreen();
+      break;
+    default: return;
+  }
+  event.preventDefault();
+});
+document.addEventListener("click", (event) =>
+  show(current + (event.clientX < window.innerWidth / 3 ? -1 : 1)));
+show((parseInt(location.hash.slice(1), 10) || 1) - 1);
+"##;
+
+/// Frame on a contact sheet.
+#[derive(Debug, PartialEq)]
+pub struct SheetFrame {
+    pub frame_idx: usize,
+    pub title: Option<String>,
+    /// File names of the images of its pages (none if the frame failed)
+    pub images: Vec<String>,
+}
+
+/// Program that renders PDF pages in `format`.
+pub fn renderer(format: &str) -> &'static str {
+    match format {
+        "svg" => "pdftocairo",
+        _ => "pdftoppm",
+    }
+}
+
+/// Arguments of the renderer that write page `page` (1-based) of `pdf` to `image` in `format`,
+/// with `dpi` pixels per inch for PNG images (or `THUMBNAIL_SIZE` pixels for thumbnails).
+pub fn render_args(
+    format: &str,
+    dpi: u32,
+    thumbnail: bool,
+    pdf: &Path,
+    page: usize,
+    image: &Path,
+) -> Vec<String> {
+    let resolution = if thumbnail {
+        vec!["-scale-to".to_string(), THUMBNAIL_SIZE.to_string()]
+    } else {
+        vec!["-r".to_string(), dpi.to_string()]
+    };
+    let page = page.to_string();
+    let pdf = pdf.to_string_lossy().into_owned();
+    match format {
+        "svg" => vec![
+            "-svg".to_string(),
+            "-f".to_string(),
+            page.clone(),
+            "-l".to_string(),
+            page,
+            pdf,
+            image.to_string_lossy().into_owned(),
+        ],
+        // pdftoppm adds the extension itself
+        _ => [
+            vec!["-png".to_string()],
+            resolution,
+            vec![
+                "-f".to_string(),
+                page.clone(),
+                "-l".to_string(),
+                page,
+                "-singlefile".to_string(),
+                pdf,
+                image.with_extension("").to_string_lossy().into_owned(),
+            ],
+        ]
+        .concat(),
+    }
+}
+
+/// File name of the image of page `page` (0-based) of a frame with `num_pages` pages: the index
+/// and title of the frame, and the page if the frame has several.
+pub fn image_filename(
+    frame_idx: usize,
+    num_frames: usize,
+    title: Option<&str>,
+    page: usize,
+    num_pages: usize,
+    format: &str,
+) -> String {
+    let stem = frame_stem(frame_idx, num_frames, title);
+    if num_pages > 1 {
+        format!("{}-{}.{}", stem, page + 1, format)
+    } else {
+        format!("{}.{}", stem, format)
+    }
+}
+
+/// HTML page that shows the images of `frames` of the presentation `title` side by side, with the
+/// index and title of every frame.
+pub fn contact_sheet(title: &str, frames: &[SheetFrame]) -> String {
+    let mut html = format!(
+        "<!DOCTYPE html>\n\
+         <html>\n\
+         <head>\n\
+         <meta charset=\"utf-8\">\n\
+         <title>{}</title>\n\
+         <style>\n\
+         body {{ font-family: sans-serif; }}\n\
+         figure {{ display: inline-block; vertical-align: top; margin: 0.5em; }}\n\
+         img {{ width: {}px; border: 1px solid #ccc; margin-right: 2px; }}\n\
+         .failed {{ color: #c00; }}\n\
+         </style>\n\
+         </head>\n\
+         <body>\n",
+        escape_xml(title),
+        THUMBNAIL_SIZE
+    );
+    for frame in frames {
+        html += "<figure>\n";
+        for image in frame.images.iter() {
+            html += &format!("<img src=\"{}\" loading=\"lazy\">", escape_xml(image));
+        }
+        let caption = match &frame.title {
+            Some(title) => format!("{}: {}", frame.frame_idx, escape_xml(title)),
+            None => frame.frame_idx.to_string(),
+        };
+        if frame.images.is_empty() {
+            html += &format!(
+                "<figcaption class=\"failed\">{} (failed)</figcaption>\n",
+                caption
+            );
+        } else {
+            html += &format!("\n<figcaption>{}</figcaption>\n", caption);
+        }
+        html += "</figure>\n";
+    }
+    html + "</body>\n</html>\n"
+}
+
+/// Base64 encoding of `bytes` (e.g. for data URIs).
+pub fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len() * 4 / 3 + 4);
+    for chunk in bytes.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (idx, byte)| {
+            bits | (*byte as u32) << (16 - 8 * idx)
+        });
+        for idx in 0..4 {
+            if idx <= chunk.len() {
+                encoded.push(ALPHABET[(bits >> (18 - 6 * idx) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Self-contained HTML page of the presentation `title` that shows `slides` (the sources of
+/// their images, e.g. data URIs) one after the other.
+pub fn slideshow(title: &str, slides: &[String]) -> String {
+    let mut html = format!(
+        "<!DOCTYPE html>\n\
+         <html>\n\
+         <head>\n\
+         <meta charset=\"utf-8\">\n\
+         <title>{}</title>\n\
+         <style>\n{}</style>\n\
+         </head>\n\
+         <body>\n",
+        escape_xml(title),
+        SLIDESHOW_STYLE
+    );
+    for (idx, slide) in slides.iter().enumerate() {
+        html += &format!(
+            "<img class=\"slide\" alt=\"Slide {}\" src=\"{}\" hidden>\n",
+            idx + 1,
+            escape_xml(slide)
+        );
+    }
+    html + "<div id=\"counter\"></div>\n<script>"
+        + SLIDESHOW_SCRIPT
+        + "</script>\n</body>\n</html>\n"
+}
+
+fn render(
+    format: &str,
+    dpi: u32,
+    thumbnail: bool,
+    pdf: &Path,
+    page: usize,
+    image: &Path,
+) -> Result<()> {
+    let program = renderer(format);
+    let output = Command::new(program)
+        .args(render_args(format, dpi, thumbnail, pdf, page, image))
+        .output();
+    match output {
+        Ok(output) if output.status.success() => Ok(()),
+        Ok(output) => Err(FasterBeamerError::Program {
+            program: program.to_string(),
+            message: format!(
+                "Failed to render page {} of {}: {}",
+                page,
+                pdf.display(),
+                String::from_utf8_lossy(&output.stderr)
+            ),
+        }),
+        Err(e) => Err(FasterBeamerError::Program {
+            program: program.to_string(),
+            message: e.to_string(),
+        }),
+    }
+}
+
+/// Compiles the frames of `input_file` and renders their pages to images in `format` in the
+/// directory `out` (as thumbnails with a contact sheet with `thumbnail`, or as a slideshow
+/// `<stem>.html` for `html`). Errors of frames are reported one by one. Returns whether all
+/// frames were exported.
+pub fn export(
+    input_file: &str,
+    out: &str,
+    format: &str,
+    dpi: u32,
+    thumbnail: bool,
+    args: &Options,
+) -> Result<bool> {
+    let is_slideshow = format == "html";
+    // Pages of slideshows are embedded as PNG images
+    let image_format = if is_slideshow { "png" } else { format };
+    let program = renderer(image_format);
+    if !is_runnable(program, "-v") {
+        return Err(FasterBeamerError::MissingProgram(vec![format!(
+            "Cannot run {}, which is needed for exporting {} images. Install poppler.",
+            program,
+            format.to_uppercase()
+        )]));
+    }
+
+    let compiled = compile_frames(input_file, args)?;
+    let cache_subdir = &compiled.cache_subdir;
+    let frames = &compiled.frames;
+    let page_map = build_page_map(frames, cache_subdir);
+    fs::create_dir_all(out).map_err(FasterBeamerError::io(out))?;
+    let tempdir = if is_slideshow {
+        Some(tempfile::tempdir().map_err(FasterBeamerError::io(out))?)
+    } else {
+        None
+    };
+    let image_dir = match &tempdir {
+        Some(tempdir) => tempdir.path(),
+        None => Path::new(out),
+    };
+
+    let results: Vec<Result<Vec<String>>> = frames
+        .par_iter()
+        .zip(page_map.par_iter())
+        .enumerate()
+        .map(|(frame_idx, (frame, pages))| {
+            if !frame.is_compiled(cache_subdir) {
+                return Err(FasterBeamerError::FrameCompile {
+                    frame_idx,
+                    log_excerpt: log_excerpt(&frame.log(cache_subdir), "No PDF was written"),
+                });
+            }
+            let title = frame_title(&frame.content).map(|title| plain_text(&title));
+            let num_pages = pages.last_page - pages.first_page + 1;
+            let mut images = Vec::with_capacity(num_pages);
+            for page in 0..num_pages {
+                let filename = image_filename(
+                    frame_idx,
+                    frames.len(),
+                    title.as_deref(),
+                    page,
+                    num_pages,
+                    image_format,
+                );
+                let image = image_dir.join(&filename);
+                render(
+                    image_format,
+                    dpi,
+                    thumbnail,
+                    &frame.pdf(cache_subdir),
+                    page + 1,
+                    &image,
+                )?;
+                images.push(filename);
+            }
+            Ok(images)
+        })
+        .collect();
+
+    if is_slideshow {
+        let slides = results
+            .iter()
+            .filter_map(|result| result.as_ref().ok())
+            .flatten()
+            .map(|image| {
+                let image = image_dir.join(image);
+                fs::read(&image)
+                    .map(|png| format!("data:image/png;base64,{}", base64(&png)))
+                    .map_err(FasterBeamerError::io(&image))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let stem = Path::new(input_file).file_stem().unwrap_or_default();
+        let html = Path::new(out).join(stem).with_extension("html");
+        fs::write(&html, slideshow(input_file, &slides)).map_err(FasterBeamerError::io(&html))?;
+        info!("Wrote slideshow {}", html.display());
+    } else if thumbnail {
+        let sheet_frames: Vec<SheetFrame> = frames
+            .iter()
+            .zip(results.iter())
+            .enumerate()
+            .map(|(frame_idx, (frame, result))| SheetFrame {
+                frame_idx,
+                title: frame_title(&frame.content).map(|title| plain_text(&title)),
+                images: result.as_ref().ok().cloned().unwrap_or_default(),
+            })
+            .collect();
+        let sheet = Path::new(out).join(CONTACT_SHEET);
+        fs::write(&sheet, contact_sheet(input_file, &sheet_frames))
+            .map_err(FasterBeamerError::io(&sheet))?;
+        info!("Wrote contact sheet {}", sheet.display());
+    }
+
+    let num_images: usize = results
+        .iter()
+        .filter_map(|r| r.as_ref().ok())
+        .map(|images| images.len())
+        .sum();
+    info!(
+        "Wrote {} images of {} frames to {}",
+        num_images,
+        frames.len(),
+        out
+    );
+    for (frame_idx, result) in results.iter().enumerate() {
+        if let Err(err) = result {
+            error!("Frame {}: {}", frame_idx, err);
+        }
+    }
+    Ok(results.iter().all(|result| result.is_ok()))
+}
+
+/// Arguments of ffmpeg that stitch the images `pattern` (e.g. `page-%03d.png`) into an animation
+/// `output` in `format`, where every image is shown for `delay` seconds.
+pub fn animation_args(format: &str, delay: f64, pattern: &Path, output: &Path) -> Vec<String> {
+    let mut args = vec![
+        "-y".to_string(),
+        "-loglevel".to_string(),
+        "error".to_string(),
+        "-framerate".to_string(),
+        (1.0 / delay).to_string(),
+        "-i".to_string(),
+        pattern.to_string_lossy().into_owned(),
+    ];
+    let filter = match format {
+        // A palette of the images instead of the default web palette
+        "gif" => "split[a][b];[a]palettegen[p];[b][p]paletteuse",
+        // H.264 needs an even size
+        _ => "pad=ceil(iw/2)*2:ceil(ih/2)*2,format=yuv420p",
+    };
+    args.push("-vf".to_string());
+    args.push(filter.to_string());
+    if format == "gif" {
+        args.extend(vec!["-loop".to_string(), "0".to_string()]);
+    } else {
+        args.extend(vec![
+            "-c:v".to_string(),
+            "libx264".to_string(),
+            "-r".to_string(),
+            "25".to_string(),
+        ]);
+    }
+    args.push(output.to_string_lossy().into_owned());
+    args
+}
+
+/// Default file of the animation of frame `frame_idx` of `input_file`:
+/// `<stem>-frame-<index>.<format>` next to it.
+pub fn animation_file(input_file: &str, frame_idx: usize, format: &str) -> PathBuf {
+    let input = Path::new(input_file);
+    let stem = input.file_stem().unwrap_or_default().to_string_lossy();
+    input.with_file_name(format!("{}-frame-{}.{}", stem, frame_idx, format))
+}
+
+/// Compiles the frames of `input_file` and writes the overlays of the frame selected by `label`
+/// or `index` as an animation in `format` to `output` (or `animation_file`), showing every
+/// overlay for `delay` seconds.
+#[allow(clippy::too_many_arguments)]
+pub fn export_frame(
+    input_file: &str,
+    label: Option<&str>,
+    index: Option<&str>,
+    output: Option<&str>,
+    format: &str,
+    dpi: u32,
+    delay: f64,
+    args: &Options,
+) -> Result<()> {
+    if delay.is_nan() || delay <= 0.0 {
+        return Err(FasterBeamerError::InvalidOptions(format!(
+            "invalid --delay {} (expected a positive number of seconds)",
+            delay
+        )));
+    }
+    let missing: Vec<String> = [
+        ("pdftoppm", "-v", "poppler"),
+        ("ffmpeg", "-version", "ffmpeg"),
+    ]
+    .iter()
+    .filter(|(program, version_arg, _)| !is_runnable(program, version_arg))
+    .map(|(program, _, package)| {
+        format!(
+            "Cannot run {}, which is needed for exporting animations. Install {}.",
+            program, package
+        )
+    })
+    .collect();
+    if !missing.is_empty() {
+        return Err(FasterBeamerError::MissingProgram(missing));
+    }
+
+    let compiled = compile_frames(input_file, args)?;
+    let cache_subdir = &compiled.cache_subdir;
+    let contents: Vec<&str> = compiled
+        .frames
+        .iter()
+        .map(|frame| &frame.content[..])
+        .collect();
+    let frame_idx = find_frame(&contents, label, index)
+        .ok_or_else(|| FasterBeamerError::NotFound(format!("No such frame in {}", input_file)))?;
+    let frame = &compiled.frames[frame_idx];
+    if !frame.is_compiled(cache_subdir) {
+        return Err(FasterBeamerError::FrameCompile {
+            frame_idx,
+            log_excerpt: log_excerpt(&frame.log(cache_subdir), "No PDF was written"),
+        });
+    }
+    let pages = &build_page_map(std::slice::from_ref(frame), cache_subdir)[0];
+    let num_pages = pages.last_page - pages.first_page + 1;
+    if num_pages == 1 {
+        warn!("Frame {} has no overlays", frame_idx);
+    }
+
+    let tempdir = tempfile::tempdir().map_err(FasterBeamerError::io(cache_subdir))?;
+    for page in 1..=num_pages {
+        let image = tempdir.path().join(format!("page-{:03}.png", page));
+        render("png", dpi, false, &frame.pdf(cache_subdir), page, &image)?;
+    }
+
+    let output = output
+        .map(PathBuf::from)
+        .unwrap_or_else(|| animation_file(input_file, frame_idx, format));
+    let pattern = tempdir.path().join("page-%03d.png");
+    let result = Command::new("ffmpeg")
+        .args(animation_args(format, delay, &pattern, &output))
+        .output();
+    match result {
+        Ok(result) if result.status.success() => {
+            info!(
+                "Wrote {} overlays of frame {} to {}",
+                num_pages,
+                frame_idx,
+                output.display()
+            );
+            Ok(())
+        }
+        Ok(result) => Err(FasterBeamerError::Program {
+            program: "ffmpeg".to_string(),
+            message: format!(
+                "Failed to write {}: {}",
+                output.display(),
+                String::from_utf8_lossy(&result.stderr)
+            ),
+        }),
+        Err(e) => Err(FasterBeamerError::Program {
+            program: "ffmpeg".to_string(),
+            message: e.to_string(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_image_filename() {
+        assert_eq!(
+            image_filename(3, 20, Some("Fast Beamer"), 0, 1, "png"),
+            "003-Fast_Beamer.png"
+        );
+        assert_eq!(image_filename(3, 20, None, 1, 2, "svg"), "003-2.svg");
+    }
+
+    #[test]
+    fn test_render_args() {
+        let args = render_args(
+            "png",
+            150,
+            false,
+            Path::new("frame.pdf"),
+            2,
+            Path::new("out/001.png"),
+        );
+        assert_eq!(
+            args,
+            vec![
+                "-png",
+                "-r",
+                "150",
+                "-f",
+                "2",
+                "-l",
+                "2",
+                "-singlefile",
+                "frame.pdf",
+                "out/001"
+            ]
+        );
+        let args = render_args(
+            "svg",
+            150,
+            false,
+            Path::new("frame.pdf"),
+            1,
+            Path::new("out/001.svg"),
+        );
+        assert_eq!(args[0], "-svg");
+        assert_eq!(args.last().unwrap(), "out/001.svg");
+        let args = render_args(
+            "png",
+            150,
+            true,
+            Path::new("frame.pdf"),
+            1,
+            Path::new("out/001.png"),
+        );
+        assert_eq!(args[1..3], ["-scale-to", "240"]);
+    }
+
+    #[test]
+    fn test_animation_args() {
+        let args = animation_args(
+            "gif",
+            0.5,
+            Path::new("tmp/page-%03d.png"),
+            Path::new("talk-frame-3.gif"),
+        );
+        assert_eq!(args[3..7], ["-framerate", "2", "-i", "tmp/page-%03d.png"]);
+        assert!(args.contains(&"-loop".to_string()));
+        assert_eq!(args.last().unwrap(), "talk-frame-3.gif");
+        let args = animation_args("mp4", 1.0, Path::new("page-%03d.png"), Path::new("a.mp4"));
+        assert!(args.contains(&"libx264".to_string()));
+        assert_eq!(
+            animation_file("slides/talk.tex", 3, "mp4"),
+            Path::new("slides/talk-frame-3.mp4")
+        );
+    }
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64(b"Man"), "TWFu");
+        assert_eq!(base64(b"Ma"), "TWE=");
+        assert_eq!(base64(b"M"), "TQ==");
+        assert_eq!(base64(b""), "");
+    }
+
+    #[test]
+    fn test_slideshow() {
+        let slides = vec!["data:image/png;base64,TWFu".to_string(); 3];
+        let html = slideshow("Q&A.tex", &slides);
+        assert!(html.contains("<title>Q&amp;A.tex</title>"));
+        assert_eq!(html.matches("<img class=\"slide\"").count(), 3);
+        assert!(html.contains("alt=\"Slide 3\" src=\"data:image/png;base64,TWFu\" hidden>"));
+    }
+
+    #[test]
+    fn test_contact_sheet() {
+        let frames = [
+            SheetFrame {
+                frame_idx: 0,
+                title: Some("Q&A".to_string()),
+                images: vec!["000-Q_A-1.png".to_string(), "000-Q_A-2.png".to_string()],
+            },
+            SheetFrame {
+                frame_idx: 1,
+                title: None,
+                images: vec![],
+            },
+        ];
+        let html = contact_sheet("talk.tex", &frames);
+        assert!(html.contains("<title>talk.tex</title>"));
+        assert!(html.contains("<img src=\"000-Q_A-2.png\" loading=\"lazy\">"));
+        assert!(html.contains("<figcaption>0: Q&amp;A</figcaption>"));
+        assert!(html.contains("<figcaption class=\"failed\">1 (failed)</figcaption>"));
+    }
+}
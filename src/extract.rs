@@ -0,0 +1,196 @@
+//
+// extract.rs
+// Distributed under terms of the GPLv3 license.
+//
+
+use crate::beamer::frame_label;
+use crate::frame_dir::DEFAULT_PREAMBLE;
+use crate::options::Options;
+use crate::preamble::precompiled_format;
+use crate::process_file::{
+    compiler_environment, document_preamble, format_name, frame_documents, frame_preamble,
+    get_cache_subdir, get_cachedir, get_input_dir, hashed_dependencies, load_frames,
+    FasterBeamerError, Frame, Result,
+};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// File name of an extracted frame: zero-padded index and (if the frame has one) its label,
+/// so that the files sort in the order of the presentation.
+pub fn frame_filename(frame_idx: usize, num_frames: usize, label: Option<&str>) -> String {
+    format!("{}.tex", frame_stem(frame_idx, num_frames, label))
+}
+
+/// Name of a file of a frame without extension: zero-padded index and `name` (if given), in which
+/// characters other than ASCII letters, digits and dashes are replaced by `_`.
+pub fn frame_stem(frame_idx: usize, num_frames: usize, name: Option<&str>) -> String {
+    let width = std::cmp::max(3, num_frames.to_string().len());
+    match name {
+        Some(name) => {
+            let name: String = name
+                .chars()
+                .map(|c| {
+                    if c.is_ascii_alphanumeric() || c == '-' {
+                        c
+                    } else {
+                        '_'
+                    }
+                })
+                .collect();
+            format!("{:0width$}-{}", frame_idx, name, width = width)
+        }
+        None => format!("{:0width$}", frame_idx, width = width),
+    }
+}
+
+/// Wraps a frame into a document that can be compiled on its own.
+pub fn standalone_document(preamble: &str, frame: &str) -> String {
+    format!(
+        "{}\n\\begin{{document}}\n{}\n\\end{{document}}\n",
+        preamble.trim_end(),
+        frame
+    )
+}
+
+/// Writes every frame of `input_file` to its own file in the directory `out`. Unless frames are
+/// written as standalone documents, the preamble is written to `preamble.tex` so that `out` can
+/// be used as input again.
+pub fn extract(input_file: &str, out: &str, standalone: bool, args: &Options) -> Result<()> {
+    let extracted = load_frames(input_file, args)?;
+    let preamble = document_preamble(&extracted.parsed_file.file_content);
+
+    let out = Path::new(out);
+    fs::create_dir_all(out).map_err(FasterBeamerError::io(out))?;
+
+    let write = |filename: &str, content: &str| {
+        fs::write(out.join(filename), content).map_err(FasterBeamerError::io(out.join(filename)))
+    };
+
+    let num_frames = extracted.frames.len();
+    for (frame_idx, frame) in extracted.frames.iter().enumerate() {
+        let filename = frame_filename(frame_idx, num_frames, frame_label(frame).as_deref());
+        if standalone {
+            write(&filename, &standalone_document(&preamble, frame))?;
+        } else {
+            write(&filename, &format!("{}\n", frame))?;
+        }
+    }
+    if !standalone {
+        write(DEFAULT_PREAMBLE, &preamble)?;
+    }
+
+    info!("Extracted {} frames to {}", num_frames, out.display());
+    Ok(())
+}
+
+/// Prefix of the names of the kept frame documents of `input_file`.
+fn intermediate_prefix(input_file: &str) -> String {
+    let stem = Path::new(input_file)
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    format!("{}-frame-", stem)
+}
+
+/// Writes the document compiled for every frame to `cache_subdir` under a readable name
+/// (`<stem>-frame-<index>[-<label>].tex`), where it can be compiled by hand. The documents kept by
+/// an earlier build of `input_file` are removed.
+pub fn keep_intermediates(
+    input_file: &str,
+    frames: &[Frame],
+    cache_subdir: &Path,
+) -> io::Result<()> {
+    let prefix = intermediate_prefix(input_file);
+    for entry in fs::read_dir(cache_subdir)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap().to_string_lossy();
+        if name.starts_with(&prefix) && name.ends_with(".tex") {
+            fs::remove_file(&path)?;
+        }
+    }
+
+    for (frame_idx, frame) in frames.iter().enumerate() {
+        let filename = frame_filename(
+            frame_idx,
+            frames.len(),
+            frame_label(&frame.content).as_deref(),
+        );
+        fs::write(cache_subdir.join(prefix.clone() + &filename), &frame.tex)?;
+    }
+    info!(
+        "Kept frame documents as {}",
+        cache_subdir.join(prefix + "*.tex").display()
+    );
+    Ok(())
+}
+
+/// Index of the frame of `frames` with `label`, or else with the index `frame_idx` (if it exists).
+pub fn find_frame<S: AsRef<str>>(
+    frames: &[S],
+    label: Option<&str>,
+    frame_idx: Option<&str>,
+) -> Option<usize> {
+    match label {
+        Some(label) => frames
+            .iter()
+            .position(|frame| frame_label(frame.as_ref()).as_deref() == Some(label)),
+        None => frame_idx
+            .and_then(|f| f.parse().ok())
+            .filter(|idx| *idx < frames.len()),
+    }
+}
+
+/// Prints the document that is compiled for a frame of `input_file` (selected by its `label` or
+/// its index `frame_idx`).
+pub fn dump_frame(
+    input_file: &str,
+    label: Option<&str>,
+    frame_idx: Option<&str>,
+    args: &Options,
+) -> Result<()> {
+    let extracted = load_frames(input_file, args)?;
+
+    let frame_idx = find_frame(&extracted.frames, label, frame_idx)
+        .ok_or_else(|| FasterBeamerError::NotFound(format!("No such frame in {}", input_file)))?;
+
+    let preamble = frame_preamble(
+        &extracted.parsed_file.file_content,
+        args,
+        args.aspectratio.as_deref(),
+    );
+    let dependencies = hashed_dependencies(&preamble, input_file);
+    let env = compiler_environment(input_file, args);
+    let input_dir = get_input_dir(input_file);
+    let cache_subdir = get_cache_subdir(&get_cachedir()?, &input_dir);
+    let preamble_filename = format_name(&preamble, &dependencies, &env, args);
+    let frames = frame_documents(
+        extracted.frames,
+        extracted.frame_offsets,
+        &extracted.parsed_file.file_content,
+        &preamble,
+        precompiled_format(&cache_subdir, &preamble_filename),
+        &input_dir,
+        args,
+    );
+    print!("{}", frames[frame_idx].tex);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extracted_files() {
+        assert_eq!(frame_filename(7, 12, None), "007.tex");
+        assert_eq!(
+            frame_filename(12, 1200, Some("sec:intro")),
+            "0012-sec_intro.tex"
+        );
+        assert_eq!(
+            standalone_document("\\documentclass{beamer}\n\n", "\\begin{frame}\\end{frame}"),
+            "\\documentclass{beamer}\n\\begin{document}\n\\begin{frame}\\end{frame}\n\\end{document}\n"
+        );
+    }
+}
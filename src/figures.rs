@@ -0,0 +1,219 @@
+//
+// figures.rs
+// Distributed under terms of the GPLv3 license.
+//
+// Every `tikzpicture` and `figure` environment of a presentation can be compiled on its own
+// (cropped by the `standalone` class), which is much faster than compiling whole frames while
+// iterating on a figure. Compiled figures are cached by the hash of their document.
+//
+
+use crate::beamer::set_document_class;
+use crate::error::log_excerpt;
+use crate::latexcompile::{LatexCompiler, LatexInput, LatexRunOptions};
+use crate::options::Options;
+use crate::process_file::{
+    compiler_environment, document_preamble, engine_command, frame_compiler_args, get_cache_subdir,
+    get_cachedir, get_input_dir, is_complete_pdf, load_frames, FasterBeamerError, Result,
+};
+use rayon::prelude::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Environments that are compiled as figures.
+const FIGURE_ENVIRONMENTS: &[&str] = &["tikzpicture", "figure"];
+
+/// Returns the outermost `environments` in `content` with their byte offsets.
+pub fn find_environments(content: &str, environments: &[&str]) -> Vec<(usize, String)> {
+    let mut found = Vec::new();
+    let mut position = 0;
+
+    while position < content.len() {
+        let next = environments
+            .iter()
+            .filter_map(|env| {
+                content[position..]
+                    .find(&format!("\\begin{{{}}}", env))
+                    .map(|i| (position + i, *env))
+            })
+            .min();
+        let (start, env) = match next {
+            Some(next) => next,
+            None => break,
+        };
+
+        let begin = format!("\\begin{{{}}}", env);
+        let end = format!("\\end{{{}}}", env);
+        let mut depth = 0;
+        let mut cursor = start;
+        let mut env_end = None;
+        while cursor < content.len() {
+            let next_begin = content[cursor..].find(&begin).map(|i| cursor + i);
+            let next_end = content[cursor..].find(&end).map(|i| cursor + i);
+            match (next_begin, next_end) {
+                (Some(b), Some(e)) if b < e => {
+                    depth += 1;
+                    cursor = b + begin.len();
+                }
+                (_, Some(e)) => {
+                    depth -= 1;
+                    cursor = e + end.len();
+                    if depth == 0 {
+                        env_end = Some(cursor);
+                        break;
+                    }
+                }
+                (_, None) => break,
+            }
+        }
+
+        match env_end {
+            Some(env_end) => {
+                found.push((start, content[start..env_end].to_string()));
+                position = env_end;
+            }
+            None => {
+                warn!("Unterminated {} environment", env);
+                break;
+            }
+        }
+    }
+    found
+}
+
+/// Document that compiles `figure` cropped to its size.
+pub fn figure_document(preamble: &str, figure: &str) -> String {
+    format!(
+        "{}\n\\begin{{document}}\n\\begin{{standaloneframe}}\n{}\n\\end{{standaloneframe}}\n\\end{{document}}\n",
+        set_document_class(preamble, "[beamer,border=2pt]{standalone}").trim_end(),
+        figure
+    )
+}
+
+fn convert_to_png(pdf: &Path, png: &Path) -> Result<()> {
+    let output = Command::new("pdftoppm")
+        .arg("-png")
+        .arg("-r")
+        .arg("300")
+        .arg("-singlefile")
+        .arg(pdf)
+        .arg(png.with_extension(""))
+        .output();
+    match output {
+        Ok(output) if output.status.success() => Ok(()),
+        Ok(output) => Err(FasterBeamerError::Program {
+            program: "pdftoppm".to_string(),
+            message: format!(
+                "Failed to convert {} to PNG: {}",
+                pdf.display(),
+                String::from_utf8_lossy(&output.stderr)
+            ),
+        }),
+        Err(e) => Err(FasterBeamerError::Program {
+            program: "pdftoppm".to_string(),
+            message: e.to_string(),
+        }),
+    }
+}
+
+/// Compiles every figure of `input_file` and writes them to `out` (`figure-<index>.pdf`, or
+/// `.png` if requested). Errors are reported for every figure, the first one is returned.
+pub fn export_figures(input_file: &str, out: &str, png: bool, args: &Options) -> Result<()> {
+    let compilercmd = engine_command(args);
+
+    let extracted = load_frames(input_file, args)?;
+    let content = &extracted.parsed_file.file_content;
+    let preamble = document_preamble(content);
+    let body_start = content.find("\\begin{document}").unwrap_or(0);
+    let figures = find_environments(&content[body_start..], FIGURE_ENVIRONMENTS);
+    info!("Found {} figures.", figures.len());
+
+    let input_dir = get_input_dir(input_file);
+    let compiler_env = compiler_environment(input_file, args);
+    let cachedir = get_cachedir()?;
+    let cache_subdir = get_cache_subdir(&cachedir, &input_dir);
+    LatexInput::from_lazy(input_dir.to_str().unwrap(), &cachedir)
+        .map_err(FasterBeamerError::latex_input(&input_dir))?;
+    fs::create_dir_all(out).map_err(FasterBeamerError::io(out))?;
+
+    let results: Vec<Result<()>> = figures
+        .par_iter()
+        .enumerate()
+        .map(|(figure_idx, (_, figure))| {
+            let tex = figure_document(&preamble, figure);
+            let name = format!("figure-{:x}", md5::compute(&tex));
+            let pdf = cache_subdir.join(format!("{}.pdf", name));
+
+            if is_complete_pdf(&pdf) {
+                trace!("{} is already compiled!", pdf.display());
+            } else {
+                let tex_file = cache_subdir.join(format!("{}.tex", name));
+                fs::write(&tex_file, &tex).map_err(FasterBeamerError::io(&tex_file))?;
+                let mut compiler = LatexCompiler::new(compilercmd)
+                    .map_err(FasterBeamerError::latex_input(&tex_file))?;
+                for arg in frame_compiler_args(args) {
+                    compiler = compiler.add_arg(&arg);
+                }
+                for (key, value) in compiler_env.iter() {
+                    compiler = compiler.add_env(key, value);
+                }
+                compiler.working_dir = cache_subdir.clone();
+                compiler
+                    .run(
+                        &tex_file.to_string_lossy(),
+                        &LatexInput::new(),
+                        LatexRunOptions::new(),
+                    )
+                    .map_err(|err| FasterBeamerError::Compile {
+                        log_excerpt: log_excerpt(&tex_file.with_extension("log"), &err.to_string()),
+                        file: tex_file.clone(),
+                    })?;
+            }
+
+            let target = PathBuf::from(out).join(format!("figure-{:03}", figure_idx));
+            if png {
+                convert_to_png(&pdf, &target.with_extension("png"))
+            } else {
+                fs::copy(&pdf, target.with_extension("pdf"))
+                    .map(|_| ())
+                    .map_err(FasterBeamerError::io(target.with_extension("pdf")))
+            }
+        })
+        .collect();
+
+    let num_failed = results.iter().filter(|r| r.is_err()).count();
+    info!(
+        "Wrote {} of {} figures to {}",
+        figures.len() - num_failed,
+        figures.len(),
+        out
+    );
+    for (figure_idx, result) in results.iter().enumerate() {
+        if let Err(err) = result {
+            error!("Figure {}: {}", figure_idx, err);
+        }
+    }
+    results.into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_environments() {
+        let content = "\\begin{frame}\n\
+                       \\begin{figure}\\begin{tikzpicture}\\draw (0,0);\\end{tikzpicture}\\end{figure}\n\
+                       \\begin{tikzpicture}\\begin{tikzpicture}\\end{tikzpicture}\\end{tikzpicture}\n\
+                       \\end{frame}";
+        let found = find_environments(content, FIGURE_ENVIRONMENTS);
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].0, 14);
+        assert!(found[0].1.starts_with("\\begin{figure}"));
+        assert!(found[0].1.ends_with("\\end{figure}"));
+        assert_eq!(
+            found[1].1,
+            "\\begin{tikzpicture}\\begin{tikzpicture}\\end{tikzpicture}\\end{tikzpicture}"
+        );
+    }
+}
@@ -0,0 +1,94 @@
+//
+// frame_dir.rs
+// Distributed under terms of the GPLv3 license.
+//
+// Large presentations are sometimes kept as a directory with one file per frame and a shared
+// preamble. Such a directory can be used as input directly: the frames are taken from the files
+// (in lexical order of their names) without extracting them from a document.
+//
+
+use crate::options::Options;
+use crate::parsing::ParsedFile;
+use crate::process_file::{ExtractedFrames, FasterBeamerError, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the preamble file of a frame directory if not given by `--preamble`.
+pub const DEFAULT_PREAMBLE: &str = "preamble.tex";
+
+/// Returns the frame files of `dir` (all `.tex` files except `preamble`) in lexical order.
+pub fn frame_files(dir: &Path, preamble: &Path) -> Result<Vec<PathBuf>> {
+    let entries = fs::read_dir(dir).map_err(FasterBeamerError::io(dir))?;
+    let preamble = preamble.canonicalize().ok();
+
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.is_file() && p.extension().map_or(false, |e| e == "tex"))
+        .filter(|p| p.canonicalize().ok() != preamble)
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// Joins preamble and frames to the document that the frames would form in a single file.
+/// Returns the document and the byte offsets of the frames in it.
+pub fn assemble_document(preamble: &str, frames: &[String]) -> (String, Vec<usize>) {
+    let mut document = preamble.trim_end().to_string() + "\n\\begin{document}\n";
+    let mut offsets = Vec::with_capacity(frames.len());
+    for frame in frames {
+        offsets.push(document.len());
+        document += frame;
+        document += "\n";
+    }
+    document += "\\end{document}\n";
+    (document, offsets)
+}
+
+/// Loads the frames of the frame directory `dir`.
+pub fn load_frame_directory(dir: &str, args: &Options) -> Result<ExtractedFrames> {
+    let dir_path = Path::new(dir);
+    let preamble_file = match &args.preamble {
+        Some(preamble) => PathBuf::from(preamble),
+        None => dir_path.join(DEFAULT_PREAMBLE),
+    };
+    let preamble = fs::read_to_string(&preamble_file)
+        .map_err(|_| FasterBeamerError::InputFileNotExistent(preamble_file.clone()))?;
+
+    let mut frames = Vec::new();
+    for file in frame_files(dir_path, &preamble_file)? {
+        let frame = fs::read_to_string(&file).map_err(FasterBeamerError::io(&file))?;
+        frames.push(frame.trim().to_string());
+    }
+    info!("Found {} frame files in {}.", frames.len(), dir);
+
+    let (document, frame_offsets) = assemble_document(&preamble, &frames);
+    Ok(ExtractedFrames {
+        parsed_file: ParsedFile::from_string(dir.to_string(), document)?,
+        priority: vec![false; frames.len()],
+        frames,
+        frame_offsets,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_assemble_document() {
+        let frames = vec![
+            "\\begin{frame}{A}\\end{frame}".to_string(),
+            "\\begin{frame}{B}\\end{frame}".to_string(),
+        ];
+        let (document, offsets) = assemble_document("\\documentclass{beamer}\n\n", &frames);
+        assert_eq!(
+            document,
+            "\\documentclass{beamer}\n\\begin{document}\n\\begin{frame}{A}\\end{frame}\n\
+             \\begin{frame}{B}\\end{frame}\n\\end{document}\n"
+        );
+        for (offset, frame) in offsets.iter().zip(frames.iter()) {
+            assert!(document[*offset..].starts_with(&frame[..]));
+        }
+    }
+}
@@ -0,0 +1,245 @@
+//
+// glossaries.rs
+// Distributed under terms of the GPLv3 license.
+//
+// `\printindex` (makeidx, imakeidx) and `\printglossary` (glossaries) read lists that makeindex
+// sorted from the entries that the previous run wrote (into `.idx`, `.glo`, ... files). The entries
+// that the frames write when they are compiled are collected, sorted once for the whole
+// presentation, and the sorted lists are given to the frames that print them. The lists are cached
+// under the hash of the collected entries, so makeindex runs again only when they change.
+//
+
+use crate::container;
+use crate::includes::is_commented;
+use crate::process_file::{FasterBeamerError, Frame, Result};
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+lazy_static! {
+    static ref MAKEINDEX_REGEX: Regex = Regex::new(r"\\makeindex\b").unwrap();
+}
+lazy_static! {
+    static ref MAKEGLOSSARIES_REGEX: Regex = Regex::new(r"\\makeglossaries\b").unwrap();
+}
+lazy_static! {
+    static ref PRINT_LIST_REGEX: Regex =
+        Regex::new(r"\\print(?:index|glossary|glossaries|acronyms)\b").unwrap();
+}
+
+/// A list that makeindex sorts from the entries of a document.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SortedList {
+    /// Extension of the file with the entries
+    pub entries: &'static str,
+    /// Extension of the sorted list
+    pub sorted: &'static str,
+    /// Extension of the log of makeindex
+    pub log: &'static str,
+    /// Whether the style of the list is the `.ist` file of the glossaries package
+    pub glossary: bool,
+}
+
+pub const INDEX: SortedList = SortedList {
+    entries: "idx",
+    sorted: "ind",
+    log: "ilg",
+    glossary: false,
+};
+
+/// The main glossary and the list of acronyms of the glossaries package
+pub const GLOSSARIES: [SortedList; 2] = [
+    SortedList {
+        entries: "glo",
+        sorted: "gls",
+        log: "glg",
+        glossary: true,
+    },
+    SortedList {
+        entries: "acn",
+        sorted: "acr",
+        log: "alg",
+        glossary: true,
+    },
+];
+
+fn is_used(regex: &Regex, content: &str) -> bool {
+    regex
+        .find_iter(content)
+        .any(|m| !is_commented(content, m.start()))
+}
+
+/// Lists that the frames of a document with `preamble` write entries for.
+pub fn sorted_lists(preamble: &str) -> Vec<SortedList> {
+    let mut lists = Vec::new();
+    if is_used(&MAKEINDEX_REGEX, preamble) {
+        lists.push(INDEX);
+    }
+    if is_used(&MAKEGLOSSARIES_REGEX, preamble) {
+        lists.extend(&GLOSSARIES);
+    }
+    lists
+}
+
+/// Whether `frame` prints the index or a glossary.
+pub fn prints_lists(frame: &str) -> bool {
+    is_used(&PRINT_LIST_REGEX, frame)
+}
+
+/// Sorted lists of a presentation, shared by all frames.
+pub struct SharedLists {
+    pub hash: md5::Digest,
+    /// Sorted lists with their extensions
+    pub files: Vec<(PathBuf, &'static str)>,
+}
+
+impl SharedLists {
+    /// Code for the document of a frame that prints the lists.
+    pub fn frame_code(&self) -> String {
+        format!("% index and glossaries {:x}\n", self.hash)
+    }
+
+    /// Makes the sorted lists available to the compile of `frame`.
+    pub fn copy_to(&self, frame: &Frame, cache_subdir: &Path) {
+        for (file, extension) in &self.files {
+            let copy = cache_subdir.join(format!("{:x}.{}", frame.hash, extension));
+            if let Err(err) = fs::copy(file, &copy) {
+                warn!(
+                    "Failed to copy {} to {}: {}",
+                    file.display(),
+                    copy.display(),
+                    err
+                );
+            }
+        }
+    }
+}
+
+/// Runs makeindex on `entries` of `list` in `working_dir`, writing the sorted list of `jobname`.
+fn makeindex(
+    list: &SortedList,
+    jobname: &str,
+    style: Option<&Path>,
+    working_dir: &Path,
+    env: &BTreeMap<String, String>,
+) -> Result<()> {
+    let mut command = container::command("makeindex", working_dir, env);
+    if let Some(style) = style {
+        command.arg("-s").arg(style);
+    }
+    let output = command
+        .arg("-o")
+        .arg(format!("{}.{}", jobname, list.sorted))
+        .arg("-t")
+        .arg(format!("{}.{}", jobname, list.log))
+        .arg(format!("{}.{}", jobname, list.entries))
+        .output();
+    match output {
+        Err(e) => Err(FasterBeamerError::Program {
+            program: "makeindex".to_string(),
+            message: e.to_string(),
+        }),
+        Ok(output) if !output.status.success() => Err(FasterBeamerError::Program {
+            program: "makeindex".to_string(),
+            message: format!(
+                "Failed to sort {}.{}: {}",
+                jobname,
+                list.entries,
+                String::from_utf8_lossy(&output.stderr)
+            ),
+        }),
+        Ok(_) => Ok(()),
+    }
+}
+
+/// Sorts the entries that the compiled `frames` of a document with `preamble` wrote into
+/// `cache_subdir`, unless they are cached. None if no frame prints a list or there are no entries.
+pub fn shared_lists(
+    frames: &[Frame],
+    preamble: &str,
+    cache_subdir: &Path,
+    env: &BTreeMap<String, String>,
+) -> Result<Option<SharedLists>> {
+    let lists = sorted_lists(preamble);
+    if lists.is_empty() || !frames.iter().any(|f| prints_lists(&f.content)) {
+        return Ok(None);
+    }
+
+    // Entries of all frames in order, and the style that the glossaries package wrote
+    let mut context = md5::Context::new();
+    let mut entries = Vec::new();
+    for list in &lists {
+        let content: String = frames
+            .iter()
+            .filter_map(|f| {
+                fs::read_to_string(cache_subdir.join(format!("{:x}.{}", f.hash, list.entries))).ok()
+            })
+            .collect();
+        if content.is_empty() {
+            continue;
+        }
+        let style = if list.glossary {
+            let style = frames
+                .iter()
+                .map(|f| cache_subdir.join(format!("{:x}.ist", f.hash)))
+                .find(|ist| ist.is_file());
+            match style {
+                Some(style) => {
+                    context.consume(fs::read(&style).unwrap_or_default());
+                    Some(style)
+                }
+                None => continue,
+            }
+        } else {
+            None
+        };
+        context.consume(list.entries.as_bytes());
+        context.consume(content.as_bytes());
+        entries.push((list, content, style));
+    }
+    if entries.is_empty() {
+        return Ok(None);
+    }
+    let hash = context.compute();
+    let jobname = format!("lists-{:x}", hash);
+
+    let mut files = Vec::with_capacity(entries.len());
+    for (list, content, style) in entries {
+        let sorted = cache_subdir.join(format!("{}.{}", jobname, list.sorted));
+        if !sorted.is_file() {
+            info!("Sorting the .{} entries of all frames", list.entries);
+            let entries_file = cache_subdir.join(format!("{}.{}", jobname, list.entries));
+            fs::write(&entries_file, &content).map_err(FasterBeamerError::io(&entries_file))?;
+            makeindex(list, &jobname, style.as_deref(), cache_subdir, env)?;
+        }
+        files.push((sorted, list.sorted));
+    }
+    Ok(Some(SharedLists { hash, files }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sorted_lists() {
+        assert_eq!(
+            sorted_lists("\\usepackage{makeidx}\n\\makeindex\n\\usepackage{glossaries}\n"),
+            vec![INDEX]
+        );
+        assert_eq!(
+            sorted_lists("\\usepackage{glossaries}\n\\makeglossaries\n% \\makeindex\n"),
+            GLOSSARIES.to_vec()
+        );
+        assert!(sorted_lists("\\documentclass{beamer}").is_empty());
+
+        assert!(prints_lists("\\begin{frame}\\printindex\\end{frame}"));
+        assert!(prints_lists(
+            "\\begin{frame}\\printglossary[type=\\acronymtype]\\end{frame}"
+        ));
+        assert!(!prints_lists(
+            "\\begin{frame}% \\printglossaries\n\\end{frame}"
+        ));
+    }
+}
@@ -0,0 +1,129 @@
+//
+// graphics.rs
+// Distributed under terms of the GPLv3 license.
+//
+// Images that a frame includes with `\includegraphics` are not part of its LaTeX code, so they are
+// hashed with the frame (and watched in watch mode), and the frame is compiled again when one of
+// them changes. Images are looked up like graphicx does: in the input directory and in the
+// directories of `\graphicspath`, trying the extensions of pdfLaTeX if the name has none.
+// Frames are compiled in the cache directory, which mirrors the input directory. Directories of
+// `\graphicspath` outside of it (e.g. `../figures/`) are mirrored as well, so that the relative
+// paths find the same images there.
+//
+
+use crate::includes::is_commented;
+use regex::Regex;
+use std::path::{Path, PathBuf};
+
+lazy_static! {
+    static ref GRAPHICS_REGEX: Regex =
+        Regex::new(r"\\includegraphics\*?\s*(?:\[[^\]]*\]\s*){0,2}\{([^}]*)\}").unwrap();
+}
+lazy_static! {
+    static ref GRAPHICSPATH_REGEX: Regex =
+        Regex::new(r"\\graphicspath\s*\{((?:\s*\{[^}]*\})*)\s*\}").unwrap();
+}
+lazy_static! {
+    static ref GROUP_REGEX: Regex = Regex::new(r"\{([^}]*)\}").unwrap();
+}
+
+/// Extensions that graphicx tries (in this order) for images without one.
+const GRAPHICS_EXTENSIONS: &[&str] = &[
+    "pdf", "png", "jpg", "mps", "jpeg", "jbig2", "jb2", "PDF", "PNG", "JPG", "JPEG", "JBIG2",
+    "JB2", "eps",
+];
+
+/// Directories of the last `\graphicspath` of `preamble`.
+pub fn graphics_path(preamble: &str) -> Vec<String> {
+    GRAPHICSPATH_REGEX
+        .captures_iter(preamble)
+        .filter(|c| !is_commented(preamble, c.get(0).unwrap().start()))
+        .last()
+        .map(|c| {
+            GROUP_REGEX
+                .captures_iter(&c[1])
+                .map(|dir| dir[1].trim().to_string())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Existing directories (relative to `input_dir`) of the `\graphicspath` of `preamble`.
+pub fn graphics_dirs(preamble: &str, input_dir: &Path) -> Vec<PathBuf> {
+    graphics_path(preamble)
+        .iter()
+        .filter_map(|dir| input_dir.join(dir).canonicalize().ok())
+        .filter(|dir| dir.is_dir())
+        .collect()
+}
+
+/// The file that `\includegraphics{name}` in a document in `input_dir` reads.
+pub fn resolve_graphics(name: &str, input_dir: &Path, graphics_path: &[String]) -> Option<PathBuf> {
+    let name = name.trim().trim_matches('"');
+    if name.is_empty() {
+        return None;
+    }
+    let has_extension = Path::new(name).extension().is_some();
+    std::iter::once(input_dir.to_path_buf())
+        .chain(graphics_path.iter().map(|dir| input_dir.join(dir)))
+        .flat_map(|dir| {
+            let exact = Some(dir.join(name)).filter(|_| has_extension);
+            exact.into_iter().chain(
+                GRAPHICS_EXTENSIONS
+                    .iter()
+                    .map(move |ext| dir.join(format!("{}.{}", name, ext))),
+            )
+        })
+        .find(|path| path.is_file())
+}
+
+/// Local images (relative to `input_dir`) that `frame` includes, looked up in the directories of
+/// the `\graphicspath` of `preamble`.
+pub fn graphics_files(frame: &str, preamble: &str, input_dir: &Path) -> Vec<PathBuf> {
+    let graphics_path = graphics_path(preamble);
+    let mut files: Vec<PathBuf> = GRAPHICS_REGEX
+        .captures_iter(frame)
+        .filter(|c| !is_commented(frame, c.get(0).unwrap().start()))
+        .filter_map(|c| resolve_graphics(&c[1], input_dir, &graphics_path))
+        .collect();
+    files.sort();
+    files.dedup();
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_graphics_files() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("figures")).unwrap();
+        fs::write(dir.path().join("plot.pdf"), "").unwrap();
+        fs::write(dir.path().join("plot.png"), "").unwrap();
+        fs::write(dir.path().join("figures/logo.png"), "").unwrap();
+        fs::write(dir.path().join("figures/photo.jpg"), "").unwrap();
+        let preamble = "% \\graphicspath{{old/}}\n\\graphicspath{ {figures/} {img/} }\n";
+        assert_eq!(graphics_path(preamble), vec!["figures/", "img/"]);
+        assert_eq!(
+            graphics_dirs(preamble, dir.path()),
+            vec![dir.path().join("figures").canonicalize().unwrap()]
+        );
+
+        let frame = "\\begin{frame}\n\
+                     \\includegraphics[width=\\linewidth]{plot}\n\
+                     \\includegraphics{logo}\\includegraphics{figures/photo.jpg}\n\
+                     % \\includegraphics{plot.png}\n\
+                     \\includegraphics{missing}\n\
+                     \\end{frame}";
+        assert_eq!(
+            graphics_files(frame, preamble, dir.path()),
+            vec![
+                dir.path().join("figures/logo.png"),
+                dir.path().join("figures/photo.jpg"),
+                dir.path().join("plot.pdf"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,314 @@
+//
+// includes.rs
+// Distributed under terms of the GPLv3 license.
+//
+// Presentations often keep their frames in files of their own (e.g. `slides/*.tex`) that the main
+// document pulls in with `\input` or `\include`. These files are spliced into the content of the
+// main document (recursively, relative to the input directory) before the frames are extracted,
+// and a source map remembers which file every part of the spliced content comes from, so that
+// positions of frames can be mapped back to the original files. Files that a frame reads without
+// being spliced into it (e.g. listings, or `\input`s that are not spliced) are hashed with the
+// frame, so that changing them compiles the frame again.
+//
+
+use regex::Regex;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+lazy_static! {
+    static ref INPUT_REGEX: Regex = Regex::new(r"\\(?:input|include)\s*\{([^}]*)\}").unwrap();
+}
+lazy_static! {
+    static ref FRAME_INPUT_REGEX: Regex = Regex::new(
+        r"\\(?:(?:input|include|subfile|lstinputlisting|verbatiminput|VerbatimInput)\s*(?:\[[^\]]*\]\s*)?|inputminted\s*(?:\[[^\]]*\]\s*)?\{[^}]*\}\s*)\{([^}]*)\}"
+    )
+    .unwrap();
+}
+
+/// How deeply included files may include other files.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// File included by the main document.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SourceFile {
+    pub path: PathBuf,
+    pub content: String,
+}
+
+/// Part of the spliced content that is copied from one file.
+#[derive(Clone, Debug, PartialEq)]
+struct Segment {
+    /// Byte offset in the spliced content
+    start: usize,
+    /// Index of the included file (None for the main document)
+    file: Option<usize>,
+    /// Byte offset in that file
+    file_offset: usize,
+    /// Byte offset in the main document (of the `\input` for included files)
+    main_offset: usize,
+}
+
+/// Where the parts of spliced content come from. Empty if the main document includes no files.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SourceMap {
+    /// Content of the main document
+    main: String,
+    files: Vec<SourceFile>,
+    segments: Vec<Segment>,
+}
+
+impl SourceMap {
+    /// Content of the main document, if it includes files.
+    pub fn main_content(&self) -> Option<&str> {
+        if self.files.is_empty() {
+            None
+        } else {
+            Some(&self.main)
+        }
+    }
+
+    pub fn files(&self) -> &[SourceFile] {
+        &self.files
+    }
+
+    fn segment(&self, offset: usize) -> Option<&Segment> {
+        let idx = self
+            .segments
+            .partition_point(|segment| segment.start <= offset);
+        self.segments.get(idx.checked_sub(1)?)
+    }
+
+    /// Byte offset in the main document of `offset` in the spliced content. Content of included
+    /// files is at the `\input` that includes it.
+    pub fn main_offset(&self, offset: usize) -> usize {
+        match self.segment(offset) {
+            Some(segment) if segment.file.is_none() => segment.main_offset + offset - segment.start,
+            Some(segment) => segment.main_offset,
+            None => offset,
+        }
+    }
+
+    /// Included files of which some content is before `offset` in the spliced content.
+    pub fn files_before(&self, offset: usize) -> Vec<&SourceFile> {
+        let mut files: Vec<usize> = self
+            .segments
+            .iter()
+            .take_while(|segment| segment.start < offset)
+            .filter_map(|segment| segment.file)
+            .collect();
+        files.sort_unstable();
+        files.dedup();
+        files.into_iter().map(|idx| &self.files[idx]).collect()
+    }
+
+    /// Included file of `offset` in the spliced content and the byte offset in it, or None if it
+    /// is in the main document.
+    pub fn source(&self, offset: usize) -> Option<(&SourceFile, usize)> {
+        let segment = self.segment(offset)?;
+        let file = &self.files[segment.file?];
+        Some((file, segment.file_offset + offset - segment.start))
+    }
+}
+
+/// The file that `\input{name}` in a document in `input_dir` reads (`name.tex` before `name`).
+pub fn resolve_include(name: &str, input_dir: &Path) -> Option<PathBuf> {
+    let name = name.trim();
+    let mut candidates = Vec::new();
+    if !name.ends_with(".tex") {
+        candidates.push(input_dir.join(format!("{}.tex", name)));
+    }
+    candidates.push(input_dir.join(name));
+    candidates.into_iter().find(|path| path.is_file())
+}
+
+/// Whether the position `offset` of `content` is in a comment.
+pub fn is_commented(content: &str, offset: usize) -> bool {
+    let line_start = content[..offset].rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let mut escaped = false;
+    for c in content[line_start..offset].chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '%' => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+struct Splicer<'a> {
+    input_dir: &'a Path,
+    map: SourceMap,
+    spliced: String,
+    /// Files that are being included (to stop at cycles)
+    stack: Vec<PathBuf>,
+}
+
+impl<'a> Splicer<'a> {
+    /// Appends `content[start..end]` of `file` to the spliced content.
+    fn copy(
+        &mut self,
+        content: &str,
+        start: usize,
+        end: usize,
+        file: Option<usize>,
+        main_offset: Option<usize>,
+    ) {
+        if start == end {
+            return;
+        }
+        self.map.segments.push(Segment {
+            start: self.spliced.len(),
+            file,
+            file_offset: start,
+            main_offset: main_offset.unwrap_or(start),
+        });
+        self.spliced += &content[start..end];
+    }
+
+    /// Splices `content` of `file`, which is included at `main_offset` of the main document.
+    fn splice(&mut self, content: &str, file: Option<usize>, main_offset: Option<usize>) {
+        let mut copied = 0;
+        for m in INPUT_REGEX.captures_iter(content) {
+            let command = m.get(0).unwrap();
+            if is_commented(content, command.start()) {
+                continue;
+            }
+            let path = match resolve_include(&m[1], self.input_dir) {
+                Some(path) => path,
+                None => continue,
+            };
+            if self.stack.contains(&path) || self.stack.len() >= MAX_INCLUDE_DEPTH {
+                warn!("Not including {} again", path.display());
+                continue;
+            }
+            let included = match fs::read_to_string(&path) {
+                Ok(included) => included,
+                Err(err) => {
+                    warn!("Failed to read {}: {}", path.display(), err);
+                    continue;
+                }
+            };
+
+            self.copy(content, copied, command.start(), file, main_offset);
+            let idx = self.map.files.len();
+            self.map.files.push(SourceFile {
+                path: path.clone(),
+                content: included.clone(),
+            });
+            self.stack.push(path);
+            self.splice(
+                &included,
+                Some(idx),
+                Some(main_offset.unwrap_or_else(|| command.start())),
+            );
+            self.stack.pop();
+            copied = command.end();
+        }
+        self.copy(content, copied, content.len(), file, main_offset);
+    }
+}
+
+/// Replaces every `\input` and `\include` of `content` (a document in `input_dir`) by the content
+/// of the file, recursively. Files that cannot be found are left to LaTeX.
+pub fn splice_includes(content: &str, input_dir: &Path) -> (String, SourceMap) {
+    let mut splicer = Splicer {
+        input_dir,
+        map: SourceMap::default(),
+        spliced: String::with_capacity(content.len()),
+        stack: Vec::new(),
+    };
+    splicer.splice(content, None, None);
+    if splicer.map.files.is_empty() {
+        return (content.to_string(), SourceMap::default());
+    }
+    splicer.map.main = content.to_string();
+    (splicer.spliced, splicer.map)
+}
+
+/// Local files (relative to `input_dir`) that `frame` reads: files it includes that are not
+/// spliced into it and files it typesets verbatim (`\lstinputlisting`, `\inputminted`, ...).
+pub fn frame_dependencies(frame: &str, input_dir: &Path) -> Vec<PathBuf> {
+    let mut dependencies: Vec<PathBuf> = FRAME_INPUT_REGEX
+        .captures_iter(frame)
+        .filter(|c| !is_commented(frame, c.get(0).unwrap().start()))
+        .filter_map(|c| resolve_include(&c[1], input_dir))
+        .collect();
+    dependencies.sort();
+    dependencies.dedup();
+    dependencies
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_splice_includes() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("slides")).unwrap();
+        fs::write(
+            dir.path().join("slides/intro.tex"),
+            "\\begin{frame}{Intro}\\end{frame}\n\\input{slides/table}\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("slides/table.tex"),
+            "\\begin{frame}{Table}\\end{frame}\n",
+        )
+        .unwrap();
+        let content = "\\begin{document}\n\
+                       % \\input{slides/intro}\n\
+                       \\input{slides/intro}\n\
+                       \\include{missing}\n\
+                       \\end{document}\n";
+
+        let (spliced, sources) = splice_includes(content, dir.path());
+        assert_eq!(
+            spliced,
+            "\\begin{document}\n\
+             % \\input{slides/intro}\n\
+             \\begin{frame}{Intro}\\end{frame}\n\
+             \\begin{frame}{Table}\\end{frame}\n\n\n\
+             \\include{missing}\n\
+             \\end{document}\n"
+        );
+        assert_eq!(sources.main_content(), Some(content));
+
+        let table = spliced.find("{Table}").unwrap();
+        let (file, offset) = sources.source(table).unwrap();
+        assert_eq!(file.path, dir.path().join("slides/table.tex"));
+        assert_eq!(offset, "\\begin{frame}".len());
+        let include = content.find("\\input{slides/intro}\n\\include").unwrap();
+        assert_eq!(sources.main_offset(table), include);
+
+        let missing = spliced.find("\\include").unwrap();
+        assert!(sources.source(missing).is_none());
+        assert_eq!(
+            sources.main_offset(missing),
+            content.find("\\include").unwrap()
+        );
+
+        let (spliced, sources) = splice_includes("\\input{missing}", dir.path());
+        assert_eq!(spliced, "\\input{missing}");
+        assert_eq!(sources, SourceMap::default());
+    }
+
+    #[test]
+    fn test_frame_dependencies() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("solver.py"), "print(1)\n").unwrap();
+        fs::write(dir.path().join("table.tex"), "a & b\n").unwrap();
+        let frame = "\\begin{frame}\n\
+                     \\lstinputlisting[language=Python]{solver.py}\n\
+                     \\inputminted{python}{solver.py}\n\
+                     \\input{table}\n\
+                     % \\verbatiminput{notes.txt}\n\
+                     \\VerbatimInput{missing.txt}\n\
+                     \\end{frame}";
+        assert_eq!(
+            frame_dependencies(frame, dir.path()),
+            vec![dir.path().join("solver.py"), dir.path().join("table.tex")]
+        );
+    }
+}
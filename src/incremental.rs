@@ -0,0 +1,255 @@
+//
+// incremental.rs
+// Distributed under terms of the GPLv3 license.
+//
+// When only some frames of a united PDF change, the united PDF of the previous build is updated in
+// place: the pages of the changed frames are swapped for the pages of their new PDFs, which is much
+// faster than merging all frames again (or, with `--unite`, compiling the united document). A
+// record in the cache remembers the frames of the united PDF and on which pages they are.
+//
+
+use crate::links::named_destinations;
+use crate::merge::{replace_pages, PageReplacement};
+use crate::page_map::build_page_map;
+use crate::process_file::{FasterBeamerError, Frame, Result};
+use lopdf::Document;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Pages of a frame in a united PDF.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FramePages {
+    pub hash: String,
+    /// First page (0-based)
+    pub first_page: usize,
+    pub num_pages: usize,
+}
+
+/// Frames of a united PDF.
+#[derive(Debug, PartialEq)]
+pub struct UnitedRecord {
+    /// What the united PDF depends on besides its frames (e.g. the document around them)
+    pub key: String,
+    /// Size and modification time of the united PDF, which must not have changed since
+    pub fingerprint: String,
+    pub frames: Vec<FramePages>,
+}
+
+impl UnitedRecord {
+    pub fn parse(content: &str) -> Option<UnitedRecord> {
+        let mut lines = content.lines();
+        let key = lines.next()?.to_string();
+        let fingerprint = lines.next()?.to_string();
+        let frames = lines
+            .map(|line| {
+                let mut fields = line.split('\t');
+                Some(FramePages {
+                    hash: fields.next()?.to_string(),
+                    first_page: fields.next()?.parse().ok()?,
+                    num_pages: fields.next()?.parse().ok()?,
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(UnitedRecord {
+            key,
+            fingerprint,
+            frames,
+        })
+    }
+}
+
+impl fmt::Display for UnitedRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.key)?;
+        writeln!(f, "{}", self.fingerprint)?;
+        for frame in self.frames.iter() {
+            writeln!(
+                f,
+                "{}\t{}\t{}",
+                frame.hash, frame.first_page, frame.num_pages
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Record of the united PDF of `output_file`.
+pub fn record_file(cache_subdir: &Path, output_file: &str) -> PathBuf {
+    cache_subdir.join(format!("united-{:x}.pages", md5::compute(output_file)))
+}
+
+fn fingerprint(pdf: &Path) -> Option<String> {
+    let metadata = fs::metadata(pdf).ok()?;
+    Some(format!(
+        "{} {:?}",
+        metadata.len(),
+        metadata.modified().ok()?
+    ))
+}
+
+/// Pages of `frames` when their PDFs are concatenated.
+pub fn concatenated_pages(frames: &[Frame], cache_subdir: &Path) -> Vec<FramePages> {
+    frames
+        .iter()
+        .zip(build_page_map(frames, cache_subdir))
+        .map(|(frame, pages)| FramePages {
+            hash: format!("{:x}", frame.hash),
+            first_page: pages.first_page - 1,
+            num_pages: pages.last_page - pages.first_page + 1,
+        })
+        .collect()
+}
+
+/// Pages of `frames` in `united_pdf`, where every frame starts at its destination in
+/// `destinations`. None if a destination is missing (e.g. in a custom `unite_template`).
+pub fn destination_pages(
+    united_pdf: &Path,
+    frames: &[Frame],
+    destinations: &[String],
+    cache_subdir: &Path,
+) -> Option<Vec<FramePages>> {
+    let doc = Document::load(united_pdf).ok()?;
+    let destination_pages = named_destinations(&doc);
+    let mut frame_pages = concatenated_pages(frames, cache_subdir);
+    for (pages, destination) in frame_pages.iter_mut().zip(destinations) {
+        pages.first_page = *destination_pages.get(destination.as_bytes())?;
+    }
+    Some(frame_pages)
+}
+
+/// Remembers that `united_pdf` (as it is now) consists of `frames`.
+pub fn write_record(
+    record_file: &Path,
+    key: &str,
+    united_pdf: &Path,
+    frames: Vec<FramePages>,
+) -> Result<()> {
+    let record = UnitedRecord {
+        key: key.to_string(),
+        fingerprint: fingerprint(united_pdf).unwrap_or_default(),
+        frames,
+    };
+    fs::write(record_file, record.to_string()).map_err(FasterBeamerError::io(record_file))
+}
+
+/// Whether the pages of `frames` follow each other within `num_pages` pages.
+fn is_consistent(frames: &[FramePages], num_pages: usize) -> bool {
+    let mut next_page = 0;
+    for frame in frames {
+        if frame.first_page < next_page {
+            return false;
+        }
+        next_page = frame.first_page + frame.num_pages;
+    }
+    next_page <= num_pages
+}
+
+/// Updates `united_pdf` to consist of `frames` by replacing the pages of the frames that changed
+/// since the record was written. Returns the pages of the frames afterwards and whether any pages
+/// were replaced, or None if the united PDF needs to be rebuilt (e.g. because the record is missing
+/// or `key` changed).
+pub fn update_united(
+    record_file: &Path,
+    key: &str,
+    united_pdf: &Path,
+    frames: &[Frame],
+    cache_subdir: &Path,
+) -> Option<(Vec<FramePages>, bool)> {
+    let record = UnitedRecord::parse(&fs::read_to_string(record_file).ok()?)?;
+    if record.key != key
+        || Some(&record.fingerprint) != fingerprint(united_pdf).as_ref()
+        || record.frames.len() != frames.len()
+    {
+        return None;
+    }
+    let changed: Vec<usize> = (0..frames.len())
+        .filter(|idx| format!("{:x}", frames[*idx].hash) != record.frames[*idx].hash)
+        .collect();
+    if changed.len() == frames.len()
+        || changed
+            .iter()
+            .any(|idx| !frames[*idx].is_compiled(cache_subdir))
+    {
+        return None;
+    }
+    if changed.is_empty() {
+        return Some((record.frames, false));
+    }
+
+    let mut doc = Document::load(united_pdf).ok()?;
+    if !is_consistent(&record.frames, doc.get_pages().len()) {
+        return None;
+    }
+    let replacements: Vec<PageReplacement> = changed
+        .iter()
+        .map(|idx| PageReplacement {
+            first_page: record.frames[*idx].first_page,
+            num_pages: record.frames[*idx].num_pages,
+            pdf: frames[*idx].pdf(cache_subdir),
+        })
+        .collect();
+    let num_pages = match replace_pages(&mut doc, &replacements).and_then(|num_pages| {
+        doc.save(united_pdf)?;
+        Ok(num_pages)
+    }) {
+        Ok(num_pages) => num_pages,
+        Err(err) => {
+            warn!("Failed to update {}: {}", united_pdf.display(), err);
+            return None;
+        }
+    };
+    info!("Replaced the pages of {} changed frames", changed.len());
+
+    // Frames after a changed frame move by the difference of its page counts
+    let mut frame_pages = record.frames;
+    let mut shift = 0isize;
+    for (idx, pages) in frame_pages.iter_mut().enumerate() {
+        pages.first_page = (pages.first_page as isize + shift) as usize;
+        if let Some(replacement) = changed.iter().position(|changed_idx| *changed_idx == idx) {
+            shift += num_pages[replacement] as isize - pages.num_pages as isize;
+            pages.num_pages = num_pages[replacement];
+            pages.hash = format!("{:x}", frames[idx].hash);
+        }
+    }
+    Some((frame_pages, true))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_united_record() {
+        let record = UnitedRecord {
+            key: "pdfunite".to_string(),
+            fingerprint: "1234 SystemTime".to_string(),
+            frames: vec![
+                FramePages {
+                    hash: "abc".to_string(),
+                    first_page: 0,
+                    num_pages: 2,
+                },
+                FramePages {
+                    hash: "def".to_string(),
+                    first_page: 3,
+                    num_pages: 1,
+                },
+            ],
+        };
+        assert_eq!(UnitedRecord::parse(&record.to_string()), Some(record));
+        assert_eq!(UnitedRecord::parse("pdfunite\n"), None);
+    }
+
+    #[test]
+    fn test_is_consistent() {
+        let pages = |first_page, num_pages| FramePages {
+            hash: String::new(),
+            first_page,
+            num_pages,
+        };
+        assert!(is_consistent(&[pages(0, 2), pages(3, 1)], 4));
+        assert!(!is_consistent(&[pages(0, 2), pages(1, 1)], 4));
+        assert!(!is_consistent(&[pages(0, 2), pages(3, 2)], 4));
+    }
+}
@@ -0,0 +1,150 @@
+//
+// labels.rs
+// Distributed under terms of the GPLv3 license.
+//
+// Every frame is compiled on its own, so `\ref` and `\pageref` to a label of another frame (or to
+// a label of the same frame, which would need a second run) come out as "??". The `.aux` files of
+// the frames cannot help, since every frame starts its counters (pages, equations, figures, ...)
+// at 1. The labels are instead taken from the `.aux` file of the last full build of the document
+// (a LaTeX run next to it, or `--tagged`), and frames that reference labels are compiled again
+// with the definitions of these labels. The definitions are part of the documents of the frames,
+// so a frame is compiled again when a label it references changes.
+//
+
+use crate::includes::is_commented;
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+lazy_static! {
+    static ref NEWLABEL_REGEX: Regex = Regex::new(r"(?m)^\\newlabel\{([^}]*)\}(.*)$").unwrap();
+}
+lazy_static! {
+    /// `\ref{a}`, `\pageref{a}`, `\eqref{a}`, `\cref{a,b}`, ... or `\hyperref[a]`
+    static ref REF_REGEX: Regex =
+        Regex::new(r"\\(?:[a-zA-Z]*ref\*?\s*\{([^}]*)\}|hyperref\s*\[([^\]]*)\])").unwrap();
+}
+
+/// Labels defined in the `.aux` file `aux` with their values.
+pub fn aux_labels(aux: &str) -> Vec<(String, String)> {
+    NEWLABEL_REGEX
+        .captures_iter(aux)
+        .map(|c| (c[1].to_string(), c[2].trim_end().to_string()))
+        .collect()
+}
+
+/// Labels that `content` references.
+pub fn referenced_labels(content: &str) -> Vec<String> {
+    let mut labels: Vec<String> = REF_REGEX
+        .captures_iter(content)
+        .filter(|c| !is_commented(content, c.get(0).unwrap().start()))
+        .flat_map(|c| {
+            let labels = c.get(1).or_else(|| c.get(2)).unwrap().as_str();
+            labels
+                .split(',')
+                .map(|label| label.trim().to_string())
+                .collect::<Vec<_>>()
+        })
+        .filter(|label| !label.is_empty())
+        .collect();
+    labels.sort();
+    labels.dedup();
+    labels
+}
+
+/// `.aux` files of full builds of `input_file`: of a LaTeX run next to it and of `--tagged`
+/// builds in `cache_subdir`.
+pub fn full_build_auxes(input_file: &str, cache_subdir: &Path) -> Vec<PathBuf> {
+    let mut auxes = vec![Path::new(input_file).with_extension("aux")];
+    auxes.extend(
+        fs::read_dir(cache_subdir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| {
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                name.starts_with("tagged-") && name.ends_with(".aux")
+            }),
+    );
+    auxes.retain(|aux| aux.is_file());
+    auxes
+}
+
+/// Labels of the most recent full build of `input_file` (see `full_build_auxes`) with their
+/// values. Empty if the document was never built as a whole.
+pub fn collect_labels(input_file: &str, cache_subdir: &Path) -> BTreeMap<String, String> {
+    let latest = full_build_auxes(input_file, cache_subdir)
+        .into_iter()
+        .max_by_key(|aux| fs::metadata(aux).and_then(|m| m.modified()).ok());
+    latest
+        .and_then(|aux| fs::read_to_string(aux).ok())
+        .map(|aux| aux_labels(&aux).into_iter().collect())
+        .unwrap_or_default()
+}
+
+/// Code that defines the labels of `labels` that `content` references (empty if there are none).
+pub fn label_definitions(content: &str, labels: &BTreeMap<String, String>) -> String {
+    let definitions: String = referenced_labels(content)
+        .iter()
+        .filter_map(|label| {
+            let value = labels.get(label)?;
+            Some(format!("\\newlabel{{{}}}{}\n", label, value))
+        })
+        .collect();
+    if definitions.is_empty() {
+        return definitions;
+    }
+    format!("\\makeatletter\n{}\\makeatother\n", definitions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_label_definitions() {
+        let aux = "\\relax\n\
+                   \\newlabel{intro<1>}{{1}{1}}\n\
+                   \\newlabel{intro}{{1}{1}}\n\
+                   \\newlabel{eq:energy}{{1}{1}{}{equation.0.1}{}}\n";
+        let labels: BTreeMap<String, String> = aux_labels(aux).into_iter().collect();
+        assert_eq!(labels.len(), 3);
+        assert_eq!(labels["eq:energy"], "{{1}{1}{}{equation.0.1}{}}");
+
+        let content = "\\begin{frame}\n\
+                       See \\eqref{eq:energy} on page~\\pageref{intro}, \\cref{intro,missing}\n\
+                       % \\ref{old}\n\
+                       \\hyperref[intro]{back}\n\
+                       \\end{frame}";
+        assert_eq!(
+            referenced_labels(content),
+            vec!["eq:energy", "intro", "missing"]
+        );
+        assert_eq!(
+            label_definitions(content, &labels),
+            "\\makeatletter\n\
+             \\newlabel{eq:energy}{{1}{1}{}{equation.0.1}{}}\n\
+             \\newlabel{intro}{{1}{1}}\n\
+             \\makeatother\n"
+        );
+        assert_eq!(label_definitions("\\ref{missing}", &labels), "");
+    }
+
+    #[test]
+    fn test_collect_labels() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("talk.tex");
+        let input = input.to_str().unwrap();
+        let cache_subdir = dir.path().join("cache");
+        fs::create_dir(&cache_subdir).unwrap();
+        assert!(collect_labels(input, &cache_subdir).is_empty());
+
+        fs::write(dir.path().join("talk.aux"), "\\newlabel{intro}{{3}{5}}\n").unwrap();
+        assert_eq!(collect_labels(input, &cache_subdir)["intro"], "{{3}{5}}");
+        fs::write(cache_subdir.join("0123.aux"), "\\newlabel{intro}{{1}{1}}\n").unwrap();
+        assert_eq!(full_build_auxes(input, &cache_subdir).len(), 1);
+        assert_eq!(collect_labels(input, &cache_subdir)["intro"], "{{3}{5}}");
+    }
+}
@@ -26,7 +26,7 @@
 //!
 //! ## Example
 //!
-//! ```
+//! ```ignore
 //! use std::collections::HashMap;
 //! use std::fs::write;
 //! use latexcompile::{LatexCompiler, LatexInput, LatexError};
@@ -49,6 +49,9 @@
 //! ```
 //!
 
+use crate::compiler::{tectonic_args, Engine};
+use crate::container;
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::process::Command;
@@ -67,6 +70,12 @@ impl LatexRunOptions {
             capture_stdout: true,
         }
     }
+
+    /// Runs the compiler a second time (e.g. for references).
+    pub fn double_compilation(mut self) -> Self {
+        self.double_compilation = true;
+        self
+    }
 }
 
 /// Specify all error cases with the fail api.
@@ -100,7 +109,7 @@ impl LatexInput {
 
     /// Add a single file as input.
     /// ## Example
-    /// ```
+    /// ```ignore
     /// # use latexcompile::{LatexCompiler, LatexInput, LatexError};
     /// 
     ///   let mut input = LatexInput::from("assets/main.tex");
@@ -122,7 +131,7 @@ impl LatexInput {
 
     /// Add a whole folder as input.
     /// ## Example
-    /// ```
+    /// ```ignore
     /// # use latexcompile::{LatexCompiler, LatexInput, LatexError};
     /// 
     ///   let mut input = LatexInput::from("assets");
@@ -227,7 +236,7 @@ impl<'a> From<&'a str> for LatexInput {
 /// The wrapper struct around some latex compiler.
 /// It provides a clean temporary enviroment for the
 /// latex compilation.
-/// ```
+/// ```ignore
 /// use std::fs::write;
 /// use std::collections::HashMap;
 /// use latexcompile::{LatexCompiler, LatexInput, LatexError};
@@ -242,6 +251,7 @@ impl<'a> From<&'a str> for LatexInput {
 pub struct LatexCompiler {
     pub working_dir: PathBuf,
     cmd: Cmd,
+    env: Vec<(String, String)>,
 }
 
 impl LatexCompiler {
@@ -253,6 +263,7 @@ impl LatexCompiler {
         Ok(LatexCompiler {
             working_dir: dir.path().to_path_buf(),
             cmd,
+            env: Vec::new(),
         })
     }
 
@@ -262,12 +273,21 @@ impl LatexCompiler {
         self
     }
 
+    /// Set an environment variable of the compiler process.
+    pub fn add_env(mut self, key: &str, value: &str) -> Self {
+        self.env.push((key.into(), value.into()));
+        self
+    }
+
     /// build the command-line
     fn get_cmd(&self, main_file: &str) -> Command {
-        let mut cmd = Command::new(&self.cmd.0);
-        cmd.args(&self.cmd.1)
-            .arg(main_file)
-            .current_dir(&self.working_dir);
+        let env: BTreeMap<String, String> = self.env.iter().cloned().collect();
+        let mut cmd = container::command(&self.cmd.0, &self.working_dir, &env);
+        let args = match Engine::of(&self.cmd.0) {
+            Engine::Tectonic => tectonic_args(&self.cmd.1, &self.working_dir),
+            _ => self.cmd.1.clone(),
+        };
+        cmd.args(&args).arg(main_file);
         cmd
     }
 
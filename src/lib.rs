@@ -0,0 +1,77 @@
+//
+// lib.rs
+// Distributed under terms of the GPLv3 license.
+//
+// faster-beamer as a library, e.g. for editor plugins. `Options` and the functions next to it
+// build documents without spawning the binary; the modules give access to the single steps.
+//
+
+#[macro_use]
+extern crate log;
+#[macro_use]
+extern crate lazy_static;
+#[macro_use]
+extern crate failure_derive;
+
+pub mod batch;
+pub mod beamer;
+pub mod bibliography;
+pub mod bookmarks;
+pub mod cache_stats;
+pub mod catalog;
+pub mod clean;
+pub mod cli;
+pub mod compiler;
+pub mod config;
+pub mod container;
+#[cfg(unix)]
+pub mod daemon;
+pub mod dependencies;
+pub mod error;
+pub mod events;
+pub mod export;
+pub mod extract;
+pub mod figures;
+pub mod frame_dir;
+pub mod glossaries;
+pub mod graphics;
+pub mod includes;
+pub mod incremental;
+pub mod labels;
+pub mod latexcompile;
+pub mod links;
+pub mod lint;
+pub mod log_filter;
+pub mod logs;
+pub mod lsp;
+pub mod markdown;
+pub mod merge;
+pub mod merger;
+pub mod metadata;
+pub mod navigation;
+pub mod nvim;
+pub mod optimize;
+pub mod options;
+pub mod outline;
+pub mod page_map;
+pub mod parsing;
+pub mod pdfpc;
+pub mod preamble;
+pub mod preflight;
+pub mod process_file;
+pub mod project;
+pub mod recovery;
+pub mod remote;
+pub mod remote_cache;
+pub mod status;
+pub mod synctex;
+pub mod tagging;
+#[cfg(test)]
+mod test_pdf;
+pub mod tree_traversal;
+pub mod warm;
+pub mod watch;
+pub mod watermark;
+
+pub use error::{FasterBeamerError, Result};
+pub use options::{compile_document, extract_frames, Options};
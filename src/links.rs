@@ -0,0 +1,574 @@
+//
+// links.rs
+// Distributed under terms of the GPLv3 license.
+//
+// Links between frames (`\hyperlink`, `\framezoom`, buttons) point to named destinations. As
+// every frame is compiled on its own, the destination of a link to another frame is not defined
+// in the PDF of the frame and the link is broken in the merged PDF. These links are rewritten to
+// point directly to the page on which the destination lands in the merged PDF. With `--unite`,
+// pdfpages drops the links of the frames, so they are copied to the united PDF first.
+//
+
+use crate::catalog::{import_dict, name_tree_entries};
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Named destinations of `doc` with the index (0-based) of the page they are on.
+pub fn named_destinations(doc: &Document) -> HashMap<Vec<u8>, usize> {
+    let page_indices: HashMap<ObjectId, usize> = doc
+        .get_pages()
+        .values()
+        .enumerate()
+        .map(|(idx, id)| (*id, idx))
+        .collect();
+    let mut destinations = HashMap::new();
+    let mut add = |name: &[u8], destination: &Object| {
+        if let Some(page) = destination_page(doc, destination) {
+            if let Some(idx) = page_indices.get(&page) {
+                destinations.insert(name.to_vec(), *idx);
+            }
+        }
+    };
+
+    let catalog = match doc.catalog() {
+        Ok(catalog) => catalog,
+        Err(_) => return destinations,
+    };
+    // PDF 1.1: dictionary of destinations
+    if let Ok(Object::Dictionary(dests)) = catalog.get_deref(b"Dests", doc) {
+        for (name, destination) in dests.iter() {
+            add(name, destination);
+        }
+    }
+    // PDF 1.2: name tree of destinations
+    if let Ok(Object::Dictionary(names)) = catalog.get_deref(b"Names", doc) {
+        if let Ok(Object::Dictionary(tree)) = names.get_deref(b"Dests", doc) {
+            for (name, destination) in name_tree_entries(doc, tree) {
+                add(&name, destination);
+            }
+        }
+    }
+    destinations
+}
+
+/// Page of an explicit destination (`[page /XYZ ...]` or `<< /D [page ...] >>`).
+fn destination_page(doc: &Document, destination: &Object) -> Option<ObjectId> {
+    match doc.dereference(destination).ok()?.1 {
+        Object::Array(array) => array.first()?.as_reference().ok(),
+        Object::Dictionary(dict) => destination_page(doc, dict.get(b"D").ok()?),
+        _ => None,
+    }
+}
+
+/// Name of the destination of a link annotation, and the dictionary (the annotation or its
+/// action) and key where it is stored.
+fn named_link(doc: &Document, annotation: ObjectId) -> Option<(ObjectId, &'static [u8], Vec<u8>)> {
+    let dict = doc.get_dictionary(annotation).ok()?;
+    if dict.get(b"Subtype").and_then(|s| s.as_name()).ok()? != b"Link" {
+        return None;
+    }
+    let name = |object: &Object| match doc.dereference(object).ok()?.1 {
+        Object::String(name, _) => Some(name.clone()),
+        Object::Name(name) => Some(name.clone()),
+        _ => None,
+    };
+    if let Ok(destination) = dict.get(b"Dest") {
+        return name(destination).map(|n| (annotation, &b"Dest"[..], n));
+    }
+    let (action_id, action) = match dict.get(b"A").ok()? {
+        Object::Reference(id) => (*id, doc.get_dictionary(*id).ok()?),
+        Object::Dictionary(action) => (annotation, action),
+        _ => return None,
+    };
+    if action.get(b"S").and_then(|s| s.as_name()).ok()? != b"GoTo" {
+        return None;
+    }
+    if action_id == annotation {
+        // Inline actions are rewritten through the annotation
+        return name(action.get(b"D").ok()?).map(|n| (annotation, &b"A"[..], n));
+    }
+    name(action.get(b"D").ok()?).map(|n| (action_id, &b"D"[..], n))
+}
+
+fn link_annotations(doc: &Document, page: ObjectId) -> Vec<ObjectId> {
+    let annots = doc
+        .get_dictionary(page)
+        .ok()
+        .and_then(|page| page.get_deref(b"Annots", doc).ok())
+        .and_then(|annots| annots.as_array().ok());
+    match annots {
+        Some(annots) => annots
+            .iter()
+            .filter_map(|a| a.as_reference().ok())
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+/// Page of the destination `Navigation<n>` that beamer defines on page `n` (of its own page
+/// numbering) for its navigation symbols.
+fn navigation_page(name: &[u8]) -> Option<usize> {
+    std::str::from_utf8(name.strip_prefix(b"Navigation")?)
+        .ok()?
+        .parse()
+        .ok()
+}
+
+/// Named destinations of the frames of a merged PDF.
+struct FrameDestinations {
+    /// Page of the merged PDF on which every frame starts
+    first_pages: Vec<usize>,
+    num_pages: Vec<usize>,
+    /// Destinations of every frame with the page (of the frame) they are on
+    frames: Vec<HashMap<Vec<u8>, usize>>,
+    /// Destinations of all frames with the page of the merged PDF (the first frame wins)
+    all: HashMap<Vec<u8>, usize>,
+    /// Whether the pages of the frames follow each other without pages in between
+    is_contiguous: bool,
+}
+
+impl FrameDestinations {
+    fn new(frame_docs: &[Document], first_pages: Vec<usize>, is_contiguous: bool) -> Self {
+        let frames: Vec<HashMap<Vec<u8>, usize>> =
+            frame_docs.iter().map(named_destinations).collect();
+        let mut all = HashMap::new();
+        for (frame_idx, destinations) in frames.iter().enumerate() {
+            for (name, page) in destinations.iter() {
+                all.entry(name.clone())
+                    .or_insert(first_pages[frame_idx] + page);
+            }
+        }
+        FrameDestinations {
+            first_pages,
+            num_pages: frame_docs.iter().map(|doc| doc.get_pages().len()).collect(),
+            frames,
+            all,
+            is_contiguous,
+        }
+    }
+
+    /// Page of the merged PDF of the navigation destination `Navigation<n>` of a link in frame
+    /// `frame_idx`. The number of the first page of the frame is that of its own navigation
+    /// destination, so that e.g. the "next slide" button of the last slide of a frame leads to
+    /// the first slide of the next frame.
+    fn navigation_target(&self, frame_idx: usize, n: usize) -> Option<usize> {
+        let first_number = self.frames[frame_idx]
+            .iter()
+            .filter(|(_, page)| **page == 0)
+            .filter_map(|(name, _)| navigation_page(name))
+            .min()?;
+        let page = (n + self.first_pages[frame_idx]).checked_sub(first_number)?;
+        let is_in_frame = page >= self.first_pages[frame_idx]
+            && page < self.first_pages[frame_idx] + self.num_pages[frame_idx];
+        let total_pages: usize = self.num_pages.iter().sum();
+        if is_in_frame || (self.is_contiguous && page < total_pages) {
+            Some(page)
+        } else {
+            None
+        }
+    }
+
+    /// Page of the merged PDF of the destination `name` of a link in frame `frame_idx`. A
+    /// destination defined by the frame itself takes precedence over those of other frames.
+    fn resolve(&self, frame_idx: usize, name: &[u8]) -> Option<usize> {
+        if let Some(n) = navigation_page(name) {
+            if let Some(page) = self.navigation_target(frame_idx, n) {
+                return Some(page);
+            }
+        }
+        self.frames[frame_idx]
+            .get(name)
+            .map(|page| self.first_pages[frame_idx] + page)
+            .or_else(|| self.all.get(name).cloned())
+    }
+}
+
+/// Destination that shows all of `page`.
+fn page_destination(page: ObjectId) -> Object {
+    Object::Array(vec![Object::Reference(page), Object::Name(b"Fit".to_vec())])
+}
+
+/// Rewrites the named links of `merged` (the concatenation of `frame_pdfs`) to point to the page
+/// of their destination. Returns the number of rewritten links.
+pub fn fix_links(merged: &Path, frame_pdfs: &[PathBuf]) -> lopdf::Result<usize> {
+    let frame_docs = frame_pdfs
+        .iter()
+        .map(Document::load)
+        .collect::<lopdf::Result<Vec<_>>>()?;
+    let mut first_pages = Vec::with_capacity(frame_pdfs.len());
+    let mut num_pages = 0;
+    for frame in frame_docs.iter() {
+        first_pages.push(num_pages);
+        num_pages += frame.get_pages().len();
+    }
+    let destinations = FrameDestinations::new(&frame_docs, first_pages, true);
+
+    let mut doc = Document::load(merged)?;
+    let pages: Vec<ObjectId> = doc.get_pages().values().cloned().collect();
+    if pages.len() != num_pages {
+        warn!(
+            "{} has {} pages instead of {}, not rewriting links",
+            merged.display(),
+            pages.len(),
+            num_pages
+        );
+        return Ok(0);
+    }
+
+    let mut rewrites = Vec::new();
+    for (page_idx, page) in pages.iter().enumerate() {
+        let frame_idx = destinations
+            .first_pages
+            .iter()
+            .rposition(|first| *first <= page_idx)
+            .unwrap();
+        for annotation in link_annotations(&doc, *page) {
+            if let Some((id, key, name)) = named_link(&doc, annotation) {
+                match destinations
+                    .resolve(frame_idx, &name)
+                    .and_then(|target| pages.get(target))
+                {
+                    Some(target) => rewrites.push((id, key, *target)),
+                    None => debug!(
+                        "Destination {} of link on page {} not found",
+                        String::from_utf8_lossy(&name),
+                        page_idx + 1
+                    ),
+                }
+            }
+        }
+    }
+
+    for (id, key, target) in rewrites.iter() {
+        let destination = page_destination(*target);
+        if let Ok(Object::Dictionary(dict)) = doc.get_object_mut(*id) {
+            if *key == b"A" {
+                let mut action = Dictionary::new();
+                action.set("S", Object::Name(b"GoTo".to_vec()));
+                action.set("D", destination);
+                dict.set("A", action);
+            } else {
+                dict.set(key.to_vec(), destination);
+            }
+        }
+    }
+    if !rewrites.is_empty() {
+        doc.save(merged)?;
+    }
+    Ok(rewrites.len())
+}
+
+fn is_link(doc: &Document, annotation: ObjectId) -> bool {
+    let subtype = doc
+        .get_dictionary(annotation)
+        .and_then(|dict| dict.get(b"Subtype"))
+        .and_then(Object::as_name);
+    matches!(subtype, Ok(b"Link"))
+}
+
+/// Media box of `page`, which may be inherited from the page tree.
+fn media_box(doc: &Document, page: ObjectId) -> Option<Vec<f64>> {
+    let mut node = doc.get_dictionary(page).ok()?;
+    loop {
+        if let Ok(Object::Array(media_box)) = node.get_deref(b"MediaBox", doc) {
+            return media_box
+                .iter()
+                .map(|value| match value {
+                    Object::Integer(value) => Some(*value as f64),
+                    Object::Real(value) => Some(*value),
+                    _ => None,
+                })
+                .collect();
+        }
+        node = node.get_deref(b"Parent", doc).ok()?.as_dict().ok()?;
+    }
+}
+
+/// `rect` of a page with the media box `from`, moved to a page with the media box `to` on which
+/// the page is scaled to fit.
+fn map_rect(rect: &[Object], from: &[f64], to: &[f64]) -> Option<Vec<Object>> {
+    if from.len() != 4 || to.len() != 4 || from[2] == from[0] || from[3] == from[1] {
+        return None;
+    }
+    let scale = [
+        (to[2] - to[0]) / (from[2] - from[0]),
+        (to[3] - to[1]) / (from[3] - from[1]),
+    ];
+    rect.iter()
+        .enumerate()
+        .map(|(idx, value)| {
+            let value = match value {
+                Object::Integer(value) => *value as f64,
+                Object::Real(value) => *value,
+                _ => return None,
+            };
+            let axis = idx % 2;
+            Some(Object::Real(to[axis] + (value - from[axis]) * scale[axis]))
+        })
+        .collect()
+}
+
+/// Copies the links of the frames `frame_pdfs` to their pages in the `--unite` PDF `united`, where
+/// the frames start on the pages `first_pages` (0-based). pdfpages includes the pages of the frames
+/// without their links. Links to named destinations are rewritten to point to the page of their
+/// destination; links that were already copied to the pages are replaced. Returns the number of
+/// copied links.
+pub fn copy_links(
+    united: &Path,
+    frame_pdfs: &[PathBuf],
+    first_pages: &[usize],
+) -> lopdf::Result<usize> {
+    let frame_docs = frame_pdfs
+        .iter()
+        .map(Document::load)
+        .collect::<lopdf::Result<Vec<_>>>()?;
+    let destinations = FrameDestinations::new(&frame_docs, first_pages.to_vec(), false);
+
+    let mut doc = Document::load(united)?;
+    let pages: Vec<ObjectId> = doc.get_pages().values().cloned().collect();
+    // Destinations of the document itself, e.g. `\hypertarget` outside of frames
+    let united_destinations = named_destinations(&doc);
+    let mut num_links = 0;
+    let mut is_changed = false;
+    for (frame_idx, frame) in frame_docs.iter().enumerate() {
+        let frame_pages: Vec<ObjectId> = frame.get_pages().values().cloned().collect();
+        // Explicit destinations within the frame point to its pages in the united PDF
+        let mut copies: HashMap<ObjectId, ObjectId> = frame_pages
+            .iter()
+            .zip(pages.iter().skip(first_pages[frame_idx]))
+            .map(|(frame_page, page)| (*frame_page, *page))
+            .collect();
+        for (idx, frame_page) in frame_pages.iter().enumerate() {
+            let page = match pages.get(first_pages[frame_idx] + idx) {
+                Some(page) => *page,
+                None => break,
+            };
+            let boxes = (media_box(frame, *frame_page), media_box(&doc, page));
+            let mut links = Vec::new();
+            for annotation in link_annotations(frame, *frame_page) {
+                if !is_link(frame, annotation) {
+                    continue;
+                }
+                let target = match named_link(frame, annotation) {
+                    Some((_, _, name)) => match destinations
+                        .resolve(frame_idx, &name)
+                        .or_else(|| united_destinations.get(&name).cloned())
+                        .and_then(|target| pages.get(target))
+                    {
+                        Some(target) => Some(*target),
+                        None => {
+                            debug!(
+                                "Destination {} of link in frame {} not found",
+                                String::from_utf8_lossy(&name),
+                                frame_idx
+                            );
+                            continue;
+                        }
+                    },
+                    None => None,
+                };
+                let mut link = import_dict(
+                    frame,
+                    &mut doc,
+                    frame.get_dictionary(annotation)?,
+                    &mut copies,
+                );
+                if let (Some(from), Some(to)) = &boxes {
+                    if let Some(rect) = link
+                        .get(b"Rect")
+                        .and_then(Object::as_array)
+                        .ok()
+                        .and_then(|rect| map_rect(rect, from, to))
+                    {
+                        link.set("Rect", rect);
+                    }
+                }
+                if let Some(target) = target {
+                    link.remove(b"Dest");
+                    let mut action = Dictionary::new();
+                    action.set("S", Object::Name(b"GoTo".to_vec()));
+                    action.set("D", page_destination(target));
+                    link.set("A", action);
+                }
+                links.push(Object::Reference(doc.add_object(link)));
+            }
+            num_links += links.len();
+            is_changed |= !links.is_empty();
+
+            // Links of an earlier copy (or of the frame PDF, whose pages replaced the included ones)
+            let old_annots = link_annotations(&doc, page);
+            let mut annots: Vec<Object> = old_annots
+                .iter()
+                .filter(|annotation| !is_link(&doc, **annotation))
+                .map(|annotation| Object::Reference(*annotation))
+                .collect();
+            is_changed |= annots.len() != old_annots.len();
+            annots.extend(links);
+            if let Ok(Object::Dictionary(page)) = doc.get_object_mut(page) {
+                if annots.is_empty() {
+                    page.remove(b"Annots");
+                } else {
+                    page.set("Annots", annots);
+                }
+            }
+        }
+    }
+    if is_changed {
+        doc.prune_objects();
+        doc.save(united)?;
+    }
+    Ok(num_links)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_pdf::{catalog_mut, pdf_with_pages};
+    use lopdf::StringFormat;
+    use tempfile::tempdir;
+
+    /// PDF with one page per entry of `pages`, each with links to the given destinations, and the
+    /// named destinations `destinations` (name and page index).
+    fn test_pdf(pages: &[&[&str]], destinations: &[(&str, usize)]) -> Document {
+        let mut doc = pdf_with_pages(pages.len(), |doc, page_idx, _, page| {
+            let annots: Vec<Object> = pages[page_idx]
+                .iter()
+                .map(|name| {
+                    let mut action = Dictionary::new();
+                    action.set("S", Object::Name(b"GoTo".to_vec()));
+                    action.set(
+                        "D",
+                        Object::String(name.as_bytes().to_vec(), StringFormat::Literal),
+                    );
+                    let mut link = Dictionary::new();
+                    link.set("Type", Object::Name(b"Annot".to_vec()));
+                    link.set("Subtype", Object::Name(b"Link".to_vec()));
+                    link.set("A", action);
+                    Object::Reference(doc.add_object(link))
+                })
+                .collect();
+            page.set("Annots", annots);
+        });
+
+        let page_ids: Vec<ObjectId> = doc.get_pages().values().cloned().collect();
+        let mut names = Vec::new();
+        for (name, page) in destinations {
+            names.push(Object::String(
+                name.as_bytes().to_vec(),
+                StringFormat::Literal,
+            ));
+            names.push(Object::Array(vec![
+                Object::Reference(page_ids[*page]),
+                Object::Name(b"Fit".to_vec()),
+            ]));
+        }
+        let mut tree = Dictionary::new();
+        tree.set("Names", names);
+        let mut names_dict = Dictionary::new();
+        names_dict.set("Dests", tree);
+        catalog_mut(&mut doc).set("Names", names_dict);
+        doc
+    }
+
+    fn link_target(doc: &Document, page: usize) -> Option<usize> {
+        let pages: Vec<ObjectId> = doc.get_pages().values().cloned().collect();
+        let link = link_annotations(doc, pages[page])[0];
+        let action = doc
+            .get_dictionary(link)
+            .ok()?
+            .get(b"A")
+            .ok()?
+            .as_dict()
+            .ok()?;
+        let target = action.get(b"D").ok()?.as_array().ok()?[0]
+            .as_reference()
+            .ok()?;
+        pages.iter().position(|p| *p == target)
+    }
+
+    #[test]
+    fn test_fix_links() {
+        let dir = tempdir().unwrap();
+        let first = dir.path().join("first.pdf");
+        let second = dir.path().join("second.pdf");
+        let merged = dir.path().join("merged.pdf");
+
+        test_pdf(&[&["results"], &["page.1"]], &[("page.1", 0)])
+            .save(&first)
+            .unwrap();
+        test_pdf(&[&["page.1"]], &[("results", 0), ("page.1", 0)])
+            .save(&second)
+            .unwrap();
+        assert_eq!(
+            named_destinations(&Document::load(&second).unwrap()).len(),
+            2
+        );
+
+        // What pdfunite produces: the pages of both, without their destinations
+        test_pdf(&[&["results"], &["page.1"], &["page.1"]], &[])
+            .save(&merged)
+            .unwrap();
+        assert_eq!(fix_links(&merged, &[first, second]).unwrap(), 3);
+
+        let doc = Document::load(&merged).unwrap();
+        assert_eq!(link_target(&doc, 0), Some(2));
+        assert_eq!(link_target(&doc, 1), Some(0));
+        assert_eq!(link_target(&doc, 2), Some(2));
+    }
+
+    #[test]
+    fn test_navigation_links() {
+        let dir = tempdir().unwrap();
+        let first = dir.path().join("first.pdf");
+        let second = dir.path().join("second.pdf");
+        let merged = dir.path().join("merged.pdf");
+
+        // Both frames number their pages from 1
+        test_pdf(
+            &[&["Navigation2"], &["Navigation3"]],
+            &[("Navigation1", 0), ("Navigation2", 1)],
+        )
+        .save(&first)
+        .unwrap();
+        test_pdf(&[&["Navigation1"]], &[("Navigation1", 0)])
+            .save(&second)
+            .unwrap();
+        test_pdf(&[&["Navigation2"], &["Navigation3"], &["Navigation1"]], &[])
+            .save(&merged)
+            .unwrap();
+        assert_eq!(fix_links(&merged, &[first, second]).unwrap(), 3);
+
+        let doc = Document::load(&merged).unwrap();
+        assert_eq!(link_target(&doc, 0), Some(1));
+        assert_eq!(link_target(&doc, 1), Some(2));
+        assert_eq!(link_target(&doc, 2), Some(2));
+    }
+
+    #[test]
+    fn test_copy_links() {
+        let dir = tempdir().unwrap();
+        let first = dir.path().join("first.pdf");
+        let second = dir.path().join("second.pdf");
+        let united = dir.path().join("united.pdf");
+
+        test_pdf(&[&["results"]], &[]).save(&first).unwrap();
+        test_pdf(&[&[]], &[("results", 0)]).save(&second).unwrap();
+        // A title page and the pages of both frames, without links
+        test_pdf(&[&[], &[], &[]], &[]).save(&united).unwrap();
+
+        let frames = [first, second];
+        assert_eq!(copy_links(&united, &frames, &[1, 2]).unwrap(), 1);
+        // Copying again replaces the links
+        assert_eq!(copy_links(&united, &frames, &[1, 2]).unwrap(), 1);
+
+        let doc = Document::load(&united).unwrap();
+        let pages: Vec<ObjectId> = doc.get_pages().values().cloned().collect();
+        assert_eq!(link_annotations(&doc, pages[1]).len(), 1);
+        assert_eq!(link_target(&doc, 1), Some(2));
+
+        // Links to pages that the united PDF does not have are left out
+        test_pdf(&[&[], &[], &[]], &[]).save(&united).unwrap();
+        assert_eq!(copy_links(&united, &frames, &[1, 3]).unwrap(), 0);
+    }
+}
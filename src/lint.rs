@@ -0,0 +1,185 @@
+//
+// lint.rs
+// Distributed under terms of the GPLv3 license.
+//
+
+use crate::logs::read_log;
+use crate::options::Options;
+use crate::process_file::{compile_frames, Result};
+use regex::Regex;
+
+lazy_static! {
+    static ref OVERFULL_REGEX: Regex = Regex::new(
+        r"(?m)^Overfull \\([hv])box \((\d+(?:\.\d+)?)pt too (?:wide|high)\)(?:.*?lines? (\d+))?"
+    )
+    .unwrap();
+}
+
+#[derive(Debug, PartialEq)]
+pub enum BoxKind {
+    Horizontal,
+    Vertical,
+}
+
+/// An overfull box reported in a LaTeX log.
+#[derive(Debug, PartialEq)]
+pub struct OverfullBox {
+    pub kind: BoxKind,
+    /// Amount by which the box overflows (in pt)
+    pub amount: f64,
+    /// Line of the compiled document the warning refers to (if reported)
+    pub line: Option<usize>,
+}
+
+#[derive(Debug, PartialEq, PartialOrd)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+pub struct LintThresholds {
+    /// Vertical overflows above this amount (in pt) are errors
+    pub vbox_tolerance: f64,
+    /// Horizontal overflows above this amount (in pt) are reported
+    pub hbox_tolerance: f64,
+    /// A frame with more reported overfull hboxes than this is an error
+    pub max_hboxes: usize,
+}
+
+pub fn parse_overfull_boxes(log: &str) -> Vec<OverfullBox> {
+    OVERFULL_REGEX
+        .captures_iter(log)
+        .map(|c| OverfullBox {
+            kind: if &c[1] == "h" {
+                BoxKind::Horizontal
+            } else {
+                BoxKind::Vertical
+            },
+            amount: c[2].parse().unwrap_or(0.),
+            line: c.get(3).and_then(|l| l.as_str().parse().ok()),
+        })
+        .collect()
+}
+
+/// Classifies the overfull boxes of one frame. Returns the reported boxes with their severity.
+pub fn check_frame<'a>(
+    boxes: &'a [OverfullBox],
+    thresholds: &LintThresholds,
+) -> Vec<(Severity, &'a OverfullBox)> {
+    let hbox_count = boxes
+        .iter()
+        .filter(|b| b.kind == BoxKind::Horizontal && b.amount > thresholds.hbox_tolerance)
+        .count();
+
+    boxes
+        .iter()
+        .filter_map(|b| match b.kind {
+            BoxKind::Vertical if b.amount > thresholds.vbox_tolerance => Some((Severity::Error, b)),
+            BoxKind::Horizontal if b.amount > thresholds.hbox_tolerance => {
+                if hbox_count > thresholds.max_hboxes {
+                    Some((Severity::Error, b))
+                } else {
+                    Some((Severity::Warning, b))
+                }
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Compiles all frames of `input_file` and reports layout problems.
+/// Returns `Ok(true)` if no errors were found.
+pub fn lint(input_file: &str, thresholds: &LintThresholds, args: &Options) -> Result<bool> {
+    let compiled = compile_frames(input_file, args)?;
+
+    let mut num_errors = 0;
+    let mut num_warnings = 0;
+    for (frame_idx, frame) in compiled.frames.iter().enumerate() {
+        // Frames of included files are reported in their file
+        let (file, frame_line, _) = compiled.parsed_file.get_source_position(frame.offset);
+        let file = file.map_or_else(|| input_file.into(), |file| file.display().to_string());
+
+        if !frame.is_compiled(&compiled.cache_subdir) {
+            error!(
+                "{}:{}: frame {} failed to compile",
+                file, frame_line, frame_idx
+            );
+            num_errors += 1;
+            continue;
+        }
+
+        let log = match read_log(&frame.log(&compiled.cache_subdir)) {
+            Some(log) => log,
+            None => {
+                warn!("No log found for frame {}", frame_idx);
+                continue;
+            }
+        };
+
+        let boxes = parse_overfull_boxes(&log);
+        for (severity, overfull) in check_frame(&boxes, thresholds) {
+            let line = overfull
+                .line
+                .filter(|&l| l >= frame.body_line)
+                .map(|l| frame_line + l - frame.body_line)
+                .unwrap_or(frame_line);
+            let description = match overfull.kind {
+                BoxKind::Vertical => "content overflows the slide",
+                BoxKind::Horizontal => "overfull hbox",
+            };
+            let message = format!(
+                "{}:{}: frame {}: {} by {}pt",
+                file, line, frame_idx, description, overfull.amount
+            );
+            if severity == Severity::Error {
+                error!("{}", message);
+                num_errors += 1;
+            } else {
+                warn!("{}", message);
+                num_warnings += 1;
+            }
+        }
+    }
+
+    info!(
+        "Lint finished: {} error(s), {} warning(s) in {} frames.",
+        num_errors,
+        num_warnings,
+        compiled.frames.len()
+    );
+    Ok(num_errors == 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_overfull_boxes() {
+        let log = r"
+Overfull \hbox (12.5pt too wide) in paragraph at lines 12--14
+[]\OT1/cmr/m/n/10.95 text
+
+Overfull \vbox (38.1234pt too high) detected at line 20
+Overfull \vbox (2.0pt too high) has occurred while \output is active []
+";
+        let boxes = parse_overfull_boxes(log);
+        assert_eq!(boxes.len(), 3);
+        assert_eq!(boxes[0].kind, BoxKind::Horizontal);
+        assert_eq!(boxes[0].line, Some(12));
+        assert_eq!(boxes[1].kind, BoxKind::Vertical);
+        assert_eq!(boxes[1].amount, 38.1234);
+        assert_eq!(boxes[1].line, Some(20));
+        assert_eq!(boxes[2].line, None);
+
+        let thresholds = LintThresholds {
+            vbox_tolerance: 5.,
+            hbox_tolerance: 1.,
+            max_hboxes: 3,
+        };
+        let reported = check_frame(&boxes, &thresholds);
+        assert_eq!(reported.len(), 2);
+        assert_eq!(reported[0].0, Severity::Warning);
+        assert_eq!(reported[1].0, Severity::Error);
+    }
+}
@@ -0,0 +1,219 @@
+//
+// log_filter.rs
+// Distributed under terms of the GPLv3 license.
+//
+// Like texfot, picks the few interesting lines (warnings, missing files, font substitutions)
+// from the logs of compiled frames. Undefined references and citations are not reported since
+// they are expected when frames are compiled on their own.
+//
+
+use regex::Regex;
+use std::collections::BTreeMap;
+
+lazy_static! {
+    static ref INTERESTING_REGEX: Regex = Regex::new(
+        r"^(?:! |.*Warning: |.*[Ff]ile `[^']*' not found|Missing character|.*Font shape .* (?:not available|undefined))"
+    )
+    .unwrap();
+}
+lazy_static! {
+    static ref EXPECTED_REGEX: Regex = Regex::new(
+        r"(?:Reference|Citation) .* undefined|There were undefined (?:references|citations)|Label\(s\) may have changed|Empty bibliography"
+    )
+    .unwrap();
+}
+lazy_static! {
+    /// Continuation lines of package warnings start with the package name in parentheses
+    static ref CONTINUATION_REGEX: Regex = Regex::new(r"^\([\w.-]+\)\s+\S").unwrap();
+}
+
+lazy_static! {
+    static ref RERUN_REGEX: Regex = Regex::new(r"(?i)\brerun\b").unwrap();
+}
+lazy_static! {
+    static ref LABELS_CHANGED_REGEX: Regex =
+        Regex::new(r"Label\(s\) may have changed|Rerun to get cross-references right").unwrap();
+}
+
+lazy_static! {
+    /// Line of the document at which TeX stopped for an error, e.g. `l.12 \foo`
+    static ref ERROR_LINE_REGEX: Regex = Regex::new(r"^l\.(\d+)").unwrap();
+}
+lazy_static! {
+    static ref INPUT_LINE_REGEX: Regex = Regex::new(r"on input line (\d+)").unwrap();
+}
+
+/// An error or warning of a LaTeX log.
+#[derive(Debug, PartialEq)]
+pub struct LogMessage {
+    pub message: String,
+    /// Line of the compiled document the message refers to (if reported)
+    pub line: Option<usize>,
+    pub is_error: bool,
+}
+
+/// Returns the errors (with the line at which TeX stopped) and the interesting warnings of a
+/// LaTeX log.
+pub fn log_messages(log: &str) -> Vec<LogMessage> {
+    let lines: Vec<&str> = log.lines().collect();
+    let mut messages: Vec<LogMessage> = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| line.starts_with("! "))
+        .map(|(i, line)| LogMessage {
+            message: line[2..].trim().to_string(),
+            line: lines[i + 1..]
+                .iter()
+                .take_while(|l| !l.starts_with("! "))
+                .find_map(|l| ERROR_LINE_REGEX.captures(l))
+                .and_then(|c| c[1].parse().ok()),
+            is_error: true,
+        })
+        .collect();
+    messages.extend(
+        interesting_lines(log)
+            .into_iter()
+            .filter(|line| !line.starts_with("! "))
+            .map(|line| LogMessage {
+                line: INPUT_LINE_REGEX
+                    .captures(&line)
+                    .and_then(|c| c[1].parse().ok()),
+                message: line,
+                is_error: false,
+            }),
+    );
+    messages
+}
+
+/// Returns the interesting lines of a LaTeX log. Continuation lines of a warning are joined to it.
+pub fn interesting_lines(log: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    let mut in_message = false;
+
+    for line in log.lines() {
+        if in_message && CONTINUATION_REGEX.is_match(line) {
+            let continuation = line[line.find(')').unwrap() + 1..].trim();
+            let last = lines.last_mut().unwrap();
+            last.push(' ');
+            last.push_str(continuation);
+            continue;
+        }
+        in_message = INTERESTING_REGEX.is_match(line) && !EXPECTED_REGEX.is_match(line);
+        if in_message {
+            lines.push(line.trim().to_string());
+        }
+    }
+    lines
+}
+
+/// Whether LaTeX asks in `log` to compile the document again (e.g. "Rerun to get cross-references
+/// right"). Without `references` in the document, changed labels (e.g. of the frame itself) are no
+/// reason to compile it again.
+pub fn asks_for_rerun(log: &str, references: bool) -> bool {
+    log.lines().any(|line| {
+        RERUN_REGEX.is_match(line) && (references || !LABELS_CHANGED_REGEX.is_match(line))
+    })
+}
+
+/// Collects the interesting lines of several frames so that every line is reported only once.
+#[derive(Default)]
+pub struct LogSummary {
+    lines: BTreeMap<String, Vec<usize>>,
+}
+
+impl LogSummary {
+    pub fn add(&mut self, frame_idx: usize, log: &str) {
+        for line in interesting_lines(log) {
+            let frames = self.lines.entry(line).or_insert_with(Vec::new);
+            if !frames.contains(&frame_idx) {
+                frames.push(frame_idx);
+            }
+        }
+    }
+
+    pub fn report(&self) {
+        for (line, frames) in self.lines.iter() {
+            let mut frames = frames.clone();
+            frames.sort();
+            let frames: Vec<String> = frames.iter().map(|f| f.to_string()).collect();
+            let location = if frames.len() == 1 {
+                format!("frame {}", frames[0])
+            } else {
+                format!("frames {}", frames.join(", "))
+            };
+            warn!("{}: {}", location, line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interesting_lines() {
+        let log = "This is pdfTeX, Version 3.141592653\n\
+                   (/usr/share/texlive/texmf-dist/tex/latex/base/article.cls\n\
+                   LaTeX Font Warning: Font shape `OT1/cmss/m/n' in size <4> not available\n\
+                   (Font)              size <5> substituted on input line 12.\n\
+                   LaTeX Warning: Reference `fig:1' on page 1 undefined on input line 20.\n\
+                   ! LaTeX Error: File `logo.png' not found.\n\
+                   Package pgf Warning: Your graphic driver pgfsys-dvips.def does not support\n\
+                   (pgf)                fadings. on input line 3.\n\
+                   Output written on frame.pdf (1 page, 1234 bytes).\n";
+        assert_eq!(
+            interesting_lines(log),
+            vec![
+                "LaTeX Font Warning: Font shape `OT1/cmss/m/n' in size <4> not available size <5> substituted on input line 12.",
+                "! LaTeX Error: File `logo.png' not found.",
+                "Package pgf Warning: Your graphic driver pgfsys-dvips.def does not support fadings. on input line 3.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_asks_for_rerun() {
+        let log =
+            "LaTeX Warning: Label(s) may have changed. Rerun to get cross-references right.\n";
+        assert!(asks_for_rerun(log, true));
+        assert!(!asks_for_rerun(log, false));
+        assert!(asks_for_rerun(
+            "Package totcount Warning: Please rerun LaTeX.\n",
+            false
+        ));
+        assert!(!asks_for_rerun(
+            "Output written on frame.pdf (1 page, 1234 bytes).\n",
+            true
+        ));
+    }
+
+    #[test]
+    fn test_log_messages() {
+        let log = "! Undefined control sequence.\n\
+                   l.14 \\foo\n\
+                   \n\
+                   LaTeX Warning: `h' float specifier changed to `ht' on input line 17.\n\
+                   ! Emergency stop.\n";
+        assert_eq!(
+            log_messages(log),
+            vec![
+                LogMessage {
+                    message: "Undefined control sequence.".to_string(),
+                    line: Some(14),
+                    is_error: true,
+                },
+                LogMessage {
+                    message: "Emergency stop.".to_string(),
+                    line: None,
+                    is_error: true,
+                },
+                LogMessage {
+                    message: "LaTeX Warning: `h' float specifier changed to `ht' on input line 17."
+                        .to_string(),
+                    line: Some(17),
+                    is_error: false,
+                },
+            ]
+        );
+    }
+}
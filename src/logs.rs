@@ -0,0 +1,292 @@
+//
+// logs.rs
+// Distributed under terms of the GPLv3 license.
+//
+// The log of every compiled frame is kept (gzip-compressed) next to the frame's PDF in the cache.
+//
+
+use crate::beamer::frame_label;
+use crate::process_file::{
+    get_cache_subdir, get_cachedir, get_input_dir, FasterBeamerError, Result,
+};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::SystemTime;
+
+fn compressed_path(log: &Path) -> PathBuf {
+    log.with_extension("log.gz")
+}
+
+/// Replaces the log file `log` by a compressed copy.
+pub fn compress_log(log: &Path) -> io::Result<()> {
+    let content = fs::read(log)?;
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&content)?;
+    fs::write(compressed_path(log), encoder.finish()?)?;
+    fs::remove_file(log)
+}
+
+/// Reads the log file `log`, or its compressed copy if the log has already been compressed.
+pub fn read_log(log: &Path) -> Option<String> {
+    if let Ok(content) = fs::read(log) {
+        return Some(String::from_utf8_lossy(&content).into_owned());
+    }
+
+    let compressed = fs::read(compressed_path(log)).ok()?;
+    let mut content = Vec::new();
+    GzDecoder::new(&compressed[..])
+        .read_to_end(&mut content)
+        .ok()?;
+    Some(String::from_utf8_lossy(&content).into_owned())
+}
+
+/// A frame of the last build of a document as recorded in its manifest.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct ManifestEntry {
+    pub index: usize,
+    pub hash: String,
+    pub label: Option<String>,
+}
+
+/// File listing the frames compiled by the last build of `input_file`.
+pub fn manifest_path(cache_subdir: &Path, input_file: &str) -> PathBuf {
+    let stem = Path::new(input_file)
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    cache_subdir.join(format!("{}.manifest", stem))
+}
+
+/// Records hash and label of every frame, as a JSON array of `{"index", "hash", "label"}`.
+pub fn write_manifest(
+    cache_subdir: &Path,
+    input_file: &str,
+    frames: &[(md5::Digest, &str)],
+) -> io::Result<()> {
+    let entries: Vec<ManifestEntry> = frames
+        .iter()
+        .enumerate()
+        .map(|(index, (hash, content))| ManifestEntry {
+            index,
+            hash: format!("{:x}", hash),
+            label: frame_label(content),
+        })
+        .collect();
+    let content = serde_json::to_string_pretty(&entries)?;
+    fs::create_dir_all(cache_subdir)?;
+    fs::write(manifest_path(cache_subdir, input_file), content + "\n")
+}
+
+/// Reads a manifest written by `write_manifest` from `path`.
+pub fn read_manifest_file(path: &Path) -> Option<Vec<ManifestEntry>> {
+    let content = fs::read_to_string(path).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+pub fn read_manifest(cache_subdir: &Path, input_file: &str) -> Option<Vec<ManifestEntry>> {
+    read_manifest_file(&manifest_path(cache_subdir, input_file))
+}
+
+/// Outcome of the last build of a document.
+pub struct BuildRecord {
+    pub succeeded: bool,
+    pub output_file: String,
+    pub time: SystemTime,
+}
+
+fn build_record_path(cache_subdir: &Path, input_file: &str) -> PathBuf {
+    manifest_path(cache_subdir, input_file).with_extension("build")
+}
+
+/// Records whether the build of `input_file` to `output_file` succeeded (at the time of writing).
+pub fn write_build_record(
+    cache_subdir: &Path,
+    input_file: &str,
+    succeeded: bool,
+    output_file: &str,
+) -> io::Result<()> {
+    let outcome = if succeeded { "ok" } else { "failed" };
+    fs::create_dir_all(cache_subdir)?;
+    fs::write(
+        build_record_path(cache_subdir, input_file),
+        format!("{}\t{}\n", outcome, output_file),
+    )
+}
+
+pub fn read_build_record(cache_subdir: &Path, input_file: &str) -> Option<BuildRecord> {
+    let path = build_record_path(cache_subdir, input_file);
+    let content = fs::read_to_string(&path).ok()?;
+    let mut fields = content.trim_end().splitn(2, '\t');
+    let succeeded = fields.next()? == "ok";
+    let output_file = fields.next()?.to_string();
+    let time = fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+    Some(BuildRecord {
+        succeeded,
+        output_file,
+        time,
+    })
+}
+
+/// Frames of the last build of a document that were already in the cache (hits) and that were
+/// compiled (misses).
+#[derive(Debug, PartialEq)]
+pub struct CacheRecord {
+    pub hits: usize,
+    pub misses: usize,
+}
+
+fn cache_record_path(cache_subdir: &Path, input_file: &str) -> PathBuf {
+    manifest_path(cache_subdir, input_file).with_extension("cache")
+}
+
+/// Records how many frames of the build of `input_file` were already in the cache.
+pub fn write_cache_record(
+    cache_subdir: &Path,
+    input_file: &str,
+    hits: usize,
+    misses: usize,
+) -> io::Result<()> {
+    fs::create_dir_all(cache_subdir)?;
+    fs::write(
+        cache_record_path(cache_subdir, input_file),
+        format!("{}\t{}\n", hits, misses),
+    )
+}
+
+pub fn read_cache_record(cache_subdir: &Path, input_file: &str) -> Option<CacheRecord> {
+    let content = fs::read_to_string(cache_record_path(cache_subdir, input_file)).ok()?;
+    let mut fields = content.trim_end().splitn(2, '\t');
+    Some(CacheRecord {
+        hits: fields.next()?.parse().ok()?,
+        misses: fields.next()?.parse().ok()?,
+    })
+}
+
+/// Prints (or opens) the log of the frame with `label` (or index `frame_idx`, by default the
+/// first) of the last build of `input_file`.
+pub fn show_log(
+    input_file: &str,
+    label: Option<&str>,
+    frame_idx: Option<&str>,
+    open: bool,
+) -> Result<()> {
+    let cache_subdir = get_cache_subdir(&get_cachedir()?, &get_input_dir(input_file));
+    let manifest = read_manifest(&cache_subdir, input_file).ok_or_else(|| {
+        FasterBeamerError::NotFound(format!("No previous build found for {}", input_file))
+    })?;
+
+    let entry = if let Some(label) = label {
+        manifest.iter().find(|e| e.label.as_deref() == Some(label))
+    } else {
+        let index: usize = frame_idx.and_then(|f| f.parse().ok()).unwrap_or(0);
+        manifest.iter().find(|e| e.index == index)
+    };
+    let entry = entry.ok_or_else(|| {
+        FasterBeamerError::NotFound(format!("No such frame in the last build of {}", input_file))
+    })?;
+
+    let log_file = cache_subdir.join(format!("{}.log", entry.hash));
+    let log = read_log(&log_file).ok_or_else(|| {
+        FasterBeamerError::NotFound(format!("No log found for frame {}", entry.index))
+    })?;
+
+    if open {
+        // The cache only keeps the compressed log, and is left as it is
+        let temp_dir = std::env::temp_dir();
+        let mut temp_file = tempfile::Builder::new()
+            .prefix(&format!("faster-beamer-{}-", entry.hash))
+            .suffix(".log")
+            .tempfile()
+            .map_err(FasterBeamerError::io(&temp_dir))?;
+        temp_file
+            .write_all(log.as_bytes())
+            .map_err(FasterBeamerError::io(temp_file.path()))?;
+        // Kept, since the viewer may only read it after faster-beamer exits
+        let log_file = temp_file
+            .into_temp_path()
+            .keep()
+            .map_err(|err| FasterBeamerError::io(&temp_dir)(err.error))?;
+        let opener = if cfg!(target_os = "macos") {
+            "open"
+        } else {
+            "xdg-open"
+        };
+        info!("Opening {}", log_file.display());
+        Command::new(opener)
+            .arg(&log_file)
+            .spawn()
+            .map_err(|err| FasterBeamerError::Program {
+                program: opener.to_string(),
+                message: err.to_string(),
+            })?;
+    } else {
+        print!("{}", log);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_compressed_log_roundtrip() {
+        let dir = tempdir().unwrap();
+        let log = dir.path().join("abc.log");
+        fs::write(&log, "Output written on abc.pdf (1 page, 10 bytes).").unwrap();
+
+        compress_log(&log).unwrap();
+        assert!(!log.exists());
+        assert!(dir.path().join("abc.log.gz").is_file());
+        assert_eq!(
+            read_log(&log).unwrap(),
+            "Output written on abc.pdf (1 page, 10 bytes)."
+        );
+    }
+
+    #[test]
+    fn test_manifest_roundtrip() {
+        let dir = tempdir().unwrap();
+        let intro = "\\begin{frame}[label=intro]\\end{frame}";
+        let other = "\\begin{frame}\\end{frame}";
+        let frames = [(md5::compute(intro), intro), (md5::compute(other), other)];
+        write_manifest(dir.path(), "talk.tex", &frames).unwrap();
+        let content = fs::read_to_string(dir.path().join("talk.manifest")).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(json[0]["label"], "intro");
+        assert_eq!(json[1]["label"], serde_json::Value::Null);
+        assert_eq!(
+            read_manifest(dir.path(), "talk.tex").unwrap(),
+            vec![
+                ManifestEntry {
+                    index: 0,
+                    hash: format!("{:x}", frames[0].0),
+                    label: Some("intro".to_string()),
+                },
+                ManifestEntry {
+                    index: 1,
+                    hash: format!("{:x}", frames[1].0),
+                    label: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cache_record_roundtrip() {
+        let dir = tempdir().unwrap();
+        assert_eq!(read_cache_record(dir.path(), "talk.tex"), None);
+        write_cache_record(dir.path(), "talk.tex", 10, 2).unwrap();
+        assert_eq!(
+            read_cache_record(dir.path(), "talk.tex"),
+            Some(CacheRecord { hits: 10, misses: 2 })
+        );
+    }
+}
@@ -0,0 +1,368 @@
+//
+// lsp.rs
+// Distributed under terms of the GPLv3 license.
+//
+// `faster-beamer lsp` is a minimal Language Server (on stdin and stdout): documents are built
+// when they are opened or saved, the errors and warnings in the logs of their frames are
+// published as diagnostics on the lines of the source, and the code action "Compile frame"
+// compiles only the frame under the cursor and shows it in the output PDF.
+//
+
+use crate::batch::{build_jobs, output_filename, process_jobs};
+use crate::cli::subcommand_options;
+use crate::events;
+use crate::log_filter::log_messages;
+use crate::logs::read_log;
+use crate::process_file::{
+    compile_extracted, compile_frames, frame_at_line, load_frames, CompiledFrames, ExtractedFrames,
+    FasterBeamerError, Result,
+};
+use serde_json::{json, Value};
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+/// Command of the code action that compiles the frame under the cursor
+pub const COMPILE_FRAME_COMMAND: &str = "faster-beamer.compileFrame";
+
+/// An error or warning at a line of the source (1-based).
+#[derive(Debug, PartialEq)]
+pub struct Diagnostic {
+    pub line: usize,
+    pub is_error: bool,
+    pub message: String,
+}
+
+/// Errors and warnings in the logs of `compiled`, on the lines of the source they refer to (or
+/// the first line of their frame).
+pub fn frame_diagnostics(compiled: &CompiledFrames) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for (frame_idx, frame) in compiled.frames.iter().enumerate() {
+        let (frame_line, _) = compiled.parsed_file.get_position(frame.offset);
+        let failed = frame.failure_marker(&compiled.cache_subdir).is_file();
+        let messages = read_log(&frame.log(&compiled.cache_subdir))
+            .map(|log| log_messages(&log))
+            .unwrap_or_default();
+        if failed && !messages.iter().any(|m| m.is_error) {
+            diagnostics.push(Diagnostic {
+                line: frame_line,
+                is_error: true,
+                message: format!("Frame {} failed to compile", frame_idx),
+            });
+        }
+        for message in messages {
+            diagnostics.push(Diagnostic {
+                line: message
+                    .line
+                    .filter(|&l| l >= frame.body_line)
+                    .map(|l| frame_line + l - frame.body_line)
+                    .unwrap_or(frame_line),
+                is_error: message.is_error,
+                message: message.message,
+            });
+        }
+    }
+    diagnostics
+}
+
+/// Path of a `file://` URI.
+pub fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let path = uri.strip_prefix("file://")?;
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut rest = path.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        match (byte, tail) {
+            (b'%', [high, low, ..]) => {
+                let hex = std::str::from_utf8(&[*high, *low]).ok()?.to_string();
+                bytes.push(u8::from_str_radix(&hex, 16).ok()?);
+                rest = &tail[2..];
+            }
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8(bytes).ok().map(PathBuf::from)
+}
+
+/// `file://` URI of `path`.
+pub fn path_to_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for byte in path.to_string_lossy().bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => {
+                uri.push(byte as char)
+            }
+            byte => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    uri
+}
+
+/// Reads a message (with its `Content-Length` header). Returns `None` at the end of the input.
+pub fn read_message<R: BufRead>(input: &mut R) -> io::Result<Option<Value>> {
+    let mut content_length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(length) = header.strip_prefix("Content-Length:") {
+            content_length = length.trim().parse().ok();
+        }
+    }
+    let content_length = content_length.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length header")
+    })?;
+    let mut content = vec![0; content_length];
+    input.read_exact(&mut content)?;
+    serde_json::from_slice(&content)
+        .map(Some)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+pub fn write_message<W: Write>(output: &mut W, message: &Value) -> io::Result<()> {
+    let content = message.to_string();
+    write!(
+        output,
+        "Content-Length: {}\r\n\r\n{}",
+        content.len(),
+        content
+    )?;
+    output.flush()
+}
+
+struct Server<W: Write> {
+    output: W,
+    shutdown: bool,
+}
+
+impl<W: Write> Server<W> {
+    fn send(&mut self, message: Value) -> Result<()> {
+        write_message(&mut self.output, &message).map_err(FasterBeamerError::io("<stdout>"))
+    }
+
+    fn publish(&mut self, input_file: &str, diagnostics: &[Diagnostic]) -> Result<()> {
+        let diagnostics: Vec<Value> = diagnostics
+            .iter()
+            .map(|d| {
+                json!({
+                    "range": {
+                        "start": {"line": d.line - 1, "character": 0},
+                        "end": {"line": d.line, "character": 0},
+                    },
+                    "severity": if d.is_error { 1 } else { 2 },
+                    "source": "faster-beamer",
+                    "message": d.message,
+                })
+            })
+            .collect();
+        let uri = path_to_uri(&Path::new(input_file).canonicalize().unwrap_or_default());
+        self.send(json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": {"uri": uri, "diagnostics": diagnostics},
+        }))
+    }
+
+    /// Builds `input_file` like `faster-beamer build` and publishes the diagnostics of its frames.
+    fn build(&mut self, input_file: &str) -> Result<()> {
+        let args = subcommand_options("build", &[input_file.to_string()])?;
+        let jobs = build_jobs(&args);
+        let result = process_jobs(&jobs, &args).remove(0);
+        let diagnostics = match result {
+            Err(FasterBeamerError::PreambleCompile { log_excerpt, .. }) => vec![Diagnostic {
+                line: 1,
+                is_error: true,
+                message: format!("Failed to compile the preamble\n{}", log_excerpt),
+            }],
+            Err(err @ FasterBeamerError::InputFileNotExistent(_)) => return Err(err),
+            // All frames are in the cache now
+            _ => frame_diagnostics(&compile_frames(input_file, &args)?),
+        };
+        self.publish(input_file, &diagnostics)
+    }
+
+    /// Compiles only the frame at `line` (0-based) of `input_file` and links the output file to
+    /// it.
+    fn compile_frame(&mut self, input_file: &str, line: usize) -> Result<()> {
+        let args = subcommand_options("build", &[input_file.to_string()])?;
+        let extracted = load_frames(input_file, &args)?;
+        let frame_idx = frame_at_line(&extracted, line + 1).ok_or_else(|| {
+            FasterBeamerError::NotFound(format!("No frame at {}:{}", input_file, line + 1))
+        })?;
+        let single_frame = ExtractedFrames {
+            frames: vec![extracted.frames[frame_idx].clone()],
+            frame_offsets: vec![extracted.frame_offsets[frame_idx]],
+            priority: vec![true],
+            parsed_file: extracted.parsed_file,
+        };
+        let compiled =
+            compile_extracted(input_file, single_frame, &args, args.aspectratio.as_deref())?;
+
+        let pdf = compiled.frames[0].pdf(&compiled.cache_subdir);
+        let output_file = output_filename(
+            args.output_file.as_deref(),
+            input_file,
+            args.profile.as_deref(),
+        );
+        if pdf.is_file() {
+            let _result = std::fs::remove_file(&output_file);
+            info!("Linking: {:?} -> {:?}", &pdf, &output_file);
+            ::symlink::symlink_file(&pdf, &output_file)
+                .map_err(FasterBeamerError::io(&output_file))?;
+        }
+        self.publish(input_file, &frame_diagnostics(&compiled))
+    }
+
+    /// Answers `request` (or handles the notification). Returns the result of a request.
+    fn handle(&mut self, method: &str, params: &Value) -> Result<Value> {
+        let document = params["textDocument"]["uri"]
+            .as_str()
+            .and_then(uri_to_path)
+            .map(|path| path.to_string_lossy().into_owned());
+        match (method, document) {
+            ("initialize", _) => Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": {"openClose": true, "change": 0, "save": true},
+                    "codeActionProvider": true,
+                    "executeCommandProvider": {"commands": [COMPILE_FRAME_COMMAND]},
+                },
+                "serverInfo": {"name": "faster-beamer"},
+            })),
+            ("textDocument/didOpen", Some(input_file))
+            | ("textDocument/didSave", Some(input_file)) => {
+                self.build(&input_file)?;
+                Ok(Value::Null)
+            }
+            ("textDocument/codeAction", Some(_)) => {
+                let uri = &params["textDocument"]["uri"];
+                let line = &params["range"]["start"]["line"];
+                Ok(json!([{
+                    "title": "Compile frame",
+                    "command": {
+                        "title": "Compile frame",
+                        "command": COMPILE_FRAME_COMMAND,
+                        "arguments": [uri, line],
+                    },
+                }]))
+            }
+            ("workspace/executeCommand", _)
+                if params["command"].as_str() == Some(COMPILE_FRAME_COMMAND) =>
+            {
+                let arguments = &params["arguments"];
+                match (
+                    arguments[0].as_str().and_then(uri_to_path),
+                    arguments[1].as_u64(),
+                ) {
+                    (Some(input_file), Some(line)) => {
+                        self.compile_frame(&input_file.to_string_lossy(), line as usize)?;
+                        Ok(Value::Null)
+                    }
+                    _ => Err(FasterBeamerError::InvalidOptions(format!(
+                        "expected a document and a line as arguments of {}",
+                        COMPILE_FRAME_COMMAND
+                    ))),
+                }
+            }
+            ("shutdown", _) => {
+                self.shutdown = true;
+                Ok(Value::Null)
+            }
+            _ => Ok(Value::Null),
+        }
+    }
+}
+
+/// Answers the messages of a client on `input` until it sends `exit`.
+pub fn serve<R: BufRead, W: Write>(mut input: R, output: W) -> Result<()> {
+    // Stdout belongs to the protocol
+    events::set_enabled(false);
+    let mut server = Server {
+        output,
+        shutdown: false,
+    };
+    while let Some(message) = read_message(&mut input).map_err(FasterBeamerError::io("<stdin>"))? {
+        let method = message["method"].as_str().unwrap_or_default().to_string();
+        if method == "exit" {
+            break;
+        }
+        let result = server.handle(&method, &message["params"]);
+        let id = &message["id"];
+        if id.is_null() {
+            // Notifications have no answer
+            if let Err(err) = result {
+                error!("{}: {}", method, err);
+            }
+            continue;
+        }
+        let answer = match result {
+            Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+            Err(err) => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": {"code": -32603, "message": err.to_string()},
+            }),
+        };
+        server.send(answer)?;
+    }
+    if !server.shutdown {
+        warn!("Exiting without shutdown request");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uri() {
+        let path = Path::new("/home/me/Talks/my talk.tex");
+        assert_eq!(path_to_uri(path), "file:///home/me/Talks/my%20talk.tex");
+        assert_eq!(uri_to_path(&path_to_uri(path)), Some(path.to_owned()));
+        assert_eq!(uri_to_path("https://example.org"), None);
+    }
+
+    #[test]
+    fn test_serve() {
+        let mut input = Vec::new();
+        for message in &[
+            json!({"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {}}),
+            json!({"jsonrpc": "2.0", "method": "initialized", "params": {}}),
+            json!({"jsonrpc": "2.0", "id": 2, "method": "textDocument/codeAction", "params": {
+                "textDocument": {"uri": "file:///talk.tex"},
+                "range": {"start": {"line": 9, "character": 0}, "end": {"line": 9, "character": 0}},
+            }}),
+            json!({"jsonrpc": "2.0", "id": 3, "method": "shutdown"}),
+            json!({"jsonrpc": "2.0", "method": "exit"}),
+        ] {
+            write_message(&mut input, message).unwrap();
+        }
+
+        let mut output = Vec::new();
+        serve(&input[..], &mut output).ok().unwrap();
+        let mut output = &output[..];
+        let mut answers = Vec::new();
+        while let Some(answer) = read_message(&mut output).unwrap() {
+            answers.push(answer);
+        }
+        assert_eq!(answers.len(), 3);
+        assert_eq!(
+            answers[0]["result"]["capabilities"]["codeActionProvider"],
+            true
+        );
+        assert_eq!(
+            answers[1]["result"][0]["command"]["arguments"],
+            json!(["file:///talk.tex", 9])
+        );
+        assert_eq!(
+            answers[2],
+            json!({"jsonrpc": "2.0", "id": 3, "result": null})
+        );
+    }
+}
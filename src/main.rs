@@ -1,22 +1,17 @@
 #[macro_use]
 extern crate log;
-#[macro_use]
-extern crate lazy_static;
-#[macro_use]
-extern crate failure_derive;
-
-mod beamer;
-mod latexcompile;
-mod parsing;
-mod process_file;
-mod tree_traversal;
 
-use clap::{App, Arg};
+use clap::ArgMatches;
+#[cfg(unix)]
+use faster_beamer::daemon;
+use faster_beamer::lint::LintThresholds;
+use faster_beamer::{
+    batch, cache_stats, clean, cli, export, extract, figures, lint, logs, lsp, merge, nvim,
+    outline, preflight, recovery, status, synctex, warm, watch, FasterBeamerError, Options, Result,
+};
 use std::env;
-use std::env::current_dir;
-use std::path::Path;
-use std::{thread, time};
-use process_file::FasterBeamerError;
+use std::io;
+use std::str::FromStr;
 
 fn main() {
     if env::var("RUST_LOG").is_err() {
@@ -27,114 +22,257 @@ fn main() {
         pretty_env_logger::init();
     }
 
-    let matches = App::new("faster-beamer")
-        .version("0.1.6")
-        .author("Stephan Seitz <stephan.seitz@fau.de>")
-        .about("Incremental compiler for Beamer LaTeX presentations")
-        .arg(
-            Arg::with_name("watch")
-                .short("w")
-                .long("watch")
-                .help("Sets a custom config file"),
-        )
-        .arg(
-            Arg::with_name("INPUT")
-                .help("Sets the input file to use")
-                .required(true)
-                .index(1),
-        )
-        .arg(
-            Arg::with_name("unite")
-                .short("u")
-                .long("unite")
-                .help("Unites all slides to a PDF (default is only to output newest slide)"),
-        )
-        .arg(
-            Arg::with_name("pdfunite")
-                .short("x")
-                .long("pdfunite")
-                .help("Unites all slides to a PDF using pdfunite"),
-        )
-        .arg(
-            Arg::with_name("frame-numbers")
-                .short("f")
-                .long("frame-numbers")
-                .help("Try to print correct frames numbers. This can harm cache performance when swapping frames."),
-        )
-        .arg(
-            Arg::with_name("tree-sitter")
-                .short("t")
-                .long("tree-sitter")
-                .help("Use tree-sitter to parse LaTeX (instead of regexes)"),
-        )
-        .arg(
-            Arg::with_name("compiler")
-                .short("c")
-                .long("compiler")
-                .takes_value(true)
-                .default_value("pdflatex")
-                .help("LaTeX compiler")
-        )
-        .arg(
-            Arg::with_name("OUTPUT")
-                .help("Filename for output PDF")
-                .takes_value(true)
-                .default_value("output.pdf")
-                .index(2),
-        )
-        //.arg(
-        //Arg::with_name("draft")
-        //.short("d")
-        //.help("Compile in draft mode")
-        //)
-        .get_matches();
+    let matches = cli::app().get_matches();
+    let options = Options::from_matches(&matches).unwrap_or_else(|err| exit_with(Err(err)));
+    cli::set_globals(&options);
+
+    if let Some(num_threads) = options.jobs {
+        if let Err(err) = rayon::ThreadPoolBuilder::new()
+            .num_threads(num_threads)
+            .build_global()
+        {
+            warn!("Failed to set number of jobs: {}", err);
+        }
+    }
+
+    match matches.subcommand() {
+        ("lint", Some(lint_matches)) => {
+            let input_file = lint_matches.value_of("INPUT").unwrap();
+            let jobs = batch::make_jobs(&[input_file.to_string()], None, None, None);
+            if let Err(err) = preflight::check(&jobs, &options) {
+                error!("{}", err);
+                std::process::exit(1);
+            }
+            let thresholds =
+                lint_thresholds(lint_matches).unwrap_or_else(|err| exit_with(Err(err)));
+            match lint::lint(input_file, &thresholds, &options) {
+                Ok(true) => std::process::exit(0),
+                Ok(false) => std::process::exit(1),
+                Err(err) => exit_with(Err(err)),
+            }
+        }
+        ("outline", Some(outline_matches)) => {
+            let input_file = outline_matches.value_of("INPUT").unwrap();
+            let output_file = outline_matches.value_of("OUTPUT").unwrap();
+            exit_with(outline::export_outline(input_file, output_file, &options))
+        }
+        ("extract", Some(extract_matches)) => {
+            let input_file = extract_matches.value_of("INPUT").unwrap();
+            let out = extract_matches.value_of("out").unwrap();
+            let standalone = extract_matches.is_present("standalone");
+            exit_with(extract::extract(input_file, out, standalone, &options))
+        }
+        ("figures", Some(figures_matches)) => {
+            let input_file = figures_matches.value_of("INPUT").unwrap();
+            let out = figures_matches.value_of("out").unwrap();
+            let png = figures_matches.is_present("png");
+            // Failed figures are reported one by one
+            match figures::export_figures(input_file, out, png, &options) {
+                Ok(()) => std::process::exit(0),
+                Err(_) => std::process::exit(1),
+            }
+        }
+        ("export", Some(export_matches)) => {
+            let input_file = export_matches.value_of("INPUT").unwrap();
+            let out = export_matches.value_of("out").unwrap();
+            let format = export_matches.value_of("format").unwrap();
+            let thumbnails = export_matches.is_present("thumbnails");
+            let jobs = batch::make_jobs(&[input_file.to_string()], None, None, None);
+            if let Err(err) = preflight::check(&jobs, &options) {
+                error!("{}", err);
+                std::process::exit(1);
+            }
+            match parse_value(export_matches, "dpi")
+                .and_then(|dpi| export::export(input_file, out, format, dpi, thumbnails, &options))
+            {
+                Ok(true) => std::process::exit(0),
+                Ok(false) => std::process::exit(1),
+                Err(err) => exit_with(Err(err)),
+            }
+        }
+        ("export-frame", Some(export_matches)) => {
+            let input_file = export_matches.value_of("INPUT").unwrap();
+            let format = export_matches.value_of("format").unwrap();
+            exit_with(parse_value(export_matches, "dpi").and_then(|dpi| {
+                export::export_frame(
+                    input_file,
+                    export_matches.value_of("label"),
+                    export_matches.value_of("index"),
+                    export_matches.value_of("output"),
+                    format,
+                    dpi,
+                    parse_value(export_matches, "delay")?,
+                    &options,
+                )
+            }))
+        }
+        ("status", Some(status_matches)) => {
+            let input_file = status_matches.value_of("INPUT").unwrap();
+            exit_with(status::status(input_file, &options))
+        }
+        ("warm", Some(warm_matches)) => {
+            let inputs: Vec<&str> = warm_matches.values_of("INPUT").unwrap().collect();
+            let input_files = batch::expand_inputs(&inputs);
+            let jobs = batch::make_jobs(&input_files, None, None, None);
+            if let Err(err) = preflight::check(&jobs, &options) {
+                error!("{}", err);
+                std::process::exit(1);
+            }
+            // Failed documents are reported one by one
+            match warm::warm(&input_files, &options) {
+                Ok(()) => std::process::exit(0),
+                Err(_) => std::process::exit(1),
+            }
+        }
+        ("merge", Some(merge_matches)) => {
+            let output_file = merge_matches.value_of("OUTPUT").unwrap();
+            let pdfs: Vec<&str> = merge_matches
+                .values_of("PDFS")
+                .into_iter()
+                .flatten()
+                .collect();
+            exit_with(merge::merge(
+                output_file,
+                merge_matches.value_of("from-manifest"),
+                &pdfs,
+                &options,
+            ))
+        }
+        ("dump-frame", Some(dump_matches)) => {
+            let input_file = dump_matches.value_of("INPUT").unwrap();
+            exit_with(extract::dump_frame(
+                input_file,
+                dump_matches.value_of("label"),
+                dump_matches.value_of("FRAME"),
+                &options,
+            ))
+        }
+        ("log", Some(log_matches)) => {
+            let input_file = log_matches.value_of("INPUT").unwrap();
+            exit_with(logs::show_log(
+                input_file,
+                log_matches.value_of("label"),
+                log_matches.value_of("frame"),
+                log_matches.is_present("open"),
+            ))
+        }
+        ("build", Some(_)) => build(&options, false),
+        ("watch", Some(_)) => build(&options, true),
+        ("clean", Some(clean_matches)) => {
+            let input_file = clean_matches
+                .value_of("INPUT")
+                .or_else(|| clean_matches.value_of("project"));
+            exit_with(clean::clean(
+                input_file,
+                clean_matches.is_present("all"),
+                clean_matches.is_present("gc"),
+                &options,
+            ))
+        }
+        ("cache", Some(cache_matches)) => match cache_matches.subcommand() {
+            ("stats", Some(_)) => exit_with(cache_stats::print_stats()),
+            _ => unreachable!("the cache subcommand requires a subcommand"),
+        },
+        ("frame-at", Some(frame_at_matches)) => {
+            let position = frame_at_matches.value_of("POSITION").unwrap();
+            match status::parse_position(position) {
+                Some((input_file, line)) => exit_with(status::frame_at(input_file, line, &options)),
+                None => exit_with(Err(FasterBeamerError::InvalidOptions(format!(
+                    "expected FILE:LINE, got {}",
+                    position
+                )))),
+            }
+        }
+        ("locate", Some(locate_matches)) => {
+            let input_file = locate_matches.value_of("INPUT").unwrap();
+            exit_with(parse_value(locate_matches, "page").and_then(|page| {
+                synctex::inverse_search(
+                    input_file,
+                    page,
+                    parse_value(locate_matches, "x")?,
+                    parse_value(locate_matches, "y")?,
+                    &options,
+                )
+            }))
+        }
+        ("frames", Some(frames_matches)) => {
+            let input_file = frames_matches.value_of("INPUT").unwrap();
+            exit_with(status::list_frames(input_file, &options))
+        }
+        #[cfg(unix)]
+        ("daemon", Some(daemon_matches)) => {
+            recovery::recover_cache();
+            exit_with(
+                daemon::socket_path(daemon_matches.value_of("socket"))
+                    .and_then(|socket| daemon::serve(&socket)),
+            )
+        }
+        ("lsp", Some(_)) => {
+            recovery::recover_cache();
+            let stdin = io::stdin();
+            exit_with(lsp::serve(stdin.lock(), io::stdout()))
+        }
+        ("nvim", Some(_)) => {
+            recovery::recover_cache();
+            exit_with(nvim::serve(io::stdin(), io::stdout()))
+        }
+        // `faster-beamer file.tex`
+        _ => build(&options, options.watch),
+    }
+}
 
-    let is_watch_mode = matches.is_present("watch");
-    let input_file = matches.value_of("INPUT").unwrap();
+/// Builds the inputs of `options` (and keeps watching them with `is_watch_mode`).
+fn build(options: &Options, is_watch_mode: bool) {
+    let jobs = batch::build_jobs(options);
 
-    let cwd = current_dir().unwrap();
-    let input_dir = Path::new(input_file)
-        .parent()
-        .unwrap_or(&cwd)
-        .canonicalize()
-        .unwrap_or_else(|_| cwd.to_owned());
+    if let Err(err) = preflight::check(&jobs, options) {
+        error!("{}", err);
+        std::process::exit(-1);
+    }
+    recovery::recover(&jobs);
 
-    info!("Processing {:?}.", input_file);
-    let result = process_file::process_file(input_file, &matches);
-    if result == Err(FasterBeamerError::InputFileNotExistent) || result == Err(FasterBeamerError::IoError) {
+    let results = batch::process_jobs(&jobs, options);
+    if results.iter().any(|result| {
+        matches!(
+            result,
+            Err(FasterBeamerError::InputFileNotExistent(_)) | Err(FasterBeamerError::Io { .. })
+        )
+    }) {
         std::process::exit(-1);
     };
 
     if is_watch_mode {
-        use hotwatch::{Event, Hotwatch};
-        let matches = matches.clone();
+        if let Err(err) = watch::watch(&jobs, options) {
+            error!("Failed to watch the input files: {}", err);
+            std::process::exit(-1);
+        }
+    }
+}
 
-        let mut hotwatch = Hotwatch::new().expect("Hotwatch failed to initialize.");
-        hotwatch
-            .watch(input_dir, move |event: Event| match event {
-                Event::Write(file) | Event::NoticeRemove(file) => {
-                    trace!("{:?} has changed.", file);
-                    thread::sleep(time::Duration::from_millis(50));
-                    let input_file = matches.value_of("INPUT").unwrap();
-                    match (Path::new(&input_file).canonicalize(), file.canonicalize()) {
-                        (Ok(file), Ok(changed_file)) if file == changed_file => {
-                            let path_str = file.to_str().unwrap();
-                            info!("Processing {:?}.", &path_str);
-                            let _result = process_file::process_file(path_str, &matches);
-                        }
-                        _ => {}
-                    }
-                }
-                _ => {
-                    trace!("{:?}", event);
-                }
-            })
-            .expect("Failed to watch file!");
-        info!("Watch mode");
-        info!("Watching {}", input_file);
+/// Thresholds of `faster-beamer lint` from the options of its subcommand.
+fn lint_thresholds(matches: &ArgMatches) -> Result<LintThresholds> {
+    Ok(LintThresholds {
+        vbox_tolerance: parse_value(matches, "vbox-tolerance")?,
+        hbox_tolerance: parse_value(matches, "hbox-tolerance")?,
+        max_hboxes: parse_value(matches, "max-hboxes")?,
+    })
+}
+
+/// Parses the value of the option `name` of `matches`.
+fn parse_value<T: FromStr>(matches: &ArgMatches, name: &str) -> Result<T> {
+    let value = matches.value_of(name).unwrap_or_default();
+    value
+        .parse()
+        .map_err(|_| FasterBeamerError::InvalidOptions(format!("invalid --{} {:?}", name, value)))
+}
 
-        loop {
-            thread::sleep(time::Duration::from_millis(100));
+/// Exits with status 0 if `result` is ok, otherwise reports the error and exits with status 1.
+fn exit_with(result: Result<()>) -> ! {
+    match result {
+        Ok(()) => std::process::exit(0),
+        Err(err) => {
+            error!("{}", err);
+            std::process::exit(1)
         }
     }
 }
@@ -0,0 +1,92 @@
+//
+// markdown.rs
+// Distributed under terms of the GPLv3 license.
+//
+// Markdown presentations are converted by pandoc's beamer writer. The generated LaTeX document is
+// written next to the input (so that relative paths of images keep working) and then split into
+// frames like any other input.
+//
+
+use crate::options::Options;
+use crate::process_file::{FasterBeamerError, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Whether `input_file` is converted by pandoc.
+pub fn is_markdown(input_file: &str) -> bool {
+    match Path::new(input_file).extension().and_then(|e| e.to_str()) {
+        Some(extension) => ["md", "markdown"].contains(&extension.to_lowercase().as_str()),
+        None => false,
+    }
+}
+
+/// LaTeX document generated for the Markdown file `input_file`: `.<name>.tex` in the same directory.
+pub fn generated_tex(input_file: &str) -> PathBuf {
+    let path = Path::new(input_file);
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{}.tex", name))
+}
+
+/// Runs pandoc on `input_file` and returns the generated LaTeX document. The document is only
+/// rewritten if pandoc's output changed.
+pub fn convert(input_file: &str, args: &Options) -> Result<PathBuf> {
+    let output = Command::new("pandoc")
+        .arg("--to=beamer")
+        .arg("--standalone")
+        .args(&args.pandoc_args)
+        .arg(input_file)
+        .output();
+    let tex = match output {
+        Err(e) => {
+            return Err(FasterBeamerError::Program {
+                program: "pandoc".to_string(),
+                message: e.to_string(),
+            });
+        }
+        Ok(output) if !output.status.success() => {
+            return Err(FasterBeamerError::Program {
+                program: "pandoc".to_string(),
+                message: format!(
+                    "Failed to convert {}: {}",
+                    input_file,
+                    String::from_utf8_lossy(&output.stderr)
+                ),
+            });
+        }
+        Ok(output) => output.stdout,
+    };
+
+    let tex_file = generated_tex(input_file);
+    if fs::read(&tex_file).ok().as_ref() != Some(&tex) {
+        fs::write(&tex_file, tex).map_err(FasterBeamerError::io(&tex_file))?;
+    }
+    Ok(tex_file)
+}
+
+/// Returns the LaTeX source of `input_file`: the file itself, or the converted document for
+/// Markdown input.
+pub fn latex_source(input_file: &str, args: &Options) -> Result<String> {
+    if is_markdown(input_file) {
+        info!("Converting {} with pandoc", input_file);
+        convert(input_file, args).map(|tex_file| tex_file.to_string_lossy().into_owned())
+    } else {
+        Ok(input_file.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_markdown_input() {
+        assert!(is_markdown("slides/talk.md"));
+        assert!(is_markdown("talk.Markdown"));
+        assert!(!is_markdown("talk.tex"));
+        assert_eq!(
+            generated_tex("slides/talk.md"),
+            PathBuf::from("slides/.talk.md.tex")
+        );
+    }
+}
@@ -0,0 +1,510 @@
+//
+// merge.rs
+// Distributed under terms of the GPLv3 license.
+//
+// Concatenation of frame PDFs, either page by page like pdfunite (see merger.rs) or with a small
+// LaTeX document using pdfpages (which also adds a named destination and a bookmark for every
+// frame).
+//
+
+use crate::beamer::{destination_name, unique_destinations};
+use crate::catalog::{import_dict, merge_catalogs};
+use crate::error::log_excerpt;
+use crate::latexcompile::{LatexCompiler, LatexInput, LatexRunOptions};
+use crate::links::{copy_links, fix_links};
+use crate::logs::{read_manifest, read_manifest_file};
+use crate::merger::merge_with;
+use crate::optimize::share_resources;
+use crate::options::Options;
+use crate::process_file::{
+    engine_command, get_cache_subdir, get_cachedir, get_input_dir, latex_args, FasterBeamerError,
+    Result,
+};
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A PDF to merge and the name of the destination on its first page.
+pub struct MergeInput {
+    pub pdf: PathBuf,
+    pub destination: String,
+}
+
+/// Attributes of a page that it inherits from the page tree if it does not set them itself.
+const INHERITED_ATTRIBUTES: &[&[u8]] = &[b"Resources", b"MediaBox", b"CropBox", b"Rotate"];
+
+/// Copy of `page` of `source` for another document, with the attributes it inherits.
+fn page_with_inherited(source: &Document, page: ObjectId) -> lopdf::Result<Dictionary> {
+    let mut dict = source.get_dictionary(page)?.clone();
+    let mut parent = dict.get(b"Parent").and_then(Object::as_reference).ok();
+    while let Some(parent_id) = parent {
+        let parent_dict = source.get_dictionary(parent_id)?;
+        for key in INHERITED_ATTRIBUTES {
+            if !dict.has(key) {
+                if let Ok(value) = parent_dict.get(key) {
+                    dict.set(key.to_vec(), value.clone());
+                }
+            }
+        }
+        parent = parent_dict
+            .get(b"Parent")
+            .and_then(Object::as_reference)
+            .ok();
+    }
+    dict.remove(b"Parent");
+    Ok(dict)
+}
+
+/// Copies the pages of `source` (with everything they reference) into `target`, as children of
+/// the page tree node `parent`. Returns the copies of the pages.
+fn import_pages(
+    source: &Document,
+    target: &mut Document,
+    parent: ObjectId,
+) -> lopdf::Result<Vec<ObjectId>> {
+    if source.version > target.version {
+        target.version = source.version.clone();
+    }
+    let pages = source.get_pages();
+    // Links and annotations refer to the copies of the pages, not to the originals
+    let mut copies = HashMap::new();
+    for page in pages.values() {
+        copies.insert(*page, target.new_object_id());
+    }
+    let mut imported = Vec::with_capacity(pages.len());
+    for page in pages.values() {
+        let mut dict = page_with_inherited(source, *page)?;
+        dict = import_dict(source, target, &dict, &mut copies);
+        dict.set("Parent", Object::Reference(parent));
+        target
+            .objects
+            .insert(copies[page], Object::Dictionary(dict));
+        imported.push(copies[page]);
+    }
+    Ok(imported)
+}
+
+/// The pages of `pdfs` (with everything they reference) in one document.
+pub fn concatenate(pdfs: &[PathBuf]) -> lopdf::Result<Document> {
+    let mut merged = Document::with_version("1.5");
+    let pages_id = merged.new_object_id();
+    let mut kids = Vec::new();
+    for pdf in pdfs {
+        let source = Document::load(pdf)?;
+        for page in import_pages(&source, &mut merged, pages_id)? {
+            kids.push(Object::Reference(page));
+        }
+    }
+
+    let mut pages_dict = Dictionary::new();
+    pages_dict.set("Type", Object::Name(b"Pages".to_vec()));
+    pages_dict.set("Count", kids.len() as i64);
+    pages_dict.set("Kids", kids);
+    merged
+        .objects
+        .insert(pages_id, Object::Dictionary(pages_dict));
+    let mut catalog = Dictionary::new();
+    catalog.set("Type", Object::Name(b"Catalog".to_vec()));
+    catalog.set("Pages", Object::Reference(pages_id));
+    let catalog_id = merged.add_object(catalog);
+    merged.trailer.set("Root", Object::Reference(catalog_id));
+    Ok(merged)
+}
+
+/// Replaces references to the keys of `replacements` in `object` by references to their values.
+fn replace_references(object: &mut Object, replacements: &HashMap<ObjectId, ObjectId>) {
+    match object {
+        Object::Reference(id) => {
+            if let Some(replacement) = replacements.get(id) {
+                *id = *replacement;
+            }
+        }
+        Object::Array(array) => {
+            for object in array.iter_mut() {
+                replace_references(object, replacements);
+            }
+        }
+        Object::Dictionary(dict) => {
+            for (_, object) in dict.iter_mut() {
+                replace_references(object, replacements);
+            }
+        }
+        Object::Stream(stream) => {
+            for (_, object) in stream.dict.iter_mut() {
+                replace_references(object, replacements);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Pages of a united PDF that are replaced by the pages of another PDF.
+pub struct PageReplacement {
+    /// First page (0-based) of the replaced pages
+    pub first_page: usize,
+    pub num_pages: usize,
+    pub pdf: PathBuf,
+}
+
+/// Replaces pages of `doc` by the pages of other PDFs. Links and destinations to a replaced page
+/// point to the page at the same position among the new pages (or to the last one) afterwards.
+/// Returns the number of pages of every replacement.
+pub fn replace_pages(
+    doc: &mut Document,
+    replacements: &[PageReplacement],
+) -> lopdf::Result<Vec<usize>> {
+    let pages: Vec<ObjectId> = doc.get_pages().values().cloned().collect();
+    let pages_id = doc.catalog()?.get(b"Pages")?.as_reference()?;
+
+    let mut kids = Vec::with_capacity(pages.len());
+    let mut kept = Vec::with_capacity(pages.len());
+    let mut replaced = HashMap::new();
+    let mut num_pages = Vec::with_capacity(replacements.len());
+    let mut next_page = 0;
+    for replacement in replacements {
+        kept.extend(pages[next_page..replacement.first_page].iter().cloned());
+        kids.extend(pages[next_page..replacement.first_page].iter().cloned());
+        let new_pages = import_pages(&Document::load(&replacement.pdf)?, doc, pages_id)?;
+        let old_pages =
+            &pages[replacement.first_page..replacement.first_page + replacement.num_pages];
+        for (idx, old_page) in old_pages.iter().enumerate() {
+            if let Some(new_page) = new_pages.get(idx).or_else(|| new_pages.last()) {
+                replaced.insert(*old_page, *new_page);
+            }
+        }
+        num_pages.push(new_pages.len());
+        kids.extend(new_pages);
+        next_page = replacement.first_page + replacement.num_pages;
+    }
+    kept.extend(pages[next_page..].iter().cloned());
+    kids.extend(pages[next_page..].iter().cloned());
+
+    // All pages become children of the root of the page tree
+    for page in kept {
+        let mut dict = page_with_inherited(doc, page)?;
+        dict.set("Parent", Object::Reference(pages_id));
+        doc.objects.insert(page, Object::Dictionary(dict));
+    }
+    let mut pages_dict = doc.get_dictionary(pages_id)?.clone();
+    pages_dict.set("Count", kids.len() as i64);
+    pages_dict.set(
+        "Kids",
+        kids.into_iter().map(Object::Reference).collect::<Vec<_>>(),
+    );
+    doc.objects.insert(pages_id, Object::Dictionary(pages_dict));
+
+    for old_page in replaced.keys() {
+        doc.objects.remove(old_page);
+    }
+    for object in doc.objects.values_mut() {
+        replace_references(object, &replaced);
+    }
+    doc.prune_objects();
+    Ok(num_pages)
+}
+
+/// Concatenates `pdfs` to `output_file` like pdfunite (with the backends of `--merger merger`),
+/// and carries over the links between them and their multimedia.
+pub fn pdfunite(pdfs: &[PathBuf], output_file: &str, merger: &str) -> Result<()> {
+    merge_with(merger, pdfs, output_file)?;
+    fix_united(pdfs, output_file);
+    Ok(())
+}
+
+/// Rewrites the links between the frames `pdfs` of the united PDF `output_file` and carries over
+/// their multimedia.
+pub fn fix_united(pdfs: &[PathBuf], output_file: &str) {
+    match fix_links(Path::new(output_file), pdfs) {
+        Ok(0) => {}
+        Ok(num_links) => debug!("Rewrote {} links between frames", num_links),
+        Err(err) => warn!(
+            "Failed to rewrite links between frames of {}: {}",
+            output_file, err
+        ),
+    }
+    match merge_catalogs(Path::new(output_file), pdfs) {
+        Ok(false) => {}
+        Ok(true) => debug!("Merged multimedia and forms of the frames"),
+        Err(err) => warn!(
+            "Failed to merge multimedia and forms of the frames of {}: {}",
+            output_file, err
+        ),
+    }
+    report_shared_resources(Path::new(output_file));
+}
+
+/// Copies the links of the frames `pdfs` to the `--unite` PDF `united_pdf`, on which they start on
+/// the pages `first_pages`.
+pub fn copy_frame_links(pdfs: &[PathBuf], united_pdf: &Path, first_pages: &[usize]) {
+    match copy_links(united_pdf, pdfs, first_pages) {
+        Ok(0) => {}
+        Ok(num_links) => debug!("Copied {} links of the frames", num_links),
+        Err(err) => warn!(
+            "Failed to copy the links of the frames to {}: {}",
+            united_pdf.display(),
+            err
+        ),
+    }
+}
+
+/// Shares the identical resources that the frames of `pdf` embed.
+pub fn report_shared_resources(pdf: &Path) {
+    match share_resources(pdf) {
+        Ok(0) => {}
+        Ok(num_shared) => debug!("Removed {} copies of shared resources", num_shared),
+        Err(err) => warn!(
+            "Failed to share resources of the frames of {}: {}",
+            pdf.display(),
+            err
+        ),
+    }
+}
+
+/// Characters of a path that TeX reads even inside `\detokenize` (comments, parameters, groups
+/// and control sequences).
+const TEX_UNSAFE_CHARS: &[char] = &['%', '#', '{', '}', '\\'];
+
+/// Whether `path` can be passed to TeX inside `\detokenize`.
+pub fn is_tex_safe(path: &Path) -> bool {
+    !path.to_string_lossy().contains(TEX_UNSAFE_CHARS)
+}
+
+/// Document that includes all pages of `inputs`, with a destination and a bookmark per input.
+/// The paths are passed through `\detokenize`, so that e.g. `_` or `~` are read literally, and
+/// must be TeX-safe (see `is_tex_safe`).
+pub fn merge_tex(inputs: &[MergeInput]) -> String {
+    let mut tex = String::from(
+        "\\documentclass{article}\n\
+         \\usepackage{pdfpages}\n\
+         \\usepackage{hyperref}\n\
+         \\begin{document}\n",
+    );
+    for input in inputs {
+        tex += &format!(
+            "\\includepdf[pages=-,fitpaper,addtotoc={{1,section,1,{},{}}}]{{\\detokenize{{{}}}}}\n",
+            input.destination,
+            input.destination,
+            input.pdf.to_string_lossy()
+        );
+    }
+    tex + "\\end{document}\n"
+}
+
+/// Concatenates `inputs` to `output_file` with pdfpages, passing `latex_args` to the compiler.
+/// `working_dir` holds the intermediate files, and links to the inputs whose paths are not
+/// TeX-safe.
+pub fn merge_pdfs(
+    inputs: &[MergeInput],
+    output_file: &str,
+    working_dir: &Path,
+    compilercmd: &str,
+    latex_args: &[String],
+) -> Result<()> {
+    let name = format!("merged-{:x}", md5::compute(output_file));
+    let tex_file = working_dir.join(format!("{}.tex", name));
+    fs::create_dir_all(working_dir).map_err(FasterBeamerError::io(working_dir))?;
+    let mut safe_inputs = Vec::with_capacity(inputs.len());
+    for (idx, input) in inputs.iter().enumerate() {
+        let pdf = if is_tex_safe(&input.pdf) {
+            input.pdf.clone()
+        } else {
+            // Relative to `working_dir`, whose path may not be TeX-safe either
+            let link = PathBuf::from(format!("{}-{}.pdf", name, idx));
+            let link_path = working_dir.join(&link);
+            let _result = fs::remove_file(&link_path);
+            symlink::symlink_file(&input.pdf, &link_path)
+                .map_err(FasterBeamerError::io(&link_path))?;
+            link
+        };
+        safe_inputs.push(MergeInput {
+            pdf,
+            destination: input.destination.clone(),
+        });
+    }
+    fs::write(&tex_file, merge_tex(&safe_inputs)).map_err(FasterBeamerError::io(&tex_file))?;
+
+    let mut compiler = LatexCompiler::new(compilercmd)
+        .map_err(FasterBeamerError::latex_input(&tex_file))?
+        .add_arg("-interaction=nonstopmode");
+    for arg in latex_args {
+        compiler = compiler.add_arg(arg);
+    }
+    compiler.working_dir = working_dir.to_owned();
+
+    let merged_pdf = compiler
+        .run(
+            &tex_file.to_string_lossy(),
+            &LatexInput::new(),
+            LatexRunOptions::new(),
+        )
+        .map_err(|err| FasterBeamerError::Merge {
+            output: output_file.into(),
+            message: log_excerpt(&tex_file.with_extension("log"), &err.to_string()),
+        })?;
+
+    fs::copy(&merged_pdf, output_file).map_err(FasterBeamerError::io(output_file))?;
+    Ok(())
+}
+
+/// Frame PDFs of a build manifest. `manifest` is either a JSON manifest (a `.manifest` file of
+/// the cache, or a `.json` copy of one next to the frame PDFs) or an input document whose last
+/// build is used.
+pub fn manifest_inputs(manifest: &str) -> Result<Vec<MergeInput>> {
+    let manifest_path = Path::new(manifest);
+    let is_manifest = manifest_path
+        .extension()
+        .map_or(false, |e| e == "manifest" || e == "json");
+    let (cache_subdir, entries) = if is_manifest {
+        let cache_subdir = manifest_path
+            .parent()
+            .unwrap_or_else(|| Path::new("."))
+            .to_owned();
+        (cache_subdir, read_manifest_file(manifest_path))
+    } else {
+        let cache_subdir = get_cache_subdir(&get_cachedir()?, &get_input_dir(manifest));
+        let entries = read_manifest(&cache_subdir, manifest);
+        (cache_subdir, entries)
+    };
+    let entries = entries.ok_or_else(|| {
+        FasterBeamerError::NotFound(format!("No build manifest found for {}", manifest))
+    })?;
+
+    let destinations =
+        unique_destinations(entries.iter().map(|e| (e.index, e.label.clone())).collect());
+    Ok(entries
+        .into_iter()
+        .zip(destinations)
+        .map(|(entry, destination)| MergeInput {
+            pdf: cache_subdir.join(format!("{}.pdf", entry.hash)),
+            destination,
+        })
+        .collect())
+}
+
+/// Merges `pdfs` (or the PDFs of the frames of `manifest`) into `output_file` without compiling
+/// any frames.
+pub fn merge(
+    output_file: &str,
+    manifest: Option<&str>,
+    pdfs: &[&str],
+    args: &Options,
+) -> Result<()> {
+    let compilercmd = engine_command(args);
+
+    let inputs = match manifest {
+        Some(manifest) => manifest_inputs(manifest)?,
+        None => pdfs
+            .iter()
+            .enumerate()
+            .map(|(index, pdf)| MergeInput {
+                pdf: PathBuf::from(pdf),
+                destination: destination_name(index, None),
+            })
+            .collect(),
+    };
+    if inputs.is_empty() {
+        return Err(FasterBeamerError::NotFound("Nothing to merge".to_string()));
+    }
+
+    let mut pdfs = Vec::with_capacity(inputs.len());
+    for input in inputs.iter() {
+        let pdf = input
+            .pdf
+            .canonicalize()
+            .map_err(|_| FasterBeamerError::InputFileNotExistent(input.pdf.clone()))?;
+        pdfs.push(pdf);
+    }
+
+    if args.pdfunite {
+        pdfunite(&pdfs, output_file, &args.merger)?;
+    } else {
+        let inputs: Vec<MergeInput> = inputs
+            .into_iter()
+            .zip(pdfs)
+            .map(|(input, pdf)| MergeInput { pdf, ..input })
+            .collect();
+        merge_pdfs(
+            &inputs,
+            output_file,
+            &get_cachedir()?.join("merge"),
+            compilercmd,
+            &latex_args(args),
+        )?;
+    }
+    info!("Wrote {}", output_file);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_pdf::{page_tree_mut, pdf_with_pages};
+    use tempfile::tempdir;
+
+    /// PDF with `num_pages` pages that inherit their media box and rotation from the page tree.
+    /// The first page has a link annotation that refers back to it.
+    fn test_pdf(num_pages: usize) -> Document {
+        let mut doc = pdf_with_pages(num_pages, |doc, page_idx, page_id, page| {
+            if page_idx == 0 {
+                let mut link = Dictionary::new();
+                link.set("Subtype", Object::Name(b"Link".to_vec()));
+                link.set("P", Object::Reference(page_id));
+                page.set("Annots", vec![Object::Reference(doc.add_object(link))]);
+            }
+        });
+        let pages = page_tree_mut(&mut doc);
+        pages.set("MediaBox", vec![0.into(), 0.into(), 364.into(), 273.into()]);
+        pages.set("Rotate", 90);
+        doc
+    }
+
+    #[test]
+    fn test_concatenate() {
+        let dir = tempdir().unwrap();
+        let first = dir.path().join("first.pdf");
+        let second = dir.path().join("second.pdf");
+        test_pdf(2).save(&first).unwrap();
+        test_pdf(1).save(&second).unwrap();
+
+        let merged = concatenate(&[first, second]).unwrap();
+        let pages: Vec<ObjectId> = merged.get_pages().values().cloned().collect();
+        assert_eq!(pages.len(), 3);
+        for page in pages.iter() {
+            let page = merged.get_dictionary(*page).unwrap();
+            assert_eq!(page.get(b"Rotate").unwrap().as_i64().unwrap(), 90);
+            assert!(page.has(b"MediaBox"));
+        }
+        // The links of both first pages refer to their copies
+        for &page_idx in &[0, 2] {
+            let page = merged.get_dictionary(pages[page_idx]).unwrap();
+            let link = page.get(b"Annots").unwrap().as_array().unwrap()[0]
+                .as_reference()
+                .unwrap();
+            let link_page = merged.get_dictionary(link).unwrap().get(b"P").unwrap();
+            assert_eq!(link_page.as_reference().unwrap(), pages[page_idx]);
+        }
+    }
+
+    #[test]
+    fn test_merge_tex() {
+        let inputs = vec![
+            MergeInput {
+                pdf: PathBuf::from("/cache/abc.pdf"),
+                destination: "frame-intro".to_string(),
+            },
+            MergeInput {
+                pdf: PathBuf::from("/cache/def.pdf"),
+                destination: "frame-1".to_string(),
+            },
+        ];
+        let tex = merge_tex(&inputs);
+        assert!(tex.contains(
+            "\\includepdf[pages=-,fitpaper,addtotoc={1,section,1,frame-intro,frame-intro}]{\\detokenize{/cache/abc.pdf}}\n"
+        ));
+        assert!(tex.contains("{\\detokenize{/cache/def.pdf}}\n\\end{document}\n"));
+        assert!(is_tex_safe(Path::new("/home/me/my_talk ~draft/abc.pdf")));
+        assert!(!is_tex_safe(Path::new("/home/me/100%/abc.pdf")));
+        assert!(!is_tex_safe(Path::new("/home/me/#1/abc.pdf")));
+    }
+}
@@ -0,0 +1,214 @@
+//
+// merger.rs
+// Distributed under terms of the GPLv3 license.
+//
+// Backends that concatenate the frame PDFs page by page (`--pdfunite`): the builtin one or one of
+// the usual command line tools (`--merger`). With `--merger auto`, the builtin backend is used and
+// a PDF it cannot handle is merged again with the first tool that is installed.
+//
+
+use crate::merge::concatenate;
+use crate::preflight::is_runnable;
+use crate::process_file::{FasterBeamerError, Result};
+use std::path::PathBuf;
+use std::process::Command;
+
+pub const MERGERS: &[&str] = &["auto", "builtin", "pdfunite", "qpdf", "mutool", "gs"];
+
+pub trait Merger {
+    fn name(&self) -> &str;
+
+    /// Whether the backend can be used (e.g. its program is installed).
+    fn is_available(&self) -> bool;
+
+    /// Concatenates the pages of `pdfs` to `output_file`.
+    fn merge(&self, pdfs: &[PathBuf], output_file: &str) -> Result<()>;
+}
+
+/// Concatenation with lopdf, which needs no external program.
+pub struct Builtin;
+
+impl Merger for Builtin {
+    fn name(&self) -> &str {
+        "builtin"
+    }
+
+    fn is_available(&self) -> bool {
+        true
+    }
+
+    fn merge(&self, pdfs: &[PathBuf], output_file: &str) -> Result<()> {
+        let merge_error = |err: lopdf::Error| FasterBeamerError::Merge {
+            output: output_file.into(),
+            message: err.to_string(),
+        };
+        concatenate(pdfs)
+            .map_err(merge_error)?
+            .save(output_file)
+            .map_err(FasterBeamerError::io(output_file))?;
+        Ok(())
+    }
+}
+
+/// A program that merges PDFs given on its command line.
+pub struct ExternalMerger {
+    pub program: &'static str,
+    /// Argument with which the program is run to check that it is installed
+    pub version_arg: &'static str,
+    /// Arguments, in which `{output}` is replaced by the output file and `{pdfs}` by the PDFs
+    pub args: &'static [&'static str],
+}
+
+pub static EXTERNAL_MERGERS: &[ExternalMerger] = &[
+    ExternalMerger {
+        program: "pdfunite",
+        version_arg: "-v",
+        args: &["{pdfs}", "{output}"],
+    },
+    ExternalMerger {
+        program: "qpdf",
+        version_arg: "--version",
+        args: &["--empty", "--pages", "{pdfs}", "--", "{output}"],
+    },
+    ExternalMerger {
+        program: "mutool",
+        version_arg: "-v",
+        args: &["merge", "-o", "{output}", "{pdfs}"],
+    },
+    ExternalMerger {
+        program: "gs",
+        version_arg: "--version",
+        args: &[
+            "-q",
+            "-dNOPAUSE",
+            "-dBATCH",
+            "-dSAFER",
+            "-sDEVICE=pdfwrite",
+            "-sOutputFile={output}",
+            "{pdfs}",
+        ],
+    },
+];
+
+impl ExternalMerger {
+    /// Command line arguments that merge `pdfs` to `output_file`.
+    pub fn command_args(&self, pdfs: &[PathBuf], output_file: &str) -> Vec<String> {
+        let mut args = Vec::new();
+        for arg in self.args {
+            if *arg == "{pdfs}" {
+                args.extend(pdfs.iter().map(|pdf| pdf.to_string_lossy().into_owned()));
+            } else {
+                args.push(arg.replace("{output}", output_file));
+            }
+        }
+        args
+    }
+}
+
+impl Merger for ExternalMerger {
+    fn name(&self) -> &str {
+        self.program
+    }
+
+    fn is_available(&self) -> bool {
+        is_runnable(self.program, self.version_arg)
+    }
+
+    fn merge(&self, pdfs: &[PathBuf], output_file: &str) -> Result<()> {
+        let output = Command::new(self.program)
+            .args(self.command_args(pdfs, output_file))
+            .output();
+        match output {
+            Err(e) => Err(FasterBeamerError::Merge {
+                output: output_file.into(),
+                message: format!("Failed to run {}: {}", self.program, e),
+            }),
+            Ok(output) if !output.status.success() => Err(FasterBeamerError::Merge {
+                output: output_file.into(),
+                message: String::from_utf8_lossy(&output.stderr).into_owned(),
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The external merger `name`, if it is one.
+pub fn external_merger(name: &str) -> Option<&'static ExternalMerger> {
+    EXTERNAL_MERGERS
+        .iter()
+        .find(|merger| merger.program == name)
+}
+
+lazy_static! {
+    /// First external merger that is installed, looked for the first time it is needed
+    static ref INSTALLED_MERGER: Option<&'static ExternalMerger> =
+        EXTERNAL_MERGERS.iter().find(|merger| merger.is_available());
+}
+
+/// Backend of `--merger name` (the builtin one for `auto`).
+pub fn merger(name: &str) -> Result<&'static dyn Merger> {
+    match name {
+        "auto" | "builtin" => Ok(&Builtin),
+        _ => match external_merger(name) {
+            Some(merger) if merger.is_available() => Ok(merger),
+            Some(merger) => Err(FasterBeamerError::MissingProgram(vec![format!(
+                "Cannot run {}, which is needed for merging frames (--merger {}). Install it or \
+                 use --merger builtin, which needs no external program.",
+                merger.program, name
+            )])),
+            None => Err(FasterBeamerError::InvalidOptions(format!(
+                "unknown merger {} (expected one of {})",
+                name,
+                MERGERS.join(", ")
+            ))),
+        },
+    }
+}
+
+/// Concatenates `pdfs` to `output_file` with the backend of `--merger name`. With `auto`, a
+/// failure of the builtin backend is retried with the first external merger that is installed.
+pub fn merge_with(name: &str, pdfs: &[PathBuf], output_file: &str) -> Result<()> {
+    let result = merger(name)?.merge(pdfs, output_file);
+    match (result, name) {
+        (Err(err), "auto") => match *INSTALLED_MERGER {
+            Some(fallback) => {
+                warn!("{}, retrying with {}", err, fallback.name());
+                fallback.merge(pdfs, output_file)
+            }
+            None => Err(err),
+        },
+        (result, _) => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_args() {
+        let pdfs = vec![PathBuf::from("a.pdf"), PathBuf::from("b.pdf")];
+        assert_eq!(
+            external_merger("qpdf")
+                .unwrap()
+                .command_args(&pdfs, "out.pdf"),
+            vec!["--empty", "--pages", "a.pdf", "b.pdf", "--", "out.pdf"]
+        );
+        assert_eq!(
+            external_merger("gs")
+                .unwrap()
+                .command_args(&pdfs, "out.pdf")[5],
+            "-sOutputFile=out.pdf"
+        );
+    }
+
+    #[test]
+    fn test_merger() {
+        assert_eq!(merger("builtin").ok().unwrap().name(), "builtin");
+        assert_eq!(merger("auto").ok().unwrap().name(), "builtin");
+        assert!(matches!(
+            merger("pdftk"),
+            Err(FasterBeamerError::InvalidOptions(_))
+        ));
+    }
+}
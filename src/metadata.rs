@@ -0,0 +1,206 @@
+//
+// metadata.rs
+// Distributed under terms of the GPLv3 license.
+//
+// hyperref takes the title and author of the PDF from `\title` and `\author` of the document, but
+// the frame PDFs that `--pdfunite` merges have no title page, so the merged PDF would have no
+// metadata. It is read from the preamble and written into the document information dictionary
+// and the XMP metadata of the merged PDF.
+//
+
+use crate::bookmarks::{plain_text, text_string};
+use crate::parsing::balanced_group;
+use lopdf::{Dictionary, Document, Object, Stream};
+use regex::Regex;
+use std::path::Path;
+
+lazy_static! {
+    static ref METADATA_REGEX: Regex =
+        Regex::new(r"\\(title|author|subject|keywords)\s*(?:\[[^\]]*\]\s*)?\{").unwrap();
+}
+lazy_static! {
+    static ref FOOTNOTE_REGEX: Regex = Regex::new(r"\\(inst|thanks)\s*\{[^}]*\}").unwrap();
+}
+lazy_static! {
+    static ref AND_REGEX: Regex = Regex::new(r"\\and\b").unwrap();
+}
+
+/// Metadata of a presentation.
+#[derive(Debug, Default, PartialEq)]
+pub struct Metadata {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub subject: Option<String>,
+    pub keywords: Option<String>,
+}
+
+impl Metadata {
+    /// Entries of the document information dictionary (with the producer).
+    fn info_entries(&self) -> Vec<(&'static str, String)> {
+        let mut entries = vec![(
+            "Producer",
+            format!("faster-beamer {}", env!("CARGO_PKG_VERSION")),
+        )];
+        let fields = [
+            ("Title", &self.title),
+            ("Author", &self.author),
+            ("Subject", &self.subject),
+            ("Keywords", &self.keywords),
+        ];
+        for (key, value) in fields.iter() {
+            if let Some(value) = value {
+                entries.push((key, value.clone()));
+            }
+        }
+        entries
+    }
+}
+
+/// Reads the metadata from `\title`, `\author`, `\subject` and `\keywords` in the preamble of
+/// `content`.
+pub fn document_metadata(content: &str) -> Metadata {
+    let preamble = &content[..content.find("\\begin{document}").unwrap_or(content.len())];
+    let mut metadata = Metadata::default();
+    for m in METADATA_REGEX.captures_iter(preamble) {
+        let start = m.get(0).unwrap().end() - 1;
+        let value = match balanced_group(preamble, start) {
+            Some((value, _)) => value,
+            None => continue,
+        };
+        let value = FOOTNOTE_REGEX.replace_all(value, "");
+        let value = AND_REGEX.replace_all(&value, ", ");
+        let value = Some(plain_text(&value).replace(" ,", ",")).filter(|v| !v.is_empty());
+        match &m[1] {
+            "title" => metadata.title = value,
+            "author" => metadata.author = value,
+            "subject" => metadata.subject = value,
+            _ => metadata.keywords = value,
+        }
+    }
+    metadata
+}
+
+/// `text` with the characters that are special in XML (and HTML) escaped.
+pub fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// XMP packet with `metadata`.
+pub fn xmp_packet(metadata: &Metadata) -> String {
+    let mut properties = String::new();
+    let mut add = |property: &str, value: String| {
+        properties += &format!("   {}\n", property.replace("{}", &escape_xml(&value)));
+    };
+    for (key, value) in metadata.info_entries() {
+        match key {
+            "Producer" => add("<pdf:Producer>{}</pdf:Producer>", value),
+            "Title" => add(
+                "<dc:title><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt></dc:title>",
+                value,
+            ),
+            "Author" => add(
+                "<dc:creator><rdf:Seq><rdf:li>{}</rdf:li></rdf:Seq></dc:creator>",
+                value,
+            ),
+            "Subject" => add(
+                "<dc:description><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt></dc:description>",
+                value,
+            ),
+            _ => add("<pdf:Keywords>{}</pdf:Keywords>", value),
+        }
+    }
+    format!(
+        "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n\
+         <x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n \
+         <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n  \
+         <rdf:Description rdf:about=\"\" xmlns:dc=\"http://purl.org/dc/elements/1.1/\" \
+         xmlns:pdf=\"http://ns.adobe.com/pdf/1.3/\">\n\
+         {}  \
+         </rdf:Description>\n \
+         </rdf:RDF>\n\
+         </x:xmpmeta>\n\
+         <?xpacket end=\"w\"?>",
+        properties
+    )
+}
+
+/// Writes `metadata` into the document information dictionary and the XMP metadata of `pdf`.
+pub fn write_metadata(pdf: &Path, metadata: &Metadata) -> lopdf::Result<()> {
+    let mut doc = Document::load(pdf)?;
+
+    let mut info = match doc
+        .trailer
+        .get(b"Info")
+        .and_then(|info| doc.dereference(info))
+    {
+        Ok((_, Object::Dictionary(info))) => info.clone(),
+        _ => Dictionary::new(),
+    };
+    for (key, value) in metadata.info_entries() {
+        info.set(key, text_string(&value));
+    }
+    let info_id = doc.add_object(info);
+    doc.trailer.set("Info", Object::Reference(info_id));
+
+    let mut xmp_dict = Dictionary::new();
+    xmp_dict.set("Type", Object::Name(b"Metadata".to_vec()));
+    xmp_dict.set("Subtype", Object::Name(b"XML".to_vec()));
+    // Readers expect XMP uncompressed
+    let xmp = Stream::new(xmp_dict, xmp_packet(metadata).into_bytes()).with_compression(false);
+    let xmp_id = doc.add_object(xmp);
+    let catalog_id = doc.trailer.get(b"Root")?.as_reference()?;
+    let mut catalog = doc.get_dictionary(catalog_id)?.clone();
+    catalog.set("Metadata", Object::Reference(xmp_id));
+    doc.objects.insert(catalog_id, Object::Dictionary(catalog));
+
+    doc.prune_objects();
+    doc.save(pdf)?;
+    Ok(())
+}
+
+/// Writes `metadata` into the merged PDF `pdf`.
+pub fn add_metadata(pdf: &Path, metadata: &Metadata) {
+    if let Err(err) = write_metadata(pdf, metadata) {
+        warn!("Failed to write the metadata of {}: {}", pdf.display(), err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_document_metadata() {
+        let content = "\\documentclass{beamer}\n\
+                       \\title[Short]{Fast \\emph{Beamer}}\n\
+                       \\author{Ada\\inst{1} \\and Alan\\thanks{Funded}}\n\
+                       \\keywords{latex, beamer}\n\
+                       \\begin{document}\n\
+                       \\subject{Not metadata}\n\
+                       \\end{document}\n";
+        assert_eq!(
+            document_metadata(content),
+            Metadata {
+                title: Some("Fast Beamer".to_string()),
+                author: Some("Ada, Alan".to_string()),
+                subject: None,
+                keywords: Some("latex, beamer".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_xmp_packet() {
+        let metadata = Metadata {
+            title: Some("Q&A".to_string()),
+            ..Metadata::default()
+        };
+        let xmp = xmp_packet(&metadata);
+        assert!(xmp.contains("<rdf:li xml:lang=\"x-default\">Q&amp;A</rdf:li>"));
+        assert!(xmp.contains("<pdf:Producer>faster-beamer "));
+        assert!(!xmp.contains("dc:creator"));
+    }
+}
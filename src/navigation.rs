@@ -0,0 +1,234 @@
+//
+// navigation.rs
+// Distributed under terms of the GPLv3 license.
+//
+// Beamer draws its navigation bars (mini frames, section lists) from the `.nav` file of the
+// previous run. Since every frame is compiled as its own document, we generate the `.nav` file
+// of the whole presentation and hand it to every frame compile. Likewise, `\tableofcontents` reads
+// the `.toc` file of the previous run, which we generate for the frames that show it.
+//
+
+use crate::includes::is_commented;
+use crate::outline::OutlineEntry;
+use crate::page_map::PageRange;
+use regex::Regex;
+
+lazy_static! {
+    static ref TABLE_OF_CONTENTS_REGEX: Regex = Regex::new(r"\\tableofcontents\b").unwrap();
+}
+
+/// Position of a frame in the structure of the whole presentation.
+#[derive(Debug, PartialEq)]
+pub struct FrameContext {
+    pub section: usize,
+    pub subsection: usize,
+    pub first_page: usize,
+}
+
+/// Generates the content of a beamer `.nav` file for the whole presentation and the context of
+/// every frame.
+pub fn generate_nav(
+    outline: &[OutlineEntry],
+    page_map: &[PageRange],
+) -> (String, Vec<FrameContext>) {
+    let mut nav = String::new();
+    let mut contexts = Vec::with_capacity(page_map.len());
+
+    let mut section = 0;
+    let mut subsection = 0;
+    let mut frame_in_subsection = 0;
+    let mut section_start = 1;
+    let mut subsection_start = 1;
+    let mut next_page = 1;
+
+    for entry in outline {
+        match entry {
+            OutlineEntry::Section(title) => {
+                if section > 0 {
+                    nav += &format!(
+                        "\\headcommand {{\\beamer@subsectionpages {{{}}}{{{}}}}}\n",
+                        subsection_start,
+                        next_page - 1
+                    );
+                    nav += &format!(
+                        "\\headcommand {{\\beamer@sectionpages {{{}}}{{{}}}}}\n",
+                        section_start,
+                        next_page - 1
+                    );
+                }
+                section += 1;
+                subsection = 0;
+                frame_in_subsection = 0;
+                section_start = next_page;
+                subsection_start = next_page;
+                nav += &format!(
+                    "\\headcommand {{\\sectionentry {{{}}}{{{}}}{{{}}}{{{}}}{{0}}}}\n",
+                    section, title, next_page, title
+                );
+            }
+            OutlineEntry::Subsection(title) => {
+                if subsection > 0 {
+                    nav += &format!(
+                        "\\headcommand {{\\beamer@subsectionpages {{{}}}{{{}}}}}\n",
+                        subsection_start,
+                        next_page - 1
+                    );
+                }
+                subsection += 1;
+                frame_in_subsection = 0;
+                subsection_start = next_page;
+                nav += &format!(
+                    "\\headcommand {{\\beamer@subsectionentry {{0}}{{{}}}{{{}}}{{{}}}{{\\ignorespaces {{{}}}\\unskip }}}}\n",
+                    section, subsection, next_page, title
+                );
+            }
+            OutlineEntry::Frame { index, .. } => {
+                let pages = match page_map.get(*index) {
+                    Some(pages) => pages,
+                    None => continue,
+                };
+                frame_in_subsection += 1;
+                nav += &format!(
+                    "\\headcommand {{\\slideentry {{{}}}{{{}}}{{{}}}{{{}/{}}}{{}}{{0}}}}\n",
+                    section, subsection, frame_in_subsection, pages.first_page, pages.last_page
+                );
+                nav += &format!(
+                    "\\headcommand {{\\beamer@framepages {{{}}}{{{}}}}}\n",
+                    pages.first_page, pages.last_page
+                );
+                contexts.push(FrameContext {
+                    section,
+                    subsection,
+                    first_page: pages.first_page,
+                });
+                next_page = pages.last_page + 1;
+            }
+        }
+    }
+
+    let last_page = next_page - 1;
+    nav += &format!(
+        "\\headcommand {{\\beamer@partpages {{1}}{{{}}}}}\n",
+        last_page
+    );
+    nav += &format!(
+        "\\headcommand {{\\beamer@subsectionpages {{{}}}{{{}}}}}\n",
+        subsection_start, last_page
+    );
+    nav += &format!(
+        "\\headcommand {{\\beamer@sectionpages {{{}}}{{{}}}}}\n",
+        section_start, last_page
+    );
+    nav += &format!("\\headcommand {{\\beamer@documentpages {{{}}}}}\n", last_page);
+    nav += &format!(
+        "\\headcommand {{\\gdef \\inserttotalframenumber {{{}}}}}\n",
+        page_map.len()
+    );
+    (nav, contexts)
+}
+
+/// Whether `frame` shows the table of contents.
+pub fn has_table_of_contents(frame: &str) -> bool {
+    TABLE_OF_CONTENTS_REGEX
+        .find_iter(frame)
+        .any(|m| !is_commented(frame, m.start()))
+}
+
+/// Generates the content of a beamer `.toc` file for the whole presentation.
+pub fn generate_toc(outline: &[OutlineEntry], page_map: &[PageRange]) -> String {
+    let mut toc = String::new();
+    let mut section = 0;
+    let mut subsection = 0;
+    let mut next_page = 1;
+
+    for entry in outline {
+        match entry {
+            OutlineEntry::Section(title) => {
+                section += 1;
+                subsection = 0;
+                toc += &format!(
+                    "\\beamer@sectionintoc {{{}}}{{{}}}{{{}}}{{0}}{{1}}\n",
+                    section, title, next_page
+                );
+            }
+            OutlineEntry::Subsection(title) => {
+                subsection += 1;
+                toc += &format!(
+                    "\\beamer@subsectionintoc {{{}}}{{{}}}{{{}}}{{{}}}{{0}}{{1}}\n",
+                    section, subsection, title, next_page
+                );
+            }
+            OutlineEntry::Frame { index, .. } => {
+                if let Some(pages) = page_map.get(*index) {
+                    next_page = pages.last_page + 1;
+                }
+            }
+        }
+    }
+    toc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_nav() {
+        let outline = vec![
+            OutlineEntry::Frame {
+                index: 0,
+                title: None,
+            },
+            OutlineEntry::Section("Intro".to_string()),
+            OutlineEntry::Frame {
+                index: 1,
+                title: None,
+            },
+        ];
+        let page_map = vec![
+            PageRange {
+                frame: 0,
+                label: None,
+                first_page: 1,
+                last_page: 1,
+            },
+            PageRange {
+                frame: 1,
+                label: None,
+                first_page: 2,
+                last_page: 4,
+            },
+        ];
+
+        let (nav, contexts) = generate_nav(&outline, &page_map);
+        assert!(nav.contains("\\headcommand {\\sectionentry {1}{Intro}{2}{Intro}{0}}\n"));
+        assert!(nav.contains("\\headcommand {\\slideentry {1}{0}{1}{2/4}{}{0}}\n"));
+        assert!(nav.contains("\\headcommand {\\beamer@documentpages {4}}\n"));
+        assert_eq!(
+            contexts,
+            vec![
+                FrameContext {
+                    section: 0,
+                    subsection: 0,
+                    first_page: 1
+                },
+                FrameContext {
+                    section: 1,
+                    subsection: 0,
+                    first_page: 2
+                },
+            ]
+        );
+
+        assert_eq!(
+            generate_toc(&outline, &page_map),
+            "\\beamer@sectionintoc {1}{Intro}{2}{0}{1}\n"
+        );
+        assert!(has_table_of_contents(
+            "\\begin{frame}{Outline}\\tableofcontents[currentsection]\\end{frame}"
+        ));
+        assert!(!has_table_of_contents(
+            "\\begin{frame}% \\tableofcontents\n\\end{frame}"
+        ));
+    }
+}
@@ -0,0 +1,278 @@
+//
+// nvim.rs
+// Distributed under terms of the GPLv3 license.
+//
+// `faster-beamer nvim` speaks msgpack-RPC on stdin and stdout, so that a thin Neovim plugin can
+// start it with `jobstart(['faster-beamer', 'nvim'], {'rpc': v:true})`. The plugin sends the
+// lines of the buffer and the line of the cursor; parsing, finding the changed frames and
+// compiling stay here. Methods:
+//
+//     update(file, lines, cursor_line)    builds the buffer contents of `file`
+//     frame_at(file, cursor_line)         frame under the cursor (after the last update)
+//
+// Both answer a map with `succeeded`, `error`, `frames`, `frame` (under the cursor) and `page`
+// (of that frame in the united PDF). Sent as notification, `update` answers by calling
+// `require('faster-beamer').on_status(status)` in Neovim instead.
+//
+
+use crate::batch::{build_jobs, process_jobs};
+use crate::cli::subcommand_options;
+use crate::events;
+use crate::options::Options;
+use crate::process_file::{
+    get_cache_subdir, get_cachedir, get_input_dir, load_frames, FasterBeamerError, Result,
+};
+use crate::status::locate_line;
+use rmpv::Value;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Type of a message in msgpack-RPC
+const REQUEST: u64 = 0;
+const RESPONSE: u64 = 1;
+const NOTIFICATION: u64 = 2;
+
+/// Outcome of a build and the position of the cursor in the presentation.
+#[derive(Debug, Default, PartialEq)]
+pub struct Status {
+    pub succeeded: bool,
+    pub error: Option<String>,
+    pub frames: usize,
+    /// Frame under the cursor
+    pub frame: Option<usize>,
+    /// First page of `frame` in the united PDF (1-based)
+    pub page: Option<usize>,
+}
+
+impl Status {
+    pub fn to_value(&self) -> Value {
+        let optional = |value: Option<usize>| value.map_or(Value::Nil, |v| Value::from(v as u64));
+        Value::Map(vec![
+            (Value::from("succeeded"), Value::from(self.succeeded)),
+            (
+                Value::from("error"),
+                self.error
+                    .as_ref()
+                    .map_or(Value::Nil, |err| Value::from(err.as_str())),
+            ),
+            (Value::from("frames"), Value::from(self.frames as u64)),
+            (Value::from("frame"), optional(self.frame)),
+            (Value::from("page"), optional(self.page)),
+        ])
+    }
+}
+
+/// File in `cache_subdir` (the cache of the documents of the directory of `input_file`) to which
+/// the contents of the buffer of `input_file` are written.
+pub fn buffer_file(input_file: &str, cache_subdir: &Path) -> PathBuf {
+    let name = Path::new(input_file)
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+    cache_subdir.join(format!("{}.buffer.tex", name))
+}
+
+/// Buffer file of `input_file` (see `buffer_file`).
+fn buffer_path(input_file: &str) -> Result<PathBuf> {
+    let cache_subdir = get_cache_subdir(&get_cachedir()?, &get_input_dir(input_file));
+    Ok(buffer_file(input_file, &cache_subdir))
+}
+
+/// Options of a build of `input_file` with the contents of `buffer`. The build is one of
+/// `input_file` otherwise, so that relative paths work and the output file is the same.
+fn buffer_args(input_file: &str, buffer: &Path) -> Result<Options> {
+    let mut args = subcommand_options("build", &[input_file.to_string()])?;
+    args.buffer = Some(buffer.to_owned());
+    Ok(args)
+}
+
+/// Frame at `cursor_line` of `file` and its first page in the united PDF.
+fn locate(file: &str, cursor_line: usize, args: &Options) -> Result<Status> {
+    let position = locate_line(file, cursor_line, args)?;
+    Ok(Status {
+        succeeded: true,
+        error: None,
+        frames: load_frames(file, args)?.frames.len(),
+        frame: position.map(|(frame_idx, _)| frame_idx),
+        page: position.map(|(_, page)| page),
+    })
+}
+
+/// Builds the buffer `lines` of `input_file`.
+pub fn update(input_file: &str, lines: &[&str], cursor_line: usize) -> Result<Status> {
+    let buffer = buffer_path(input_file)?;
+    let content = lines.join("\n") + "\n";
+    // Unchanged buffers keep their modification time
+    if fs::read_to_string(&buffer).ok().as_ref() != Some(&content) {
+        if let Some(dir) = buffer.parent() {
+            fs::create_dir_all(dir).map_err(FasterBeamerError::io(dir))?;
+        }
+        fs::write(&buffer, &content).map_err(FasterBeamerError::io(&buffer))?;
+    }
+    let args = buffer_args(input_file, &buffer)?;
+    let jobs = build_jobs(&args);
+    match process_jobs(&jobs, &args).remove(0) {
+        Ok(()) => locate(input_file, cursor_line, &args),
+        Err(err) => Ok(Status {
+            error: Some(err.to_string()),
+            ..Status::default()
+        }),
+    }
+}
+
+/// Frame at `cursor_line` of the buffer of `input_file` (or of the file if it was not updated).
+pub fn frame_at(input_file: &str, cursor_line: usize) -> Result<Status> {
+    let buffer = buffer_path(input_file)?;
+    if buffer.is_file() {
+        let args = buffer_args(input_file, &buffer)?;
+        locate(input_file, cursor_line, &args)
+    } else {
+        let args = subcommand_options("build", &[input_file.to_string()])?;
+        locate(input_file, cursor_line, &args)
+    }
+}
+
+fn call(method: &str, params: &[Value]) -> Result<Status> {
+    let invalid = || {
+        FasterBeamerError::InvalidOptions(format!("invalid arguments of {}: {:?}", method, params))
+    };
+    let string = |i: usize| params.get(i).and_then(|p| p.as_str()).ok_or_else(invalid);
+    let number = |i: usize| {
+        params
+            .get(i)
+            .and_then(|p| p.as_u64())
+            .map(|n| n as usize)
+            .ok_or_else(invalid)
+    };
+    match method {
+        "update" => {
+            let lines = params
+                .get(1)
+                .and_then(|p| p.as_array())
+                .ok_or_else(invalid)?
+                .iter()
+                .map(|line| line.as_str().ok_or_else(invalid))
+                .collect::<Result<Vec<&str>>>()?;
+            update(string(0)?, &lines, number(2)?)
+        }
+        "frame_at" => frame_at(string(0)?, number(1)?),
+        _ => Err(FasterBeamerError::InvalidOptions(format!(
+            "unknown method {}",
+            method
+        ))),
+    }
+}
+
+fn write_message<W: Write>(output: &mut W, message: &Value) -> Result<()> {
+    rmpv::encode::write_value(output, message)
+        .map_err(|err| io::Error::new(io::ErrorKind::Other, err.to_string()))
+        .and_then(|()| output.flush())
+        .map_err(FasterBeamerError::io("<stdout>"))
+}
+
+/// Answers the requests on `input` until it is closed.
+pub fn serve<R: Read, W: Write>(mut input: R, mut output: W) -> Result<()> {
+    // Stdout belongs to the protocol
+    events::set_enabled(false);
+    loop {
+        let message = match rmpv::decode::read_value(&mut input) {
+            Ok(message) => message,
+            Err(rmpv::decode::Error::InvalidMarkerRead(err))
+                if err.kind() == io::ErrorKind::UnexpectedEof =>
+            {
+                return Ok(());
+            }
+            Err(err) => {
+                return Err(FasterBeamerError::Io {
+                    path: "<stdin>".into(),
+                    source: io::Error::new(io::ErrorKind::InvalidData, err.to_string()),
+                })
+            }
+        };
+        let fields = message.as_array().cloned().unwrap_or_default();
+        match fields.get(0).and_then(|t| t.as_u64()) {
+            Some(REQUEST) if fields.len() == 4 => {
+                let method = fields[2].as_str().unwrap_or_default();
+                let params = fields[3].as_array().cloned().unwrap_or_default();
+                let (error, result) = match call(method, &params) {
+                    Ok(status) => (Value::Nil, status.to_value()),
+                    Err(err) => (Value::from(err.to_string()), Value::Nil),
+                };
+                let response = vec![Value::from(RESPONSE), fields[1].clone(), error, result];
+                write_message(&mut output, &Value::from(response))?;
+            }
+            Some(NOTIFICATION) if fields.len() == 3 => {
+                let method = fields[1].as_str().unwrap_or_default();
+                let params = fields[2].as_array().cloned().unwrap_or_default();
+                match call(method, &params) {
+                    Ok(status) => {
+                        let lua = "require('faster-beamer').on_status(...)";
+                        let notification = vec![
+                            Value::from(NOTIFICATION),
+                            Value::from("nvim_exec_lua"),
+                            Value::from(vec![
+                                Value::from(lua),
+                                Value::from(vec![status.to_value()]),
+                            ]),
+                        ];
+                        write_message(&mut output, &Value::from(notification))?;
+                    }
+                    Err(err) => error!("{}: {}", method, err),
+                }
+            }
+            // Responses to our notifications (Neovim does not send any) and invalid messages
+            _ => debug!("Ignoring {}", message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buffer_file() {
+        assert_eq!(
+            buffer_file("slides/talk.tex", Path::new("/cache/home/me/slides")),
+            PathBuf::from("/cache/home/me/slides/talk.tex.buffer.tex")
+        );
+    }
+
+    #[test]
+    fn test_serve() {
+        let mut input = Vec::new();
+        let request = Value::from(vec![
+            Value::from(REQUEST),
+            Value::from(7),
+            Value::from("restart"),
+            Value::from(Vec::<Value>::new()),
+        ]);
+        rmpv::encode::write_value(&mut input, &request).unwrap();
+
+        let mut output = Vec::new();
+        serve(&input[..], &mut output).ok().unwrap();
+        let response = rmpv::decode::read_value(&mut &output[..]).unwrap();
+        assert_eq!(
+            response,
+            Value::from(vec![
+                Value::from(RESPONSE),
+                Value::from(7),
+                Value::from("Invalid options: unknown method restart"),
+                Value::Nil,
+            ])
+        );
+
+        let status = Status {
+            succeeded: true,
+            frames: 3,
+            frame: Some(1),
+            page: Some(2),
+            ..Status::default()
+        };
+        assert_eq!(
+            status.to_value().as_map().unwrap()[4],
+            (Value::from("page"), Value::from(2))
+        );
+    }
+}
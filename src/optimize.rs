@@ -0,0 +1,157 @@
+//
+// optimize.rs
+// Distributed under terms of the GPLv3 license.
+//
+// Every frame is compiled on its own, so the merged PDF contains the fonts, images and other
+// resources once per frame. Identical objects are shared whenever frames are merged;
+// `--optimize-output` also removes unused objects and compresses the streams that are not
+// compressed yet.
+//
+
+use lopdf::{Document, Object, ObjectId};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// Types of dictionaries that are merged when they are identical. Other dictionaries (e.g. pages,
+/// annotations or optional content groups) have an identity that matters.
+const SHARED_TYPES: &[&[u8]] = &[b"Font", b"FontDescriptor", b"Encoding", b"ExtGState"];
+
+/// Key of objects that can be merged with other identical objects.
+fn shared_key(object: &Object) -> Option<md5::Digest> {
+    match object {
+        Object::Stream(stream) => {
+            let mut context = md5::Context::new();
+            context.consume(format!("{:?}", stream.dict));
+            context.consume(&stream.content);
+            Some(context.compute())
+        }
+        Object::Dictionary(dict) => {
+            let object_type = dict.get(b"Type").and_then(|t| t.as_name()).ok()?;
+            if SHARED_TYPES.contains(&object_type) {
+                Some(md5::compute(format!("{:?}", dict)))
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Makes all references to identical objects point to the same object. Merging objects can make
+/// the objects referencing them identical, so this is repeated until nothing changes. Returns the
+/// number of merged objects.
+pub fn merge_identical_objects(doc: &mut Document) -> usize {
+    let mut num_merged = 0;
+    loop {
+        let mut originals: HashMap<md5::Digest, ObjectId> = HashMap::new();
+        let mut duplicates: HashMap<ObjectId, ObjectId> = HashMap::new();
+        for (id, object) in doc.objects.iter() {
+            if let Some(key) = shared_key(object) {
+                let original = *originals.entry(key).or_insert(*id);
+                if original != *id {
+                    duplicates.insert(*id, original);
+                }
+            }
+        }
+        if duplicates.is_empty() {
+            return num_merged;
+        }
+        num_merged += duplicates.len();
+
+        for id in duplicates.keys() {
+            doc.objects.remove(id);
+        }
+        doc.traverse_objects(|object| {
+            if let Object::Reference(id) = object {
+                if let Some(original) = duplicates.get(id) {
+                    *id = *original;
+                }
+            }
+        });
+    }
+}
+
+/// Shares identical objects of `pdf` (e.g. the fonts and logos of the theme, which every frame
+/// embeds) in place and removes the copies. Returns the number of removed objects.
+pub fn share_resources(pdf: &Path) -> lopdf::Result<usize> {
+    let mut doc = Document::load(pdf)?;
+    let num_merged = merge_identical_objects(&mut doc);
+    if num_merged > 0 {
+        doc.prune_objects();
+        doc.save(pdf)?;
+    }
+    Ok(num_merged)
+}
+
+/// Optimizes the size of `pdf` in place. A symbolic link is replaced by the optimized file.
+/// Returns the sizes before and after.
+pub fn optimize_pdf(pdf: &Path) -> lopdf::Result<(u64, u64)> {
+    let size_before = fs::metadata(pdf)?.len();
+    let mut doc = Document::load(pdf)?;
+
+    let num_merged = merge_identical_objects(&mut doc);
+    let num_pruned = doc.prune_objects().len();
+    debug!(
+        "Merged {} identical and removed {} unused objects of {}",
+        num_merged,
+        num_pruned,
+        pdf.display()
+    );
+    doc.compress();
+    doc.renumber_objects();
+
+    // Do not write through a link into the cache
+    fs::remove_file(pdf)?;
+    doc.save(pdf)?;
+    Ok((size_before, fs::metadata(pdf)?.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lopdf::{Dictionary, Stream};
+
+    #[test]
+    fn test_merge_identical_objects() {
+        let mut doc = Document::with_version("1.5");
+        let mut fonts = Vec::new();
+        for _ in 0..3 {
+            let font_file = doc.add_object(Stream::new(Dictionary::new(), b"glyphs".to_vec()));
+            let mut descriptor = Dictionary::new();
+            descriptor.set("Type", Object::Name(b"FontDescriptor".to_vec()));
+            descriptor.set("FontFile", Object::Reference(font_file));
+            fonts.push(Object::Reference(doc.add_object(descriptor)));
+        }
+        let mut page = Dictionary::new();
+        page.set("Type", Object::Name(b"Page".to_vec()));
+        let pages = vec![
+            Object::Reference(doc.add_object(page.clone())),
+            Object::Reference(doc.add_object(page)),
+        ];
+        let mut catalog = Dictionary::new();
+        catalog.set("Fonts", fonts);
+        catalog.set("Pages", pages);
+        let catalog_id = doc.add_object(catalog);
+        doc.trailer.set("Root", Object::Reference(catalog_id));
+
+        // Two font files, then two descriptors
+        assert_eq!(merge_identical_objects(&mut doc), 4);
+        let catalog = doc.get_dictionary(catalog_id).unwrap();
+        let references = |key: &[u8]| -> Vec<ObjectId> {
+            catalog
+                .get(key)
+                .unwrap()
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|o| o.as_reference().unwrap())
+                .collect()
+        };
+        let fonts = references(b"Fonts");
+        assert!(fonts.iter().all(|font| *font == fonts[0]));
+        // Pages are never merged
+        let pages = references(b"Pages");
+        assert_ne!(pages[0], pages[1]);
+    }
+}
@@ -0,0 +1,690 @@
+//
+// options.rs
+// Distributed under terms of the GPLv3 license.
+//
+// The options of a build as typed values. The command line is converted into `Options` once (see
+// `Options::from_matches`) and the library builds them with the methods below, so that the steps
+// of a build never look at the parsed command line. Options start with their defaults, the
+// configuration of the input file (see `config`) overrides them, and the command line overrides
+// both.
+//
+
+use crate::batch::{make_jobs, process_jobs};
+use crate::beamer::{parse_conditional, split_labels, BEAMER_MODES, NOTES_MODES, OVERLAY_MODES};
+use crate::clean::parse_size;
+use crate::config::Config;
+use crate::container::RUNTIMES;
+use crate::events::MESSAGE_FORMATS;
+use crate::merger::MERGERS;
+use crate::preflight;
+use crate::process_file::{load_frames, FasterBeamerError, Result};
+use crate::recovery::recover;
+use crate::remote::is_valid_host;
+use clap::ArgMatches;
+use std::path::PathBuf;
+use std::str::FromStr;
+use toml::Value;
+
+/// Interaction modes of the compiler (see `--interaction`).
+pub const INTERACTION_MODES: &[&str] = &["nonstopmode", "scrollmode", "batchmode"];
+
+/// Long names of the command line options that make up `Options`, in the order in which they are
+/// read (`no-shell-escape` after `shell-escape`).
+const OPTION_NAMES: &[&str] = &[
+    "INPUT",
+    "OUTPUT",
+    "page-map",
+    "find-root",
+    "watch",
+    "debounce",
+    "watch-ignore",
+    "profile",
+    "jobs",
+    "compiler",
+    "max-reruns",
+    "latexmk-engine",
+    "latex-arg",
+    "shell-escape",
+    "no-shell-escape",
+    "container",
+    "container-runtime",
+    "remote",
+    "remote-dir",
+    "remote-cache",
+    "remote-cache-read-only",
+    "cache-dir",
+    "cache-max-size",
+    "cache-max-age",
+    "message-format",
+    "merger",
+    "interaction",
+    "halt-on-error",
+    "navigation",
+    "bibliography",
+    "strip-decorations",
+    "only-frames",
+    "conditional",
+    "aspectratio",
+    "beamer-mode",
+    "handout",
+    "overlays",
+    "notes",
+    "theme",
+    "preamble-extra",
+    "preamble",
+    "pandoc-arg",
+    "keep-intermediates",
+    "frame-numbers",
+    "tree-sitter",
+    "unite",
+    "pdfunite",
+    "synctex",
+    "pdfpc",
+    "optimize-output",
+    "tagged",
+    "variants",
+    "watermark",
+];
+
+/// Options of a build, the typed counterpart of the command line options (see `cli` for their
+/// meaning).
+///
+/// ```ignore
+/// let options = Options::new().aspectratio("169").unite().output_file("talk.pdf");
+/// faster_beamer::compile_document("talk.tex", &options)?;
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Options {
+    /// Input files, frame directories or glob patterns
+    pub inputs: Vec<String>,
+    /// File with the contents that are built instead of those of the input file (e.g. the
+    /// unsaved buffer of an editor). Not an option of the command line.
+    pub buffer: Option<PathBuf>,
+    pub output_file: Option<String>,
+    pub page_map: Option<String>,
+    pub find_root: bool,
+    pub watch: bool,
+    /// Milliseconds without changes before a build in watch mode
+    pub debounce: Option<u64>,
+    pub watch_ignore: Vec<String>,
+    pub profile: Option<String>,
+    pub jobs: Option<usize>,
+    pub compiler: String,
+    pub max_reruns: Option<usize>,
+    pub latexmk_engine: String,
+    pub latex_args: Vec<String>,
+    pub shell_escape: bool,
+    /// Image of `--container`
+    pub container: Option<String>,
+    pub container_runtime: String,
+    /// SSH hosts of `--remote`
+    pub remote: Vec<String>,
+    pub remote_dir: Option<String>,
+    /// URL of `--remote-cache`
+    pub remote_cache: Option<String>,
+    pub remote_cache_read_only: bool,
+    pub cache_dir: Option<PathBuf>,
+    /// In bytes
+    pub cache_max_size: Option<u64>,
+    /// In days
+    pub cache_max_age: Option<u64>,
+    pub message_format: String,
+    pub merger: String,
+    pub interaction: String,
+    pub halt_on_error: bool,
+    pub navigation: bool,
+    pub bibliography: bool,
+    pub strip_decorations: bool,
+    /// Labels of `--only-frames`
+    pub only_frames: Option<Vec<String>>,
+    /// Conditionals set with `--conditional`
+    pub conditionals: Vec<(String, bool)>,
+    pub aspectratio: Option<String>,
+    pub beamer_mode: Option<String>,
+    pub handout: bool,
+    pub overlays: Option<String>,
+    pub notes: Option<String>,
+    pub theme: Option<String>,
+    pub preamble_extra: Vec<String>,
+    /// Preamble file of a frame directory
+    pub preamble: Option<String>,
+    pub pandoc_args: Vec<String>,
+    pub keep_intermediates: bool,
+    pub frame_numbers: bool,
+    pub tree_sitter: bool,
+    /// Draft mode (not an option of the command line yet)
+    pub draft: bool,
+    pub unite: bool,
+    pub pdfunite: bool,
+    pub synctex: bool,
+    pub pdfpc: bool,
+    pub optimize_output: bool,
+    pub tagged: bool,
+    /// Aspect ratios of `--variants`
+    pub variants: Vec<String>,
+    pub watermark: Option<String>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            inputs: Vec::new(),
+            buffer: None,
+            output_file: None,
+            page_map: None,
+            find_root: false,
+            watch: false,
+            debounce: None,
+            watch_ignore: Vec::new(),
+            profile: None,
+            jobs: None,
+            compiler: "pdflatex".to_string(),
+            max_reruns: None,
+            latexmk_engine: "pdflatex".to_string(),
+            latex_args: Vec::new(),
+            shell_escape: false,
+            container: None,
+            container_runtime: "auto".to_string(),
+            remote: Vec::new(),
+            remote_dir: None,
+            remote_cache: None,
+            remote_cache_read_only: false,
+            cache_dir: None,
+            cache_max_size: None,
+            cache_max_age: None,
+            message_format: "human".to_string(),
+            merger: "auto".to_string(),
+            interaction: "nonstopmode".to_string(),
+            halt_on_error: false,
+            navigation: false,
+            bibliography: false,
+            strip_decorations: false,
+            only_frames: None,
+            conditionals: Vec::new(),
+            aspectratio: None,
+            beamer_mode: None,
+            handout: false,
+            overlays: None,
+            notes: None,
+            theme: None,
+            preamble_extra: Vec::new(),
+            preamble: None,
+            pandoc_args: Vec::new(),
+            keep_intermediates: false,
+            frame_numbers: false,
+            tree_sitter: false,
+            draft: false,
+            unite: false,
+            pdfunite: false,
+            synctex: false,
+            pdfpc: false,
+            optimize_output: false,
+            tagged: false,
+            variants: Vec::new(),
+            watermark: None,
+        }
+    }
+}
+
+/// The last of `values`.
+fn single(values: &[&str]) -> std::result::Result<String, String> {
+    values
+        .last()
+        .map(|value| value.to_string())
+        .ok_or_else(|| "expected a value".to_string())
+}
+
+/// The last of `values` as a number.
+fn number<T: FromStr>(values: &[&str]) -> std::result::Result<T, String> {
+    let value = single(values)?;
+    value
+        .parse()
+        .map_err(|_| format!("expected a number, got '{}'", value))
+}
+
+/// Whether the flag is set to `true` (or `false`).
+fn flag(values: &[&str]) -> std::result::Result<bool, String> {
+    let value = single(values)?;
+    value
+        .parse()
+        .map_err(|_| format!("expected true or false, got '{}'", value))
+}
+
+/// The last of `values`, which has to be one of `possible_values`.
+fn choice(values: &[&str], possible_values: &[&str]) -> std::result::Result<String, String> {
+    let value = single(values)?;
+    if possible_values.contains(&value.as_str()) {
+        Ok(value)
+    } else {
+        Err(format!(
+            "expected one of {}, got '{}'",
+            possible_values.join(", "),
+            value
+        ))
+    }
+}
+
+fn list(values: &[&str]) -> Vec<String> {
+    values.iter().map(|value| value.to_string()).collect()
+}
+
+/// The values of an option of the configuration as on the command line (`true` or `false` for a
+/// flag).
+fn config_values(value: &Value) -> Vec<String> {
+    let values = match value {
+        Value::Array(values) => values.iter().collect(),
+        value => vec![value],
+    };
+    values
+        .into_iter()
+        .map(|value| match value {
+            Value::String(value) => value.clone(),
+            value => value.to_string(),
+        })
+        .collect()
+}
+
+impl Options {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The options that the configuration of `input_file` sets (with the overrides of `profile`).
+    /// Invalid values of the configuration are ignored with a warning.
+    pub fn from_config(input_file: &str, profile: Option<&str>) -> Self {
+        let mut options = Options {
+            profile: profile.map(|profile| profile.to_string()),
+            ..Options::default()
+        };
+        let defaults = Config::find(input_file).option_defaults(profile);
+        for name in defaults.keys() {
+            if !OPTION_NAMES.contains(&name.as_str()) {
+                debug!("Ignoring {} of the configuration", name);
+            }
+        }
+        for name in OPTION_NAMES {
+            if let Some(value) = defaults.get(*name) {
+                let values = config_values(value);
+                let values: Vec<&str> = values.iter().map(String::as_str).collect();
+                if let Err(err) = options.set(name, &values) {
+                    warn!("Ignoring {} of the configuration: {}", name, err);
+                }
+            }
+        }
+        options
+    }
+
+    /// The options of the parsed command line `matches` (those of the subcommand override those
+    /// of the command) over those of the configuration of the (first) input file.
+    pub fn from_matches(matches: &ArgMatches) -> Result<Self> {
+        let sub_matches = matches.subcommand().1;
+        // Default values of the command line are those of `Options`, which the configuration
+        // overrides
+        let values_of = |name: &str| -> Option<Vec<&str>> {
+            let given = match sub_matches {
+                Some(sub_matches) if sub_matches.occurrences_of(name) > 0 => sub_matches,
+                _ if matches.occurrences_of(name) > 0 => matches,
+                _ => return None,
+            };
+            let values: Vec<&str> = given.values_of(name).into_iter().flatten().collect();
+            if values.is_empty() {
+                // A flag
+                Some(vec!["true"])
+            } else {
+                Some(values)
+            }
+        };
+        let profile = values_of("profile").and_then(|values| values.last().copied());
+        let mut options = match values_of("INPUT") {
+            Some(inputs) => Options::from_config(inputs[0], profile),
+            None => Options::default(),
+        };
+        for name in OPTION_NAMES {
+            if let Some(values) = values_of(name) {
+                options.set(name, &values).map_err(|err| {
+                    FasterBeamerError::InvalidOptions(format!("--{}: {}", name, err))
+                })?;
+            }
+        }
+        Ok(options)
+    }
+
+    /// Sets the option `name` (its long name on the command line) to `values`: the values of an
+    /// option that takes them, or `true` or `false` for a flag.
+    pub fn set(&mut self, name: &str, values: &[&str]) -> std::result::Result<(), String> {
+        match name {
+            "INPUT" => self.inputs = list(values),
+            "OUTPUT" => self.output_file = Some(single(values)?),
+            "page-map" => self.page_map = Some(single(values)?),
+            "find-root" => self.find_root = flag(values)?,
+            "watch" => self.watch = flag(values)?,
+            "debounce" => self.debounce = Some(number(values)?),
+            "watch-ignore" => self.watch_ignore = list(values),
+            "profile" => self.profile = Some(single(values)?),
+            "jobs" => self.jobs = Some(number(values)?),
+            "compiler" => self.compiler = single(values)?,
+            "max-reruns" => self.max_reruns = Some(number(values)?),
+            "latexmk-engine" => self.latexmk_engine = single(values)?,
+            "latex-arg" => self.latex_args = list(values),
+            "shell-escape" => self.shell_escape = flag(values)?,
+            "no-shell-escape" => self.shell_escape = !flag(values)?,
+            "container" => self.container = Some(single(values)?),
+            "container-runtime" => self.container_runtime = choice(values, RUNTIMES)?,
+            "remote" => {
+                if let Some(host) = values.iter().find(|host| !is_valid_host(host)) {
+                    return Err(format!("invalid host '{}'", host));
+                }
+                self.remote = list(values)
+            }
+            "remote-dir" => self.remote_dir = Some(single(values)?),
+            "remote-cache" => self.remote_cache = Some(single(values)?),
+            "remote-cache-read-only" => self.remote_cache_read_only = flag(values)?,
+            "cache-dir" => self.cache_dir = Some(PathBuf::from(single(values)?)),
+            "cache-max-size" => {
+                let size = single(values)?;
+                self.cache_max_size =
+                    Some(parse_size(&size).ok_or_else(|| {
+                        format!("expected a size like 500M or 2G, got '{}'", size)
+                    })?);
+            }
+            "cache-max-age" => self.cache_max_age = Some(number(values)?),
+            "message-format" => self.message_format = choice(values, MESSAGE_FORMATS)?,
+            "merger" => self.merger = choice(values, MERGERS)?,
+            "interaction" => self.interaction = choice(values, INTERACTION_MODES)?,
+            "halt-on-error" => self.halt_on_error = flag(values)?,
+            "navigation" => self.navigation = flag(values)?,
+            "bibliography" => self.bibliography = flag(values)?,
+            "strip-decorations" => self.strip_decorations = flag(values)?,
+            "only-frames" => self.only_frames = Some(split_labels(&single(values)?)),
+            "conditional" => {
+                self.conditionals = values
+                    .iter()
+                    .map(|value| {
+                        parse_conditional(value).ok_or_else(|| {
+                            format!("expected NAME=true or NAME=false, got '{}'", value)
+                        })
+                    })
+                    .collect::<std::result::Result<_, _>>()?
+            }
+            "aspectratio" => self.aspectratio = Some(single(values)?),
+            "beamer-mode" => self.beamer_mode = Some(choice(values, BEAMER_MODES)?),
+            "handout" => self.handout = flag(values)?,
+            "overlays" => self.overlays = Some(choice(values, OVERLAY_MODES)?),
+            "notes" => self.notes = Some(choice(values, NOTES_MODES)?),
+            "theme" => self.theme = Some(single(values)?),
+            "preamble-extra" => self.preamble_extra = list(values),
+            "preamble" => self.preamble = Some(single(values)?),
+            "pandoc-arg" => self.pandoc_args = list(values),
+            "keep-intermediates" => self.keep_intermediates = flag(values)?,
+            "frame-numbers" => self.frame_numbers = flag(values)?,
+            "tree-sitter" => self.tree_sitter = flag(values)?,
+            "unite" => self.unite = flag(values)?,
+            "pdfunite" => self.pdfunite = flag(values)?,
+            "synctex" => self.synctex = flag(values)?,
+            "pdfpc" => self.pdfpc = flag(values)?,
+            "optimize-output" => self.optimize_output = flag(values)?,
+            "tagged" => self.tagged = flag(values)?,
+            "variants" => {
+                self.variants = values
+                    .iter()
+                    .flat_map(|value| value.split(','))
+                    .map(|variant| variant.trim().to_string())
+                    .filter(|variant| !variant.is_empty())
+                    .collect()
+            }
+            "watermark" => self.watermark = Some(single(values)?),
+            _ => return Err(format!("unknown option {}", name)),
+        }
+        Ok(())
+    }
+
+    /// Checks the values that the command line restricts (for options built by the library).
+    pub fn validate(&self) -> Result<()> {
+        let check = |name: &str, value: Option<&str>, possible_values: &[&str]| match value {
+            Some(value) if !possible_values.contains(&value) => {
+                Err(FasterBeamerError::InvalidOptions(format!(
+                    "--{}: expected one of {}, got '{}'",
+                    name,
+                    possible_values.join(", "),
+                    value
+                )))
+            }
+            _ => Ok(()),
+        };
+        check("beamer-mode", self.beamer_mode.as_deref(), BEAMER_MODES)?;
+        check("overlays", self.overlays.as_deref(), OVERLAY_MODES)?;
+        check("notes", self.notes.as_deref(), NOTES_MODES)?;
+        check("interaction", Some(&self.interaction), INTERACTION_MODES)?;
+        check("merger", Some(&self.merger), MERGERS)?;
+        check("container-runtime", Some(&self.container_runtime), RUNTIMES)?;
+        if self.handout && self.beamer_mode.is_some() {
+            return Err(FasterBeamerError::InvalidOptions(
+                "--handout cannot be used with --beamer-mode".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn output_file(mut self, output_file: &str) -> Self {
+        self.output_file = Some(output_file.to_string());
+        self
+    }
+
+    /// Reinserts the frames into the original document.
+    pub fn unite(mut self) -> Self {
+        self.unite = true;
+        self
+    }
+
+    /// Glues the frames together page by page (like pdfunite).
+    pub fn pdfunite(mut self) -> Self {
+        self.pdfunite = true;
+        self
+    }
+
+    pub fn compiler(mut self, command: &str) -> Self {
+        self.compiler = command.to_string();
+        self
+    }
+
+    pub fn profile(mut self, profile: &str) -> Self {
+        self.profile = Some(profile.to_string());
+        self
+    }
+
+    pub fn aspectratio(mut self, aspectratio: &str) -> Self {
+        self.aspectratio = Some(aspectratio.to_string());
+        self
+    }
+
+    pub fn theme(mut self, theme: &str) -> Self {
+        self.theme = Some(theme.to_string());
+        self
+    }
+
+    /// `presentation`, `handout` or `article`
+    pub fn beamer_mode(mut self, mode: &str) -> Self {
+        self.beamer_mode = Some(mode.to_string());
+        self
+    }
+
+    /// `all`, `collapse` or `last`
+    pub fn overlays(mut self, mode: &str) -> Self {
+        self.overlays = Some(mode.to_string());
+        self
+    }
+
+    /// `none`, `show` or `only`
+    pub fn notes(mut self, mode: &str) -> Self {
+        self.notes = Some(mode.to_string());
+        self
+    }
+
+    /// Same as `beamer_mode("handout")`
+    pub fn handout(mut self) -> Self {
+        self.handout = true;
+        self
+    }
+
+    /// Only the frames with `labels` (like `\includeonlyframes`)
+    pub fn only_frames(mut self, labels: &[&str]) -> Self {
+        self.only_frames = Some(labels.iter().map(|label| label.to_string()).collect());
+        self
+    }
+
+    /// Sets the conditional `\ifname` (of `\newif`) to `value`
+    pub fn conditional(mut self, name: &str, value: bool) -> Self {
+        self.conditionals.push((name.to_string(), value));
+        self
+    }
+
+    pub fn page_map(mut self, page_map_file: &str) -> Self {
+        self.page_map = Some(page_map_file.to_string());
+        self
+    }
+}
+
+/// Builds `input_file` (a LaTeX or Markdown presentation or a frame directory) like
+/// `faster-beamer <options> <input_file>` does.
+pub fn compile_document(input_file: &str, options: &Options) -> Result<()> {
+    options.validate()?;
+    let jobs = make_jobs(
+        &[input_file.to_string()],
+        options.output_file.as_deref(),
+        options.page_map.as_deref(),
+        options.profile.as_deref(),
+    );
+    preflight::check(&jobs, options)?;
+    recover(&jobs);
+    process_jobs(&jobs, options).remove(0)
+}
+
+/// The source of every frame of `input_file`, in the order of the document.
+pub fn extract_frames(input_file: &str, options: &Options) -> Result<Vec<String>> {
+    options.validate()?;
+    Ok(load_frames(input_file, options)?.frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::app;
+    use crate::config::CONFIG_FILE;
+    use crate::process_file::beamer_mode;
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn test_options() {
+        let mut options = Options::new()
+            .aspectratio("169")
+            .unite()
+            .only_frames(&["intro", "results"])
+            .conditional("draft", false);
+        options.set("strip-decorations", &["true"]).unwrap();
+        assert_eq!(options.aspectratio.as_deref(), Some("169"));
+        assert_eq!(
+            options.only_frames,
+            Some(vec!["intro".to_string(), "results".to_string()])
+        );
+        assert_eq!(options.conditionals, vec![("draft".to_string(), false)]);
+        assert!(options.unite);
+        assert!(options.strip_decorations);
+
+        let invalid = Options::new().beamer_mode("slides").validate();
+        assert!(matches!(invalid, Err(FasterBeamerError::InvalidOptions(_))));
+        assert!(Options::new().set("conditional", &["draft=maybe"]).is_err());
+
+        assert_eq!(beamer_mode(&Options::new().handout()), Some("handout"));
+        let conflicting = Options::new().handout().beamer_mode("article").validate();
+        assert!(matches!(
+            conflicting,
+            Err(FasterBeamerError::InvalidOptions(_))
+        ));
+    }
+
+    #[test]
+    fn test_from_matches() {
+        let matches = app()
+            .get_matches_from_safe(&["faster-beamer", "talk.tex"])
+            .unwrap();
+        assert_eq!(
+            Options::from_matches(&matches).ok().unwrap(),
+            Options {
+                inputs: vec!["talk.tex".to_string()],
+                ..Options::default()
+            }
+        );
+        // The default values of the command line are those of `Options`
+        let mut defaults = Options::default();
+        for name in OPTION_NAMES {
+            if let Some(value) = matches.value_of(name).filter(|_| *name != "INPUT") {
+                defaults.set(name, &[value]).unwrap();
+            }
+        }
+        assert_eq!(defaults, Options::default());
+
+        let matches = app()
+            .get_matches_from_safe(&[
+                "faster-beamer",
+                "--shell-escape",
+                "build",
+                "--unite",
+                "--conditional",
+                "draft=false",
+                "--variants",
+                "169,43",
+                "talk.tex",
+            ])
+            .unwrap();
+        let options = Options::from_matches(&matches).ok().unwrap();
+        assert!(options.unite);
+        assert!(options.shell_escape);
+        assert_eq!(options.conditionals, vec![("draft".to_string(), false)]);
+        assert_eq!(options.variants, vec!["169", "43"]);
+        assert_eq!(options.inputs, vec!["talk.tex"]);
+    }
+
+    #[test]
+    fn test_from_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let talk = dir.path().join("talk.tex");
+        fs::write(&talk, "").unwrap();
+        fs::write(
+            dir.path().join(CONFIG_FILE),
+            "[defaults]\nunite = true\naspectratio = 169\nvariants = [\"169\", \"43\"]\n\
+             remote = [\"attacker\"]\ninteraction = \"sometimes\"\n\
+             [profile.print.defaults]\nunite = false\n",
+        )
+        .unwrap();
+        let talk = talk.to_string_lossy().into_owned();
+
+        let options = Options::from_config(&talk, None);
+        assert!(options.unite);
+        assert_eq!(options.aspectratio.as_deref(), Some("169"));
+        assert_eq!(options.variants, vec!["169", "43"]);
+        // Not from the configuration of a project
+        assert!(options.remote.is_empty());
+        // Invalid values are ignored
+        assert_eq!(options.interaction, "nonstopmode");
+        assert!(!Options::from_config(&talk, Some("print")).unite);
+
+        // The command line overrides the configuration
+        let matches = app()
+            .get_matches_from_safe(&["faster-beamer", "--aspectratio", "43", talk.as_str()])
+            .unwrap();
+        let options = Options::from_matches(&matches).ok().unwrap();
+        assert!(options.unite);
+        assert_eq!(options.aspectratio.as_deref(), Some("43"));
+    }
+
+    #[test]
+    fn test_extract_frames() {
+        let talk = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/talk.tex");
+        let frames = extract_frames(&talk.to_string_lossy(), &Options::new())
+            .ok()
+            .unwrap();
+        assert_eq!(frames.len(), 3);
+        assert!(frames.iter().all(|f| f.contains("\\end{frame}")));
+    }
+}
@@ -0,0 +1,188 @@
+//
+// outline.rs
+// Distributed under terms of the GPLv3 license.
+//
+
+use crate::beamer::frame_title;
+use crate::error::log_excerpt;
+use crate::includes::is_commented;
+use crate::latexcompile::{LatexCompiler, LatexInput, LatexRunOptions};
+use crate::options::Options;
+use crate::parsing::balanced_group;
+use crate::process_file::{
+    engine_command, get_cache_subdir, get_cachedir, get_input_dir, latex_args, load_frames,
+    FasterBeamerError, Result,
+};
+use regex::Regex;
+use std::fs;
+use std::path::Path;
+
+lazy_static! {
+    static ref SECTION_REGEX: Regex =
+        Regex::new(r"\\(section|subsection)\*?\s*(?:\[[^\]]*\]\s*)?\{").unwrap();
+}
+lazy_static! {
+    static ref TITLE_REGEX: Regex = Regex::new(r"\\title\s*(?:\[[^\]]*\]\s*)?\{").unwrap();
+}
+
+#[derive(Debug, PartialEq)]
+pub enum OutlineEntry {
+    Section(String),
+    Subsection(String),
+    Frame { index: usize, title: Option<String> },
+}
+
+/// Returns the sections, subsections and frames of a document in order of appearance.
+/// `frames` are pairs of (byte offset of frame in `content`, frame source).
+pub fn extract_outline(content: &str, frames: &[(usize, &str)]) -> Vec<OutlineEntry> {
+    let mut entries: Vec<(usize, OutlineEntry)> = Vec::new();
+
+    for m in SECTION_REGEX.find_iter(content) {
+        if is_commented(content, m.start()) {
+            continue;
+        }
+        let inside_frame = frames
+            .iter()
+            .any(|(offset, frame)| m.start() > *offset && m.start() < offset + frame.len());
+        if inside_frame {
+            continue;
+        }
+        if let Some((title, _)) = balanced_group(content, m.end() - 1) {
+            let title = title.trim().to_string();
+            let entry = if m.as_str().starts_with("\\subsection") {
+                OutlineEntry::Subsection(title)
+            } else {
+                OutlineEntry::Section(title)
+            };
+            entries.push((m.start(), entry));
+        }
+    }
+
+    for (index, (offset, frame)) in frames.iter().enumerate() {
+        entries.push((
+            *offset,
+            OutlineEntry::Frame {
+                index,
+                title: frame_title(frame),
+            },
+        ));
+    }
+
+    entries.sort_by_key(|(offset, _)| *offset);
+    entries.into_iter().map(|(_, entry)| entry).collect()
+}
+
+/// Generates a short LaTeX document listing the outline, one line per frame.
+pub fn outline_tex(document_title: Option<&str>, outline: &[OutlineEntry]) -> String {
+    let mut tex = String::from(
+        "\\documentclass{article}\n\
+         \\usepackage[margin=2cm]{geometry}\n\
+         \\setlength{\\parindent}{0pt}\n\
+         \\begin{document}\n",
+    );
+    if let Some(title) = document_title {
+        tex += &format!("{{\\LARGE {}\\par}}\n\\bigskip\n", title);
+    }
+
+    for entry in outline {
+        match entry {
+            OutlineEntry::Section(title) => tex += &format!("\\section*{{{}}}\n", title),
+            OutlineEntry::Subsection(title) => tex += &format!("\\subsection*{{{}}}\n", title),
+            OutlineEntry::Frame { index, title } => {
+                tex += &format!(
+                    "\\makebox[3em][r]{{{}}}\\quad {}\\par\n",
+                    index,
+                    title.as_deref().unwrap_or("\\textit{(untitled)}")
+                )
+            }
+        }
+    }
+    tex + "\\end{document}\n"
+}
+
+/// Writes a PDF with the section headers and frame titles of `input_file` to `output_file`.
+pub fn export_outline(input_file: &str, output_file: &str, args: &Options) -> Result<()> {
+    if !Path::new(input_file).is_file() {
+        return Err(FasterBeamerError::InputFileNotExistent(input_file.into()));
+    }
+    let compilercmd = engine_command(args);
+
+    let extracted = load_frames(input_file, args)?;
+    let frames: Vec<(usize, &str)> = extracted
+        .frame_offsets
+        .iter()
+        .cloned()
+        .zip(extracted.frames.iter().map(|f| &f[..]))
+        .collect();
+
+    let content = &extracted.parsed_file.file_content;
+    let document_title = TITLE_REGEX
+        .find(content)
+        .and_then(|m| balanced_group(content, m.end() - 1))
+        .map(|(title, _)| title.trim());
+    let tex = outline_tex(document_title, &extract_outline(content, &frames));
+
+    let cache_subdir = get_cache_subdir(&get_cachedir()?, &get_input_dir(input_file));
+    fs::create_dir_all(&cache_subdir).map_err(FasterBeamerError::io(&cache_subdir))?;
+    let outline_file = cache_subdir.join("outline.tex");
+    fs::write(&outline_file, tex).map_err(FasterBeamerError::io(&outline_file))?;
+
+    let mut compiler = LatexCompiler::new(compilercmd)
+        .map_err(FasterBeamerError::latex_input(&outline_file))?
+        .add_arg("-interaction=nonstopmode");
+    for arg in latex_args(args) {
+        compiler = compiler.add_arg(&arg);
+    }
+    compiler.working_dir = cache_subdir;
+
+    let outline_pdf = compiler
+        .run(
+            &outline_file.to_string_lossy(),
+            &LatexInput::new(),
+            LatexRunOptions::new(),
+        )
+        .map_err(|err| FasterBeamerError::Compile {
+            log_excerpt: log_excerpt(&outline_file.with_extension("log"), &err.to_string()),
+            file: outline_file.clone(),
+        })?;
+
+    fs::copy(&outline_pdf, output_file).map_err(FasterBeamerError::io(output_file))?;
+    info!("Wrote outline to {}", output_file);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_outline() {
+        let content = "\\section{Intro}\n\
+                       % \\section{Old}\n\
+                       \\begin{frame}{Motivation}\\end{frame}\n\
+                       \\subsection{Details}\n\
+                       \\begin{frame}\\section{Not a section}\\end{frame}\n";
+        let first = "\\begin{frame}{Motivation}\\end{frame}";
+        let second = "\\begin{frame}\\section{Not a section}\\end{frame}";
+        let frames = vec![
+            (content.find(first).unwrap(), first),
+            (content.find(second).unwrap(), second),
+        ];
+
+        assert_eq!(
+            extract_outline(content, &frames),
+            vec![
+                OutlineEntry::Section("Intro".to_string()),
+                OutlineEntry::Frame {
+                    index: 0,
+                    title: Some("Motivation".to_string())
+                },
+                OutlineEntry::Subsection("Details".to_string()),
+                OutlineEntry::Frame {
+                    index: 1,
+                    title: None
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,208 @@
+//
+// page_map.rs
+// Distributed under terms of the GPLv3 license.
+//
+
+use crate::beamer::{frame_label, frame_options};
+use crate::logs::read_log;
+use crate::process_file::{FasterBeamerError, Frame, Result};
+use regex::Regex;
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+
+lazy_static! {
+    static ref OUTPUT_WRITTEN_REGEX: Regex =
+        Regex::new(r"Output written on .*?\((\d+) pages?").unwrap();
+}
+
+/// Location of a frame in the merged PDF
+#[derive(Debug, PartialEq, Serialize)]
+pub struct PageRange {
+    pub frame: usize,
+    pub label: Option<String>,
+    /// First page of the frame (1-based)
+    pub first_page: usize,
+    /// Last page of the frame (1-based, inclusive)
+    pub last_page: usize,
+}
+
+/// Reads the number of pages from a LaTeX log ("Output written on ... (N pages, ...)").
+pub fn count_pages(log: &str) -> Option<usize> {
+    // TeX wraps long log lines (e.g. with long paths to the cache dir)
+    let log = log.replace('\n', "");
+    OUTPUT_WRITTEN_REGEX
+        .captures_iter(&log)
+        .last()
+        .and_then(|c| c[1].parse().ok())
+}
+
+/// Computes on which pages of the concatenated frame PDFs each frame lands.
+pub fn build_page_map(frames: &[Frame], cache_subdir: &Path) -> Vec<PageRange> {
+    let mut page = 1;
+    let mut page_map = Vec::with_capacity(frames.len());
+
+    for (frame_idx, frame) in frames.iter().enumerate() {
+        let num_pages = read_log(&frame.log(cache_subdir))
+            .and_then(|log| count_pages(&log))
+            .unwrap_or_else(|| {
+                warn!(
+                    "Could not determine page count of frame {}, assuming 1",
+                    frame_idx
+                );
+                1
+            });
+
+        page_map.push(PageRange {
+            frame: frame_idx,
+            label: frame_label(&frame.content),
+            first_page: page,
+            last_page: page + num_pages - 1,
+        });
+        page += num_pages;
+    }
+    page_map
+}
+
+/// Number of frames that `frame` (on `pages`) counts as: one per page with `allowframebreaks`,
+/// none with `noframenumbering`.
+fn frame_count(frame: &Frame, pages: &PageRange) -> usize {
+    let options = frame_options(&frame.content).unwrap_or("");
+    let has_option = |name: &str| {
+        options
+            .split(',')
+            .any(|option| option.split('=').next().unwrap().trim() == name)
+    };
+    if has_option("noframenumbering") {
+        0
+    } else if has_option("allowframebreaks") {
+        pages.last_page + 1 - pages.first_page
+    } else {
+        1
+    }
+}
+
+/// Value of the `framenumber` counter before every frame of `page_map`.
+pub fn frame_numbers(frames: &[Frame], page_map: &[PageRange]) -> Vec<usize> {
+    let mut number = 0;
+    frames
+        .iter()
+        .zip(page_map)
+        .map(|(frame, pages)| {
+            let before = number;
+            number += frame_count(frame, pages);
+            before
+        })
+        .collect()
+}
+
+/// Number of frames of the whole presentation (`\inserttotalframenumber`).
+pub fn total_frame_number(frames: &[Frame], page_map: &[PageRange]) -> usize {
+    frames
+        .iter()
+        .zip(page_map)
+        .map(|(frame, pages)| frame_count(frame, pages))
+        .sum()
+}
+
+pub fn to_json(page_map: &[PageRange]) -> String {
+    serde_json::to_string_pretty(page_map).unwrap_or_default() + "\n"
+}
+
+/// One line per frame: `<frame index>\t<label or ->\t<first page>-<last page>`
+pub fn to_text(page_map: &[PageRange]) -> String {
+    page_map
+        .iter()
+        .map(|p| {
+            format!(
+                "{}\t{}\t{}-{}\n",
+                p.frame,
+                p.label.as_deref().unwrap_or("-"),
+                p.first_page,
+                p.last_page
+            )
+        })
+        .collect()
+}
+
+/// Writes the page map as JSON if `path` ends with `.json`, otherwise as text index.
+pub fn write_page_map(page_map: &[PageRange], path: &Path) -> Result<()> {
+    let content = match path.extension().and_then(|e| e.to_str()) {
+        Some("json") => to_json(page_map),
+        _ => to_text(page_map),
+    };
+    fs::write(path, content).map_err(FasterBeamerError::io(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_page_map_output() {
+        assert_eq!(
+            count_pages("Output written on /tmp/abc.pdf (3 pages, 1234 bytes)."),
+            Some(3)
+        );
+        assert_eq!(
+            count_pages("Output written on /tmp/abc.pdf (12 p\nages, 1234 bytes)."),
+            Some(12)
+        );
+        assert_eq!(count_pages("No pages of output."), None);
+
+        let page_map = vec![
+            PageRange {
+                frame: 0,
+                label: Some("intro".to_string()),
+                first_page: 1,
+                last_page: 3,
+            },
+            PageRange {
+                frame: 1,
+                label: None,
+                first_page: 4,
+                last_page: 4,
+            },
+        ];
+        assert_eq!(to_text(&page_map), "0\tintro\t1-3\n1\t-\t4-4\n");
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&to_json(&page_map)).unwrap(),
+            serde_json::json!([
+                {"frame": 0, "label": "intro", "first_page": 1, "last_page": 3},
+                {"frame": 1, "label": null, "first_page": 4, "last_page": 4},
+            ])
+        );
+    }
+
+    #[test]
+    fn test_frame_numbers() {
+        let frames: Vec<Frame> = vec![
+            "\\begin{frame}{Overlays}\\pause\\end{frame}",
+            "\\begin{frame}[allowframebreaks=0.9]{References}\\end{frame}",
+            "\\begin{frame}[plain,noframenumbering]\\end{frame}",
+            "\\begin{frame}{Last}\\end{frame}",
+        ]
+        .into_iter()
+        .map(|content| Frame {
+            content: content.to_string(),
+            offset: 0,
+            tex: String::new(),
+            body_line: 1,
+            hash: md5::compute(content),
+        })
+        .collect();
+        let pages = [(1, 2), (3, 5), (6, 6), (7, 7)];
+        let page_map: Vec<PageRange> = pages
+            .iter()
+            .enumerate()
+            .map(|(frame, &(first_page, last_page))| PageRange {
+                frame,
+                label: None,
+                first_page,
+                last_page,
+            })
+            .collect();
+        assert_eq!(frame_numbers(&frames, &page_map), vec![0, 1, 4, 4]);
+        assert_eq!(total_frame_number(&frames, &page_map), 5);
+    }
+}
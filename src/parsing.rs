@@ -4,37 +4,127 @@
 // Distributed under terms of the MIT license.
 //
 
+use crate::error::{FasterBeamerError, Result};
+use crate::includes::SourceMap;
 use crate::tree_traversal::get_nodes_of_type;
 use std::fs;
-use tree_sitter::{Node, Parser};
+use std::path::{Path, PathBuf};
+use tree_sitter::{InputEdit, Node, Parser, Point};
 use tree_sitter_latex;
 
+/// Replacement of the bytes `start..end` of a text by `text`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextEdit {
+    pub start: usize,
+    pub end: usize,
+    pub text: String,
+}
+
+impl TextEdit {
+    /// The edit that turns `old` into `new` (None if they are equal): the part between their
+    /// common prefix and suffix.
+    pub fn between(old: &str, new: &str) -> Option<TextEdit> {
+        if old == new {
+            return None;
+        }
+        let mut start = old
+            .bytes()
+            .zip(new.bytes())
+            .take_while(|(a, b)| a == b)
+            .count();
+        while !old.is_char_boundary(start) || !new.is_char_boundary(start) {
+            start -= 1;
+        }
+        let max_suffix = old.len().min(new.len()) - start;
+        let mut suffix = old
+            .bytes()
+            .rev()
+            .zip(new.bytes().rev())
+            .take(max_suffix)
+            .take_while(|(a, b)| a == b)
+            .count();
+        while !old.is_char_boundary(old.len() - suffix) || !new.is_char_boundary(new.len() - suffix)
+        {
+            suffix -= 1;
+        }
+        Some(TextEdit {
+            start,
+            end: old.len() - suffix,
+            text: new[start..new.len() - suffix].to_string(),
+        })
+    }
+}
+
+/// Tree-sitter position (row and byte column, both 0-based) of `byte_offset` in `text`.
+fn point(text: &str, byte_offset: usize) -> Point {
+    let before = &text[..byte_offset];
+    let row = before.matches('\n').count();
+    let column = byte_offset - before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    Point::new(row, column)
+}
+
+fn latex_parser() -> Parser {
+    let mut parser = Parser::new();
+    parser.set_language(tree_sitter_latex::language()).unwrap();
+    parser
+}
+
+#[derive(Clone)]
 pub struct ParsedFile {
     pub filename: String,
     pub file_content: String,
     pub syntax_tree: tree_sitter::Tree,
+    /// Files spliced into `file_content` (by `\input` or `\include`)
+    pub sources: SourceMap,
 }
 
 impl ParsedFile {
-    pub fn new(filename: String) -> ParsedFile {
-        let file_content = fs::read_to_string(&filename).expect("Failed to read file");
+    pub fn new(filename: String) -> Result<ParsedFile> {
+        let file_content =
+            fs::read_to_string(&filename).map_err(FasterBeamerError::io(&filename))?;
         ParsedFile::from_string(filename, file_content)
     }
 
-    pub fn from_string(filename: String, file_content: String) -> ParsedFile {
-        let mut parser = Parser::new();
-        let language = tree_sitter_latex::language();
-
-        parser.set_language(language).unwrap();
-
-        let tree = parser
+    pub fn from_string(filename: String, file_content: String) -> Result<ParsedFile> {
+        let tree = latex_parser()
             .parse(&file_content, None)
-            .expect("Failed to parse file");
-        ParsedFile {
+            .ok_or_else(|| FasterBeamerError::Parse(PathBuf::from(&filename)))?;
+        Ok(ParsedFile {
             filename,
             file_content,
             syntax_tree: tree,
+            sources: SourceMap::default(),
+        })
+    }
+
+    /// Applies `edits` (one after the other, each to the content edited by the previous ones) to
+    /// the content and reparses it, reusing the unchanged parts of the syntax tree.
+    pub fn update(&mut self, edits: &[TextEdit]) -> Result<()> {
+        for edit in edits {
+            let start_position = point(&self.file_content, edit.start);
+            let old_end_position = point(&self.file_content, edit.end);
+            self.file_content
+                .replace_range(edit.start..edit.end, &edit.text);
+            let new_end_byte = edit.start + edit.text.len();
+            self.syntax_tree.edit(&InputEdit {
+                start_byte: edit.start,
+                old_end_byte: edit.end,
+                new_end_byte,
+                start_position,
+                old_end_position,
+                new_end_position: point(&self.file_content, new_end_byte),
+            });
         }
+        self.syntax_tree = latex_parser()
+            .parse(&self.file_content, Some(&self.syntax_tree))
+            .ok_or_else(|| FasterBeamerError::Parse(PathBuf::from(&self.filename)))?;
+        Ok(())
+    }
+
+    /// Sets the files that are spliced into the content.
+    pub fn with_sources(mut self, sources: SourceMap) -> ParsedFile {
+        self.sources = sources;
+        self
     }
 
     pub fn get_nodes_of_type(&self, node_type: String) -> Vec<Node> {
@@ -45,6 +135,82 @@ impl ParsedFile {
     pub fn get_node_string(&self, node: &Node) -> &str {
         &self.file_content[node.start_byte()..node.end_byte()]
     }
+
+    /// Converts a byte offset into a (line, column) pair (both 1-based) of the input file.
+    /// Content of included files is at the line of the `\input` that includes it.
+    pub fn get_position(&self, byte_offset: usize) -> (usize, usize) {
+        match self.sources.main_content() {
+            Some(main) => line_column(main, self.sources.main_offset(byte_offset)),
+            None => line_column(&self.file_content, byte_offset),
+        }
+    }
+
+    /// Converts a byte offset into the file it comes from (None for the input file) and a (line,
+    /// column) pair in that file.
+    pub fn get_source_position(&self, byte_offset: usize) -> (Option<&Path>, usize, usize) {
+        match self.sources.source(byte_offset) {
+            Some((file, offset)) => {
+                let (line, column) = line_column(&file.content, offset);
+                (Some(&file.path), line, column)
+            }
+            None => {
+                let (line, column) = self.get_position(byte_offset);
+                (None, line, column)
+            }
+        }
+    }
+}
+
+/// (line, column) pair (both 1-based) of the byte offset `byte_offset` of `text`.
+pub fn line_column(text: &str, byte_offset: usize) -> (usize, usize) {
+    let before = &text[..byte_offset.min(text.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.len() - before.rfind('\n').map(|i| i + 1).unwrap_or(0) + 1;
+    (line, column)
+}
+
+/// Returns the content of the brace group starting at byte `start` of `text` (which must be a `{`)
+/// and the byte offset after its closing brace. Escaped braces (`\{`, `\}`) are skipped.
+pub fn balanced_group(text: &str, start: usize) -> Option<(&str, usize)> {
+    if !text[start..].starts_with('{') {
+        return None;
+    }
+
+    let mut depth = 0;
+    let mut escaped = false;
+    for (i, c) in text[start..].char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some((&text[start + 1..start + i], start + i + 1));
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Escapes characters with a special meaning in LaTeX.
+pub fn escape_latex(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '#' | '$' | '%' | '&' | '_' | '{' | '}' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '~' => escaped.push_str("\\textasciitilde{}"),
+            '^' => escaped.push_str("\\textasciicircum{}"),
+            '\\' => escaped.push_str("\\textbackslash{}"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
 }
 
 #[cfg(test)]
@@ -62,4 +228,52 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_get_position() {
+        let parsed =
+            ParsedFile::from_string("test.tex".to_string(), "ab\ncde\nf".to_string()).unwrap();
+        assert_eq!(parsed.get_position(0), (1, 1));
+        assert_eq!(parsed.get_position(4), (2, 2));
+        assert_eq!(parsed.get_position(7), (3, 1));
+    }
+
+    #[test]
+    fn test_update() {
+        let old = "\\begin{document}\n\\begin{frame}{Über}\n\\end{frame}\n\\end{document}\n";
+        let new = old.replace("{Über}", "{Überblick}\n\\pause");
+        let edit = TextEdit::between(old, &new).unwrap();
+        assert_eq!(
+            edit,
+            TextEdit {
+                start: old.find("}\n\\end{frame}").unwrap(),
+                end: old.find("}\n\\end{frame}").unwrap() + 1,
+                text: "blick}\n\\pause".to_string(),
+            }
+        );
+        assert_eq!(TextEdit::between(old, old), None);
+
+        let mut parsed = ParsedFile::from_string("test.tex".to_string(), old.to_string()).unwrap();
+        parsed.update(&[edit]).unwrap();
+        let reparsed = ParsedFile::from_string("test.tex".to_string(), new.clone()).unwrap();
+        assert_eq!(parsed.file_content, new);
+        assert_eq!(
+            parsed.syntax_tree.root_node().to_sexp(),
+            reparsed.syntax_tree.root_node().to_sexp()
+        );
+    }
+
+    #[test]
+    fn test_balanced_group() {
+        let text = r"\frametitle{A {nested} \} title} rest";
+        assert_eq!(balanced_group(text, 11), Some((r"A {nested} \} title", 32)));
+        assert_eq!(balanced_group(text, 0), None);
+        assert_eq!(balanced_group("{unbalanced", 0), None);
+    }
+
+    #[test]
+    fn test_escape_latex() {
+        assert_eq!(escape_latex("DRAFT 100% #1"), r"DRAFT 100\% \#1");
+        assert_eq!(escape_latex(r"a\b~"), r"a\textbackslash{}b\textasciitilde{}");
+    }
 }
@@ -0,0 +1,233 @@
+//
+// pdfpc.rs
+// Distributed under terms of the GPLv3 license.
+//
+// pdfpc reads the notes and the overlays of a presentation from `<presentation>.pdfpc` next to the
+// PDF. As the frames are compiled on their own, the pages of the output do not tell which of them
+// are overlays of the same slide, so the file is written from the frames (`--pdfpc`): the pages of
+// a frame are overlays of one slide (except for `allowframebreaks`) and the `\note`s of a frame are
+// shown with its pages.
+//
+
+use crate::beamer::frame_options;
+use crate::bookmarks::plain_text;
+use crate::incremental::FramePages;
+use crate::parsing::balanced_group;
+use crate::process_file::{FasterBeamerError, Frame, Result};
+use lopdf::Document;
+use regex::Regex;
+use serde_json::json;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+lazy_static! {
+    static ref NOTE_REGEX: Regex =
+        Regex::new(r"\\note\s*(?:<([^>]*)>)?\s*(?:\[[^\]]*\]\s*)?\{").unwrap();
+}
+lazy_static! {
+    static ref ENVIRONMENT_REGEX: Regex = Regex::new(r"\\(begin|end)\s*\{[^}]*\}").unwrap();
+}
+lazy_static! {
+    static ref ITEM_REGEX: Regex = Regex::new(r"\\item\b").unwrap();
+}
+
+/// Note of a frame and the overlay it belongs to (all overlays if None).
+#[derive(Debug, PartialEq)]
+pub struct Note {
+    pub overlay: Option<usize>,
+    pub text: String,
+}
+
+/// Page of the presentation for pdfpc.
+#[derive(Debug, PartialEq)]
+pub struct PdfpcPage {
+    /// Pages with the same label are overlays of one slide
+    pub label: String,
+    pub overlay: usize,
+    pub note: Option<String>,
+}
+
+/// Plain text of the LaTeX code of a note, with one line per `\item`.
+fn note_text(tex: &str) -> String {
+    let text = ENVIRONMENT_REGEX.replace_all(tex, "\n");
+    let text = ITEM_REGEX.replace_all(&text, "\n- ");
+    text.lines()
+        .map(plain_text)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The `\note`s of `frame`. A note for one overlay (`\note<2>{...}`) belongs to that overlay.
+pub fn frame_notes(frame: &str) -> Vec<Note> {
+    NOTE_REGEX
+        .captures_iter(frame)
+        .filter_map(|c| {
+            let (text, _) = balanced_group(frame, c.get(0).unwrap().end() - 1)?;
+            Some(Note {
+                overlay: c.get(1).and_then(|spec| spec.as_str().trim().parse().ok()),
+                text: note_text(text),
+            })
+        })
+        .filter(|note| !note.text.is_empty())
+        .collect()
+}
+
+/// Pages of a presentation of `num_pages` pages, in which `frames` are on the pages
+/// `frame_pages`. Pages that belong to no frame (e.g. with `--unite`, pages of `\AtBeginSection`)
+/// are slides of their own.
+pub fn pdfpc_pages(
+    frames: &[&str],
+    frame_pages: &[FramePages],
+    num_pages: usize,
+) -> Vec<PdfpcPage> {
+    let mut pages = Vec::with_capacity(num_pages);
+    let mut slide = 0;
+    let mut new_slide = |pages: &mut Vec<PdfpcPage>, overlay, note| {
+        if overlay == 0 {
+            slide += 1;
+        }
+        pages.push(PdfpcPage {
+            label: slide.to_string(),
+            overlay,
+            note,
+        });
+    };
+    for (frame, frame_pages) in frames.iter().zip(frame_pages) {
+        while pages.len() < frame_pages.first_page.min(num_pages) {
+            new_slide(&mut pages, 0, None);
+        }
+        let is_breakable = matches!(
+            frame_options(frame),
+            Some(options) if options.contains("allowframebreaks")
+        );
+        let notes = frame_notes(frame);
+        for overlay in 0..frame_pages.num_pages {
+            if pages.len() >= num_pages {
+                break;
+            }
+            let note: Vec<&str> = notes
+                .iter()
+                .filter(|note| note.overlay.is_none() || note.overlay == Some(overlay + 1))
+                .map(|note| &note.text[..])
+                .collect();
+            let note = Some(note.join("\n\n")).filter(|note| !note.is_empty());
+            new_slide(&mut pages, if is_breakable { 0 } else { overlay }, note);
+        }
+    }
+    while pages.len() < num_pages {
+        new_slide(&mut pages, 0, None);
+    }
+    pages
+}
+
+/// Contents of a `.pdfpc` file with `pages`.
+pub fn pdfpc_json(pages: &[PdfpcPage]) -> String {
+    let pages: Vec<_> = pages
+        .iter()
+        .enumerate()
+        .map(|(idx, page)| {
+            let mut value = json!({
+                "idx": idx,
+                "label": page.label,
+                "overlay": page.overlay,
+                "hidden": false,
+            });
+            if let Some(note) = &page.note {
+                value["note"] = json!(note);
+            }
+            value
+        })
+        .collect();
+    json!({
+        "pdfpcFormat": 2,
+        "disableMarkdown": true,
+        "pages": pages,
+    })
+    .to_string()
+}
+
+/// File next to `pdf` from which pdfpc reads its notes.
+pub fn pdfpc_file(pdf: &Path) -> PathBuf {
+    pdf.with_extension("pdfpc")
+}
+
+/// Writes the `.pdfpc` file of the presentation `pdf`, in which `frames` are on the pages
+/// `frame_pages`.
+pub fn write_pdfpc(pdf: &Path, frames: &[Frame], frame_pages: &[FramePages]) -> Result<()> {
+    let num_pages = Document::load(pdf)
+        .map_err(|err| FasterBeamerError::Io {
+            path: pdf.into(),
+            source: std::io::Error::new(std::io::ErrorKind::InvalidData, err.to_string()),
+        })?
+        .get_pages()
+        .len();
+    let frames: Vec<&str> = frames.iter().map(|frame| &frame.content[..]).collect();
+    let pdfpc_file = pdfpc_file(pdf);
+    fs::write(
+        &pdfpc_file,
+        pdfpc_json(&pdfpc_pages(&frames, frame_pages, num_pages)),
+    )
+    .map_err(FasterBeamerError::io(&pdfpc_file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_notes() {
+        let frame = "\\begin{frame}{Results}\n\
+                     \\note{Mention the \\emph{error bars}}\n\
+                     \\note<2>{\\begin{itemize}\\item First\\item Second\\end{itemize}}\n\
+                     \\end{frame}";
+        assert_eq!(
+            frame_notes(frame),
+            vec![
+                Note {
+                    overlay: None,
+                    text: "Mention the error bars".to_string(),
+                },
+                Note {
+                    overlay: Some(2),
+                    text: "- First\n- Second".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pdfpc_pages() {
+        let frames = [
+            "\\begin{frame}\\note<2>{Pause}\\end{frame}",
+            "\\begin{frame}[allowframebreaks]\\end{frame}",
+        ];
+        let frame_pages = [
+            FramePages {
+                hash: String::new(),
+                first_page: 1,
+                num_pages: 2,
+            },
+            FramePages {
+                hash: String::new(),
+                first_page: 3,
+                num_pages: 2,
+            },
+        ];
+        let pages = pdfpc_pages(&frames, &frame_pages, 5);
+        let labels: Vec<(&str, usize)> = pages
+            .iter()
+            .map(|page| (&page.label[..], page.overlay))
+            .collect();
+        assert_eq!(
+            labels,
+            vec![("1", 0), ("2", 0), ("2", 1), ("3", 0), ("4", 0)]
+        );
+        assert_eq!(pages[1].note, None);
+        assert_eq!(pages[2].note, Some("Pause".to_string()));
+
+        let json: serde_json::Value = serde_json::from_str(&pdfpc_json(&pages)).unwrap();
+        assert_eq!(json["pages"][2]["note"], "Pause");
+        assert_eq!(json["pages"][2]["overlay"], 1);
+    }
+}
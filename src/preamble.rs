@@ -0,0 +1,270 @@
+//
+// preamble.rs
+// Distributed under terms of the GPLv3 license.
+//
+// The preamble is precompiled to a format. Its name is derived from the preamble and from the
+// content of all local files it loads (e.g. a preamble shared by all lectures of a course that
+// is `\input` by each of them), so that the format is rebuilt whenever one of them changes.
+// Which files are hashed can be adjusted in `faster-beamer.toml` (e.g. to ignore a file with
+// the date of the build, or to hash data that the frames load).
+// Formats are also kept in a shared directory of the cache, so documents in different
+// directories with the same preamble only precompile it once.
+// XeTeX and LuaTeX cannot dump the system fonts of fontspec into a format, so the part of the
+// preamble from the font setup on is read by every frame (after `\endofdump` of mylatexformat).
+// If the engine still fails to precompile the preamble, the frames are compiled with the full
+// preamble, which is remembered so that precompiling is not tried again for every build.
+//
+
+use glob::{glob, Pattern};
+use regex::Regex;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+lazy_static! {
+    static ref PREAMBLE_DEPENDENCY_REGEX: Regex = Regex::new(
+        r"(?m)^[^%\n]*?\\(input|include|usepackage|RequirePackage|usetheme|usecolortheme|usefonttheme|useinnertheme|useoutertheme)\s*(?:\[[^\]]*\]\s*)?\{([^}]*)\}"
+    )
+    .unwrap();
+}
+
+lazy_static! {
+    /// Packages and commands that load system fonts
+    static ref NATIVE_FONTS_REGEX: Regex = Regex::new(
+        r"(?m)^[^%\n]*?\\(?:(?:usepackage|RequirePackage)\s*(?:\[[^\]]*\]\s*)?\{[^}]*\b(?:fontspec|unicode-math|polyglossia)\b|set(?:main|sans|mono|math)font\b|newfontfamily\b)"
+    )
+    .unwrap();
+}
+lazy_static! {
+    static ref END_OF_DUMP_REGEX: Regex = Regex::new(r"(?m)^[^%\n]*?\\endofdump\b").unwrap();
+}
+
+/// File names under which the argument `name` of `command` may be found.
+fn candidate_files(command: &str, name: &str) -> Vec<String> {
+    let names: Vec<&str> = name
+        .split(',')
+        .map(|n| n.trim())
+        .filter(|n| !n.is_empty())
+        .collect();
+    let prefix = match command {
+        "usetheme" => "beamertheme",
+        "usecolortheme" => "beamercolortheme",
+        "usefonttheme" => "beamerfonttheme",
+        "useinnertheme" => "beamerinnertheme",
+        "useoutertheme" => "beameroutertheme",
+        _ => "",
+    };
+    names
+        .iter()
+        .flat_map(|name| match command {
+            "input" | "include" => vec![name.to_string(), format!("{}.tex", name)],
+            _ => vec![format!("{}{}.sty", prefix, name)],
+        })
+        .collect()
+}
+
+/// Local files (relative to `input_dir`) that are loaded by `preamble`.
+pub fn local_dependencies(preamble: &str, input_dir: &Path) -> Vec<PathBuf> {
+    let mut dependencies: Vec<PathBuf> = PREAMBLE_DEPENDENCY_REGEX
+        .captures_iter(preamble)
+        .flat_map(|c| candidate_files(&c[1], &c[2]))
+        .map(|file| input_dir.join(file))
+        .filter(|path| path.is_file())
+        .filter_map(|path| path.canonicalize().ok())
+        .collect();
+    dependencies.sort();
+    dependencies.dedup();
+    dependencies
+}
+
+/// `dependencies` without the files matching the glob patterns `exclude` and with the files
+/// matching `include` (relative to `base_dir`).
+pub fn scope_dependencies(
+    dependencies: Vec<PathBuf>,
+    include: &[String],
+    exclude: &[String],
+    base_dir: &Path,
+) -> Vec<PathBuf> {
+    let base_dir = base_dir
+        .canonicalize()
+        .unwrap_or_else(|_| base_dir.to_owned());
+    let pattern = |p: &String| base_dir.join(p).to_string_lossy().into_owned();
+    let exclude: Vec<Pattern> = exclude
+        .iter()
+        .filter_map(|p| match Pattern::new(&pattern(p)) {
+            Ok(pattern) => Some(pattern),
+            Err(err) => {
+                warn!("Ignoring dependency pattern {}: {}", p, err);
+                None
+            }
+        })
+        .collect();
+
+    let mut scoped: Vec<PathBuf> = dependencies
+        .into_iter()
+        .chain(
+            include
+                .iter()
+                .filter_map(|p| glob(&pattern(p)).ok())
+                .flat_map(|paths| paths.filter_map(|path| path.ok()))
+                .filter(|path| path.is_file())
+                .filter_map(|path| path.canonicalize().ok()),
+        )
+        .filter(|path| !exclude.iter().any(|p| p.matches_path(path)))
+        .collect();
+    scoped.sort();
+    scoped.dedup();
+    scoped
+}
+
+/// Hash of `content` (e.g. a preamble or a frame) and the paths and contents of its
+/// `dependencies`.
+pub fn content_hash(content: &str, dependencies: &[PathBuf]) -> md5::Digest {
+    let mut context = md5::Context::new();
+    context.consume(content.as_bytes());
+    for dependency in dependencies {
+        context.consume(dependency.to_string_lossy().as_bytes());
+        context.consume(fs::read(dependency).unwrap_or_default());
+    }
+    context.compute()
+}
+
+/// `preamble` with `\endofdump` before the line on which it loads system fonts (if it does and has
+/// no `\endofdump` yet), so that the fonts are loaded by every frame instead of the format.
+pub fn end_of_dump(preamble: &str) -> String {
+    if END_OF_DUMP_REGEX.is_match(preamble) {
+        return preamble.to_string();
+    }
+    match NATIVE_FONTS_REGEX.find(preamble) {
+        Some(m) => format!(
+            "{}\\endofdump\n{}",
+            &preamble[..m.start()],
+            &preamble[m.start()..]
+        ),
+        None => preamble.to_string(),
+    }
+}
+
+/// Marker of the format `format_name` that the engine could not precompile. Frames of its
+/// preamble are compiled with the full preamble.
+pub fn format_fallback_marker(cache_subdir: &Path, format_name: &str) -> PathBuf {
+    cache_subdir.join(format!("{}.nofmt", format_name))
+}
+
+/// `format_name` unless the frames of its preamble are compiled without a format.
+pub fn precompiled_format<'a>(cache_subdir: &Path, format_name: &'a str) -> Option<&'a str> {
+    if format_fallback_marker(cache_subdir, format_name).is_file() {
+        None
+    } else {
+        Some(format_name)
+    }
+}
+
+/// Directory in which formats are shared between documents.
+pub fn shared_format_dir(cachedir: &Path) -> PathBuf {
+    cachedir.join("formats")
+}
+
+/// Links the shared copy of the format `fmt` (if there is one) to `fmt`.
+pub fn link_shared_format(cachedir: &Path, fmt: &Path) -> bool {
+    let shared = shared_format_dir(cachedir).join(fmt.file_name().unwrap());
+    if !shared.is_file() {
+        return false;
+    }
+    if let Some(parent) = fmt.parent() {
+        let _result = fs::create_dir_all(parent);
+    }
+    ::symlink::symlink_file(&shared, fmt).is_ok()
+}
+
+/// Keeps a copy of the format `fmt` for other documents.
+pub fn share_format(cachedir: &Path, fmt: &Path) {
+    let shared_dir = shared_format_dir(cachedir);
+    let result = fs::create_dir_all(&shared_dir)
+        .and_then(|_| fs::copy(fmt, shared_dir.join(fmt.file_name().unwrap())));
+    if let Err(err) = result {
+        warn!(
+            "Failed to share precompiled preamble {}: {}",
+            fmt.display(),
+            err
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_local_dependencies() {
+        let dir = tempdir().unwrap();
+        fs::create_dir(dir.path().join("common")).unwrap();
+        fs::write(dir.path().join("common/preamble.tex"), "\\usepackage{tikz}").unwrap();
+        fs::write(dir.path().join("beamerthemecourse.sty"), "").unwrap();
+        fs::write(dir.path().join("macros.sty"), "").unwrap();
+
+        let preamble = "\\documentclass{beamer}\n\\input{common/preamble}\n\
+                        \\usepackage{amsmath,macros}\n\\usetheme{course}\n% \\input{old}\n";
+        let dependencies = local_dependencies(preamble, dir.path());
+        let names: Vec<_> = dependencies
+            .iter()
+            .map(|d| d.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(
+            names,
+            vec!["beamerthemecourse.sty", "preamble.tex", "macros.sty"]
+        );
+
+        let hash = content_hash(preamble, &dependencies);
+        fs::write(
+            dir.path().join("common/preamble.tex"),
+            "\\usepackage{pgfplots}",
+        )
+        .unwrap();
+        assert_ne!(hash, content_hash(preamble, &dependencies));
+    }
+
+    #[test]
+    fn test_end_of_dump() {
+        let preamble = "\\documentclass{beamer}\n\\usepackage{tikz}\n\
+                        % \\usepackage{fontspec}\n\
+                        \\usepackage[math-style=ISO]{unicode-math}\n\
+                        \\setmainfont{Fira Sans}\n";
+        assert_eq!(
+            end_of_dump(preamble),
+            "\\documentclass{beamer}\n\\usepackage{tikz}\n\
+             % \\usepackage{fontspec}\n\
+             \\endofdump\n\
+             \\usepackage[math-style=ISO]{unicode-math}\n\
+             \\setmainfont{Fira Sans}\n"
+        );
+        assert_eq!(end_of_dump(&end_of_dump(preamble)), end_of_dump(preamble));
+        assert_eq!(
+            end_of_dump("\\documentclass{beamer}\n"),
+            "\\documentclass{beamer}\n"
+        );
+    }
+
+    #[test]
+    fn test_scope_dependencies() {
+        let dir = tempdir().unwrap();
+        fs::create_dir(dir.path().join("data")).unwrap();
+        fs::write(dir.path().join("data/results.csv"), "").unwrap();
+        fs::write(dir.path().join("data/notes.txt"), "").unwrap();
+        fs::write(dir.path().join("date.tex"), "").unwrap();
+        fs::write(dir.path().join("macros.sty"), "").unwrap();
+
+        let preamble = "\\usepackage{macros}\n\\input{date}\n";
+        let dependencies = scope_dependencies(
+            local_dependencies(preamble, dir.path()),
+            &["data/*.csv".to_string()],
+            &["date.tex".to_string()],
+            dir.path(),
+        );
+        let names: Vec<_> = dependencies
+            .iter()
+            .map(|d| d.file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(names, vec!["results.csv", "macros.sty"]);
+    }
+}
@@ -0,0 +1,229 @@
+//
+// preflight.rs
+// Distributed under terms of the GPLv3 license.
+//
+// Checks that all external programs needed by a build can be run before any frame is compiled.
+//
+
+use crate::batch::Job;
+use crate::compiler::{is_latexmk, Engine};
+use crate::container::container;
+use crate::markdown::is_markdown;
+use crate::merger::{external_merger, ExternalMerger};
+use crate::options::Options;
+use crate::process_file::{engine_command, FasterBeamerError, Result};
+use std::fs;
+use std::process::{Command, Stdio};
+
+/// An external program needed by the build.
+#[derive(Debug, PartialEq)]
+pub struct Requirement {
+    pub program: String,
+    /// Argument with which the program is run to check that it works
+    pub version_arg: &'static str,
+    /// What the program is needed for
+    pub purpose: &'static str,
+    /// What to do if the program is missing
+    pub hint: &'static str,
+    /// A missing optional program only causes a warning
+    pub optional: bool,
+}
+
+/// Programs needed to build documents with the given properties.
+pub fn requirements(
+    compilercmd: &str,
+    merger: Option<&ExternalMerger>,
+    markdown: bool,
+    biblatex: bool,
+    makeindex: bool,
+) -> Vec<Requirement> {
+    let mut requirements = vec![Requirement {
+        program: compilercmd.to_string(),
+        version_arg: "--version",
+        purpose: "compiling frames",
+        hint: "Install a TeX distribution or choose another compiler with --compiler",
+        optional: false,
+    }];
+    if let Some(merger) = merger {
+        requirements.push(Requirement {
+            program: merger.program.to_string(),
+            version_arg: merger.version_arg,
+            purpose: "merging frames (--merger)",
+            hint: "Install it or use --merger builtin, which needs no external program",
+            optional: false,
+        });
+    }
+    if markdown {
+        requirements.push(Requirement {
+            program: "pandoc".to_string(),
+            version_arg: "--version",
+            purpose: "converting Markdown input",
+            hint: "Install pandoc or convert the presentation to LaTeX",
+            optional: false,
+        });
+    }
+    if biblatex {
+        requirements.push(Requirement {
+            program: "biber".to_string(),
+            version_arg: "--version",
+            purpose: "the bibliography of biblatex",
+            hint: "Install biber or load biblatex with backend=bibtex",
+            optional: true,
+        });
+    }
+    if makeindex {
+        requirements.push(Requirement {
+            program: "makeindex".to_string(),
+            version_arg: "-q",
+            purpose: "the index and glossaries",
+            hint: "Install makeindex (part of TeX distributions)",
+            optional: true,
+        });
+    }
+    requirements
+}
+
+/// Whether `program` can be run.
+pub fn is_runnable(program: &str, version_arg: &str) -> bool {
+    Command::new(program)
+        .arg(version_arg)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok()
+}
+
+/// Whether `mylatexformat.ltx` (needed to precompile the preamble) can be found by TeX. Assumes
+/// it can if `kpsewhich` is not available.
+fn has_mylatexformat() -> bool {
+    match Command::new("kpsewhich").arg("mylatexformat.ltx").output() {
+        Ok(output) => !output.stdout.is_empty(),
+        Err(_) => true,
+    }
+}
+
+/// Checks that all programs needed to build `jobs` can be run.
+pub fn check(jobs: &[Job], args: &Options) -> Result<()> {
+    let compilercmd = engine_command(args);
+    let markdown = jobs.iter().any(|job| is_markdown(&job.input_file));
+    let contents: Vec<String> = jobs
+        .iter()
+        .filter_map(|job| fs::read_to_string(&job.input_file).ok())
+        .collect();
+    let biblatex = contents
+        .iter()
+        .any(|content| content.contains("{biblatex}"));
+    let makeindex = contents
+        .iter()
+        .any(|content| content.contains("\\makeindex") || content.contains("\\makeglossaries"));
+
+    let merger = if args.pdfunite {
+        external_merger(&args.merger)
+    } else {
+        None
+    };
+
+    let mut requirements = requirements(compilercmd, merger, markdown, biblatex, makeindex);
+    let driver = args.compiler.as_str();
+    if is_latexmk(driver) {
+        requirements.push(Requirement {
+            program: driver.to_string(),
+            version_arg: "-v",
+            purpose: "compiling frames (--compiler latexmk)",
+            hint: "Install latexmk or compile with the engine directly (e.g. --compiler pdflatex)",
+            optional: false,
+        });
+    }
+    if !args.remote.is_empty() {
+        for program in &["ssh", "rsync"] {
+            requirements.push(Requirement {
+                program: program.to_string(),
+                version_arg: "-V",
+                purpose: "compiling frames on the hosts of --remote",
+                hint: "Install it or compile locally without --remote",
+                optional: false,
+            });
+        }
+    }
+    if args.remote_cache.is_some() {
+        requirements.push(Requirement {
+            program: "curl".to_string(),
+            version_arg: "--version",
+            purpose: "the remote cache (--remote-cache)",
+            hint: "Install curl or build without --remote-cache",
+            optional: false,
+        });
+    }
+    let container = container();
+    if let Some(container) = &container {
+        // The TeX programs run in the container, which only needs its runtime on the host
+        let tex_programs = [compilercmd, driver, "biber", "makeindex"];
+        requirements.retain(|r| !tex_programs.contains(&r.program.as_str()));
+        requirements.insert(
+            0,
+            Requirement {
+                program: container.runtime.clone(),
+                version_arg: "--version",
+                purpose: "compiling in the container of --container",
+                hint: "Install docker or podman, or compile on the host without --container",
+                optional: false,
+            },
+        );
+    }
+
+    let mut missing = Vec::new();
+    for requirement in requirements {
+        if is_runnable(&requirement.program, requirement.version_arg) {
+            continue;
+        }
+        let message = format!(
+            "Cannot run {}, which is needed for {}. {}.",
+            requirement.program, requirement.purpose, requirement.hint
+        );
+        if requirement.optional {
+            warn!("{}", message);
+        } else {
+            missing.push(message);
+        }
+    }
+
+    if missing.is_empty()
+        && container.is_none()
+        && Engine::of(compilercmd).precompiles_formats()
+        && !has_mylatexformat()
+    {
+        missing.push(
+            "mylatexformat.ltx, which is needed to precompile the preamble, was not found. \
+             Install the mylatexformat package (e.g. tlmgr install mylatexformat)."
+                .to_string(),
+        );
+    }
+
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(FasterBeamerError::MissingProgram(missing))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_requirements() {
+        let programs: Vec<String> =
+            requirements("lualatex", external_merger("qpdf"), false, true, true)
+                .into_iter()
+                .map(|r| r.program)
+                .collect();
+        assert_eq!(programs, vec!["lualatex", "qpdf", "biber", "makeindex"]);
+        assert_eq!(requirements("pdflatex", None, false, false, false).len(), 1);
+
+        assert!(!is_runnable(
+            "faster-beamer-nonexistent-program",
+            "--version"
+        ));
+    }
+}
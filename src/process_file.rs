@@ -3,48 +3,287 @@
 // Copyright (C) 2019 seitz_local <seitz_local@lmeXX>
 // Distributed under terms of the GPLv3 license.
 //
-use crate::beamer::get_frames;
+use crate::batch::Job;
+use crate::beamer::{
+    againframe_label, content_between_frames, document_conditionals, find_duplicate_labels,
+    frame_destinations, frame_label, frame_ranges, get_frames, includeonlyframes, is_frame_command,
+    remove_includeonlyframes, resolve_againframes, select_frames, set_beamer_mode,
+    set_class_option, set_conditionals, set_notes, set_overlays, set_theme, CARRIED_CONTENT_BEGIN,
+    CARRIED_CONTENT_END, STRIP_DECORATIONS,
+};
+use crate::bookmarks::{add_bookmarks, sections_key};
+use crate::page_map::{build_page_map, frame_numbers, total_frame_number, write_page_map};
+use crate::recovery::InProgress;
+use crate::remote::{RemoteCompiler, DEFAULT_REMOTE_DIR};
+use crate::remote_cache::{RemoteCache, RemoteCacheCompiler};
+use crate::preamble::{
+    content_hash, end_of_dump, format_fallback_marker, link_shared_format, local_dependencies,
+    precompiled_format, scope_dependencies, share_format,
+};
+use crate::compiler::{
+    is_latexmk, Compiler, Engine, LatexmkCompiler, RerunningCompiler, TexCompiler,
+};
+use crate::config::Config;
+use crate::dependencies::frame_files;
+use crate::error::log_excerpt;
+use crate::events::{self, Event};
+use crate::extract::keep_intermediates;
+use crate::frame_dir::load_frame_directory;
+use crate::glossaries::{prints_lists, shared_lists, SharedLists};
+use crate::graphics::graphics_dirs;
+use crate::includes::splice_includes;
+use crate::markdown::latex_source;
+use crate::incremental::{
+    concatenated_pages, destination_pages, record_file, update_united, write_record,
+};
+use crate::bibliography::{shared_bibliography, uses_bibliography, SharedBibliography};
+use crate::labels::{collect_labels, label_definitions};
+use crate::merge::{copy_frame_links, fix_united, pdfunite, report_shared_resources};
+use crate::metadata::{add_metadata, document_metadata};
 use crate::parsing;
+use crate::pdfpc::write_pdfpc;
+use crate::synctex::write_synctex;
+use crate::tagging::{compile_tagged, is_tagged};
+use crate::watermark::stamp_watermark;
 
 use log::Level::Trace;
 
+use crate::logs::{compress_log, write_build_record, write_cache_record, write_manifest};
+use crate::log_filter::LogSummary;
+use crate::navigation::{generate_nav, generate_toc, has_table_of_contents};
+use crate::optimize::optimize_pdf;
+use crate::outline::extract_outline;
 use crate::latexcompile::{LatexCompiler, LatexInput, LatexRunOptions};
-use clap::ArgMatches;
+use crate::options::Options;
 use indicatif::ProgressBar;
 use rayon::prelude::*;
 use regex::Regex;
+use std::collections::{BTreeMap, HashMap};
 use std::env::current_dir;
 use std::fs::write;
-use std::path::Path;
-use std::process::Command;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
 use std::str;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 use std::vec::Vec;
 
-#[derive(PartialEq)]
-pub enum FasterBeamerError {
-    InputFileNotExistent,
-    IoError,
-    CompileError,
-    PdfUniteError,
+pub use crate::error::{FasterBeamerError, Result};
+
+lazy_static! {
+    static ref DOCUMENT_REGEX: Regex =
+        Regex::new(r"(?ms)^[\s\t]*?\\begin\{document\}.*^[\s\t]*?\\end\{document\}").unwrap();
 }
 
-pub type Result<T> = ::std::result::Result<T, FasterBeamerError>;
+lazy_static! {
+    /// Frames of the previous build of every input file
+    static ref PREVIOUS_FRAMES: Mutex<HashMap<String, Vec<String>>> = Mutex::new(HashMap::new());
+}
 
 lazy_static! {
-    static ref FRAME_REGEX: Regex =
-        Regex::new(r"(?ms)^[\s\t]*?\\begin\{frame\}.*?^[\s\t]*?\\end\{frame\}").unwrap();
+    /// Last parse of every input file, reused while the source of the file does not change. The
+    /// frames are extracted again, as they also depend on the options (e.g. `--only-frames`).
+    static ref PARSED_FILES: Mutex<HashMap<String, parsing::ParsedFile>> =
+        Mutex::new(HashMap::new());
 }
+
 lazy_static! {
-    static ref DOCUMENT_REGEX: Regex =
-        Regex::new(r"(?ms)^[\s\t]*?\\begin\{document\}.*^[\s\t]*?\\end\{document\}").unwrap();
+    /// Cache directory chosen with `--cache-dir` (or in the configuration)
+    static ref CACHE_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
 }
 
 lazy_static! {
-    static ref PREVIOUS_FRAMES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+    /// Locks of files in the cache that concurrent builds of several documents may write
+    static ref CACHE_LOCKS: Mutex<HashMap<PathBuf, Arc<Mutex<()>>>> = Mutex::new(HashMap::new());
+}
+
+/// Returns the lock that has to be held while `path` in the cache is written.
+fn cache_lock(path: &Path) -> Arc<Mutex<()>> {
+    CACHE_LOCKS
+        .lock()
+        .unwrap()
+        .entry(path.to_owned())
+        .or_insert_with(|| Arc::new(Mutex::new(())))
+        .clone()
+}
+
+/// A frame of the input document and the standalone document that is compiled for it.
+#[derive(Clone)]
+pub struct Frame {
+    /// Source of the frame as it appears in the input file
+    pub content: String,
+    /// Byte offset of the frame in the input file
+    pub offset: usize,
+    /// Document that is compiled for this frame
+    pub tex: String,
+    /// Line of `tex` (1-based) on which `content` starts
+    pub body_line: usize,
+    pub hash: md5::Digest,
+}
+
+impl Frame {
+    /// Returns a copy of this frame whose document additionally contains `code` directly before
+    /// the frame (after the content of the document that is carried into it).
+    pub fn inject(&self, code: &str) -> Frame {
+        let body_start: usize = self
+            .tex
+            .split_inclusive('\n')
+            .take(self.body_line - 1)
+            .map(str::len)
+            .sum();
+        let tex = format!(
+            "{}{}{}",
+            &self.tex[..body_start],
+            code,
+            &self.tex[body_start..]
+        );
+        Frame {
+            content: self.content.clone(),
+            offset: self.offset,
+            // The hash of this frame includes the files that it reads
+            hash: md5::compute(format!("{:x}\n{}", self.hash, code)),
+            body_line: self.body_line + code.matches('\n').count(),
+            tex,
+        }
+    }
+
+    pub fn pdf(&self, cache_subdir: &Path) -> PathBuf {
+        cache_subdir.join(format!("{:x}.pdf", self.hash))
+    }
+
+    pub fn log(&self, cache_subdir: &Path) -> PathBuf {
+        cache_subdir.join(format!("{:x}.log", self.hash))
+    }
+
+    /// SyncTeX file of the frame PDF (with `--synctex`)
+    pub fn synctex(&self, cache_subdir: &Path) -> PathBuf {
+        cache_subdir.join(format!("{:x}.synctex.gz", self.hash))
+    }
+
+    /// Marker of a frame whose last compile failed. Its PDF (if any) is not reused.
+    pub fn failure_marker(&self, cache_subdir: &Path) -> PathBuf {
+        cache_subdir.join(format!("{:x}.failed", self.hash))
+    }
+
+    /// Empty file whose modification time is when a build last took the frame from the cache
+    /// (many file systems do not update the access times of files), see `clean`.
+    pub fn use_marker(&self, cache_subdir: &Path) -> PathBuf {
+        cache_subdir.join(format!("{:x}.used", self.hash))
+    }
+
+    /// Whether the PDF of this frame is in the cache, was written completely and comes from a
+    /// successful compile.
+    pub fn is_compiled(&self, cache_subdir: &Path) -> bool {
+        is_complete_pdf(&self.pdf(cache_subdir)) && !self.failure_marker(cache_subdir).is_file()
+    }
+}
+
+/// Whether `pdf` is a PDF file that was written completely. The PDF of a compile that was
+/// interrupted (e.g. by Ctrl-C) lacks the `%%EOF` marker at its end.
+pub fn is_complete_pdf(pdf: &Path) -> bool {
+    let mut file = match std::fs::File::open(pdf) {
+        Ok(file) => file,
+        Err(_) => return false,
+    };
+    let mut header = [0; 5];
+    if file.read_exact(&mut header).is_err() || &header != b"%PDF-" {
+        return false;
+    }
+
+    let len = file.metadata().map(|m| m.len()).unwrap_or(0);
+    let tail_len = std::cmp::min(len, 1024);
+    let mut tail = Vec::with_capacity(tail_len as usize);
+    file.seek(SeekFrom::Start(len - tail_len))
+        .and_then(|_| file.read_to_end(&mut tail))
+        .is_ok()
+        && tail.windows(5).any(|w| w == b"%%EOF")
+}
+
+/// Frames extracted from an input file.
+#[derive(Clone)]
+pub struct ExtractedFrames {
+    pub parsed_file: parsing::ParsedFile,
+    pub frames: Vec<String>,
+    pub frame_offsets: Vec<usize>,
+    /// Frames that are compiled before all others (e.g. frames of the edited file)
+    pub priority: Vec<bool>,
+}
+
+/// Result of compiling all frames of an input file.
+pub struct CompiledFrames {
+    pub parsed_file: parsing::ParsedFile,
+    pub frames: Vec<Frame>,
+    pub cachedir: PathBuf,
+    pub cache_subdir: PathBuf,
+}
+
+pub fn get_cachedir() -> Result<PathBuf> {
+    let chosen = CACHE_DIR.lock().unwrap().clone();
+    let is_default = chosen.is_none();
+    let cachedir = match chosen {
+        Some(cachedir) => cachedir,
+        None => dirs::cache_dir()
+            .ok_or_else(|| {
+                FasterBeamerError::NotFound("No cache directory on this OS".to_string())
+            })?
+            .join("faster-beamer"),
+    };
+    let is_new = std::fs::read_dir(&cachedir).map_or(true, |mut dir| dir.next().is_none());
+    std::fs::create_dir_all(&cachedir).map_err(FasterBeamerError::io(&cachedir))?;
+    // A directory of `--cache-dir` that already has other files (e.g. `.` by mistake) is not
+    // tagged, so that `clean` refuses to remove it
+    let tag = cachedir.join(CACHEDIR_TAG);
+    if (is_new || is_default) && !tag.is_file() {
+        write(&tag, CACHEDIR_TAG_CONTENT).map_err(FasterBeamerError::io(&tag))?;
+    }
+    Ok(cachedir)
+}
+
+/// File that marks the directories that faster-beamer created as its cache (see
+/// <https://bford.info/cachedir/>). Files are only ever removed from tagged directories.
+pub const CACHEDIR_TAG: &str = "CACHEDIR.TAG";
+
+const CACHEDIR_TAG_CONTENT: &str = "Signature: 8a477f597d28d172789f06886806bc55\n\
+    # This file is a cache directory tag created by faster-beamer.\n";
+
+/// Whether `cachedir` was created as a cache by faster-beamer (see `CACHEDIR_TAG`).
+pub fn is_tagged_cachedir(cachedir: &Path) -> bool {
+    cachedir.join(CACHEDIR_TAG).is_file()
+}
+
+/// Uses `cachedir` instead of the cache directory of the user.
+pub fn set_cachedir(cachedir: &Path) {
+    *CACHE_DIR.lock().unwrap() = Some(cachedir.to_owned());
+}
+
+/// Directory in which the frames of documents in `input_dir` are cached.
+pub fn get_cache_subdir(cachedir: &Path, input_dir: &Path) -> PathBuf {
+    cachedir.join(format!(
+        "./{}",
+        &input_dir
+            .to_str()
+            .unwrap() // append input to cachedir
+            .replace(":", "_") // Escape forbidden characters like ..cache_dir/c:/
+    ))
+}
+
+/// Canonicalized directory containing `input_file` (or `input_file` itself for frame
+/// directories).
+pub fn get_input_dir(input_file: &str) -> PathBuf {
+    let cwd = current_dir().unwrap();
+    if Path::new(input_file).is_dir() {
+        return Path::new(input_file)
+            .canonicalize()
+            .unwrap_or_else(|_| cwd.to_owned());
+    }
+    Path::new(input_file)
+        .parent()
+        .unwrap_or(&cwd)
+        .canonicalize()
+        .unwrap_or_else(|_| cwd.to_owned())
 }
 
-fn show_error_slide(cachedir: &Path, output_file: &str, compilercmd: &str) {
+fn show_error_slide(cachedir: &Path, output_file: &str, compilercmd: &str, latex_args: &[String]) {
     if Path::new(&output_file).is_file() {
         let _result = ::std::fs::remove_file(&output_file);
     }
@@ -56,8 +295,10 @@ fn show_error_slide(cachedir: &Path, output_file: &str, compilercmd: &str) {
     if !error_pdf.exists() && write(&error_file, &error_frame[..]).is_ok() {
         let mut compiler = LatexCompiler::new(compilercmd)
             .unwrap()
-            .add_arg("-shell-escape")
             .add_arg("-interaction=nonstopmode");
+        for arg in latex_args {
+            compiler = compiler.add_arg(arg);
+        }
         compiler.working_dir = cachedir.to_owned();
 
         let _result = compiler.run(
@@ -71,54 +312,380 @@ fn show_error_slide(cachedir: &Path, output_file: &str, compilercmd: &str) {
             let _result = ::std::fs::remove_file(&output_file);
         }
 
-        ::symlink::symlink_file(error_pdf, output_file)
-            .expect("Failed to create symlink to error file.");
+        if let Err(err) = ::symlink::symlink_file(error_pdf, output_file) {
+            warn!("Failed to link the error slide to {}: {}", output_file, err);
+        }
     }
 }
 
-pub fn process_file(input_file: &str, args: &ArgMatches) -> Result<()> {
-    let cwd = current_dir().unwrap();
-    let input_path = Path::new(&input_file);
-    let input_dir = input_path
-        .parent()
-        .unwrap_or(&cwd)
-        .canonicalize()
-        .unwrap_or_else(|_| cwd.to_owned());
-    let output_file = args.value_of("OUTPUT").unwrap_or("output.pdf");
-    let correct_frame_numbers = args.is_present("frame-numbers");
-    let compilercmd = args.value_of("compiler").unwrap_or("pdflatex");
-
-    if !input_path.is_file() {
-        error!("Could not open {}", input_file);
-        return Err(FasterBeamerError::InputFileNotExistent);
+/// Returns everything before `\\begin{document}` (or a default beamer preamble).
+pub fn document_preamble(file_content: &str) -> String {
+    let find = file_content.find("\\begin{document}");
+    match find {
+        Some(x) => Some(file_content[..x].to_owned()),
+        None => None,
     }
+    .unwrap_or_else(|| r"\documentclass[aspectratio=43,c,xcolor=dvipsnames]{beamer}".to_string())
+}
 
-    let parsed_file = parsing::ParsedFile::new(input_file.to_string());
-    trace!("{}", parsed_file.syntax_tree.root_node().to_sexp());
+/// Values of the conditionals of `\newif` in the preamble of `file_content`, with the ones set by
+/// `--conditional` (which need not be declared).
+pub fn frame_conditionals(file_content: &str, args: &Options) -> BTreeMap<String, bool> {
+    let mut conditionals = document_conditionals(&document_preamble(file_content));
+    conditionals.extend(args.conditionals.iter().cloned());
+    conditionals
+}
 
-    let frame_nodes = if args.is_present("tree-sitter") {
+/// Extracts the source of all frames together with their byte offsets in the input file.
+pub fn extract_frames(
+    parsed_file: &parsing::ParsedFile,
+    args: &Options,
+) -> (Vec<String>, Vec<usize>) {
+    let frame_nodes = if args.tree_sitter {
         get_frames(&parsed_file)
     } else {
         Vec::new()
     };
 
+    let conditionals = frame_conditionals(&parsed_file.file_content, args);
     let mut frames = Vec::with_capacity(frame_nodes.len());
+    let mut frame_offsets = Vec::with_capacity(frame_nodes.len());
     if !frame_nodes.is_empty() {
-        for f in frame_nodes.iter() {
-            info!("Found {} frames with tree-sitter.", frame_nodes.len());
-            let node_string = parsed_file.get_node_string(&f);
-            frames.push(node_string.to_string());
+        let scanned = frame_ranges(&parsed_file.file_content, &conditionals);
+        // Frames in comments and false branches of conditionals are not among the scanned frames
+        let is_active = |offset: usize| {
+            scanned
+                .iter()
+                .any(|(start, end)| (*start..*end).contains(&offset))
+        };
+        let mut ranges: Vec<(usize, usize)> = frame_nodes
+            .iter()
+            .filter(|f| is_active(f.start_byte()))
+            .map(|f| (f.start_byte(), f.end_byte()))
+            .collect();
+        let num_parsed = ranges.len();
+        // The syntax tree only has frame environments
+        ranges.extend(scanned.iter().cloned().filter(|(start, end)| {
+            let frame = &parsed_file.file_content[*start..*end];
+            is_frame_command(frame) || againframe_label(frame).is_some()
+        }));
+        ranges.sort_unstable();
+        info!(
+            "Found {} frames ({} with tree-sitter).",
+            ranges.len(),
+            num_parsed
+        );
+        for (start, end) in ranges {
+            frames.push(parsed_file.file_content[start..end].to_string());
+            frame_offsets.push(start);
         }
     } else {
-        for cap in FRAME_REGEX.captures_iter(&parsed_file.file_content) {
-            let frame_string = cap[0].to_string();
+        for (start, end) in frame_ranges(&parsed_file.file_content, &conditionals) {
+            let frame_string = parsed_file.file_content[start..end].to_string();
             trace!("Frame {}:\n{}", frames.len() + 1, &frame_string);
             frames.push(frame_string);
+            frame_offsets.push(start);
         }
     }
+    let labels = match &args.only_frames {
+        Some(labels) => Some(labels.clone()),
+        None => includeonlyframes(&document_preamble(&parsed_file.file_content)),
+    };
+    match labels {
+        Some(labels) => select_frames(frames, frame_offsets, &labels),
+        None => resolve_againframes(frames, frame_offsets),
+    }
+}
+
+/// Arguments of the compiler for frames: the interaction mode and whether to stop at the first
+/// error. With `-interaction=nonstopmode`, broken frames still produce a (possibly garbled) PDF.
+pub fn frame_compiler_args(args: &Options) -> Vec<String> {
+    let mut compiler_args = vec![format!("-interaction={}", args.interaction)];
+    if args.halt_on_error {
+        compiler_args.push("-halt-on-error".to_string());
+    }
+    if args.synctex {
+        compiler_args.push("-synctex=1".to_string());
+    }
+    compiler_args.extend(latex_args(args));
+    compiler_args
+}
+
+/// Arguments that are passed to every run of the LaTeX compiler: `-shell-escape` with
+/// `--shell-escape`, and the arguments given with `--latex-arg`.
+pub fn latex_args(args: &Options) -> Vec<String> {
+    let shell_escape = if args.shell_escape {
+        Some("-shell-escape".to_string())
+    } else {
+        None
+    };
+    shell_escape
+        .into_iter()
+        .chain(args.latex_args.iter().cloned())
+        .collect()
+}
+
+/// TeX engine of `args`: `--compiler`, or the `--latexmk-engine` with `--compiler latexmk`.
+/// Documents other than frames (e.g. the united document) are compiled with it.
+pub fn engine_command(args: &Options) -> &str {
+    if is_latexmk(&args.compiler) {
+        &args.latexmk_engine
+    } else {
+        &args.compiler
+    }
+}
+
+/// How often a frame is compiled again at most when its log asks for it (without `--max-reruns`).
+pub const DEFAULT_MAX_RERUNS: usize = 2;
+
+/// Compiler of the frames and the preamble for `args`.
+pub fn frame_compiler(args: &Options) -> Box<dyn Compiler> {
+    let compilercmd = args.compiler.as_str();
+    let mut compiler: Box<dyn Compiler> = if is_latexmk(compilercmd) {
+        Box::new(LatexmkCompiler::new(compilercmd, engine_command(args)))
+    } else {
+        Box::new(TexCompiler::new(compilercmd))
+    };
+    if !args.remote.is_empty() {
+        compiler = Box::new(RemoteCompiler::new(
+            args.remote.clone(),
+            args.remote_dir.as_deref().unwrap_or(DEFAULT_REMOTE_DIR),
+            compilercmd,
+            if is_latexmk(compilercmd) {
+                Some(engine_command(args))
+            } else {
+                None
+            },
+            compiler,
+        ));
+    }
+    let max_reruns = args.max_reruns.unwrap_or(DEFAULT_MAX_RERUNS);
+    if max_reruns > 0 {
+        compiler = Box::new(RerunningCompiler {
+            compiler,
+            max_reruns,
+        });
+    }
+    match &args.remote_cache {
+        Some(url) => Box::new(RemoteCacheCompiler {
+            cache: RemoteCache::new(url, args.remote_cache_read_only),
+            compiler,
+        }),
+        None => compiler,
+    }
+}
+
+/// Environment variables of the compiler processes for `input_file` (from its configuration).
+pub fn compiler_environment(input_file: &str, args: &Options) -> BTreeMap<String, String> {
+    Config::find(input_file).environment(args.profile.as_deref())
+}
+
+/// Compiles all frames of `input_file` whose PDF is not already in `cache_subdir` (in parallel).
+/// Frames marked in `priority` are compiled before the others. Returns the number of frames that
+/// were already compiled.
+fn compile_documents(
+    input_file: &str,
+    frames: &[Frame],
+    priority: &[bool],
+    input_dir: &Path,
+    cachedir: &Path,
+    cache_subdir: &Path,
+    compiler: &dyn Compiler,
+    compiler_args: &[String],
+    compiler_env: &BTreeMap<String, String>,
+) -> usize {
+    let num_compiled = frames
+        .iter()
+        .filter(|f| f.is_compiled(cache_subdir))
+        .inspect(|f| {
+            let _result = write(f.use_marker(cache_subdir), "");
+        })
+        .count();
+    if num_compiled > 0 && num_compiled < frames.len() {
+        info!(
+            "{} of {} frames are already compiled.",
+            num_compiled,
+            frames.len()
+        );
+    }
+
+    let progress_bar = if events::is_enabled() {
+        ProgressBar::hidden()
+    } else {
+        ProgressBar::new(frames.len() as u64)
+    };
+    let log_summary = Mutex::new(LogSummary::default());
+    let is_priority = |frame_idx: usize| priority.get(frame_idx).cloned().unwrap_or(false);
+
+    for &prioritized in &[true, false] {
+        frames
+            .par_iter()
+            .enumerate()
+            .filter(|(frame_idx, _)| is_priority(*frame_idx) == prioritized)
+            .for_each(|(frame_idx, frame)| {
+                let pdf = frame.pdf(cache_subdir);
+                // Another document may contain the same frame
+                let lock = cache_lock(&pdf);
+                let _guard = lock.lock().unwrap();
+
+                if frame.is_compiled(cache_subdir) {
+                    trace!("{} is already compiled!", pdf.to_str().unwrap_or("???"));
+                    events::emit(Event::CacheHit {
+                        input_file,
+                        frame: frame_idx,
+                    });
+                } else {
+                    if pdf.is_file() {
+                        debug!(
+                            "Recompiling incomplete or failed {}",
+                            pdf.to_str().unwrap_or("???")
+                        );
+                        let _result = std::fs::remove_file(&pdf);
+                    }
+
+                    // Makes the files of the input directory available in the cache dir
+                    LatexInput::from_lazy(
+                        input_dir.canonicalize().unwrap().to_str().unwrap(),
+                        cachedir,
+                    )
+                    .expect("Failed to create LatexInput");
+
+                    let temp_file = cache_subdir.join(format!("{:x}.tex", frame.hash));
+
+                    if write(&temp_file, &frame.tex).is_ok() {
+                        let _in_progress = InProgress::new(&pdf);
+                        events::emit(Event::CompileStarted {
+                            input_file,
+                            frame: frame_idx,
+                        });
+                        let start = Instant::now();
+                        let result = compiler.compile(
+                            &temp_file.canonicalize().unwrap(),
+                            &temp_file.parent().unwrap().canonicalize().unwrap(),
+                            compiler_args,
+                            compiler_env,
+                        );
+                        let failure_marker = frame.failure_marker(cache_subdir);
+                        if result.is_ok() {
+                            trace!("Compiled file {}", &temp_file.to_str().unwrap());
+                            let _result = std::fs::remove_file(&failure_marker);
+                            if let Ok(log) = std::fs::read(frame.log(cache_subdir)) {
+                                log_summary
+                                    .lock()
+                                    .unwrap()
+                                    .add(frame_idx, &String::from_utf8_lossy(&log));
+                            }
+                        } else {
+                            error!(
+                                "Failed to compile frame {} ({})",
+                                frame_idx,
+                                &temp_file.to_str().unwrap()
+                            );
+                            error!("{}", frame.content);
+                            error!("{}", result.as_ref().err().unwrap());
+                            let _result = write(&failure_marker, "");
+                        };
+                        events::emit(Event::CompileFinished {
+                            input_file,
+                            frame: frame_idx,
+                            success: result.is_ok(),
+                            seconds: start.elapsed().as_secs_f64(),
+                        });
+                        if result.is_err() && events::is_enabled() {
+                            events::emit(Event::Error {
+                                input_file,
+                                frame: Some(frame_idx),
+                                message: format!("Failed to compile frame {}", frame_idx),
+                                log_excerpt: Some(log_excerpt(&frame.log(cache_subdir), "")),
+                            });
+                        }
+                        if let Err(err) = compress_log(&frame.log(cache_subdir)) {
+                            warn!("Failed to compress log of frame {}: {}", frame_idx, err);
+                        }
+                    }
+                };
+                progress_bar.inc(1);
+            });
+    }
+    progress_bar.finish_and_clear();
+    log_summary.into_inner().unwrap().report();
+    num_compiled
+}
+
+/// The frames of `file_content` (at `offsets`) that define their labels, with their offsets. The
+/// frames that an `\againframe` shows again repeat the labels of the original frame.
+fn labelled_frames<'a>(
+    file_content: &str,
+    frames: &'a [String],
+    offsets: &[usize],
+) -> Vec<(usize, &'a str)> {
+    offsets
+        .iter()
+        .cloned()
+        .zip(frames.iter().map(|f| &f[..]))
+        .filter(|(offset, _)| againframe_label(&file_content[*offset..]).is_none())
+        .collect()
+}
+
+/// Parses `input_file` and extracts its frames.
+pub fn load_frames(input_file: &str, args: &Options) -> Result<ExtractedFrames> {
+    let input_path = Path::new(&input_file);
+
+    if input_path.is_dir() {
+        return load_frame_directory(input_file, args);
+    }
+    if !input_path.is_file() {
+        return Err(FasterBeamerError::InputFileNotExistent(input_path.to_owned()));
+    }
+
+    let source = latex_source(input_file, args)?;
+    let content_file = args.buffer.as_deref().unwrap_or_else(|| Path::new(&source));
+    let file_content =
+        std::fs::read_to_string(content_file).map_err(FasterBeamerError::io(content_file))?;
+    let (file_content, sources) = splice_includes(&file_content, &get_input_dir(input_file));
+    let previous = PARSED_FILES.lock().unwrap().get(input_file).cloned();
+    let parsed_file = match previous {
+        Some(parsed_file) if parsed_file.file_content == file_content => {
+            debug!("{} has not changed since it was parsed.", input_file);
+            parsed_file
+        }
+        // Only the changed part of the document is parsed again (e.g. in watch mode)
+        Some(mut parsed_file) => {
+            let edits: Vec<_> =
+                parsing::TextEdit::between(&parsed_file.file_content, &file_content)
+                    .into_iter()
+                    .collect();
+            parsed_file.update(&edits)?;
+            parsed_file
+        }
+        None => parsing::ParsedFile::from_string(source, file_content)?,
+    }
+    .with_sources(sources);
+    trace!("{}", parsed_file.syntax_tree.root_node().to_sexp());
+    PARSED_FILES
+        .lock()
+        .unwrap()
+        .insert(input_file.to_string(), parsed_file.clone());
+
+    let (frames, frame_offsets) = extract_frames(&parsed_file, args);
     info!("Found {} frames.", frames.len());
 
-    if log_enabled!(Trace) && args.is_present("tree-sitter") {
+    let labelled_frames = labelled_frames(&parsed_file.file_content, &frames, &frame_offsets);
+    for duplicate in find_duplicate_labels(&labelled_frames) {
+        for position in duplicate.positions.iter() {
+            let (file, line, column) = parsed_file.get_source_position(*position);
+            warn!(
+                "{}:{}:{}: label \"{}\" is defined {} times",
+                file.map_or_else(|| input_file.into(), |file| file.display().to_string()),
+                line,
+                column,
+                duplicate.label,
+                duplicate.positions.len()
+            );
+        }
+    }
+
+    if log_enabled!(Trace) && args.tree_sitter {
         let root_node = parsed_file.syntax_tree.root_node();
         let mut stack = vec![root_node];
 
@@ -138,6 +705,32 @@ pub fn process_file(input_file: &str, args: &ArgMatches) -> Result<()> {
         }
     }
 
+    Ok(ExtractedFrames {
+        parsed_file,
+        priority: vec![false; frames.len()],
+        frames,
+        frame_offsets,
+    })
+}
+
+/// Index of the frame of `extracted` that contains `line` (1-based) of the input file.
+pub fn frame_at_line(extracted: &ExtractedFrames, line: usize) -> Option<usize> {
+    let parsed_file = &extracted.parsed_file;
+    extracted
+        .frames
+        .iter()
+        .zip(&extracted.frame_offsets)
+        .position(|(frame, offset)| {
+            let (first_line, _) = parsed_file.get_position(*offset);
+            let (last_line, _) = parsed_file.get_position(offset + frame.len());
+            first_line <= line && line <= last_line
+        })
+}
+
+/// Preamble of the documents compiled for the frames: the preamble of the input file with the
+/// overrides given in `args`, and `aspectratio` (if given) instead of the aspect ratio of the
+/// document.
+pub fn frame_preamble(file_content: &str, args: &Options, aspectratio: Option<&str>) -> String {
     //let document_env = tree_traversal::get_children(
     //parsed_file.syntax_tree.root_node(),
     //&|n| n.kind() == "document_env",
@@ -151,98 +744,678 @@ pub fn process_file(input_file: &str, args: &ArgMatches) -> Result<()> {
     //"Could not find document environment with tree_sitter ({})",
     //input_file
     /*);*/
-    let find = parsed_file.file_content.find("\\begin{document}");
-    let preamble = match find {
-        Some(x) => Some(parsed_file.file_content[..x].to_owned()),
-        None => None,
-    }
-    .unwrap_or_else(|| r"\documentclass[aspectratio=43,c,xcolor=dvipsnames]{beamer}".to_string());
-
-    let cachedir = dirs::cache_dir().expect("This OS is not supported").join("faster-beamer");
-    std::fs::create_dir_all(&cachedir).map_err(|ref err| {
-        error!("Failed to create cache dir \"{}\": {}", cachedir.display(), err);
-        FasterBeamerError::IoError
-    })?;
+    // Frames are selected when they are extracted
+    let mut preamble = remove_includeonlyframes(&document_preamble(file_content));
 
-    let cache_subdir = cachedir.join(format!(
-        "./{}",
-        &input_dir
-            .to_str()
-            .unwrap() // append input to cachedir
-            .replace(":", "_") // Escape forbidden characters like ..cache_dir/c:/
-    ));
+    if let Some(aspectratio) = aspectratio {
+        preamble = set_class_option(&preamble, "aspectratio", Some(aspectratio));
+    }
+    if let Some(mode) = beamer_mode(args) {
+        preamble = set_beamer_mode(&preamble, mode);
+    }
+    if let Some(mode) = &args.notes {
+        preamble = set_notes(&preamble, mode);
+    }
+    // Every frame is a document of its own
+    if let Some(mode) = &args.overlays {
+        preamble = set_overlays(&preamble, mode);
+    }
+    if let Some(theme) = &args.theme {
+        preamble = set_theme(&preamble, theme);
+    }
+    preamble = set_conditionals(&preamble, &args.conditionals);
+    for extra in &args.preamble_extra {
+        preamble = preamble + "\n" + extra + "\n";
+    }
+    if Engine::of(engine_command(args)).has_native_fonts() {
+        preamble = end_of_dump(&preamble);
+    }
+    preamble
+}
 
-    let preamble_hash = md5::compute(&preamble);
-    let preamble_filename = format!("{:x}_{}", preamble_hash, args.is_present("draft"));
-    if input_path
-        .parent()
-        .unwrap()
-        .join(format!("{}.fmt", preamble_filename))
-        .is_file()
-    {
-        info!("Precompiled preamble already exists");
+/// Beamer mode selected in `args` (`--handout` is short for `--beamer-mode handout`).
+pub fn beamer_mode(args: &Options) -> Option<&str> {
+    if args.handout {
+        Some("handout")
     } else {
-        info!(
-            "Precompiling preamble {:?}",
-            input_path.join(format!("{}.fmt", preamble_filename))
-        );
-        let output = Command::new(compilercmd)
-            .arg("-shell-escape")
-            .arg("-ini")
-            .arg(format!("-jobname=\"{}\"", preamble_filename))
-            .arg("\"&".to_owned() + compilercmd + "\"")
-            .arg("mylatexformat.ltx")
-            .arg(&input_file)
-            .output();
-        match output {
-            Err(e) => {
-                error!("Failed to compile preamble!\n{}", e);
-                show_error_slide(&cachedir, output_file, compilercmd);
+        args.beamer_mode.as_deref()
+    }
+}
 
-                *PREVIOUS_FRAMES.lock().unwrap() = Vec::new();
-                return Err(FasterBeamerError::CompileError);
-            }
-            Ok(output) if !output.status.success() => {
-                error!(
-                    "Failed to compile preamble! {}",
-                    str::from_utf8(&output.stderr).unwrap()
-                );
-                show_error_slide(&cachedir, output_file, compilercmd);
+/// `document` in the beamer mode and with the notes selected in `args` (if any).
+fn in_beamer_mode(document: &str, args: &Options) -> String {
+    let document = match beamer_mode(args) {
+        Some(mode) => set_beamer_mode(document, mode),
+        None => document.to_string(),
+    };
+    match &args.notes {
+        Some(mode) => set_notes(&document, mode),
+        None => document,
+    }
+}
 
-                *PREVIOUS_FRAMES.lock().unwrap() = Vec::new();
-                return Err(FasterBeamerError::CompileError);
-            }
-            _ => {}
-        };
+/// Files whose content is part of the name of the format of `preamble` of `input_file`: the
+/// local files it loads, with the changes configured in `faster-beamer.toml`.
+pub fn hashed_dependencies(preamble: &str, input_file: &str) -> Vec<PathBuf> {
+    let input_dir = get_input_dir(input_file);
+    let config = Config::find(input_file);
+    scope_dependencies(
+        local_dependencies(preamble, &input_dir),
+        &config.dependencies.include,
+        &config.dependencies.exclude,
+        config.directory.as_deref().unwrap_or(&input_dir),
+    )
+}
+
+/// Name of the precompiled format of `preamble`, which loads the local files `dependencies` and
+/// is compiled with the environment variables `env`.
+pub fn format_name(
+    preamble: &str,
+    dependencies: &[PathBuf],
+    env: &BTreeMap<String, String>,
+    args: &Options,
+) -> String {
+    let mut hash = format!("{:x}", content_hash(preamble, dependencies));
+    // The environment (e.g. TEXINPUTS) may change which files are loaded
+    if !env.is_empty() {
+        hash = format!("{:x}", md5::compute(format!("{}{:?}", hash, env)));
     }
+    // Only the TeX installation that dumped a format can load it
+    if let Some(image) = &args.container {
+        hash = format!("{:x}", md5::compute(format!("{}{}", hash, image)));
+    }
+    format!("{}_{}", hash, args.draft)
+}
+
+/// First line of a document that is compiled with the precompiled format `format_name` (if any).
+pub fn format_line(format_name: Option<&str>) -> String {
+    format_name.map_or_else(String::new, |name| format!("%&{}\n", name))
+}
+
+/// Generates the document that is compiled for every frame of `file_content`, with the content of
+/// the document body between the frames before it. `format_name` is the name of the precompiled
+/// format of `preamble` (None if the frames are compiled with the full preamble). The hash of a
+/// frame includes the local files (relative to `input_dir`) that it reads.
+pub fn frame_documents(
+    frames: Vec<String>,
+    frame_offsets: Vec<usize>,
+    file_content: &str,
+    preamble: &str,
+    format_name: Option<&str>,
+    input_dir: &Path,
+    args: &Options,
+) -> Vec<Frame> {
+    let correct_frame_numbers = args.frame_numbers;
+    let strip_decorations = args.strip_decorations;
+    let all_frames = frame_ranges(file_content, &frame_conditionals(file_content, args));
+    let carried = content_between_frames(file_content, &all_frames, &frame_offsets);
 
-    let mut generated_documents = Vec::new();
-    let mut command = &mut Command::new("pdfunite");
-    for (frame_idx, f) in frames.iter().enumerate() {
+    let num_frames = frames.len();
+    let mut generated_documents = Vec::with_capacity(num_frames);
+    for (frame_idx, ((f, offset), carried)) in frames
+        .into_iter()
+        .zip(frame_offsets)
+        .zip(carried)
+        .enumerate()
+    {
         let frame_idx_str = if correct_frame_numbers {
             format!("{}", frame_idx)
         } else {
             format!("{}", 0)
         };
-        let compile_string = format!("%&{}\n", preamble_filename)
-            + &preamble
+        let mut header = format_line(format_name)
+            + preamble
             + "\n\\begin{document}\n"
             + "\\addtocounter{framenumber}{"
             + &frame_idx_str
-            + "}\n"
-            + &f
-            + "\n\\end{document}\n";
+            + "}\n";
+        if correct_frame_numbers {
+            // Until the pages of the frames are known, every frame is assumed to have one page
+            header += &format!(
+                "\\setcounter{{page}}{{{}}}\\def\\inserttotalframenumber{{{}}}\n",
+                frame_idx + 1,
+                num_frames
+            );
+        }
+        // Definitions between frames (e.g. `\newcommand` or `\setbeamertemplate`)
+        if !carried.trim().is_empty() {
+            header = header + CARRIED_CONTENT_BEGIN + &carried + CARRIED_CONTENT_END;
+        }
+        if strip_decorations {
+            header += STRIP_DECORATIONS;
+        }
+        let compile_string = header.clone() + &f + "\n\\end{document}\n";
+        // e.g. a `\graphicspath` between frames
+        let dependencies = frame_files(&f, &(preamble.to_string() + &carried), input_dir);
+
+        generated_documents.push(Frame {
+            hash: content_hash(&compile_string, &dependencies),
+            body_line: header.matches('\n').count() + 1,
+            tex: compile_string,
+            content: f,
+            offset,
+        });
+    }
+    generated_documents
+}
+
+/// Writes the files of the whole presentation that frames read as their own (the `.bbl` of the
+/// bibliography, the `.toc` of the table of contents and the sorted index and glossaries) for the
+/// frames that are not compiled yet.
+/// Every pass that injects code into the frames changes their hashes and needs them again.
+fn provide_shared_files(
+    frames: &[Frame],
+    cache_subdir: &Path,
+    bibliography: Option<&SharedBibliography>,
+    toc: Option<&str>,
+    lists: Option<&SharedLists>,
+) -> Result<()> {
+    for frame in frames.iter().filter(|f| !f.is_compiled(cache_subdir)) {
+        if let Some(bibliography) = bibliography {
+            if uses_bibliography(&frame.content) {
+                bibliography.copy_to(frame, cache_subdir);
+            }
+        }
+        if let Some(toc) = toc {
+            if has_table_of_contents(&frame.content) {
+                let toc_file = cache_subdir.join(format!("{:x}.toc", frame.hash));
+                write(&toc_file, toc).map_err(FasterBeamerError::io(&toc_file))?;
+            }
+        }
+        if let Some(lists) = lists {
+            if prints_lists(&frame.content) {
+                lists.copy_to(frame, cache_subdir);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Extracts the frames of `input_file` and compiles all frames that are not already cached.
+pub fn compile_frames(input_file: &str, args: &Options) -> Result<CompiledFrames> {
+    compile_extracted(
+        input_file,
+        load_frames(input_file, args)?,
+        args,
+        args.aspectratio.as_deref(),
+    )
+}
+
+/// Compiles all frames that are not already cached, using `aspectratio` (if given) instead of the
+/// aspect ratio of the document.
+pub fn compile_extracted(
+    input_file: &str,
+    extracted: ExtractedFrames,
+    args: &Options,
+    aspectratio: Option<&str>,
+) -> Result<CompiledFrames> {
+    let compiler = frame_compiler(args);
+    compile_extracted_with(
+        compiler.as_ref(),
+        &get_cachedir()?,
+        input_file,
+        extracted,
+        args,
+        aspectratio,
+    )
+}
+
+/// Like `compile_extracted`, with `compiler` and the cache in `cachedir`.
+pub fn compile_extracted_with(
+    compiler: &dyn Compiler,
+    cachedir: &Path,
+    input_file: &str,
+    extracted: ExtractedFrames,
+    args: &Options,
+    aspectratio: Option<&str>,
+) -> Result<CompiledFrames> {
+    let ExtractedFrames {
+        parsed_file,
+        frames,
+        frame_offsets,
+        priority,
+    } = extracted;
+    let input_dir = get_input_dir(input_file);
+    let compiler_args = frame_compiler_args(args);
+    let compiler_env = compiler_environment(input_file, args);
+
+    let preamble = frame_preamble(&parsed_file.file_content, args, aspectratio);
+    let compilercmd = engine_command(args);
+    let engine = Engine::of(compilercmd);
+
+    let cachedir = cachedir.to_owned();
+
+    let cache_subdir = get_cache_subdir(&cachedir, &input_dir);
+
+    let dependencies = local_dependencies(&preamble, &input_dir);
+    let graphics_dirs = graphics_dirs(&preamble, &input_dir);
+    // Files of the preamble and directories of `\graphicspath` outside of the input directory
+    // (e.g. a preamble shared by several documents) need to be available in the cache dir, too
+    let dependency_dirs = dependencies
+        .iter()
+        .filter_map(|d| d.parent())
+        .chain(graphics_dirs.iter().map(|d| d.as_path()));
+    for dependency_dir in dependency_dirs {
+        if !dependency_dir.starts_with(&input_dir) {
+            LatexInput::from_lazy(dependency_dir.to_str().unwrap(), &cachedir)
+                .map_err(FasterBeamerError::latex_input(dependency_dir))?;
+        }
+    }
+
+    let preamble_filename = format_name(
+        &preamble,
+        &hashed_dependencies(&preamble, input_file),
+        &compiler_env,
+        args,
+    );
+    let preamble_fmt = cache_subdir.join(format!("{}.fmt", preamble_filename));
+    // Documents in the same directory may share the preamble
+    let lock = cache_lock(&preamble_fmt);
+    let guard = lock.lock().unwrap();
+    let fallback_marker = format_fallback_marker(&cache_subdir, &preamble_filename);
+    if preamble_fmt.is_file() {
+        info!("Precompiled preamble already exists");
+    } else if link_shared_format(&cachedir, &preamble_fmt) {
+        info!("Using precompiled preamble of another document");
+    } else if fallback_marker.is_file() {
+        info!("Compiling frames with the full preamble, which cannot be precompiled");
+    } else if !engine.precompiles_formats() {
+        info!("{} cannot precompile the preamble, compiling frames with it", compilercmd);
+        std::fs::create_dir_all(&cache_subdir).map_err(FasterBeamerError::io(&cache_subdir))?;
+        write(&fallback_marker, "").map_err(FasterBeamerError::io(&fallback_marker))?;
+    } else {
+        info!("Precompiling preamble {:?}", preamble_fmt);
+        // Makes the files of the input directory available in the cache dir
+        LatexInput::from_lazy(input_dir.to_str().unwrap(), &cachedir)
+            .map_err(FasterBeamerError::latex_input(&input_dir))?;
+        let preamble_file = cache_subdir.join(format!("{}.tex", preamble_filename));
+        write(
+            &preamble_file,
+            preamble.clone() + "\n\\begin{document}\n\\end{document}\n",
+        )
+        .map_err(FasterBeamerError::io(&preamble_file))?;
+
+        let _in_progress = InProgress::new(&preamble_fmt);
+        match compiler.precompile_format(
+            &preamble_file,
+            &preamble_filename,
+            &cache_subdir,
+            &latex_args(args),
+            &compiler_env,
+        ) {
+            // Formats of XeTeX and LuaTeX fail for more reasons (e.g. fonts that the preamble of
+            // a theme loads), but the frames still compile with the full preamble
+            Err(e) if engine.has_native_fonts() => {
+                warn!(
+                    "Failed to precompile the preamble with {} ({}), compiling frames with the full preamble",
+                    compilercmd, e
+                );
+                write(&fallback_marker, "").map_err(FasterBeamerError::io(&fallback_marker))?;
+            }
+            Err(e) => {
+                return Err(FasterBeamerError::PreambleCompile {
+                    log_excerpt: log_excerpt(
+                        &cache_subdir.join(format!("{}.log", preamble_filename)),
+                        &e.to_string(),
+                    ),
+                    preamble: preamble_file,
+                });
+            }
+            Ok(()) => share_format(&cachedir, &preamble_fmt),
+        };
+    }
+    drop(guard);
+
+    let mut generated_documents = frame_documents(
+        frames,
+        frame_offsets,
+        &parsed_file.file_content,
+        &preamble,
+        precompiled_format(&cache_subdir, &preamble_filename),
+        &input_dir,
+        args,
+    );
+    let bibliography = if args.bibliography {
+        let frames: Vec<&str> = generated_documents
+            .iter()
+            .map(|f| &f.content[..])
+            .collect();
+        shared_bibliography(
+            compiler,
+            precompiled_format(&cache_subdir, &preamble_filename),
+            &preamble,
+            &parsed_file.file_content,
+            &frames,
+            &input_dir,
+            &cache_subdir,
+            &compiler_args,
+            &compiler_env,
+        )
+        .unwrap_or_else(|err| {
+            warn!("Failed to process the bibliography: {}", err);
+            None
+        })
+    } else {
+        None
+    };
+    if let Some(bibliography) = &bibliography {
+        let code = bibliography.frame_code();
+        generated_documents = generated_documents
+            .iter()
+            .map(|frame| {
+                if uses_bibliography(&frame.content) {
+                    frame.inject(&code)
+                } else {
+                    frame.clone()
+                }
+            })
+            .collect();
+    }
+    let mut toc: Option<String> = None;
+    let mut lists: Option<SharedLists> = None;
+    if events::is_enabled() {
+        for (frame_idx, frame) in generated_documents.iter().enumerate() {
+            events::emit(Event::Frame {
+                input_file,
+                frame: frame_idx,
+                line: parsed_file.get_position(frame.offset).0,
+                label: frame_label(&frame.content),
+            });
+        }
+    }
+
+    provide_shared_files(
+        &generated_documents,
+        &cache_subdir,
+        bibliography.as_ref(),
+        toc.as_deref(),
+        lists.as_ref(),
+    )?;
+    let cache_hits = compile_documents(
+        input_file,
+        &generated_documents,
+        &priority,
+        &input_dir,
+        &cachedir,
+        &cache_subdir,
+        compiler,
+        &compiler_args,
+        &compiler_env,
+    );
+    // Later passes compile variants of the frames, which are not counted
+    let cache_misses = generated_documents.len() - cache_hits;
+    if let Err(err) = write_cache_record(&cache_subdir, input_file, cache_hits, cache_misses) {
+        warn!("Failed to record the cache hits of the build: {}", err);
+    }
+
+    if args.frame_numbers {
+        // Frames with several pages (overlays or `allowframebreaks`) shift the pages and numbers
+        // of the frames after them (and change the total number of frames)
+        let page_map = build_page_map(&generated_documents, &cache_subdir);
+        let numbers = frame_numbers(&generated_documents, &page_map);
+        let total = total_frame_number(&generated_documents, &page_map);
+        let num_frames = generated_documents.len();
+        generated_documents = generated_documents
+            .iter()
+            .zip(&page_map)
+            .zip(numbers)
+            .enumerate()
+            .map(|(frame_idx, ((frame, pages), number))| {
+                if pages.first_page == frame_idx + 1 && number == frame_idx && total == num_frames {
+                    return frame.clone();
+                }
+                frame.inject(&format!(
+                    "\\setcounter{{page}}{{{}}}\\setcounter{{framenumber}}{{{}}}\
+                     \\def\\inserttotalframenumber{{{}}}\n",
+                    pages.first_page, number, total
+                ))
+            })
+            .collect();
+        provide_shared_files(
+            &generated_documents,
+            &cache_subdir,
+            bibliography.as_ref(),
+            toc.as_deref(),
+            lists.as_ref(),
+        )?;
+        compile_documents(
+            input_file,
+            &generated_documents,
+            &priority,
+            &input_dir,
+            &cachedir,
+            &cache_subdir,
+            compiler,
+            &compiler_args,
+            &compiler_env,
+        );
+    }
+
+    if args.navigation {
+        let page_map = build_page_map(&generated_documents, &cache_subdir);
+        let frames: Vec<(usize, &str)> = generated_documents
+            .iter()
+            .map(|f| (f.offset, &f.content[..]))
+            .collect();
+        let outline = extract_outline(&parsed_file.file_content, &frames);
+        let (nav, contexts) = generate_nav(&outline, &page_map);
+        let nav_hash = md5::compute(&nav);
+
+        generated_documents = generated_documents
+            .iter()
+            .zip(contexts)
+            .map(|(frame, context)| {
+                frame.inject(&format!(
+                    "\\setcounter{{page}}{{{}}}\\setcounter{{section}}{{{}}}\\setcounter{{subsection}}{{{}}}\n\
+                     % navigation {:x}\n",
+                    context.first_page, context.section, context.subsection, nav_hash
+                ))
+            })
+            .collect();
+        for frame in generated_documents.iter() {
+            if !frame.is_compiled(&cache_subdir) {
+                std::fs::create_dir_all(&cache_subdir)
+                    .map_err(FasterBeamerError::io(&cache_subdir))?;
+                let nav_file = cache_subdir.join(format!("{:x}.nav", frame.hash));
+                write(&nav_file, &nav).map_err(FasterBeamerError::io(&nav_file))?;
+            }
+        }
+        provide_shared_files(
+            &generated_documents,
+            &cache_subdir,
+            bibliography.as_ref(),
+            toc.as_deref(),
+            lists.as_ref(),
+        )?;
+        compile_documents(
+            input_file,
+            &generated_documents,
+            &priority,
+            &input_dir,
+            &cachedir,
+            &cache_subdir,
+            compiler,
+            &compiler_args,
+            &compiler_env,
+        );
+    }
+
+    // Tables of contents of the whole presentation
+    if generated_documents
+        .iter()
+        .any(|frame| has_table_of_contents(&frame.content))
+    {
+        let page_map = build_page_map(&generated_documents, &cache_subdir);
+        let frames: Vec<(usize, &str)> = generated_documents
+            .iter()
+            .map(|f| (f.offset, &f.content[..]))
+            .collect();
+        let outline = extract_outline(&parsed_file.file_content, &frames);
+        let contents = generate_toc(&outline, &page_map);
+        let toc_code = format!("% table of contents {:x}\n", md5::compute(&contents));
+        toc = Some(contents);
+
+        generated_documents = generated_documents
+            .iter()
+            .map(|frame| {
+                if has_table_of_contents(&frame.content) {
+                    frame.inject(&toc_code)
+                } else {
+                    frame.clone()
+                }
+            })
+            .collect();
+        provide_shared_files(
+            &generated_documents,
+            &cache_subdir,
+            bibliography.as_ref(),
+            toc.as_deref(),
+            lists.as_ref(),
+        )?;
+        compile_documents(
+            input_file,
+            &generated_documents,
+            &priority,
+            &input_dir,
+            &cachedir,
+            &cache_subdir,
+            compiler,
+            &compiler_args,
+            &compiler_env,
+        );
+    }
+
+    // Index and glossaries of the entries of all frames
+    lists = shared_lists(&generated_documents, &preamble, &cache_subdir, &compiler_env)
+        .unwrap_or_else(|err| {
+            warn!("Failed to sort the index and glossaries: {}", err);
+            None
+        });
+    if let Some(lists) = &lists {
+        let code = lists.frame_code();
+        generated_documents = generated_documents
+            .iter()
+            .map(|frame| {
+                if prints_lists(&frame.content) {
+                    frame.inject(&code)
+                } else {
+                    frame.clone()
+                }
+            })
+            .collect();
+        provide_shared_files(
+            &generated_documents,
+            &cache_subdir,
+            bibliography.as_ref(),
+            toc.as_deref(),
+            Some(lists),
+        )?;
+        compile_documents(
+            input_file,
+            &generated_documents,
+            &priority,
+            &input_dir,
+            &cachedir,
+            &cache_subdir,
+            compiler,
+            &compiler_args,
+            &compiler_env,
+        );
+    }
+
+    // References to labels of other frames (or of the same frame)
+    let labels = collect_labels(input_file, &cache_subdir);
+    if !labels.is_empty() {
+        let with_labels: Vec<Frame> = generated_documents
+            .iter()
+            .map(|frame| match label_definitions(&frame.content, &labels) {
+                definitions if definitions.is_empty() => frame.clone(),
+                definitions => frame.inject(&definitions),
+            })
+            .collect();
+        if with_labels
+            .iter()
+            .any(|frame| !frame.is_compiled(&cache_subdir))
+        {
+            provide_shared_files(
+                &with_labels,
+                &cache_subdir,
+                bibliography.as_ref(),
+                toc.as_deref(),
+                lists.as_ref(),
+            )?;
+            compile_documents(
+                input_file,
+                &with_labels,
+                &priority,
+                &input_dir,
+                &cachedir,
+                &cache_subdir,
+                compiler,
+                &compiler_args,
+                &compiler_env,
+            );
+        }
+        generated_documents = with_labels;
+    }
+
+    let manifest: Vec<(md5::Digest, &str)> = generated_documents
+        .iter()
+        .map(|f| (f.hash, &f.content[..]))
+        .collect();
+    if let Err(err) = write_manifest(&cache_subdir, input_file, &manifest) {
+        warn!("Failed to write build manifest: {}", err);
+    }
+    if args.keep_intermediates {
+        if let Err(err) = keep_intermediates(input_file, &generated_documents, &cache_subdir) {
+            warn!("Failed to keep frame documents: {}", err);
+        }
+    }
+
+    Ok(CompiledFrames {
+        parsed_file,
+        frames: generated_documents,
+        cachedir,
+        cache_subdir,
+    })
+}
 
-        let hash = md5::compute(&compile_string);
-        let output = cache_subdir.join(format!("{:x}.pdf", hash));
-        generated_documents.push((hash, compile_string));
+pub fn process_file(job: &Job, args: &Options) -> Result<()> {
+    let result = build_outputs(job, args);
+    if !matches!(result, Err(FasterBeamerError::InputFileNotExistent(_))) {
+        let cache_subdir = get_cache_subdir(&get_cachedir()?, &get_input_dir(&job.input_file));
+        let succeeded = result.is_ok();
+        if let Err(err) =
+            write_build_record(&cache_subdir, &job.input_file, succeeded, &job.output_file)
+        {
+            warn!("Failed to record the outcome of the build: {}", err);
+        }
+    }
+    result
+}
 
-        command = command.arg(output.to_str().unwrap());
+/// Compiles the frames of `job` and writes its outputs.
+fn build_outputs(job: &Job, args: &Options) -> Result<()> {
+    let input_file = &job.input_file[..];
+    let output_file = &job.output_file[..];
+    let compilercmd = engine_command(args);
+
+    let mut extracted = load_frames(input_file, args)?;
+    let frames = extracted.frames.clone();
+    if let Some(edited_file) = &job.edited_file {
+        let edited = std::fs::read_to_string(edited_file).unwrap_or_default();
+        extracted.priority = frames.iter().map(|f| edited.contains(&f[..])).collect();
     }
 
     trace!("Comparing frames");
     let mut first_changed_frame = 0;
-    for frame_pair in frames.iter().zip((*PREVIOUS_FRAMES.lock().unwrap()).iter()) {
+    let previous_frames = PREVIOUS_FRAMES
+        .lock()
+        .unwrap()
+        .get(input_file)
+        .cloned()
+        .unwrap_or_default();
+    for frame_pair in frames.iter().zip(previous_frames.iter()) {
         match frame_pair {
             (lhs, rhs) if lhs != rhs => {
                 break;
@@ -256,165 +1429,752 @@ pub fn process_file(input_file: &str, args: &ArgMatches) -> Result<()> {
         frames.len()
     );
 
-    let progress_bar = ProgressBar::new(generated_documents.len() as u64);
+    // Other modes than the presentation get their own output file
+    let (output_file, page_map_file) = match beamer_mode(args) {
+        Some(mode) if mode != "presentation" => (
+            variant_filename(output_file, mode),
+            job.page_map_file.as_ref().map(|p| variant_filename(p, mode)),
+        ),
+        _ => (output_file.to_string(), job.page_map_file.clone()),
+    };
+    // So do builds with notes
+    let (output_file, page_map_file) = match args.notes.as_deref() {
+        Some(mode) if mode != "none" => {
+            let variant = if mode == "only" { "notes-only" } else { "notes" };
+            (
+                variant_filename(&output_file, variant),
+                page_map_file.as_ref().map(|p| variant_filename(p, variant)),
+            )
+        }
+        _ => (output_file, page_map_file),
+    };
+    // Each aspect ratio variant gets its own output file
+    let variants: Vec<(Option<&str>, String, Option<String>)> = if args.variants.is_empty() {
+        vec![(args.aspectratio.as_deref(), output_file, page_map_file)]
+    } else {
+        args.variants
+            .iter()
+            .map(|v| {
+                (
+                    Some(v.as_str()),
+                    variant_filename(&output_file, v),
+                    page_map_file.as_ref().map(|p| variant_filename(p, v)),
+                )
+            })
+            .collect()
+    };
 
-    generated_documents
-        .par_iter()
-        .enumerate()
-        .for_each(|(frame_idx, (hash, tex_content))| {
-            let pdf = cache_subdir.join(format!("{:x}.pdf", hash));
+    let mut result = Ok(());
+    for (aspectratio, output_file, page_map_file) in variants {
+        let compiled = match compile_extracted(input_file, extracted.clone(), args, aspectratio) {
+            Err(err @ FasterBeamerError::PreambleCompile { .. }) => {
+                show_error_slide(
+                    &get_cachedir()?,
+                    &output_file,
+                    compilercmd,
+                    &latex_args(args),
+                );
 
-            if pdf.is_file() {
-                trace!("{} is already compiled!", pdf.to_str().unwrap_or("???"));
-            } else {
-                let latex_input = LatexInput::from_lazy(
-                    input_dir.canonicalize().unwrap().to_str().unwrap(),
-                    &cachedir,
-                )
-                .expect("Failed to create LatexInput");
-
-                let temp_file = cache_subdir.join(format!("{:x}.tex", hash));
-
-                if write(&temp_file, &tex_content).is_ok() {
-                    let mut compiler = LatexCompiler::new(compilercmd)
-                        .unwrap()
-                        .add_arg("-shell-escape")
-                        .add_arg("-interaction=nonstopmode");
-                    compiler.working_dir = temp_file.parent().unwrap().canonicalize().unwrap();
-
-                    let result = compiler.run(
-                        &temp_file.canonicalize().unwrap().to_string_lossy(),
-                        &latex_input,
-                        LatexRunOptions::new(),
-                    );
-                    if result.is_ok() {
-                        trace!("Compiled file {}", &temp_file.to_str().unwrap());
-                    } else {
-                        error!(
-                            "Failed to compile frame {} ({})",
-                            frame_idx,
-                            &temp_file.to_str().unwrap()
-                        );
-                        error!("{}", frames[frame_idx]);
-                        error!("{}", result.err().unwrap());
-                    };
-                }
-            };
-            progress_bar.inc(1);
+                PREVIOUS_FRAMES.lock().unwrap().remove(input_file);
+                return Err(err);
+            }
+            result => result?,
+        };
+
+        if let Some(page_map_file) = page_map_file {
+            write_page_map(
+                &build_page_map(&compiled.frames, &compiled.cache_subdir),
+                Path::new(&page_map_file),
+            )?;
+        }
+
+        result = write_output(&compiled, args, &output_file, first_changed_frame);
+        if result.is_err() {
+            break;
+        }
+        events::emit(Event::Output {
+            input_file,
+            output_file: &output_file,
         });
-    progress_bar.finish_and_clear();
+    }
 
-    if args.is_present("pdfunite") {
-        let output = command.arg(output_file).output();
+    PREVIOUS_FRAMES
+        .lock()
+        .unwrap()
+        .insert(input_file.to_string(), frames);
+    result
+}
 
-        match output {
-            Err(e) => {
-                error!("Failed to run pdf unite!\n{}", e);
-                show_error_slide(&cachedir, output_file, compilercmd);
+/// Replaces every frame in `content` (at its offset, so that identical frames are replaced one by
+/// one) by `replacement(frame_idx, frame)`.
+pub fn splice_frames<F>(content: &str, frames: &[Frame], replacement: F) -> String
+where
+    F: Fn(usize, &Frame) -> String,
+{
+    let mut spliced = String::with_capacity(content.len());
+    let mut position = 0;
+    for (frame_idx, frame) in frames.iter().enumerate() {
+        let start = frame.offset;
+        if start < position || !content[start..].starts_with(&frame.content) {
+            warn!("Frame {} is not at its position in the document", frame_idx);
+            continue;
+        }
+        spliced += &content[position..start];
+        spliced += &replacement(frame_idx, frame);
+        position = start + frame.content.len();
+    }
+    spliced + &content[position..]
+}
 
-                *PREVIOUS_FRAMES.lock().unwrap() = frames;
-                return Err(FasterBeamerError::PdfUniteError);
-            }
-            Ok(output) if !output.status.success() => {
-                error!(
-                    "Failed to run pdfunite! {}",
-                    str::from_utf8(&output.stderr).unwrap()
-                );
-                show_error_slide(&cachedir, output_file, compilercmd);
+/// Code that replaces every frame in the united document (unless configured otherwise).
+pub const DEFAULT_UNITE_TEMPLATE: &str = "{\\setbeamercolor{background canvas}{bg=}\n\
+     \\includepdf[pages=-,{destination_option}]{{pdf}}\n}";
+
+/// Replaces the placeholders of `template`: `{pdf}` by the PDF of the frame, `{destination}` by
+/// the name of its destination and `{destination_option}` by an option of `\\includepdf` that
+/// puts this destination on the first page.
+pub fn fill_unite_template(template: &str, pdf: &str, destination: &str) -> String {
+    let destination_option = format!(
+        "picturecommand*={{\\put(0,\\LenToUnit{{\\paperheight}}){{\\hypertarget{{{}}}{{}}}}}}",
+        destination
+    );
+    template
+        .replace("{destination_option}", &destination_option)
+        .replace("{destination}", destination)
+        .replace("{pdf}", pdf)
+}
+
+/// Hash of the united document `united_tex` and of the frame PDFs it includes. A frame PDF that is
+/// compiled again (e.g. after a failed compile) changes its size or modification time.
+fn united_hash(
+    united_tex: &str,
+    compilercmd: &str,
+    frames: &[Frame],
+    cache_subdir: &Path,
+) -> String {
+    let mut context = md5::Context::new();
+    context.consume(compilercmd.as_bytes());
+    context.consume(united_tex.as_bytes());
+    for frame in frames {
+        if let Ok(metadata) = std::fs::metadata(frame.pdf(cache_subdir)) {
+            context.consume(format!("{}{:?}", metadata.len(), metadata.modified().ok()));
+        }
+    }
+    format!("{:x}", context.compute())
+}
+
+/// Inserts `-<variant>` before the extension of `filename`.
+pub fn variant_filename(filename: &str, variant: &str) -> String {
+    let path = Path::new(filename);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{}-{}.{}", stem, variant, extension.to_string_lossy()),
+        None => format!("{}-{}", stem, variant),
+    };
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
 
-                *PREVIOUS_FRAMES.lock().unwrap() = frames;
-                return Err(FasterBeamerError::PdfUniteError);
+/// Writes the output PDF from the compiled frames (the most recently changed frame, or all frames
+/// united).
+fn write_output(
+    compiled: &CompiledFrames,
+    args: &Options,
+    output_file: &str,
+    mut first_changed_frame: usize,
+) -> Result<()> {
+    let compilercmd = engine_command(args);
+    let CompiledFrames {
+        parsed_file,
+        frames: generated_documents,
+        cachedir,
+        cache_subdir,
+    } = compiled;
+
+    let is_merged = args.pdfunite || args.unite;
+    // United PDF, what it depends on besides its frames and on which pages the frames are
+    let mut united_record = None;
+    let united_record_file = record_file(cache_subdir, output_file);
+    if is_merged && !args.tagged && is_tagged(&parsed_file.file_content) {
+        warn!(
+            "{} is tagged, but the structure tree is lost when merging its frames (see --tagged)",
+            parsed_file.filename
+        );
+    }
+
+    if args.tagged {
+        let tagged_pdf = match compile_tagged(
+            &in_beamer_mode(&parsed_file.file_content, args),
+            &parsed_file.filename,
+            output_file,
+            cache_subdir,
+            args,
+        ) {
+            Ok(tagged_pdf) => tagged_pdf,
+            Err(err) => {
+                show_error_slide(&cachedir, output_file, compilercmd, &latex_args(args));
+                return Err(err);
             }
-            _ => {}
         };
-    } else if args.is_present("unite") {
+        if Path::new(&output_file).is_file() {
+            let _result = ::std::fs::remove_file(&output_file);
+        }
+        info!("Linking: {:?} -> {:?}", &tagged_pdf, &output_file);
+        ::symlink::symlink_file(tagged_pdf, output_file)
+            .map_err(FasterBeamerError::io(output_file))?;
+    } else if args.pdfunite {
+        let pdfs: Vec<PathBuf> = generated_documents
+            .iter()
+            .map(|frame| frame.pdf(&cache_subdir))
+            .collect();
+        let output_path = Path::new(output_file);
+        let frames: Vec<(usize, &str)> = generated_documents
+            .iter()
+            .map(|f| (f.offset, &f.content[..]))
+            .collect();
+        let outline = extract_outline(&parsed_file.file_content, &frames);
+        let metadata = document_metadata(&parsed_file.file_content);
+        let united_key = format!(
+            "pdfunite {} {:x}",
+            sections_key(&outline),
+            md5::compute(format!("{:?}", metadata))
+        );
+        // The watermark replaces the output by a stamped copy, which cannot be updated
+        let updated = if args.watermark.is_some() {
+            None
+        } else {
+            update_united(
+                &united_record_file,
+                &united_key,
+                output_path,
+                generated_documents,
+                cache_subdir,
+            )
+        };
+        let is_up_to_date = matches!(updated, Some((_, false)));
+        let pages = match updated {
+            Some((pages, is_changed)) => {
+                if is_changed {
+                    fix_united(&pdfs, output_file);
+                }
+                pages
+            }
+            None => {
+                if let Err(err) = pdfunite(&pdfs, output_file, &args.merger) {
+                    show_error_slide(&cachedir, output_file, compilercmd, &latex_args(args));
+                    return Err(err);
+                }
+                concatenated_pages(generated_documents, cache_subdir)
+            }
+        };
+        if is_up_to_date {
+            info!("United PDF is up to date");
+        } else {
+            // The frame PDFs know neither the structure nor the title of the document
+            let page_map = build_page_map(generated_documents, cache_subdir);
+            add_bookmarks(output_path, &outline, &page_map);
+            add_metadata(output_path, &metadata);
+        }
+        if args.watermark.is_none() {
+            united_record = Some((output_path.to_owned(), united_key, pages));
+        }
+    } else if args.unite {
         info!("Pasting precompiled frames into original document!");
         if Path::new(&output_file).is_file() {
-            let _result =
-                ::std::fs::remove_file(&output_file).expect("Tried to delete previous output file");
+            ::std::fs::remove_file(&output_file).map_err(FasterBeamerError::io(output_file))?;
         }
 
-        let mut united_tex = format!(
-            "{}\n{}",
-            "\\RequirePackage{pdfpages}", parsed_file.file_content
+        let destinations = frame_destinations(
+            &generated_documents
+                .iter()
+                .map(|frame| &frame.content[..])
+                .collect::<Vec<_>>(),
         );
-        for (f, (hash, _)) in frames.iter().zip(generated_documents) {
-            let pdf = format!("{:x}.pdf", hash);
-            united_tex = united_tex.replacen(
-                f,
-                &format!("{{\\setbeamercolor{{background canvas}}{{bg=}}\n\\includepdf[pages=-]{{{}}}\n}}", &pdf),
-                1,
+        let config = Config::find(&parsed_file.filename);
+        let template = config
+            .unite_template(args.profile.as_deref())
+            .unwrap_or(DEFAULT_UNITE_TEMPLATE);
+        let united_tex = "\\RequirePackage{pdfpages}\n".to_string()
+            + &in_beamer_mode(
+                &splice_frames(
+                    &parsed_file.file_content,
+                    generated_documents,
+                    |frame_idx, frame| {
+                        fill_unite_template(
+                            template,
+                            &format!("{:x}.pdf", frame.hash),
+                            &destinations[frame_idx],
+                        )
+                    },
+                ),
+                args,
             );
-        }
 
-        let united_tex_file = cache_subdir.join("united.tex");
-        let united_pdf = cache_subdir.join("united.pdf");
-        let write_result = write(&united_tex_file, united_tex);
-        if write_result.is_ok() {
+        // Different output files (e.g. of aspect ratio variants) must not share a united PDF
+        let united_name = format!("united-{:x}", md5::compute(output_file));
+        let united_tex_file = cache_subdir.join(format!("{}.tex", united_name));
+        let united_pdf = cache_subdir.join(format!("{}.pdf", united_name));
+        let united_hash_file = cache_subdir.join(format!("{}.hash", united_name));
+        let united_hash = united_hash(&united_tex, compilercmd, generated_documents, cache_subdir);
+        let is_up_to_date = is_complete_pdf(&united_pdf)
+            && std::fs::read_to_string(&united_hash_file).ok().as_ref() == Some(&united_hash);
+        // Another build of the same output (e.g. after a crash) must not remove the united PDF
+        // while it is updated or compiled
+        let _in_progress = InProgress::new(&united_pdf);
+        // The united document without the frames, whose pages are replaced when it is unchanged
+        let skeleton = splice_frames(
+            &parsed_file.file_content,
+            generated_documents,
+            |frame_idx, _| {
+                fill_unite_template(
+                    template,
+                    &format!("frame-{}.pdf", frame_idx),
+                    &destinations[frame_idx],
+                )
+            },
+        );
+        let unite_key = format!(
+            "unite {:x}",
+            md5::compute(compilercmd.to_string() + &in_beamer_mode(&skeleton, args))
+        );
+        // Pages of a custom template (e.g. scaled or several frames per page) are not the pages of
+        // the frames, so the united PDF is compiled again
+        let updated = if template == DEFAULT_UNITE_TEMPLATE {
+            update_united(
+                &united_record_file,
+                &unite_key,
+                &united_pdf,
+                generated_documents,
+                cache_subdir,
+            )
+        } else {
+            None
+        };
+
+        let mut compile_message = "No PDF was written".to_string();
+        if is_up_to_date {
+            info!("United PDF is up to date");
+            if let Some((pages, _)) = updated {
+                united_record = Some((united_pdf.clone(), unite_key, pages));
+            }
+        } else if let Some((pages, _)) = updated {
+            write(&united_tex_file, united_tex).map_err(FasterBeamerError::io(&united_tex_file))?;
+            if let Err(err) = write(&united_hash_file, &united_hash) {
+                warn!("Failed to write {}: {}", united_hash_file.display(), err);
+            }
+            united_record = Some((united_pdf.clone(), unite_key, pages));
+        } else {
+            let _result = std::fs::remove_file(&united_hash_file);
+            write(&united_tex_file, united_tex)
+                .map_err(FasterBeamerError::io(&united_tex_file))?;
+
             let mut compiler = LatexCompiler::new(compilercmd)
-                .unwrap()
-                .add_arg("-shell-escape")
+                .map_err(FasterBeamerError::latex_input(&united_tex_file))?
                 .add_arg("-interaction=nonstopmode");
-            compiler.working_dir = cache_subdir;
+            for arg in latex_args(args) {
+                compiler = compiler.add_arg(&arg);
+            }
+            for (key, value) in compiler_environment(&parsed_file.filename, args) {
+                compiler = compiler.add_env(&key, &value);
+            }
+            compiler.working_dir = cache_subdir.clone();
 
             let compile_result = compiler.run(
-                &united_tex_file.canonicalize().unwrap().to_string_lossy(),
+                &united_tex_file.to_string_lossy(),
                 &LatexInput::new(),
                 LatexRunOptions::new(),
             );
 
-            if compile_result.is_err() {
-                error!(
-                    "Failed to run pdf unite!\n{}",
-                    compile_result.err().unwrap()
-                );
-            }
-
-            if Path::new(&output_file).is_file() {
-                let _result = ::std::fs::remove_file(&output_file)
-                    .expect("Tried to delete previous output file");
+            match compile_result {
+                Ok(_) => {
+                    // pdfpages embeds the resources of every included PDF separately
+                    report_shared_resources(&united_pdf);
+                    if let Err(err) = write(&united_hash_file, &united_hash) {
+                        warn!("Failed to write {}: {}", united_hash_file.display(), err);
+                    }
+                    if let Some(pages) = destination_pages(
+                        &united_pdf,
+                        generated_documents,
+                        &destinations,
+                        cache_subdir,
+                    ) {
+                        united_record = Some((united_pdf.clone(), unite_key, pages));
+                    }
+                }
+                Err(err) => compile_message = err.to_string(),
             }
-            if Path::new(&united_pdf).is_file() {
-                info!("Linking: {:?} -> {:?}", &united_pdf, &output_file);
-                ::symlink::symlink_file(united_pdf, output_file)
-                    .expect("Failed to create symlink to output file.");
-            } else {
-                error!("Compilation failed!");
-                show_error_slide(&cachedir, output_file, compilercmd);
-
-                *PREVIOUS_FRAMES.lock().unwrap() = frames;
-                return Err(FasterBeamerError::CompileError);
+        }
+        if !is_up_to_date {
+            if let Some((_, _, pages)) = &united_record {
+                let pdfs: Vec<PathBuf> = generated_documents
+                    .iter()
+                    .map(|frame| frame.pdf(cache_subdir))
+                    .collect();
+                let first_pages: Vec<usize> = pages.iter().map(|pages| pages.first_page).collect();
+                copy_frame_links(&pdfs, &united_pdf, &first_pages);
             }
+        }
+        if Path::new(&output_file).is_file() {
+            ::std::fs::remove_file(&output_file).map_err(FasterBeamerError::io(output_file))?;
+        }
+        if Path::new(&united_pdf).is_file() {
+            info!("Linking: {:?} -> {:?}", &united_pdf, &output_file);
+            ::symlink::symlink_file(united_pdf, output_file)
+                .map_err(FasterBeamerError::io(output_file))?;
         } else {
-            error!("Failed to write united.tex: {:?}", write_result.err());
-            return Err(FasterBeamerError::PdfUniteError);
+            show_error_slide(&cachedir, output_file, compilercmd, &latex_args(args));
+
+            return Err(FasterBeamerError::Compile {
+                log_excerpt: log_excerpt(&united_tex_file.with_extension("log"), &compile_message),
+                file: united_tex_file,
+            });
         }
     } else {
         if first_changed_frame == generated_documents.len() {
             first_changed_frame = 0;
         }
         if first_changed_frame < generated_documents.len() {
-            let (hash, _) = generated_documents[first_changed_frame];
-            let compiled_pdf = cache_subdir.join(format!("{:x}.pdf", hash));
+            let frame = &generated_documents[first_changed_frame];
+            let compiled_pdf = frame.pdf(&cache_subdir);
 
             if Path::new(&output_file).is_file() {
-                let _result = ::std::fs::remove_file(&output_file)
-                    .expect("Tried to delete previous output file");
+                ::std::fs::remove_file(&output_file).map_err(FasterBeamerError::io(output_file))?;
             }
             if Path::new(&compiled_pdf).is_file() {
                 info!("Linking: {:?} -> {:?}", &compiled_pdf, &output_file);
                 ::symlink::symlink_file(compiled_pdf, output_file)
-                    .expect("Failed to create symlink to output file.");
+                    .map_err(FasterBeamerError::io(output_file))?;
             } else {
-                error!("Compilation failed!");
-                show_error_slide(&cachedir, output_file, compilercmd);
+                show_error_slide(&cachedir, output_file, compilercmd, &latex_args(args));
 
-                *PREVIOUS_FRAMES.lock().unwrap() = frames;
-                return Err(FasterBeamerError::CompileError);
+                return Err(FasterBeamerError::FrameCompile {
+                    frame_idx: first_changed_frame,
+                    log_excerpt: log_excerpt(&frame.log(&cache_subdir), "No PDF was written"),
+                });
             }
         }
     }
 
-    *PREVIOUS_FRAMES.lock().unwrap() = frames;
+    if args.synctex && !args.tagged {
+        // Without merging, the output is the most recently changed frame
+        let frames = if is_merged {
+            &generated_documents[..]
+        } else {
+            generated_documents
+                .get(first_changed_frame..=first_changed_frame)
+                .unwrap_or_default()
+        };
+        if let Err(err) = write_synctex(
+            &parsed_file.filename,
+            parsed_file,
+            frames,
+            cache_subdir,
+            output_file,
+        ) {
+            warn!("Failed to write the SyncTeX file of {}: {}", output_file, err);
+        }
+    }
+
+    if args.pdfpc && is_merged {
+        let frame_pages = match &united_record {
+            Some((_, _, pages)) => Some(pages.clone()),
+            None if args.pdfunite => {
+                Some(concatenated_pages(generated_documents, cache_subdir))
+            }
+            None => None,
+        };
+        match frame_pages {
+            Some(frame_pages) => {
+                if let Err(err) =
+                    write_pdfpc(Path::new(output_file), generated_documents, &frame_pages)
+                {
+                    warn!("Failed to write the pdfpc file of {}: {}", output_file, err);
+                }
+            }
+            None => warn!(
+                "Not writing the pdfpc file of {}: the pages of the frames are unknown",
+                output_file
+            ),
+        }
+    }
+
+    if let Some(text) = &args.watermark {
+        if Path::new(&output_file).is_file() {
+            let stamped = stamp_watermark(
+                Path::new(&output_file),
+                text,
+                &cache_subdir,
+                compilercmd,
+                &latex_args(args),
+            )?;
+            let _result = ::std::fs::remove_file(&output_file);
+            info!("Linking: {:?} -> {:?}", &stamped, &output_file);
+            ::symlink::symlink_file(stamped, output_file)
+                .map_err(FasterBeamerError::io(output_file))?;
+        }
+    }
+
+    if args.optimize_output && (is_merged || args.tagged) {
+        match optimize_pdf(Path::new(output_file)) {
+            Ok((size_before, size_after)) => info!(
+                "Optimized {}: {} kB -> {} kB",
+                output_file,
+                size_before / 1024,
+                size_after / 1024
+            ),
+            Err(err) => warn!("Failed to optimize {}: {}", output_file, err),
+        }
+    }
+
+    // After all changes of the united PDF
+    if let Some((united_pdf, key, pages)) = united_record {
+        if let Err(err) = write_record(&united_record_file, &key, &united_pdf, pages) {
+            warn!("Failed to remember the pages of {}: {}", output_file, err);
+        }
+    }
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::fake::FakeCompiler;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_is_complete_pdf() {
+        let dir = tempdir().unwrap();
+        let pdf = dir.path().join("frame.pdf");
+        assert!(!is_complete_pdf(&pdf));
+
+        write(&pdf, "%PDF-1.5\n1 0 obj\n<<>>\nendobj\ntrailer\n<<>>\n%%EOF\n").unwrap();
+        assert!(is_complete_pdf(&pdf));
+
+        write(&pdf, "%PDF-1.5\n1 0 obj\n<<>>\nen").unwrap();
+        assert!(!is_complete_pdf(&pdf));
+    }
+
+    #[test]
+    fn test_failed_frame_is_not_compiled() {
+        let dir = tempdir().unwrap();
+        let tex = "\\begin{frame}\\end{frame}".to_string();
+        let frame = Frame {
+            content: tex.clone(),
+            offset: 0,
+            hash: md5::compute(&tex),
+            body_line: 1,
+            tex,
+        };
+        write(
+            frame.pdf(dir.path()),
+            "%PDF-1.5\n1 0 obj\n<<>>\nendobj\ntrailer\n<<>>\n%%EOF\n",
+        )
+        .unwrap();
+        assert!(frame.is_compiled(dir.path()));
+
+        write(frame.failure_marker(dir.path()), "").unwrap();
+        assert!(!frame.is_compiled(dir.path()));
+    }
+
+    #[test]
+    fn test_unite_template() {
+        assert_eq!(
+            fill_unite_template(DEFAULT_UNITE_TEMPLATE, "abc.pdf", "frame-intro"),
+            "{\\setbeamercolor{background canvas}{bg=}\n\
+             \\includepdf[pages=-,picturecommand*={\\put(0,\\LenToUnit{\\paperheight}){\\hypertarget{frame-intro}{}}}]{abc.pdf}\n}"
+        );
+        assert_eq!(
+            fill_unite_template(
+                "\\includepdf[fitpaper]{{pdf}}% {destination}",
+                "abc.pdf",
+                "frame-1"
+            ),
+            "\\includepdf[fitpaper]{abc.pdf}% frame-1"
+        );
+    }
+
+    #[test]
+    fn test_splice_duplicated_frames() {
+        let divider = "\\begin{frame}Divider\\end{frame}";
+        let content = format!("A\n{}\nB\n{}\nC\n", divider, divider);
+        let frames: Vec<Frame> = content
+            .match_indices(divider)
+            .map(|(offset, f)| Frame {
+                content: f.to_string(),
+                offset,
+                tex: String::new(),
+                body_line: 1,
+                hash: md5::compute(f),
+            })
+            .collect();
+        assert_eq!(frames[0].hash, frames[1].hash);
+        assert_eq!(
+            splice_frames(&content, &frames, |idx, _| format!("<{}>", idx)),
+            "A\n<0>\nB\n<1>\nC\n"
+        );
+    }
+
+    #[test]
+    fn test_frame_documents() {
+        let content = "\\documentclass{beamer}\n\
+                       \\begin{document}\n\
+                       \\begin{frame}{One}\n\\end{frame}\n\
+                       \\newcommand{\\R}{\\mathbb{R}}\n\
+                       \\section{Results}\n\
+                       \\begin{frame}{Two}$\\R$\n\\end{frame}\n\
+                       \\end{document}\n";
+        let args = Options::new();
+        let (frames, offsets): (Vec<String>, Vec<usize>) = frame_ranges(content, &BTreeMap::new())
+            .into_iter()
+            .map(|(start, end)| (content[start..end].to_string(), start))
+            .unzip();
+        let documents = frame_documents(
+            frames,
+            offsets,
+            content,
+            "\\documentclass{beamer}",
+            Some("preamble"),
+            Path::new("."),
+            &args,
+        );
+        assert!(!documents[0].tex.contains("\\newcommand"));
+        assert!(!documents[0].tex.contains(CARRIED_CONTENT_BEGIN));
+        let carried = CARRIED_CONTENT_BEGIN.to_string()
+            + "\n\n\\newcommand{\\R}{\\mathbb{R}}\n\\section{Results}\n"
+            + CARRIED_CONTENT_END;
+        assert!(documents[1].tex.contains(&carried));
+        assert!(!documents[1].tex.contains("{One}"));
+
+        let body_line =
+            |frame: &Frame| frame.tex.lines().nth(frame.body_line - 1).map(String::from);
+        let frame_line = Some("\\begin{frame}{Two}$\\R$".to_string());
+        assert_eq!(body_line(&documents[1]), frame_line);
+        let injected = documents[1].inject("\\setcounter{page}{2}\n");
+        assert_eq!(body_line(&injected), frame_line);
+        assert!(injected
+            .tex
+            .contains(&(carried + "\\setcounter{page}{2}\n\\begin{frame}")));
+    }
+
+    #[test]
+    fn test_againframe_labels() {
+        let content = "\\documentclass{beamer}\n\
+                       \\begin{document}\n\
+                       \\begin{frame}<1>[label=result]\n\\label{eq:result}\n\\end{frame}\n\
+                       \\begin{frame}\n\\label{eq:other}\n\\end{frame}\n\
+                       \\againframe<2>{result}\n\
+                       \\end{document}\n";
+        let (frames, offsets): (Vec<String>, Vec<usize>) = frame_ranges(content, &BTreeMap::new())
+            .into_iter()
+            .map(|(start, end)| (content[start..end].to_string(), start))
+            .unzip();
+        let (frames, offsets) = resolve_againframes(frames, offsets);
+        assert_eq!(frames.len(), 3);
+        assert!(frames[2].contains("\\label{eq:result}"));
+        let labelled = labelled_frames(content, &frames, &offsets);
+        assert_eq!(labelled.len(), 2);
+        assert!(find_duplicate_labels(&labelled).is_empty());
+    }
+
+    #[test]
+    fn test_note_between_frames() {
+        let content = "\\documentclass{beamer}\n\
+                       \\begin{document}\n\
+                       \\begin{frame}{One}\n\\end{frame}\n\
+                       \\note{Say hello}\n\
+                       \\begin{frame}{Two}\n\\end{frame}\n\
+                       \\end{document}\n";
+        let args = Options::new().notes("show");
+        let (frames, offsets): (Vec<String>, Vec<usize>) = frame_ranges(content, &BTreeMap::new())
+            .into_iter()
+            .map(|(start, end)| (content[start..end].to_string(), start))
+            .unzip();
+        let documents = frame_documents(
+            frames,
+            offsets,
+            content,
+            "\\documentclass{beamer}",
+            None,
+            Path::new("."),
+            &args,
+        );
+        // The note page is shipped out before the frame, and discarded
+        let carried =
+            CARRIED_CONTENT_BEGIN.to_string() + "\n\n\\note{Say hello}\n" + CARRIED_CONTENT_END;
+        assert!(documents[1]
+            .tex
+            .contains(&(carried + "\\begin{frame}{Two}")));
+        assert!(CARRIED_CONTENT_BEGIN.contains("\\fasterbeamer@discardtrue"));
+        let end = CARRIED_CONTENT_END;
+        assert!(end.find("\\clearpage") < end.find("\\fasterbeamer@discardfalse"));
+        assert!(!documents[0].tex.contains("\\note"));
+    }
+
+    #[test]
+    fn test_compile_with_fake_compiler() {
+        let dir = tempdir().unwrap();
+        let cachedir = dir.path().join("cache");
+        let input_file = dir.path().join("talk.tex");
+        let input_file = input_file.to_str().unwrap();
+        let fixture = std::fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/talk.tex"),
+        )
+        .unwrap();
+        write(input_file, &fixture).unwrap();
+
+        let args = Options::new();
+        let merge_args = Options::new().pdfunite();
+        let output_file = dir.path().join("talk.pdf");
+        let output_file = output_file.to_str().unwrap();
+        let compiler = FakeCompiler::default();
+        let build = || {
+            compiler.clear();
+            let extracted = load_frames(input_file, &args).ok().unwrap();
+            compile_extracted_with(&compiler, &cachedir, input_file, extracted, &args, None)
+                .ok()
+                .unwrap()
+        };
+
+        // The preamble and all frames
+        let compiled = build();
+        assert_eq!(compiler.num_compiled(), 4);
+        assert!(compiled
+            .frames
+            .iter()
+            .all(|f| f.is_compiled(&compiled.cache_subdir)));
+        let page_map = build_page_map(&compiled.frames, &compiled.cache_subdir);
+        assert_eq!(page_map[1].label.as_deref(), Some("two"));
+        assert_eq!((page_map[2].first_page, page_map[2].last_page), (4, 4));
+        // Merged with the builtin merger, which needs no TeX either
+        let num_merged_pages = |compiled: &CompiledFrames| {
+            write_output(compiled, &merge_args, output_file, 0).unwrap();
+            lopdf::Document::load(output_file)
+                .unwrap()
+                .get_pages()
+                .len()
+        };
+        assert_eq!(num_merged_pages(&compiled), 4);
+
+        // Nothing changed
+        build();
+        assert_eq!(compiler.num_compiled(), 0);
+
+        // Only the changed frame
+        write(
+            input_file,
+            fixture.replace("Third frame", "Changed \\pause"),
+        )
+        .unwrap();
+        let compiled = build();
+        assert_eq!(compiler.num_compiled(), 1);
+        assert_eq!(num_merged_pages(&compiled), 5);
+
+        // Failed frames are compiled again in the next build
+        write(input_file, fixture.replace("Third frame", "\\fakeerror")).unwrap();
+        let compiled = build();
+        assert_eq!(compiler.num_compiled(), 1);
+        assert!(!compiled.frames[2].is_compiled(&compiled.cache_subdir));
+        build();
+        assert_eq!(compiler.num_compiled(), 1);
+    }
+}
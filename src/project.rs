@@ -0,0 +1,159 @@
+//
+// project.rs
+// Distributed under terms of the GPLv3 license.
+//
+// Multi-file presentations are built from their main document. When faster-beamer is invoked on
+// a file that is `\input` by another document, the main document is found by (in this order)
+// a magic `% !TeX root = main.tex` comment, `@default_files` of a latexmkrc, or by searching the
+// parent directories for a document that includes the file.
+//
+
+use crate::batch::Job;
+use regex::Regex;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+lazy_static! {
+    static ref MAGIC_ROOT_REGEX: Regex =
+        Regex::new(r"(?mi)^\s*%\s*!\s*TeX\s+root\s*=\s*(.+?)\s*$").unwrap();
+}
+lazy_static! {
+    static ref DEFAULT_FILES_REGEX: Regex =
+        Regex::new(r#"@default_files\s*=\s*\(\s*['"]([^'"]+)['"]"#).unwrap();
+}
+lazy_static! {
+    static ref INCLUDE_REGEX: Regex =
+        Regex::new(r"(?m)^[^%\n]*?\\(?:input|include|subfile)\s*\{([^}]*)\}").unwrap();
+}
+lazy_static! {
+    static ref DOCUMENTCLASS_REGEX: Regex = Regex::new(r"(?m)^[^%\n]*\\documentclass").unwrap();
+}
+
+/// How many parent directories are searched for a latexmkrc or the main document.
+const MAX_SEARCH_DEPTH: usize = 4;
+
+/// Returns the root given by a `% !TeX root = ...` comment in the first lines of `content`.
+pub fn magic_root(content: &str) -> Option<String> {
+    let head: String = content.lines().take(20).collect::<Vec<_>>().join("\n");
+    MAGIC_ROOT_REGEX
+        .captures(&head)
+        .map(|c| c[1].to_string())
+}
+
+/// Returns the first file of `@default_files` in a latexmkrc.
+pub fn latexmkrc_root(content: &str) -> Option<String> {
+    DEFAULT_FILES_REGEX
+        .captures(content)
+        .map(|c| c[1].to_string())
+}
+
+/// Whether `content` includes the file at `relative_path` (relative to the including document)
+/// by `\input`, `\include` or `\subfile`.
+pub fn includes_file(content: &str, relative_path: &str) -> bool {
+    let normalize = |path: &str| {
+        let path = path.trim().trim_start_matches("./").replace('\\', "/");
+        path.strip_suffix(".tex").map(|p| p.to_string()).unwrap_or(path)
+    };
+    let relative_path = normalize(relative_path);
+    INCLUDE_REGEX
+        .captures_iter(content)
+        .any(|c| normalize(&c[1]) == relative_path)
+}
+
+fn is_root_document(content: &str) -> bool {
+    DOCUMENTCLASS_REGEX.is_match(content)
+}
+
+/// Finds the main document of `file` if `file` is not a standalone document itself.
+pub fn find_root(file: &Path) -> Option<PathBuf> {
+    let file = file.canonicalize().ok()?;
+    let content = fs::read_to_string(&file).ok()?;
+    if is_root_document(&content) {
+        return None;
+    }
+
+    if let Some(root) = magic_root(&content) {
+        let root = file.parent()?.join(root);
+        if root.is_file() {
+            return Some(root);
+        }
+        warn!("TeX root {} of {} does not exist", root.display(), file.display());
+    }
+
+    let search_dirs: Vec<&Path> = file.ancestors().skip(1).take(MAX_SEARCH_DEPTH).collect();
+    for dir in search_dirs.iter() {
+        for latexmkrc in &["latexmkrc", ".latexmkrc"] {
+            let root = fs::read_to_string(dir.join(latexmkrc))
+                .ok()
+                .and_then(|content| latexmkrc_root(&content))
+                .map(|root| dir.join(root));
+            match root {
+                Some(root) if root.is_file() && root != file => return Some(root),
+                _ => {}
+            }
+        }
+    }
+
+    for dir in search_dirs.iter() {
+        let relative_path = file.strip_prefix(dir).ok()?.to_string_lossy().into_owned();
+        let mut candidates: Vec<PathBuf> = match fs::read_dir(dir) {
+            Ok(entries) => entries
+                .filter_map(|e| e.ok())
+                .map(|e| e.path())
+                .filter(|p| p.extension().map_or(false, |e| e == "tex") && *p != file)
+                .collect(),
+            Err(_) => continue,
+        };
+        candidates.sort();
+        for candidate in candidates {
+            let is_root = fs::read_to_string(&candidate)
+                .map(|c| is_root_document(&c) && includes_file(&c, &relative_path))
+                .unwrap_or(false);
+            if is_root {
+                return Some(candidate);
+            }
+        }
+    }
+    None
+}
+
+/// Replaces the input of `job` by its main document (if it has one). The original input is kept
+/// as `edited_file` so that its frames can be compiled first.
+pub fn resolve_root(job: Job) -> Job {
+    match find_root(Path::new(&job.input_file)) {
+        Some(root) => {
+            let root = root.to_string_lossy().into_owned();
+            info!("{} is part of {}", job.input_file, root);
+            Job {
+                edited_file: Some(job.input_file),
+                input_file: root,
+                ..job
+            }
+        }
+        None => job,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_root_detection() {
+        assert_eq!(
+            magic_root("% !TeX root = ../main.tex\n\\begin{frame}\n\\end{frame}"),
+            Some("../main.tex".to_string())
+        );
+        assert_eq!(magic_root("\\begin{frame}\n\\end{frame}"), None);
+        assert_eq!(
+            latexmkrc_root("$pdf_mode = 1;\n@default_files = ('talk.tex');\n"),
+            Some("talk.tex".to_string())
+        );
+
+        let main = "\\documentclass{beamer}\n\\begin{document}\n\\input{sections/intro}\n\
+                    % \\input{sections/old}\n\\end{document}\n";
+        assert!(includes_file(main, "sections/intro.tex"));
+        assert!(!includes_file(main, "sections/old.tex"));
+        assert!(!includes_file(main, "intro.tex"));
+    }
+}
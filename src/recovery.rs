@@ -0,0 +1,199 @@
+//
+// recovery.rs
+// Distributed under terms of the GPLv3 license.
+//
+// A build that crashes (or is killed) can leave incomplete PDFs and formats in the cache and an
+// output link to a missing file. Files in the cache are marked while they are written, so that
+// these leftovers can be told apart from files that another process is still writing and
+// removed when faster-beamer starts (see `recover` and `recover_cache`), so that the next build
+// compiles them again and relinks the output.
+//
+
+use crate::batch::Job;
+use crate::process_file::{get_cache_subdir, get_cachedir, get_input_dir, is_complete_pdf};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+const MARKER_SUFFIX: &str = ".building";
+
+/// Markers older than this are stale on systems where processes cannot be looked up.
+const MAX_MARKER_AGE: Duration = Duration::from_secs(3600);
+
+fn marker_path(path: &Path) -> PathBuf {
+    let mut marker = path.as_os_str().to_owned();
+    marker.push(MARKER_SUFFIX);
+    PathBuf::from(marker)
+}
+
+/// Marks a file in the cache as being written by this process until it is dropped.
+pub struct InProgress {
+    marker: PathBuf,
+}
+
+impl InProgress {
+    pub fn new(path: &Path) -> InProgress {
+        let marker = marker_path(path);
+        if let Err(err) = fs::write(&marker, std::process::id().to_string()) {
+            debug!("Failed to write {}: {}", marker.display(), err);
+        }
+        InProgress { marker }
+    }
+}
+
+impl Drop for InProgress {
+    fn drop(&mut self) {
+        let _result = fs::remove_file(&self.marker);
+    }
+}
+
+/// Whether the process that wrote `marker` is gone.
+fn is_stale(marker: &Path) -> bool {
+    if cfg!(target_os = "linux") {
+        if let Ok(pid) = fs::read_to_string(marker) {
+            return !Path::new("/proc").join(pid.trim()).exists();
+        }
+    }
+    fs::metadata(marker)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|time| SystemTime::now().duration_since(time).ok())
+        .map_or(true, |age| age > MAX_MARKER_AGE)
+}
+
+/// Removes the leftovers of crashed builds from `cache_subdir`: files whose writer is gone and
+/// incomplete PDFs. Returns the number of removed files.
+pub fn clean_cache_dir(cache_subdir: &Path) -> usize {
+    let entries = match fs::read_dir(cache_subdir) {
+        Ok(entries) => entries,
+        Err(_) => return 0,
+    };
+    let mut num_removed = 0;
+    for path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+        let name = path.to_string_lossy();
+        if name.ends_with(MARKER_SUFFIX) {
+            if is_stale(&path) {
+                let written = PathBuf::from(&name[..name.len() - MARKER_SUFFIX.len()]);
+                debug!("Removing {} of a crashed build", written.display());
+                if fs::remove_file(&written).is_ok() {
+                    num_removed += 1;
+                }
+                let _result = fs::remove_file(&path);
+            }
+        } else if path.extension().map_or(false, |e| e == "pdf")
+            // Files of the input directory are linked into the cache
+            && fs::symlink_metadata(&path).map_or(false, |m| m.file_type().is_file())
+            && !is_complete_pdf(&path)
+            && !marker_path(&path).exists()
+            && fs::remove_file(&path).is_ok()
+        {
+            debug!("Removed incomplete {}", path.display());
+            num_removed += 1;
+        }
+    }
+    num_removed
+}
+
+/// Removes the leftovers of crashed builds from `dir` and its subdirectories. Returns the number
+/// of removed files.
+fn clean_cache_tree(dir: &Path) -> usize {
+    let mut num_removed = clean_cache_dir(dir);
+    for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+        // Directories of the input directory are linked into the cache
+        if entry.file_type().map_or(false, |t| t.is_dir()) {
+            num_removed += clean_cache_tree(&entry.path());
+        }
+    }
+    num_removed
+}
+
+/// Repairs the cache and the output of `job` after a crashed build.
+fn recover_job(job: &Job) {
+    let mut num_repaired = match get_cachedir() {
+        Ok(cachedir) => clean_cache_dir(&get_cache_subdir(
+            &cachedir,
+            &get_input_dir(&job.input_file),
+        )),
+        Err(_) => 0,
+    };
+
+    // A link to a missing or incomplete PDF (the build relinks a valid one)
+    let output = Path::new(&job.output_file);
+    let is_link = fs::symlink_metadata(output).map_or(false, |m| m.file_type().is_symlink());
+    if (is_link || output.is_file()) && !is_complete_pdf(output) {
+        warn!("Removing broken output {}", job.output_file);
+        if fs::remove_file(output).is_ok() {
+            num_repaired += 1;
+        }
+    }
+
+    if num_repaired > 0 {
+        warn!(
+            "Repaired {} leftovers of a crashed build of {}",
+            num_repaired, job.input_file
+        );
+    }
+}
+
+/// Repairs the caches and the outputs of `jobs` after crashed builds. Runs once at startup,
+/// before the first build of `jobs`.
+pub fn recover(jobs: &[Job]) {
+    for job in jobs {
+        recover_job(job);
+    }
+}
+
+/// Repairs the whole cache after crashed builds, for servers (e.g. `faster-beamer daemon`) that do
+/// not know at startup which documents they will build.
+pub fn recover_cache() {
+    let cachedir = match get_cachedir() {
+        Ok(cachedir) => cachedir,
+        Err(_) => return,
+    };
+    let num_repaired = clean_cache_tree(&cachedir);
+    if num_repaired > 0 {
+        warn!(
+            "Repaired {} leftovers of crashed builds in {}",
+            num_repaired,
+            cachedir.display()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_clean_cache_dir() {
+        let dir = tempdir().unwrap();
+        let pdf = |name: &str| dir.path().join(name);
+        let complete = "%PDF-1.5\n1 0 obj\n<<>>\nendobj\ntrailer\n<<>>\n%%EOF\n";
+        fs::write(pdf("complete.pdf"), complete).unwrap();
+        fs::write(pdf("partial.pdf"), "%PDF-1.5\n1 0 obj\n").unwrap();
+        // Being written by this process
+        fs::write(pdf("writing.pdf"), "%PDF-1.5\n").unwrap();
+        let _in_progress = InProgress::new(&pdf("writing.pdf"));
+
+        assert_eq!(clean_cache_dir(dir.path()), 1);
+        assert!(pdf("complete.pdf").is_file());
+        assert!(!pdf("partial.pdf").exists());
+        assert!(pdf("writing.pdf").is_file());
+
+        if cfg!(target_os = "linux") {
+            // Written by a process that is gone
+            fs::write(pdf("preamble.fmt"), "").unwrap();
+            fs::write(marker_path(&pdf("preamble.fmt")), "4294967295").unwrap();
+            assert_eq!(clean_cache_dir(dir.path()), 1);
+            assert!(!pdf("preamble.fmt").exists());
+            assert!(!marker_path(&pdf("preamble.fmt")).exists());
+        }
+
+        fs::create_dir(pdf("talks")).unwrap();
+        fs::write(pdf("talks/partial.pdf"), "%PDF-1.5\n").unwrap();
+        assert_eq!(clean_cache_tree(dir.path()), 1);
+        assert!(!pdf("talks/partial.pdf").exists());
+        assert!(pdf("writing.pdf").is_file());
+    }
+}
@@ -0,0 +1,320 @@
+//
+// remote.rs
+// Distributed under terms of the GPLv3 license.
+//
+// With `--remote HOST` (repeatable), frames are compiled on remote workers over SSH. The files
+// that a frame needs (its document, the precompiled preamble and the files of the input
+// directory) are copied with rsync into a directory of the worker that mirrors the cache
+// directory of the frame, and the PDF and log are copied back. Workers take turns, and a frame
+// whose worker cannot be reached is compiled locally. The workers need the same TeX distribution
+// as the local machine, since the preamble is precompiled locally.
+//
+
+use crate::compiler::{latexmk_args, tectonic_args, Compiler, Engine};
+use crate::latexcompile::LatexError;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Directory of the frames on the workers (relative to the home directory) without
+/// `--remote-dir`.
+pub const DEFAULT_REMOTE_DIR: &str = ".cache/faster-beamer-remote";
+
+/// Quotes `arg` for the shell of a worker.
+pub fn shell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+/// Whether `key` can be assigned in the shell of a worker (`[A-Za-z_][A-Za-z0-9_]*`).
+pub fn is_variable_name(key: &str) -> bool {
+    let mut chars = key.chars();
+    chars
+        .next()
+        .map_or(false, |c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Whether `worker` can be passed to ssh and rsync as a host (and not as an option).
+pub fn is_valid_host(worker: &str) -> bool {
+    !worker.is_empty() && !worker.starts_with('-')
+}
+
+/// Files in `working_dir` (relative to it) that the compile of `tex_file` needs: the document
+/// and the other files of the frame (e.g. a shared `.bbl`), its precompiled format, and the links
+/// to the files of the input directory. Hidden files (e.g. `.git`) are left out.
+pub fn files_to_send(tex_file: &Path, working_dir: &Path) -> Vec<String> {
+    let stem = tex_file
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let mut files = Vec::new();
+    let format = fs::read_to_string(working_dir.join(tex_file.file_name().unwrap_or_default()))
+        .ok()
+        .and_then(|tex| {
+            tex.lines()
+                .next()
+                .and_then(|line| line.strip_prefix("%&"))
+                .map(|name| format!("{}.fmt", name))
+        });
+    files.extend(format);
+    let mut entries: Vec<_> = fs::read_dir(working_dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .collect();
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let is_link = entry.file_type().map_or(false, |t| t.is_symlink());
+        // Neither the PDF nor the marker of the compile in progress (`.pdf.building`)
+        let is_frame_file = name.starts_with(&format!("{}.", stem)) && !name.contains(".pdf");
+        if !name.starts_with('.') && (is_link || is_frame_file) && !files.contains(&name) {
+            files.push(name);
+        }
+    }
+    files
+}
+
+/// Compiler that compiles frames on the SSH hosts `workers`, in turns. The preamble is
+/// precompiled with `local`, which also compiles frames whose worker cannot be reached.
+pub struct RemoteCompiler {
+    pub workers: Vec<String>,
+    /// Directory of the frames on the workers
+    pub remote_dir: String,
+    /// Command that compiles a frame on the workers (e.g. pdflatex or latexmk)
+    pub command: String,
+    /// Engine that latexmk runs, if `command` is latexmk
+    pub latexmk_engine: Option<String>,
+    pub local: Box<dyn Compiler>,
+    next_worker: AtomicUsize,
+}
+
+impl RemoteCompiler {
+    pub fn new(
+        workers: Vec<String>,
+        remote_dir: &str,
+        command: &str,
+        latexmk_engine: Option<&str>,
+        local: Box<dyn Compiler>,
+    ) -> RemoteCompiler {
+        RemoteCompiler {
+            workers,
+            remote_dir: remote_dir.to_string(),
+            command: command.to_string(),
+            latexmk_engine: latexmk_engine.map(|engine| engine.to_string()),
+            local,
+            next_worker: AtomicUsize::new(0),
+        }
+    }
+
+    /// Shell command that compiles `tex_name` in `remote_dir` on a worker.
+    fn command_line(
+        &self,
+        tex_name: &str,
+        remote_dir: &str,
+        args: &[String],
+        env: &BTreeMap<String, String>,
+    ) -> String {
+        let args = match (&self.latexmk_engine, Engine::of(&self.command)) {
+            (Some(engine), _) => latexmk_args(engine, args),
+            (None, Engine::Tectonic) => tectonic_args(args, Path::new(".")),
+            (None, _) => args.to_vec(),
+        };
+        let mut command_line = format!("cd {} &&", shell_quote(remote_dir));
+        for (key, value) in env {
+            if is_variable_name(key) {
+                command_line += &format!(" {}={}", key, shell_quote(value));
+            } else {
+                warn!("Ignoring the variable {:?} on the workers", key);
+            }
+        }
+        command_line += &format!(" {}", shell_quote(&self.command));
+        for arg in args.iter().map(String::as_str).chain(Some(tex_name)) {
+            command_line += &format!(" {}", shell_quote(arg));
+        }
+        command_line
+    }
+
+    /// Compiles `tex_file` on `worker`. Err if the worker cannot be reached, otherwise the result
+    /// of the compiler.
+    fn compile_on(
+        &self,
+        worker: &str,
+        tex_file: &Path,
+        working_dir: &Path,
+        args: &[String],
+        env: &BTreeMap<String, String>,
+    ) -> Result<Result<(), LatexError>, String> {
+        if !is_valid_host(worker) {
+            return Err(format!("Invalid host {:?}", worker));
+        }
+        let tex_name = tex_file
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let stem = tex_file
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let remote_dir = format!(
+            "{}/{:x}",
+            self.remote_dir,
+            md5::compute(working_dir.to_string_lossy().as_bytes())
+        );
+
+        let mut rsync = Command::new("rsync")
+            .args(&["-a", "-r", "--copy-links", "--files-from=-", "--rsync-path"])
+            .arg(format!("mkdir -p {} && rsync", shell_quote(&remote_dir)))
+            .arg("--")
+            .arg(format!("{}/", working_dir.display()))
+            .arg(format!("{}:{}/", worker, remote_dir))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Failed to run rsync: {}", e))?;
+        if let Some(mut stdin) = rsync.stdin.take() {
+            let _result =
+                stdin.write_all(files_to_send(tex_file, working_dir).join("\n").as_bytes());
+        }
+        let sent = rsync
+            .wait_with_output()
+            .map_err(|e| format!("Failed to run rsync: {}", e))?;
+        if !sent.status.success() {
+            return Err(String::from_utf8_lossy(&sent.stderr).into_owned());
+        }
+
+        let output = Command::new("ssh")
+            .arg("--")
+            .arg(worker)
+            .arg(self.command_line(&tex_name, &remote_dir, args, env))
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|e| format!("Failed to run ssh: {}", e))?;
+        // ssh exits with 255 if the connection fails
+        if output.status.code() == Some(255) {
+            return Err(String::from_utf8_lossy(&output.stderr).into_owned());
+        }
+
+        let fetched = Command::new("rsync")
+            .arg("-a")
+            .arg(format!("--include={}.*", stem))
+            .arg("--exclude=*")
+            .arg("--")
+            .arg(format!("{}:{}/", worker, remote_dir))
+            .arg(format!("{}/", working_dir.display()))
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|e| format!("Failed to run rsync: {}", e))?;
+        if !fetched.status.success() {
+            return Err(String::from_utf8_lossy(&fetched.stderr).into_owned());
+        }
+
+        if output.status.success() {
+            Ok(Ok(()))
+        } else {
+            let err_msg = String::from_utf8_lossy(&output.stderr).into_owned();
+            error!("{}", &err_msg);
+            error!("{}", String::from_utf8_lossy(&output.stdout));
+            Ok(Err(LatexError::LatexError(err_msg)))
+        }
+    }
+}
+
+impl Compiler for RemoteCompiler {
+    fn precompile_format(
+        &self,
+        preamble_file: &Path,
+        jobname: &str,
+        working_dir: &Path,
+        args: &[String],
+        env: &BTreeMap<String, String>,
+    ) -> Result<(), LatexError> {
+        self.local
+            .precompile_format(preamble_file, jobname, working_dir, args, env)
+    }
+
+    fn compile(
+        &self,
+        tex_file: &Path,
+        working_dir: &Path,
+        args: &[String],
+        env: &BTreeMap<String, String>,
+    ) -> Result<(), LatexError> {
+        let worker =
+            &self.workers[self.next_worker.fetch_add(1, Ordering::Relaxed) % self.workers.len()];
+        match self.compile_on(worker, tex_file, working_dir, args, env) {
+            Ok(result) => result,
+            Err(err) => {
+                warn!(
+                    "Compiling {} locally, as worker {} failed: {}",
+                    tex_file.display(),
+                    worker,
+                    err.trim()
+                );
+                self.local.compile(tex_file, working_dir, args, env)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::TexCompiler;
+
+    #[test]
+    fn test_files_to_send() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = tempfile::tempdir().unwrap();
+        fs::write(input.path().join("figure.pdf"), "").unwrap();
+        symlink::symlink_file(
+            input.path().join("figure.pdf"),
+            dir.path().join("figure.pdf"),
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("abc.tex"),
+            "%&preamble\n\\begin{frame}\\end{frame}\n",
+        )
+        .unwrap();
+        fs::write(dir.path().join("abc.bbl"), "").unwrap();
+        fs::write(dir.path().join("abc.pdf"), "").unwrap();
+        fs::write(dir.path().join("def.tex"), "").unwrap();
+        assert_eq!(
+            files_to_send(Path::new("abc.tex"), dir.path()),
+            vec!["preamble.fmt", "abc.bbl", "abc.tex", "figure.pdf"]
+        );
+    }
+
+    #[test]
+    fn test_command_line() {
+        let compiler = RemoteCompiler::new(
+            vec!["worker".to_string()],
+            DEFAULT_REMOTE_DIR,
+            "pdflatex",
+            None,
+            Box::new(TexCompiler::new("pdflatex")),
+        );
+        let mut env = BTreeMap::new();
+        env.insert("TEXINPUTS".to_string(), "./styles//:".to_string());
+        env.insert("$(reboot)".to_string(), "".to_string());
+        assert_eq!(
+            compiler.command_line(
+                "abc.tex",
+                "frames",
+                &["-interaction=nonstopmode".to_string()],
+                &env
+            ),
+            "cd 'frames' && TEXINPUTS='./styles//:' 'pdflatex' '-interaction=nonstopmode' 'abc.tex'"
+        );
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+        assert!(is_variable_name("_TEXINPUTS2"));
+        assert!(!is_variable_name("2X") && !is_variable_name("A;B") && !is_variable_name(""));
+        assert!(is_valid_host("user@build-server"));
+        assert!(!is_valid_host("-oProxyCommand=reboot"));
+    }
+}
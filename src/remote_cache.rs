@@ -0,0 +1,270 @@
+//
+// remote_cache.rs
+// Distributed under terms of the GPLv3 license.
+//
+// With `--remote-cache URL`, the PDFs of frames (and the files that later passes read, e.g. `.aux`
+// and `.nav`) are shared through a server under the hashes of the frames, so that CI and
+// teammates download frames that someone else already compiled instead of compiling them. The
+// files are transferred with curl: GET and PUT for HTTP(S) servers and WebDAV, and S3 with
+// `s3://bucket/prefix` and the credentials of the usual AWS environment variables.
+//
+
+use crate::compiler::Compiler;
+use crate::latexcompile::LatexError;
+use crate::process_file::is_complete_pdf;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+/// Files of a frame that are shared (besides its PDF), if the compile wrote them
+pub const SHARED_EXTENSIONS: &[&str] = &[
+    "log",
+    "aux",
+    "nav",
+    "synctex.gz",
+    "idx",
+    "glo",
+    "acn",
+    "ist",
+];
+
+/// Region of S3 buckets without `AWS_REGION`
+pub const DEFAULT_S3_REGION: &str = "us-east-1";
+
+/// Quotes `value` for a configuration file of curl.
+fn curl_config_quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Server of the shared cache.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RemoteCache {
+    /// URL of the directory of the files (HTTP(S))
+    pub url: String,
+    /// Arguments of curl for the authentication (e.g. the signature of S3 requests)
+    pub auth_args: Vec<String>,
+    /// Configuration of curl with the credentials, which is passed on its standard input, since
+    /// other users can see its arguments
+    pub auth_config: Option<String>,
+    /// Only downloads frames (e.g. without write access to the server)
+    pub read_only: bool,
+}
+
+impl RemoteCache {
+    /// Cache at `url`: an HTTP(S) or WebDAV URL, or `s3://bucket/prefix` (signed with
+    /// `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY` in the region `AWS_REGION`). Credentials of
+    /// other servers are read from `~/.netrc`.
+    pub fn new(url: &str, read_only: bool) -> RemoteCache {
+        let url = url.trim_end_matches('/');
+        match url.strip_prefix("s3://") {
+            Some(path) => {
+                let region =
+                    env::var("AWS_REGION").unwrap_or_else(|_| DEFAULT_S3_REGION.to_string());
+                let credentials = env::var("AWS_ACCESS_KEY_ID")
+                    .and_then(|key| Ok((key, env::var("AWS_SECRET_ACCESS_KEY")?)))
+                    .ok();
+                let credentials = credentials
+                    .as_ref()
+                    .map(|(key, secret)| (key.as_str(), secret.as_str()));
+                RemoteCache::new_s3(path, &region, credentials, read_only)
+            }
+            None => RemoteCache {
+                url: url.to_string(),
+                auth_args: vec!["--netrc-optional".to_string()],
+                auth_config: None,
+                read_only,
+            },
+        }
+    }
+
+    /// Cache at `path` (`bucket/prefix`) of S3 in `region`, whose requests are signed with the
+    /// access key and secret of `credentials` (if any).
+    pub fn new_s3(
+        path: &str,
+        region: &str,
+        credentials: Option<(&str, &str)>,
+        read_only: bool,
+    ) -> RemoteCache {
+        let path = path.trim_end_matches('/');
+        let (bucket, prefix) = match path.find('/') {
+            Some(slash) => (&path[..slash], &path[slash..]),
+            None => (path, ""),
+        };
+        let mut auth_args = Vec::new();
+        let mut auth_config = None;
+        if let Some((key, secret)) = credentials {
+            auth_args.push("--aws-sigv4".to_string());
+            auth_args.push(format!("aws:amz:{}:s3", region));
+            auth_config = Some(format!(
+                "user = {}\n",
+                curl_config_quote(&format!("{}:{}", key, secret))
+            ));
+        }
+        RemoteCache {
+            url: format!("https://{}.s3.{}.amazonaws.com{}", bucket, region, prefix),
+            auth_args,
+            auth_config,
+            read_only,
+        }
+    }
+
+    fn curl(&self) -> Command {
+        let mut command = Command::new("curl");
+        command
+            .args(&["--fail", "--silent", "--show-error", "--location"])
+            .args(&self.auth_args);
+        if self.auth_config.is_some() {
+            command.args(&["--config", "-"]);
+        }
+        command
+    }
+
+    /// Runs the `command` of `curl`, with the configuration of the authentication on its input.
+    fn run(&self, command: &mut Command) -> io::Result<Output> {
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            let config = self.auth_config.as_deref().unwrap_or_default();
+            stdin.write_all(config.as_bytes())?;
+        }
+        child.wait_with_output()
+    }
+
+    /// Downloads the file `name` to `file`. Whether the server has it.
+    pub fn download(&self, name: &str, file: &Path) -> bool {
+        let output = self.run(
+            self.curl()
+                .arg("--output")
+                .arg(file)
+                .arg(format!("{}/{}", self.url, name)),
+        );
+        match output {
+            Ok(output) if output.status.success() => true,
+            Ok(_) => {
+                let _result = fs::remove_file(file);
+                false
+            }
+            Err(err) => {
+                warn!("Failed to run curl for the remote cache: {}", err);
+                false
+            }
+        }
+    }
+
+    /// Uploads `file` as `name`.
+    pub fn upload(&self, file: &Path, name: &str) {
+        let output = self.run(
+            self.curl()
+                .arg("--upload-file")
+                .arg(file)
+                .arg(format!("{}/{}", self.url, name)),
+        );
+        match output {
+            Ok(output) if output.status.success() => {}
+            Ok(output) => warn!(
+                "Failed to upload {} to the remote cache: {}",
+                name,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+            Err(err) => warn!("Failed to run curl for the remote cache: {}", err),
+        }
+    }
+}
+
+/// Compiler that downloads frames from the `cache` and compiles the others with `compiler`,
+/// uploading them afterwards.
+pub struct RemoteCacheCompiler {
+    pub cache: RemoteCache,
+    pub compiler: Box<dyn Compiler>,
+}
+
+impl Compiler for RemoteCacheCompiler {
+    fn precompile_format(
+        &self,
+        preamble_file: &Path,
+        jobname: &str,
+        working_dir: &Path,
+        args: &[String],
+        env: &BTreeMap<String, String>,
+    ) -> Result<(), LatexError> {
+        self.compiler
+            .precompile_format(preamble_file, jobname, working_dir, args, env)
+    }
+
+    fn compile(
+        &self,
+        tex_file: &Path,
+        working_dir: &Path,
+        args: &[String],
+        env: &BTreeMap<String, String>,
+    ) -> Result<(), LatexError> {
+        // Documents of frames are named after their hash
+        let hash = match tex_file.file_stem() {
+            Some(stem) => stem.to_string_lossy().into_owned(),
+            None => return self.compiler.compile(tex_file, working_dir, args, env),
+        };
+        let pdf_name = format!("{}.pdf", hash);
+        let pdf = working_dir.join(&pdf_name);
+        if self.cache.download(&pdf_name, &pdf) && is_complete_pdf(&pdf) {
+            debug!("Downloaded {} from the remote cache", pdf_name);
+            for extension in SHARED_EXTENSIONS {
+                let name = format!("{}.{}", hash, extension);
+                self.cache.download(&name, &working_dir.join(&name));
+            }
+            return Ok(());
+        }
+        let _result = fs::remove_file(&pdf);
+
+        self.compiler.compile(tex_file, working_dir, args, env)?;
+        if !self.cache.read_only && is_complete_pdf(&pdf) {
+            for extension in SHARED_EXTENSIONS {
+                let name = format!("{}.{}", hash, extension);
+                let file = working_dir.join(&name);
+                if file.is_file() {
+                    self.cache.upload(&file, &name);
+                }
+            }
+            // The PDF last, so that a frame is only downloaded once all its files are there
+            self.cache.upload(&pdf, &pdf_name);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remote_cache_url() {
+        let cache = RemoteCache::new("https://cache.example.org/frames/", true);
+        assert_eq!(cache.url, "https://cache.example.org/frames");
+        assert_eq!(cache.auth_args, vec!["--netrc-optional"]);
+        assert_eq!(cache.auth_config, None);
+
+        let cache = RemoteCache::new_s3(
+            "slides/faster-beamer/",
+            "eu-central-1",
+            Some(("AKIA", "se\"cret")),
+            false,
+        );
+        assert_eq!(
+            cache.url,
+            "https://slides.s3.eu-central-1.amazonaws.com/faster-beamer"
+        );
+        assert_eq!(
+            cache.auth_args,
+            vec!["--aws-sigv4", "aws:amz:eu-central-1:s3"]
+        );
+        assert_eq!(
+            cache.auth_config.as_deref(),
+            Some("user = \"AKIA:se\\\"cret\"\n")
+        );
+    }
+}
@@ -0,0 +1,226 @@
+//
+// status.rs
+// Distributed under terms of the GPLv3 license.
+//
+
+use crate::beamer::{frame_label, frame_title};
+use crate::logs::read_build_record;
+use crate::options::Options;
+use crate::page_map::build_page_map;
+use crate::preamble::precompiled_format;
+use crate::process_file::{
+    compiler_environment, format_name, frame_at_line, frame_documents, frame_preamble,
+    get_cache_subdir, get_cachedir, get_input_dir, hashed_dependencies, load_frames,
+    FasterBeamerError, Frame, Result,
+};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// How long ago something happened, e.g. "5 min ago".
+pub fn format_age(age: Duration) -> String {
+    let seconds = age.as_secs();
+    match seconds {
+        0..=59 => format!("{} s ago", seconds),
+        60..=3599 => format!("{} min ago", seconds / 60),
+        3600..=86399 => format!("{} h ago", seconds / 3600),
+        _ => format!("{} days ago", seconds / 86400),
+    }
+}
+
+/// What the output file `output_file` currently is (e.g. a link to the PDF of a frame).
+fn describe_output(output_file: &str, frames: &[Frame], cache_subdir: &Path) -> String {
+    let target = match fs::read_link(output_file) {
+        Ok(target) => target,
+        Err(_) if Path::new(output_file).is_file() => return "a file".to_string(),
+        Err(_) => return "missing".to_string(),
+    };
+    let description = if !target.is_file() {
+        "a dangling link".to_string()
+    } else if target.file_name().map_or(false, |name| name == "error.pdf") {
+        "the error slide".to_string()
+    } else if let Some(frame_idx) = frames.iter().position(|f| f.pdf(cache_subdir) == target) {
+        format!("frame {}", frame_idx)
+    } else {
+        let name = target.file_name().unwrap_or_default().to_string_lossy();
+        if name.starts_with("united-") {
+            "the united PDF".to_string()
+        } else {
+            "an older build".to_string()
+        }
+    };
+    format!("{} ({})", description, target.display())
+}
+
+/// Frames of `input_file` as they are compiled, with the directory of the cache and the name of
+/// the precompiled preamble.
+pub fn cached_frames(input_file: &str, args: &Options) -> Result<(Vec<Frame>, PathBuf, String)> {
+    let extracted = load_frames(input_file, args)?;
+    let input_dir = get_input_dir(input_file);
+    let cache_subdir = get_cache_subdir(&get_cachedir()?, &input_dir);
+
+    let preamble = frame_preamble(
+        &extracted.parsed_file.file_content,
+        args,
+        args.aspectratio.as_deref(),
+    );
+    let dependencies = hashed_dependencies(&preamble, input_file);
+    let compiler_env = compiler_environment(input_file, args);
+    let preamble_filename = format_name(&preamble, &dependencies, &compiler_env, args);
+    let frames = frame_documents(
+        extracted.frames,
+        extracted.frame_offsets,
+        &extracted.parsed_file.file_content,
+        &preamble,
+        precompiled_format(&cache_subdir, &preamble_filename),
+        &input_dir,
+        args,
+    );
+    Ok((frames, cache_subdir, preamble_filename))
+}
+
+/// Prints the state of the cache for `input_file`: whether its preamble is precompiled, how many
+/// of its frames are compiled, and the outcome of the last build.
+pub fn status(input_file: &str, args: &Options) -> Result<()> {
+    let (frames, cache_subdir, preamble_filename) = cached_frames(input_file, args)?;
+
+    let fmt_state = if cache_subdir
+        .join(format!("{}.fmt", preamble_filename))
+        .is_file()
+    {
+        "precompiled"
+    } else if precompiled_format(&cache_subdir, &preamble_filename).is_none() {
+        "compiled with every frame (the engine cannot precompile it)"
+    } else {
+        "not precompiled (changed since the last build)"
+    };
+    println!("Preamble: {}", fmt_state);
+
+    let num_compiled = frames
+        .iter()
+        .filter(|f| f.is_compiled(&cache_subdir))
+        .count();
+    let failed: Vec<String> = frames
+        .iter()
+        .enumerate()
+        .filter(|(_, f)| f.failure_marker(&cache_subdir).is_file())
+        .map(|(frame_idx, _)| frame_idx.to_string())
+        .collect();
+    println!(
+        "Frames: {} of {} compiled, {} to compile",
+        num_compiled,
+        frames.len(),
+        frames.len() - num_compiled
+    );
+    if !failed.is_empty() {
+        println!("Failed frames: {}", failed.join(", "));
+    }
+
+    match read_build_record(&cache_subdir, input_file) {
+        Some(record) => {
+            let age = SystemTime::now()
+                .duration_since(record.time)
+                .unwrap_or_default();
+            println!(
+                "Last build: {} {}",
+                if record.succeeded {
+                    "succeeded"
+                } else {
+                    "failed"
+                },
+                format_age(age)
+            );
+            println!(
+                "Output: {} is {}",
+                record.output_file,
+                describe_output(&record.output_file, &frames, &cache_subdir)
+            );
+        }
+        None => println!("Last build: none"),
+    }
+    Ok(())
+}
+
+/// Prints one line per frame of `input_file` with its index, state, label and title.
+pub fn list_frames(input_file: &str, args: &Options) -> Result<()> {
+    let (frames, cache_subdir, _) = cached_frames(input_file, args)?;
+    for (frame_idx, frame) in frames.iter().enumerate() {
+        let state = if frame.failure_marker(&cache_subdir).is_file() {
+            "failed"
+        } else if frame.is_compiled(&cache_subdir) {
+            "compiled"
+        } else {
+            "-"
+        };
+        println!(
+            "{:>4}  {:<8}  {:<20}  {}",
+            frame_idx,
+            state,
+            frame_label(&frame.content).unwrap_or_default(),
+            frame_title(&frame.content).unwrap_or_default()
+        );
+    }
+    Ok(())
+}
+
+/// Splits a position `FILE:LINE` (e.g. `talk.tex:42`).
+pub fn parse_position(position: &str) -> Option<(&str, usize)> {
+    let mut parts = position.rsplitn(2, ':');
+    let line = parts.next()?.parse().ok()?;
+    Some((parts.next()?, line))
+}
+
+/// The frame at `line` (1-based) of `input_file` and its first page in the united PDF. Frames
+/// that are not compiled yet count as one page.
+pub fn locate_line(
+    input_file: &str,
+    line: usize,
+    args: &Options,
+) -> Result<Option<(usize, usize)>> {
+    let frame_idx = match frame_at_line(&load_frames(input_file, args)?, line) {
+        Some(frame_idx) => frame_idx,
+        None => return Ok(None),
+    };
+    let (frames, cache_subdir, _) = cached_frames(input_file, args)?;
+    let page_map = build_page_map(&frames[..=frame_idx], &cache_subdir);
+    Ok(Some((frame_idx, page_map[frame_idx].first_page)))
+}
+
+/// Prints the index of the frame at `line` of `input_file` and its first page in the united
+/// PDF, separated by a tab.
+pub fn frame_at(input_file: &str, line: usize, args: &Options) -> Result<()> {
+    match locate_line(input_file, line, args)? {
+        Some((frame_idx, page)) => {
+            println!("{}\t{}", frame_idx, page);
+            Ok(())
+        }
+        None => Err(FasterBeamerError::NotFound(format!(
+            "No frame at {}:{}",
+            input_file, line
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_age() {
+        assert_eq!(format_age(Duration::from_secs(42)), "42 s ago");
+        assert_eq!(format_age(Duration::from_secs(125)), "2 min ago");
+        assert_eq!(format_age(Duration::from_secs(7300)), "2 h ago");
+        assert_eq!(format_age(Duration::from_secs(3 * 86400)), "3 days ago");
+    }
+
+    #[test]
+    fn test_parse_position() {
+        assert_eq!(
+            parse_position("slides/talk.tex:42"),
+            Some(("slides/talk.tex", 42))
+        );
+        assert_eq!(parse_position("C:/talk.tex:7"), Some(("C:/talk.tex", 7)));
+        assert_eq!(parse_position("talk.tex"), None);
+        assert_eq!(parse_position("talk.tex:x"), None);
+    }
+}
@@ -0,0 +1,543 @@
+//
+// synctex.rs
+// Distributed under terms of the GPLv3 license.
+//
+// With `--synctex`, frames are compiled with `-synctex=1`. The SyncTeX file of a frame refers to
+// the pages of the frame PDF and to the lines of the frame document in the cache, so the files of
+// all frames are merged into one for the output PDF: pages are shifted by the pages of the
+// previous frames and lines of the frame documents are mapped back to the input file (or to the
+// file that the input file includes the frame from).
+//
+// `faster-beamer locate` does the opposite for a position in the united PDF (inverse search): it
+// looks up the frame on that page and the nearest record in the SyncTeX file of the frame.
+//
+
+use crate::options::Options;
+use crate::page_map::build_page_map;
+use crate::parsing::ParsedFile;
+use crate::process_file::{load_frames, FasterBeamerError, Frame, Result};
+use crate::status::cached_frames;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::cmp::min;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Records of SyncTeX content that start with `<tag>,<line>`
+const RECORDS_WITH_LINE: &str = "[(vhxkg$";
+
+/// SyncTeX file of `pdf`, where viewers look for it.
+pub fn synctex_file(pdf: &Path) -> PathBuf {
+    pdf.with_extension("synctex.gz")
+}
+
+/// Position of a frame in the input file and in the output PDF.
+#[derive(Debug)]
+pub struct FramePosition {
+    /// Page of the output PDF on which the frame starts (1-based)
+    pub first_page: usize,
+    /// Line of the frame document on which the frame starts (1-based)
+    pub body_line: usize,
+    /// First and last line of the frame in its file (1-based)
+    pub first_line: usize,
+    pub last_line: usize,
+    /// File of the frame if it is included by the input file
+    pub file: Option<PathBuf>,
+}
+
+impl FramePosition {
+    /// Position of `frame` of the document `parsed_file`.
+    pub fn new(frame: &Frame, parsed_file: &ParsedFile, first_page: usize) -> FramePosition {
+        let (file, first_line, _) = parsed_file.get_source_position(frame.offset);
+        FramePosition {
+            first_page,
+            body_line: frame.body_line,
+            first_line,
+            last_line: first_line + frame.content.matches('\n').count(),
+            file: file.map(Path::to_path_buf),
+        }
+    }
+
+    /// Line of the input file for `line` of the frame document. Lines outside of the frame (e.g.
+    /// of the preamble) belong to its first or last line.
+    fn source_line(&self, line: usize) -> usize {
+        min(
+            self.first_line + line.saturating_sub(self.body_line),
+            self.last_line,
+        )
+    }
+}
+
+/// SyncTeX file of the output PDF, merged frame by frame.
+pub struct MergedSynctex {
+    /// Input files; the tag of an input is its index plus one
+    inputs: Vec<String>,
+    /// `Output:`, `Magnification:`, `Unit:` and offsets (from the first frame)
+    header: Vec<String>,
+    content: Vec<String>,
+    count: usize,
+}
+
+impl MergedSynctex {
+    /// `input_file` gets tag 1.
+    pub fn new(input_file: &str) -> MergedSynctex {
+        MergedSynctex {
+            inputs: vec![input_file.to_string()],
+            header: Vec::new(),
+            content: Vec::new(),
+            count: 0,
+        }
+    }
+
+    fn input_tag(&mut self, path: String) -> usize {
+        match self.inputs.iter().position(|input| *input == path) {
+            Some(idx) => idx + 1,
+            None => {
+                self.inputs.push(path);
+                self.inputs.len()
+            }
+        }
+    }
+
+    /// Adds the SyncTeX file `synctex` of a frame. `frame_tex` is the name of the frame document,
+    /// `resolve` makes the paths of other inputs absolute.
+    pub fn add_frame(
+        &mut self,
+        synctex: &str,
+        frame_tex: &str,
+        position: &FramePosition,
+        resolve: &dyn Fn(&str) -> String,
+    ) {
+        let is_first_frame = self.header.is_empty();
+        let frame_input = match &position.file {
+            Some(file) => self.input_tag(file.to_string_lossy().into_owned()),
+            None => 1,
+        };
+        let mut tags = HashMap::new();
+        let mut frame_tag = None;
+        let mut in_content = false;
+
+        for line in synctex.lines() {
+            if let Some(input) = line.strip_prefix("Input:") {
+                let mut fields = input.splitn(2, ':');
+                let tag = fields.next().and_then(|tag| tag.parse::<usize>().ok());
+                if let (Some(tag), Some(path)) = (tag, fields.next()) {
+                    if Path::new(path).file_name() == Some(frame_tex.as_ref()) {
+                        frame_tag = Some(tag);
+                        tags.insert(tag, frame_input);
+                    } else {
+                        tags.insert(tag, self.input_tag(resolve(path)));
+                    }
+                }
+            } else if line == "Content:" {
+                in_content = true;
+            } else if line == "Postamble:" {
+                in_content = false;
+            } else if !in_content {
+                let is_header = [
+                    "Output:",
+                    "Magnification:",
+                    "Unit:",
+                    "X Offset:",
+                    "Y Offset:",
+                ]
+                .iter()
+                .any(|field| line.starts_with(field));
+                if is_first_frame && is_header {
+                    self.header.push(line.to_string());
+                }
+            } else if line.starts_with('!') {
+                // Byte offsets, which do not hold in the merged file
+            } else if line.starts_with('{') || line.starts_with('}') {
+                let (marker, page) = line.split_at(1);
+                match page.parse::<usize>() {
+                    Ok(page) => {
+                        self.content
+                            .push(format!("{}{}", marker, page + position.first_page - 1))
+                    }
+                    Err(_) => self.content.push(line.to_string()),
+                }
+            } else if line.starts_with(|c| RECORDS_WITH_LINE.contains(c)) {
+                self.count += 1;
+                self.content
+                    .push(rewrite_record(line, &tags, frame_tag, position));
+            } else {
+                self.content.push(line.to_string());
+            }
+        }
+    }
+}
+
+impl fmt::Display for MergedSynctex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut lines = vec!["SyncTeX Version:1".to_string()];
+        for (idx, input) in self.inputs.iter().enumerate() {
+            lines.push(format!("Input:{}:{}", idx + 1, input));
+        }
+        if self.header.is_empty() {
+            lines.extend(
+                [
+                    "Output:pdf",
+                    "Magnification:1000",
+                    "Unit:1",
+                    "X Offset:0",
+                    "Y Offset:0",
+                ]
+                .iter()
+                .map(|line| line.to_string()),
+            );
+        } else {
+            lines.extend(self.header.iter().cloned());
+        }
+        lines.push("Content:".to_string());
+        lines.extend(self.content.iter().cloned());
+        lines.push("Postamble:".to_string());
+        lines.push(format!("Count:{}", self.count));
+        lines.push("Post scriptum:".to_string());
+        writeln!(f, "{}", lines.join("\n"))
+    }
+}
+
+/// Replaces the tag and line at the start of `record` (`[tag,line(,column):...`).
+fn rewrite_record(
+    record: &str,
+    tags: &HashMap<usize, usize>,
+    frame_tag: Option<usize>,
+    position: &FramePosition,
+) -> String {
+    let (kind, rest) = record.split_at(1);
+    let (tag_line, tail) = rest.split_at(rest.find(':').unwrap_or(rest.len()));
+    let mut fields = tag_line.splitn(3, ',');
+    let tag = fields.next().and_then(|tag| tag.parse::<usize>().ok());
+    let line = fields.next().and_then(|line| line.parse::<usize>().ok());
+    let column = fields.next().map(|column| format!(",{}", column));
+    match (
+        tag.and_then(|tag| tags.get(&tag).map(|merged| (tag, *merged))),
+        line,
+    ) {
+        (Some((tag, merged_tag)), Some(line)) => {
+            let line = if Some(tag) == frame_tag {
+                position.source_line(line)
+            } else {
+                line
+            };
+            format!(
+                "{}{},{}{}{}",
+                kind,
+                merged_tag,
+                line,
+                column.unwrap_or_default(),
+                tail
+            )
+        }
+        _ => record.to_string(),
+    }
+}
+
+/// A record of SyncTeX content with its position: a box, or a point (e.g. glue or math) without
+/// size. Dimensions are in scaled points from the top left corner of the page.
+#[derive(Debug, PartialEq)]
+struct Record {
+    tag: usize,
+    line: usize,
+    h: i64,
+    v: i64,
+    width: i64,
+    height: i64,
+    depth: i64,
+}
+
+impl Record {
+    fn parse(record: &str, unit: i64) -> Option<Record> {
+        if !record.starts_with(|c| RECORDS_WITH_LINE.contains(c)) {
+            return None;
+        }
+        let numbers = |field: Option<&str>| -> Vec<i64> {
+            field
+                .unwrap_or_default()
+                .split(',')
+                .filter_map(|n| n.parse().ok())
+                .collect()
+        };
+        let mut fields = record[1..].split(':');
+        let tag_line = numbers(fields.next());
+        let position = numbers(fields.next());
+        let size = numbers(fields.next());
+        let dimension = |i: usize| size.get(i).map_or(0, |d| d * unit);
+        Some(Record {
+            tag: *tag_line.first()? as usize,
+            line: *tag_line.get(1)? as usize,
+            h: position.first()? * unit,
+            v: position.get(1)? * unit,
+            width: dimension(0).abs(),
+            height: dimension(1),
+            depth: dimension(2),
+        })
+    }
+
+    /// Distance of (`x`, `y`) to the box of the record (0 inside).
+    fn distance(&self, x: i64, y: i64) -> i64 {
+        let outside = |value: i64, from: i64, to: i64| {
+            if value < from {
+                from - value
+            } else if value > to {
+                value - to
+            } else {
+                0
+            }
+        };
+        outside(x, self.h, self.h + self.width)
+            + outside(y, self.v - self.height, self.v + self.depth)
+    }
+}
+
+/// Input file and line of the record of `synctex` nearest to (`x`, `y`) on `page` (1-based), in
+/// scaled points from the top left corner of the page. Of the boxes containing the position, the
+/// smallest (e.g. the line rather than the page) wins.
+fn find_record(synctex: &str, page: usize, x: i64, y: i64) -> Option<(String, usize)> {
+    let mut inputs = HashMap::new();
+    let mut unit = 1;
+    let mut current_page = None;
+    let mut best: Option<((i64, i64), Record)> = None;
+
+    for line in synctex.lines() {
+        if let Some(input) = line.strip_prefix("Input:") {
+            let mut fields = input.splitn(2, ':');
+            if let (Some(Ok(tag)), Some(path)) =
+                (fields.next().map(|tag| tag.parse::<usize>()), fields.next())
+            {
+                inputs.insert(tag, path.to_string());
+            }
+        } else if let Some(value) = line.strip_prefix("Unit:") {
+            unit = value.parse().unwrap_or(1);
+        } else if let Some(value) = line.strip_prefix('{') {
+            current_page = value.parse::<usize>().ok();
+        } else if line.starts_with('}') {
+            current_page = None;
+        } else if current_page == Some(page) {
+            if let Some(record) = Record::parse(line, unit) {
+                let key = (
+                    record.distance(x, y),
+                    record.width * (record.height + record.depth),
+                );
+                if best.as_ref().map_or(true, |(best_key, _)| key < *best_key) {
+                    best = Some((key, record));
+                }
+            }
+        }
+    }
+    let (_, record) = best?;
+    Some((inputs.get(&record.tag)?.clone(), record.line))
+}
+
+/// Path of the input `path` of a frame compiled in `cache_subdir`.
+fn resolve_input(cache_subdir: &Path, path: &str) -> String {
+    let path = cache_subdir.join(path);
+    fs::canonicalize(&path)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Source file and line of the position (`x`, `y`) on `page` (1-based) of the united PDF of
+/// `input_file`. Coordinates are in PDF points from the top left corner of the page.
+pub fn locate(
+    input_file: &str,
+    page: usize,
+    x: f64,
+    y: f64,
+    args: &Options,
+) -> Result<(String, usize)> {
+    let parsed_file = load_frames(input_file, args)?.parsed_file;
+    let (frames, cache_subdir, _) = cached_frames(input_file, args)?;
+    let page_map = build_page_map(&frames, &cache_subdir);
+    let pages = page_map
+        .iter()
+        .find(|pages| pages.first_page <= page && page <= pages.last_page)
+        .ok_or_else(|| {
+            FasterBeamerError::NotFound(format!("{} has no page {}", input_file, page))
+        })?;
+    let frame = &frames[pages.frame];
+    let synctex = read_synctex(&frame.synctex(&cache_subdir))?;
+
+    let scaled_points = |bp: f64| (bp * 72.27 / 72.0 * 65536.0).round() as i64;
+    let (path, line) = find_record(
+        &synctex,
+        page - pages.first_page + 1,
+        scaled_points(x),
+        scaled_points(y),
+    )
+    .ok_or_else(|| {
+        FasterBeamerError::NotFound(format!(
+            "No SyncTeX record on page {} of {}",
+            page, input_file
+        ))
+    })?;
+    if Path::new(&path).file_name() == Some(format!("{:x}.tex", frame.hash).as_ref()) {
+        let position = FramePosition::new(frame, &parsed_file, pages.first_page);
+        let file = match &position.file {
+            Some(file) => file.display().to_string(),
+            None => parsed_file.filename,
+        };
+        Ok((file, position.source_line(line)))
+    } else {
+        Ok((resolve_input(&cache_subdir, &path), line))
+    }
+}
+
+/// Prints `FILE:LINE` of the position (`x`, `y`) on `page` of the united PDF of `input_file`.
+pub fn inverse_search(input_file: &str, page: usize, x: f64, y: f64, args: &Options) -> Result<()> {
+    let (file, line) = locate(input_file, page, x, y, args)?;
+    println!("{}:{}", file, line);
+    Ok(())
+}
+
+fn read_synctex(path: &Path) -> Result<String> {
+    let mut content = String::new();
+    File::open(path)
+        .and_then(|file| GzDecoder::new(file).read_to_string(&mut content))
+        .map_err(FasterBeamerError::io(path))?;
+    Ok(content)
+}
+
+/// Writes the SyncTeX file of `output_file`, which consists of the pages of `frames` of
+/// `input_file` (parsed as `parsed_file`). Frames without SyncTeX file (e.g. compiled before
+/// `--synctex` was given) are left out.
+pub fn write_synctex(
+    input_file: &str,
+    parsed_file: &ParsedFile,
+    frames: &[Frame],
+    cache_subdir: &Path,
+    output_file: &str,
+) -> Result<()> {
+    let input_path = fs::canonicalize(input_file).map_err(FasterBeamerError::io(input_file))?;
+    let mut merged = MergedSynctex::new(&input_path.to_string_lossy());
+    // Inputs of the frames are found relative to the cache directory in which they are compiled
+    let resolve = |path: &str| resolve_input(cache_subdir, path);
+
+    for (frame, pages) in frames.iter().zip(build_page_map(frames, cache_subdir)) {
+        let frame_synctex = frame.synctex(cache_subdir);
+        let synctex = match read_synctex(&frame_synctex) {
+            Ok(synctex) => synctex,
+            Err(err) => {
+                warn!(
+                    "Leaving frame {} out of the SyncTeX file: {}",
+                    pages.frame, err
+                );
+                continue;
+            }
+        };
+        let position = FramePosition::new(frame, parsed_file, pages.first_page);
+        merged.add_frame(
+            &synctex,
+            &format!("{:x}.tex", frame.hash),
+            &position,
+            &resolve,
+        );
+    }
+
+    let path = synctex_file(Path::new(output_file));
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder
+        .write_all(merged.to_string().as_bytes())
+        .and_then(|()| encoder.finish())
+        .and_then(|compressed| fs::write(&path, compressed))
+        .map_err(FasterBeamerError::io(&path))?;
+    info!("Wrote {}", path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_record() {
+        let synctex = "SyncTeX Version:1\n\
+                       Input:1:/cache/abc.tex\n\
+                       Input:2:./figure.tex\n\
+                       Unit:1\n\
+                       Content:\n\
+                       {1\n\
+                       [1,12:4736286,3908927:22609920,30000000,0\n\
+                       (1,14:4736286,8000000:22609920,500000,100000\n\
+                       (2,3:4736286,9000000:22609920,500000,100000\n\
+                       g1,15:5000000,9500000\n\
+                       ]\n\
+                       }1\n";
+        // Inside the page and the line of figure.tex
+        assert_eq!(
+            find_record(synctex, 1, 6000000, 8900000),
+            Some(("./figure.tex".to_string(), 3))
+        );
+        assert_eq!(
+            find_record(synctex, 1, 6000000, 7800000),
+            Some(("/cache/abc.tex".to_string(), 14))
+        );
+        // Below all lines, the glue is nearest
+        assert_eq!(
+            find_record(synctex, 1, 5000000, 9600000),
+            Some(("/cache/abc.tex".to_string(), 15))
+        );
+        assert_eq!(find_record(synctex, 2, 0, 0), None);
+    }
+
+    #[test]
+    fn test_merge_synctex() {
+        let synctex = "SyncTeX Version:1\n\
+                       Input:1:/cache/abc.tex\n\
+                       Output:pdf\n\
+                       Magnification:1000\n\
+                       Content:\n\
+                       !120\n\
+                       Input:2:./figure.tex\n\
+                       {1\n\
+                       [1,12:4736286,3908927:22609920,4736286,0\n\
+                       h2,3:4736286,3908927:100,0,0\n\
+                       x1,2:10,20\n\
+                       ]\n\
+                       }1\n\
+                       {2\n\
+                       k1,13,5:20,30:40\n\
+                       }2\n\
+                       Postamble:\n\
+                       Count:4\n\
+                       Post scriptum:\n";
+        let position = FramePosition {
+            first_page: 4,
+            body_line: 12,
+            first_line: 30,
+            last_line: 31,
+            file: None,
+        };
+        let mut merged = MergedSynctex::new("/talk/talk.tex");
+        merged.add_frame(synctex, "abc.tex", &position, &|path| {
+            format!("/talk/{}", path.trim_start_matches("./"))
+        });
+        assert_eq!(
+            merged.to_string(),
+            "SyncTeX Version:1\n\
+             Input:1:/talk/talk.tex\n\
+             Input:2:/talk/figure.tex\n\
+             Output:pdf\n\
+             Magnification:1000\n\
+             Content:\n\
+             {4\n\
+             [1,30:4736286,3908927:22609920,4736286,0\n\
+             h2,3:4736286,3908927:100,0,0\n\
+             x1,30:10,20\n\
+             ]\n\
+             }4\n\
+             {5\n\
+             k1,31,5:20,30:40\n\
+             }5\n\
+             Postamble:\n\
+             Count:4\n\
+             Post scriptum:\n"
+        );
+    }
+}
@@ -0,0 +1,93 @@
+//
+// tagging.rs
+// Distributed under terms of the GPLv3 license.
+//
+// Tagged PDFs (`\DocumentMetadata{tagging=on}`, tagpdf) have a single structure tree for the
+// whole document, which is lost when the PDFs of the frames are merged. With `--tagged` the
+// output is compiled from the whole document instead.
+//
+
+use crate::error::log_excerpt;
+use crate::latexcompile::{LatexCompiler, LatexInput, LatexRunOptions};
+use crate::options::Options;
+use crate::process_file::{
+    compiler_environment, document_preamble, engine_command, frame_compiler_args,
+    FasterBeamerError, Result,
+};
+use regex::Regex;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+lazy_static! {
+    static ref TAGGING_REGEX: Regex = Regex::new(
+        r"\\DocumentMetadata\s*\{[^}]*(tagging\s*=\s*on|testphase)|\\usepackage(\[[^\]]*\])?\{tagpdf\}"
+    )
+    .unwrap();
+}
+
+/// Whether the preamble of `file_content` turns on tagging.
+pub fn is_tagged(file_content: &str) -> bool {
+    TAGGING_REGEX.is_match(&document_preamble(file_content))
+}
+
+/// Compiles the whole document `file_content` of `input_file` (twice, as tagging needs the
+/// structure of the previous run). The PDF is created in `working_dir`.
+pub fn compile_tagged(
+    file_content: &str,
+    input_file: &str,
+    output_file: &str,
+    working_dir: &Path,
+    args: &Options,
+) -> Result<PathBuf> {
+    let compilercmd = engine_command(args);
+    // Different output files (e.g. of aspect ratio variants) must not share a tagged PDF
+    let tex_file = working_dir.join(format!("tagged-{:x}.tex", md5::compute(output_file)));
+    fs::write(&tex_file, file_content).map_err(FasterBeamerError::io(&tex_file))?;
+
+    let mut compiler =
+        LatexCompiler::new(compilercmd).map_err(FasterBeamerError::latex_input(&tex_file))?;
+    for arg in frame_compiler_args(args) {
+        compiler = compiler.add_arg(&arg);
+    }
+    for (key, value) in compiler_environment(input_file, args) {
+        compiler = compiler.add_env(&key, &value);
+    }
+    compiler.working_dir = working_dir.to_owned();
+
+    info!("Compiling tagged PDF of {}", input_file);
+    compiler
+        .run(
+            &tex_file.to_string_lossy(),
+            &LatexInput::new(),
+            LatexRunOptions::new().double_compilation(),
+        )
+        .map_err(|err| FasterBeamerError::Compile {
+            log_excerpt: log_excerpt(&tex_file.with_extension("log"), &err.to_string()),
+            file: tex_file.clone(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_tagged() {
+        assert!(is_tagged(
+            "\\DocumentMetadata{lang=en, pdfversion=2.0, tagging=on}\n\
+             \\documentclass{beamer}\n\\begin{document}\n\\end{document}\n"
+        ));
+        assert!(is_tagged(
+            "\\DocumentMetadata{testphase={phase-III,math}}\n\\documentclass{beamer}\n\
+             \\begin{document}\n\\end{document}\n"
+        ));
+        assert!(is_tagged(
+            "\\documentclass{beamer}\n\\usepackage[tabsorder=structure]{tagpdf}\n\
+             \\begin{document}\n\\end{document}\n"
+        ));
+        assert!(!is_tagged(
+            "\\DocumentMetadata{pdfversion=2.0}\n\\documentclass{beamer}\n\
+             \\begin{document}\n\\usepackage{tagpdf}\n\\end{document}\n"
+        ));
+    }
+}
@@ -0,0 +1,65 @@
+//
+// test_pdf.rs
+// Distributed under terms of the GPLv3 license.
+//
+// PDFs for the tests of the modules that read, merge and rewrite PDFs, built with lopdf.
+//
+
+use lopdf::{Dictionary, Document, Object, ObjectId};
+
+/// PDF with `num_pages` pages in a single page tree. `fill_page` completes the dictionary of every
+/// page, given its index and its id (e.g. for annotations that refer to it).
+pub fn pdf_with_pages<F>(num_pages: usize, mut fill_page: F) -> Document
+where
+    F: FnMut(&mut Document, usize, ObjectId, &mut Dictionary),
+{
+    let mut doc = Document::with_version("1.5");
+    let pages_id = doc.new_object_id();
+    let mut page_ids = Vec::with_capacity(num_pages);
+    for page_idx in 0..num_pages {
+        let page_id = doc.new_object_id();
+        let mut page = Dictionary::new();
+        page.set("Type", Object::Name(b"Page".to_vec()));
+        page.set("Parent", Object::Reference(pages_id));
+        fill_page(&mut doc, page_idx, page_id, &mut page);
+        doc.objects.insert(page_id, Object::Dictionary(page));
+        page_ids.push(page_id);
+    }
+    let mut pages = Dictionary::new();
+    pages.set("Type", Object::Name(b"Pages".to_vec()));
+    pages.set(
+        "Kids",
+        page_ids
+            .iter()
+            .map(|id| Object::Reference(*id))
+            .collect::<Vec<_>>(),
+    );
+    pages.set("Count", num_pages as i64);
+    doc.objects.insert(pages_id, Object::Dictionary(pages));
+
+    let mut catalog = Dictionary::new();
+    catalog.set("Type", Object::Name(b"Catalog".to_vec()));
+    catalog.set("Pages", Object::Reference(pages_id));
+    let catalog_id = doc.add_object(catalog);
+    doc.trailer.set("Root", Object::Reference(catalog_id));
+    doc
+}
+
+/// Catalog of `doc`.
+pub fn catalog_mut(doc: &mut Document) -> &mut Dictionary {
+    let catalog_id = doc.trailer.get(b"Root").unwrap().as_reference().unwrap();
+    doc.get_object_mut(catalog_id)
+        .unwrap()
+        .as_dict_mut()
+        .unwrap()
+}
+
+/// Root of the page tree of `doc` (e.g. for attributes that the pages inherit).
+pub fn page_tree_mut(doc: &mut Document) -> &mut Dictionary {
+    let pages_id = catalog_mut(doc)
+        .get(b"Pages")
+        .unwrap()
+        .as_reference()
+        .unwrap();
+    doc.get_object_mut(pages_id).unwrap().as_dict_mut().unwrap()
+}
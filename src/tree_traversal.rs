@@ -81,7 +81,8 @@ std::cout << "Hello, World!";
 return 0;
 }
     "#;
-        let parsed = ParsedFile::from_string("main.c".to_string(), source_code.to_string());
+        let parsed =
+            ParsedFile::from_string("main.c".to_string(), source_code.to_string()).unwrap();
         parsed.syntax_tree.root_node();
     }
 }
@@ -0,0 +1,53 @@
+//
+// warm.rs
+// Distributed under terms of the GPLv3 license.
+//
+
+use crate::error::log_excerpt;
+use crate::options::Options;
+use crate::process_file::{compile_frames, FasterBeamerError, Result};
+
+/// Precompiles the preamble and all frames of `input_files` without writing an output, so that
+/// later builds (e.g. in CI or a live-editing session) find them in the cache. Fails if a frame
+/// does not compile. Errors are reported for every input file, the first one is returned.
+pub fn warm(input_files: &[String], args: &Options) -> Result<()> {
+    let mut errors = Vec::new();
+    for input_file in input_files {
+        info!("Warming up the cache for {}", input_file);
+        let compiled = match compile_frames(input_file, args) {
+            Ok(compiled) => compiled,
+            Err(err) => {
+                error!("{}: {}", input_file, err);
+                errors.push(err);
+                continue;
+            }
+        };
+        let failed: Vec<usize> = (0..compiled.frames.len())
+            .filter(|frame_idx| !compiled.frames[*frame_idx].is_compiled(&compiled.cache_subdir))
+            .collect();
+        if let Some(&frame_idx) = failed.first() {
+            error!(
+                "{} of {} frames of {} failed to compile",
+                failed.len(),
+                compiled.frames.len(),
+                input_file
+            );
+            let err = FasterBeamerError::FrameCompile {
+                frame_idx,
+                log_excerpt: log_excerpt(
+                    &compiled.frames[frame_idx].log(&compiled.cache_subdir),
+                    "No PDF was written",
+                ),
+            };
+            error!("{}: {}", input_file, err);
+            errors.push(err);
+        } else {
+            info!(
+                "Cached the preamble and {} frames of {}",
+                compiled.frames.len(),
+                input_file
+            );
+        }
+    }
+    errors.into_iter().next().map_or(Ok(()), Err)
+}
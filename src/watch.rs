@@ -0,0 +1,222 @@
+//
+// watch.rs
+// Distributed under terms of the GPLv3 license.
+//
+// Editors often save a file in several steps (e.g. writing a backup, renaming and writing the
+// file). Events are collected until the files have been quiet for the debounce delay, then every
+// document affected by one of the changed files is built once. Besides the document itself, this
+// includes the files it `\input`s and the images, listings and bibliographies that its frames
+// read, which are mapped to the frames that read them.
+//
+
+use crate::batch::{process_jobs, Job};
+use crate::dependencies::dependency_map;
+use crate::options::Options;
+use crate::process_file::{get_input_dir, load_frames};
+use glob::Pattern;
+use notify::{DebouncedEvent, RecursiveMode, Watcher};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::channel;
+use std::time::Duration;
+
+/// Delay after the last change of a file before a build starts.
+pub const DEFAULT_DEBOUNCE_MS: u64 = 200;
+
+/// File whose content has changed with `event`.
+fn changed_path(event: DebouncedEvent) -> Option<PathBuf> {
+    match event {
+        DebouncedEvent::Create(path) | DebouncedEvent::Write(path) => Some(path),
+        // Editors that save by renaming a temporary file
+        DebouncedEvent::Rename(_, path) => Some(path),
+        DebouncedEvent::Error(err, path) => {
+            warn!("Error while watching {:?}: {}", path, err);
+            None
+        }
+        event => {
+            trace!("{:?}", event);
+            None
+        }
+    }
+}
+
+/// Whether changes of `path` are ignored because of one of the `--watch-ignore` patterns, which
+/// match either the whole path or the file name.
+fn is_ignored(path: &Path, ignore_patterns: &[Pattern]) -> bool {
+    ignore_patterns.iter().any(|pattern| {
+        pattern.matches_path(path)
+            || path
+                .file_name()
+                .map_or(false, |name| pattern.matches(&name.to_string_lossy()))
+    })
+}
+
+/// Local files that `input_file` reads besides itself, with the frames that read them.
+pub fn document_dependencies(input_file: &str, args: &Options) -> BTreeMap<PathBuf, Vec<usize>> {
+    match load_frames(input_file, args) {
+        Ok(extracted) => dependency_map(&extracted, &get_input_dir(input_file)),
+        Err(_) => BTreeMap::new(),
+    }
+}
+
+/// Jobs that need to be built again after `changed_files` have changed.
+pub fn affected_jobs(jobs: &[Job], changed_files: &BTreeSet<PathBuf>, args: &Options) -> Vec<Job> {
+    let changed_files: Vec<PathBuf> = changed_files
+        .iter()
+        .filter_map(|file| file.canonicalize().ok())
+        .collect();
+    let is_changed = |file: &String| match Path::new(file).canonicalize() {
+        // Frame directories change with any of their files
+        Ok(file) => changed_files
+            .iter()
+            .any(|changed| *changed == file || (file.is_dir() && changed.starts_with(&file))),
+        Err(_) => false,
+    };
+    let is_dependency_changed = |job: &Job| {
+        let dependencies = document_dependencies(&job.input_file, args);
+        let mut is_affected = false;
+        for changed in changed_files.iter() {
+            if let Some(frames) = dependencies.get(changed) {
+                debug!(
+                    "{} changed, which {} frames of {} read",
+                    changed.display(),
+                    frames.len(),
+                    job.input_file
+                );
+                is_affected = true;
+            }
+        }
+        is_affected
+    };
+    jobs.iter()
+        .filter(|job| {
+            is_changed(&job.input_file)
+                || job.edited_file.iter().any(is_changed)
+                || is_dependency_changed(job)
+        })
+        .cloned()
+        .collect()
+}
+
+/// Watches the inputs of `jobs` and builds every job again when one of its files changes. Only
+/// returns if the files cannot be watched.
+pub fn watch(jobs: &[Job], args: &Options) -> notify::Result<()> {
+    let debounce = args.debounce.unwrap_or(DEFAULT_DEBOUNCE_MS);
+    let ignore_patterns: Vec<Pattern> = args
+        .watch_ignore
+        .iter()
+        .filter_map(|pattern| match Pattern::new(pattern) {
+            Ok(pattern) => Some(pattern),
+            Err(err) => {
+                warn!("Invalid pattern {:?}: {}", pattern, err);
+                None
+            }
+        })
+        .collect();
+    let (tx, rx) = channel();
+    let mut watcher = notify::watcher(tx, Duration::from_millis(debounce))?;
+
+    let input_dirs: BTreeSet<PathBuf> = jobs
+        .iter()
+        .flat_map(|job| job.edited_file.iter().chain(Some(&job.input_file)))
+        .map(|file| get_input_dir(file))
+        .collect();
+    // Dependencies outside of the input directories (e.g. `../figures/plot.pdf`)
+    let dependency_dirs: BTreeSet<PathBuf> = jobs
+        .iter()
+        .flat_map(|job| document_dependencies(&job.input_file, args).into_keys())
+        .filter(|file| !input_dirs.iter().any(|dir| file.starts_with(dir)))
+        .filter_map(|file| file.parent().map(Path::to_path_buf))
+        .collect();
+    for input_dir in input_dirs {
+        watcher.watch(&input_dir, RecursiveMode::Recursive)?;
+    }
+    for dir in dependency_dirs {
+        watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+    }
+    info!("Watch mode");
+    for job in jobs {
+        info!("Watching {}", job.input_file);
+    }
+
+    while let Ok(event) = rx.recv() {
+        let mut changed_files: BTreeSet<PathBuf> = changed_path(event).into_iter().collect();
+        // Events of the same save arrive together
+        changed_files.extend(rx.try_iter().filter_map(changed_path));
+        changed_files.retain(|file| !is_ignored(file, &ignore_patterns));
+        if changed_files.is_empty() {
+            continue;
+        }
+        trace!("{:?} have changed.", changed_files);
+
+        let affected = affected_jobs(jobs, &changed_files, args);
+        if !affected.is_empty() {
+            let _results = process_jobs(&affected, args);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_affected_jobs() {
+        let dir = tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name);
+        fs::write(
+            path("talk.tex"),
+            "\\begin{document}\n\\input{slides}\n\\end{document}\n",
+        )
+        .unwrap();
+        fs::write(
+            path("slides.tex"),
+            "\\begin{frame}\\includegraphics{plot}\n\\end{frame}\n",
+        )
+        .unwrap();
+        fs::write(path("plot.pdf"), "").unwrap();
+        fs::write(path("other.tex"), "").unwrap();
+        fs::create_dir(path("frames")).unwrap();
+        fs::write(path("frames/001.tex"), "").unwrap();
+
+        let job = |input_file: &str| Job {
+            input_file: path(input_file).to_string_lossy().into_owned(),
+            output_file: "out.pdf".to_string(),
+            page_map_file: None,
+            edited_file: None,
+        };
+        let mut project = job("other.tex");
+        project.edited_file = Some(path("talk.tex").to_string_lossy().into_owned());
+        let jobs = vec![job("talk.tex"), job("frames"), project];
+
+        let args = Options::new();
+        let affected = |names: &[&str]| {
+            let changed: BTreeSet<PathBuf> = names.iter().map(|n| path(n)).collect();
+            affected_jobs(&jobs, &changed, &args)
+        };
+        assert_eq!(
+            affected(&["talk.tex"]),
+            vec![jobs[0].clone(), jobs[2].clone()]
+        );
+        assert_eq!(affected(&["frames/001.tex"]), vec![jobs[1].clone()]);
+        // Included files and images
+        assert_eq!(affected(&["slides.tex"]), vec![jobs[0].clone()]);
+        assert_eq!(affected(&["plot.pdf"]), vec![jobs[0].clone()]);
+        // Outputs written next to the input
+        assert!(affected(&["talk.preview.pdf"]).is_empty());
+    }
+
+    #[test]
+    fn test_is_ignored() {
+        let patterns = vec![
+            Pattern::new("*.bak").unwrap(),
+            Pattern::new("/talk/generated/*").unwrap(),
+        ];
+        assert!(is_ignored(Path::new("/talk/frames/001.tex.bak"), &patterns));
+        assert!(is_ignored(Path::new("/talk/generated/plot.tex"), &patterns));
+        assert!(!is_ignored(Path::new("/talk/talk.tex"), &patterns));
+    }
+}
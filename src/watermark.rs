@@ -0,0 +1,65 @@
+//
+// watermark.rs
+// Distributed under terms of the GPLv3 license.
+//
+
+use crate::error::log_excerpt;
+use crate::latexcompile::{LatexCompiler, LatexInput, LatexRunOptions};
+use crate::parsing::escape_latex;
+use crate::process_file::{FasterBeamerError, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Document that overlays `text` diagonally over every page of `pdf`.
+pub fn watermark_tex(pdf: &str, text: &str) -> String {
+    format!(
+        "\\documentclass{{article}}\n\
+         \\usepackage{{pdfpages}}\n\
+         \\usepackage{{xcolor}}\n\
+         \\begin{{document}}\n\
+         \\includepdf[pages=-,fitpaper,pagecommand={{}},picturecommand={{%\n\
+         \\put(\\LenToUnit{{.5\\paperwidth}},\\LenToUnit{{.5\\paperheight}}){{%\n\
+         \\makebox(0,0){{\\rotatebox{{30}}{{\\resizebox{{.8\\paperwidth}}{{!}}{{%\n\
+         \\textcolor{{red!50}}{{\\sffamily\\bfseries {}}}}}}}}}}}}}]{{{}}}\n\
+         \\end{{document}}\n",
+        escape_latex(text),
+        pdf
+    )
+}
+
+/// Stamps `text` on every page of `pdf`. The stamped PDF is created in `working_dir`, passing
+/// `latex_args` to the compiler.
+pub fn stamp_watermark(
+    pdf: &Path,
+    text: &str,
+    working_dir: &Path,
+    compilercmd: &str,
+    latex_args: &[String],
+) -> Result<PathBuf> {
+    let name = format!("watermarked-{:x}", md5::compute(pdf.to_string_lossy().as_bytes()));
+    let input_name = format!("{}-input.pdf", name);
+    let tex_file = working_dir.join(format!("{}.tex", name));
+
+    fs::copy(pdf, working_dir.join(&input_name)).map_err(FasterBeamerError::io(pdf))?;
+    fs::write(&tex_file, watermark_tex(&input_name, text))
+        .map_err(FasterBeamerError::io(&tex_file))?;
+
+    let mut compiler = LatexCompiler::new(compilercmd)
+        .map_err(FasterBeamerError::latex_input(&tex_file))?
+        .add_arg("-interaction=nonstopmode");
+    for arg in latex_args {
+        compiler = compiler.add_arg(arg);
+    }
+    compiler.working_dir = working_dir.to_owned();
+
+    compiler
+        .run(
+            &tex_file.to_string_lossy(),
+            &LatexInput::new(),
+            LatexRunOptions::new(),
+        )
+        .map_err(|err| FasterBeamerError::Compile {
+            log_excerpt: log_excerpt(&tex_file.with_extension("log"), &err.to_string()),
+            file: tex_file.clone(),
+        })
+}